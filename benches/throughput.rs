@@ -17,6 +17,44 @@ fn benchmark_request_parsing(c: &mut Criterion) {
     group.finish();
 }
 
+fn benchmark_file_parsing(c: &mut Criterion) {
+    use blaze_api::input::{stream_requests, ReadOptions};
+    use futures::StreamExt;
+    use std::io::Write;
+
+    const LINES: u64 = 100_000;
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    for n in 0..LINES {
+        writeln!(
+            file,
+            r#"{{"input": "Classify support ticket {n} as billing or technical.", "ticket_id": {n}, "tags": ["support"]}}"#
+        )
+        .unwrap();
+    }
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let cpus = std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get);
+
+    let mut group = c.benchmark_group("file_parsing");
+    group.throughput(Throughput::Elements(LINES));
+    group.sample_size(10);
+
+    // Always try at least two threads, so a single CPU shows what they cost
+    for workers in [1, cpus.max(2)] {
+        let options = ReadOptions {
+            parse_workers: workers,
+            ..Default::default()
+        };
+        group.bench_function(format!("parse_workers_{workers}"), |b| {
+            b.to_async(&runtime).iter(|| async {
+                let requests = stream_requests(file.path(), &options).await.unwrap();
+                requests.count().await
+            });
+        });
+    }
+
+    group.finish();
+}
+
 fn benchmark_load_balancer(c: &mut Criterion) {
     use blaze_api::{EndpointConfig, LoadBalancer};
 
@@ -27,6 +65,7 @@ fn benchmark_load_balancer(c: &mut Criterion) {
             api_key: None,
             model: None,
             max_concurrent: 100,
+            ..Default::default()
         },
         EndpointConfig {
             url: "http://b.test".to_string(),
@@ -34,6 +73,7 @@ fn benchmark_load_balancer(c: &mut Criterion) {
             api_key: None,
             model: None,
             max_concurrent: 100,
+            ..Default::default()
         },
         EndpointConfig {
             url: "http://c.test".to_string(),
@@ -41,6 +81,7 @@ fn benchmark_load_balancer(c: &mut Criterion) {
             api_key: None,
             model: None,
             max_concurrent: 100,
+            ..Default::default()
         },
     ];
 
@@ -58,5 +99,10 @@ fn benchmark_load_balancer(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, benchmark_request_parsing, benchmark_load_balancer);
+criterion_group!(
+    benches,
+    benchmark_request_parsing,
+    benchmark_file_parsing,
+    benchmark_load_balancer
+);
 criterion_main!(benches);
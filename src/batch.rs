@@ -0,0 +1,133 @@
+//! Grouping of requests into batches for endpoints that take array payloads.
+//!
+//! Some APIs, embeddings in particular, accept an array of inputs in one
+//! call and answer with an array of results in the same order. Sending
+//! requests together cuts the number of HTTP calls, and with it rate limit
+//! usage, by up to the batch size. Requests join the pending batch of their
+//! endpoint, which is sent once it is full or its wait has passed.
+
+use crate::request::{ApiRequest, RequestResult};
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// A request waiting in a batch, with the body rendered for its endpoint.
+#[derive(Debug)]
+pub struct BatchItem {
+    /// The request, for correlating its result.
+    pub request: ApiRequest,
+    /// The body sent as this request's element of the array.
+    pub body: Value,
+    /// Where the request's result is sent.
+    pub reply: oneshot::Sender<RequestResult>,
+}
+
+/// What joining a batch did.
+#[derive(Debug)]
+pub enum Joined {
+    /// The item opened a new batch, which the caller must flush with
+    /// [`Batcher::take`] once the wait has passed.
+    Opened(u64),
+    /// The item joined a batch that is still filling.
+    Joined,
+    /// The item filled the batch, which the caller must send.
+    Filled(Vec<BatchItem>),
+}
+
+#[derive(Debug, Default)]
+struct BatchState {
+    /// Items of the batch being filled.
+    pending: Vec<BatchItem>,
+    /// Counts opened batches, so a late flush does not take a newer batch.
+    generation: u64,
+}
+
+/// Pending batch of an endpoint.
+#[derive(Debug)]
+pub struct Batcher {
+    size: usize,
+    wait: Duration,
+    state: Mutex<BatchState>,
+}
+
+impl Batcher {
+    /// Create a batcher sending up to `size` requests at once, after at most `wait`.
+    #[must_use]
+    pub fn new(size: usize, wait: Duration) -> Self {
+        Self {
+            size,
+            wait,
+            state: Mutex::new(BatchState::default()),
+        }
+    }
+
+    /// How long a batch waits to fill.
+    #[must_use]
+    pub fn wait(&self) -> Duration {
+        self.wait
+    }
+
+    /// Add an item to the pending batch.
+    pub fn join(&self, item: BatchItem) -> Joined {
+        let mut state = self.state.lock();
+        state.pending.push(item);
+        if state.pending.len() >= self.size {
+            return Joined::Filled(std::mem::take(&mut state.pending));
+        }
+        if state.pending.len() == 1 {
+            state.generation += 1;
+            return Joined::Opened(state.generation);
+        }
+        Joined::Joined
+    }
+
+    /// Take the pending batch if it is still the one opened as `generation`.
+    pub fn take(&self, generation: u64) -> Option<Vec<BatchItem>> {
+        let mut state = self.state.lock();
+        (state.generation == generation && !state.pending.is_empty())
+            .then(|| std::mem::take(&mut state.pending))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(line: usize) -> (BatchItem, oneshot::Receiver<RequestResult>) {
+        let (reply, receiver) = oneshot::channel();
+        let mut request = ApiRequest::simple(format!("input {line}"));
+        request.line_number = line;
+        let body = serde_json::json!({ "input": line });
+        (
+            BatchItem {
+                request,
+                body,
+                reply,
+            },
+            receiver,
+        )
+    }
+
+    #[test]
+    fn test_batch_fill_and_flush() {
+        let batcher = Batcher::new(3, Duration::from_millis(10));
+        let Joined::Opened(first) = batcher.join(item(1).0) else {
+            panic!("first item should open a batch");
+        };
+        assert!(matches!(batcher.join(item(2).0), Joined::Joined));
+        let Joined::Filled(items) = batcher.join(item(3).0) else {
+            panic!("third item should fill the batch");
+        };
+        let lines: Vec<_> = items.iter().map(|i| i.request.line_number).collect();
+        assert_eq!(lines, [1, 2, 3]);
+
+        // The flush of the filled batch finds nothing, not the next batch
+        let Joined::Opened(second) = batcher.join(item(4).0) else {
+            panic!("fourth item should open a batch");
+        };
+        assert!(batcher.take(first).is_none());
+        assert_eq!(batcher.take(second).unwrap().len(), 1);
+        assert!(batcher.take(second).is_none());
+    }
+}
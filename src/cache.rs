@@ -0,0 +1,241 @@
+//! Responses cached on disk across runs.
+//!
+//! Iterating on prompts means running nearly the same file again and again.
+//! With a cache directory, each successful response is stored under a hash
+//! of the request as rendered for its endpoint's model, and a request with
+//! the same hash, in the same run or a later one, gets the stored response
+//! without calling the API. Entries older than a TTL are ignored and
+//! replaced by the next response.
+
+use crate::endpoint::Endpoint;
+use crate::error::{BlazeError, Result};
+use crate::request::{ApiRequest, ApiResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::warn;
+
+/// FNV-1a parameters, which keep keys stable across builds and platforms.
+const FNV_OFFSET: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+const FNV_PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+
+/// A response as stored in the cache.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    /// When the response was stored.
+    stored_at: DateTime<Utc>,
+    /// The response.
+    response: ApiResponse,
+}
+
+/// Directory of cached responses.
+#[derive(Debug)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl ResponseCache {
+    /// Open the cache in `dir`, creating the directory if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::CacheDir`] if the directory cannot be created.
+    pub fn open(dir: &Path, ttl: Duration) -> Result<Self> {
+        std::fs::create_dir_all(dir).map_err(|e| BlazeError::CacheDir {
+            path: dir.to_path_buf(),
+            source: e,
+        })?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            ttl,
+        })
+    }
+
+    /// Compute the cache key of a request from its method, path, headers
+    /// and body as rendered for `endpoint`, and the model it is sent to.
+    ///
+    /// Returns `None` if the body cannot be rendered, so the request is sent
+    /// and fails on its own.
+    #[must_use]
+    pub fn key(
+        request: &ApiRequest,
+        endpoint: &Endpoint,
+        template: Option<&Value>,
+        strict: bool,
+    ) -> Option<String> {
+        let body = if request.method().has_body() {
+            let body = request
+                .build_llm_body(
+                    endpoint.model(),
+                    endpoint.request_format(),
+                    template,
+                    strict,
+                )
+                .ok()?;
+            // Object keys are sorted, so equal bodies serialize identically
+            body.to_string()
+        } else {
+            String::new()
+        };
+        let headers: BTreeMap<_, _> = request.headers.iter().flatten().collect();
+        let headers = serde_json::to_string(&headers).ok()?;
+        let method = reqwest::Method::from(request.method());
+
+        let parts = [
+            method.as_str(),
+            request.path.as_deref().unwrap_or_default(),
+            &headers,
+            &body,
+            request
+                .model
+                .as_deref()
+                .or(endpoint.model())
+                .unwrap_or_default(),
+        ];
+        let mut hash = FNV_OFFSET;
+        for part in parts {
+            // A byte no string contains keeps the parts apart
+            for byte in part.bytes().chain([0xff]) {
+                hash ^= u128::from(byte);
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+        }
+        Some(format!("{hash:032x}"))
+    }
+
+    /// Get the stored response for `key`, marked as cached and correlated with `request`.
+    ///
+    /// Missing and expired entries are misses, as are unreadable ones, with a
+    /// warning.
+    pub async fn get(&self, key: &str, request: &ApiRequest) -> Option<ApiResponse> {
+        let path = self.path(key);
+        let content = match tokio::fs::read(&path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                warn!(path = %path.display(), "Ignoring unreadable cache entry: {}", e);
+                return None;
+            }
+        };
+        let entry: CacheEntry = match serde_json::from_slice(&content) {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!(path = %path.display(), "Ignoring malformed cache entry: {}", e);
+                return None;
+            }
+        };
+        let fresh = (Utc::now() - entry.stored_at)
+            .to_std()
+            .map_or(true, |age| age <= self.ttl);
+        if !fresh {
+            return None;
+        }
+
+        let mut response = entry.response;
+        response.input.clone_from(&request.input);
+        response.request_metadata = request.metadata_fields();
+        if let Some(metadata) = &mut response.metadata {
+            metadata.source = request.source.as_deref().map(str::to_string);
+            metadata.cached = true;
+        }
+        Some(response)
+    }
+
+    /// Store a response under `key`.
+    ///
+    /// The entry is written to a temporary file and renamed into place, so a
+    /// concurrent reader never sees it half written. Failures are logged, as
+    /// a response that cannot be cached is still a response.
+    pub async fn put(&self, key: &str, response: &ApiResponse) {
+        let entry = CacheEntry {
+            stored_at: Utc::now(),
+            response: response.clone(),
+        };
+        let path = self.path(key);
+        let temp = self
+            .dir
+            .join(format!("{key}.{:08x}.tmp", rand::random::<u32>()));
+        let written = match serde_json::to_vec(&entry) {
+            Ok(json) => match tokio::fs::write(&temp, json).await {
+                Ok(()) => tokio::fs::rename(&temp, &path).await,
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = written {
+            warn!(path = %path.display(), "Failed to write cache entry: {}", e);
+            let _ = tokio::fs::remove_file(&temp).await;
+        }
+    }
+
+    /// Path of the entry for `key`.
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.json"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EndpointConfig;
+    use serde_json::json;
+
+    fn endpoint(model: &str) -> Endpoint {
+        Endpoint::new(EndpointConfig {
+            url: "http://localhost:8080".to_string(),
+            model: Some(model.to_string()),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_response_cache() {
+        let (gpt, mini) = (endpoint("gpt-4o"), endpoint("gpt-4o-mini"));
+        let first = ApiRequest::simple("hello");
+        let key = ResponseCache::key(&first, &gpt, None, false).unwrap();
+        assert_eq!(
+            ResponseCache::key(&ApiRequest::simple("hello"), &gpt, None, false),
+            Some(key.clone())
+        );
+        assert_ne!(
+            ResponseCache::key(&first, &mini, None, false),
+            Some(key.clone())
+        );
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let cache =
+            ResponseCache::open(&dir.path().join("cache"), Duration::from_secs(60)).unwrap();
+        assert!(cache.get(&key, &first).await.is_none());
+
+        let metadata = serde_json::from_value(json!({
+            "endpoint": "http://localhost:8080",
+            "status": 200,
+            "latency_ms": 120,
+            "attempts": 1
+        }))
+        .unwrap();
+        let response =
+            ApiResponse::new(first.input.clone(), json!({"ok": true})).with_metadata(metadata);
+        cache.put(&key, &response).await;
+
+        // A later run reads the response back, marked as cached
+        let cache =
+            ResponseCache::open(&dir.path().join("cache"), Duration::from_secs(60)).unwrap();
+        let mut again = ApiRequest::simple("hello");
+        again.source = Some("second.jsonl".into());
+        let hit = cache.get(&key, &again).await.unwrap();
+        assert_eq!(hit.response, json!({"ok": true}));
+        let metadata = hit.metadata.unwrap();
+        assert!(metadata.cached);
+        assert_eq!(metadata.source.as_deref(), Some("second.jsonl"));
+
+        // Expired entries are misses
+        let cache = ResponseCache::open(&dir.path().join("cache"), Duration::ZERO).unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(cache.get(&key, &again).await.is_none());
+    }
+}
@@ -0,0 +1,318 @@
+//! Checkpointing of completed requests for resumable runs.
+//!
+//! A checkpoint is an append-only sidecar file next to the output file. The
+//! first line is a JSON header identifying the input file by name, size and
+//! a hash of its contents, followed by one completed line number per line.
+
+use crate::error::{BlazeError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Number of recorded lines between syncs to disk.
+pub const CHECKPOINT_SYNC_INTERVAL: usize = 1000;
+
+/// Buffer size that holds a full sync interval of line numbers, so records
+/// never reach the file before [`Checkpoint::sync`] is called.
+const WRITER_CAPACITY: usize = CHECKPOINT_SYNC_INTERVAL * (usize::MAX.ilog10() as usize + 2);
+
+/// FNV-1a parameters for the input content hash.
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Identifies the input file a checkpoint was created for.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct CheckpointHeader {
+    /// Input file name.
+    input: String,
+    /// Input file size in bytes.
+    size: u64,
+    /// FNV-1a hash of the input file contents, so an input edited in place
+    /// to the same size does not resume against stale line numbers.
+    hash: String,
+}
+
+impl CheckpointHeader {
+    fn for_input(input: &Path) -> Result<Self> {
+        let read_error = |e| BlazeError::InputFileRead {
+            path: input.to_path_buf(),
+            source: e,
+        };
+        let metadata = std::fs::metadata(input).map_err(read_error)?;
+        let hash = content_hash(input).map_err(read_error)?;
+
+        Ok(Self {
+            input: input
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            size: metadata.len(),
+            hash: format!("{hash:016x}"),
+        })
+    }
+}
+
+/// Hash the contents of a file with FNV-1a, which is stable across builds.
+fn content_hash(path: &Path) -> std::io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0; 64 * 1024];
+    let mut hash = FNV_OFFSET;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hash);
+        }
+        for &byte in &buffer[..read] {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+/// Set of completed line numbers, stored as a bitset.
+#[derive(Debug, Default)]
+pub struct CompletedLines {
+    bits: Vec<u64>,
+    count: usize,
+}
+
+impl CompletedLines {
+    /// Mark a line number as completed.
+    pub fn insert(&mut self, line: usize) {
+        let (word, bit) = (line / 64, line % 64);
+        if word >= self.bits.len() {
+            self.bits.resize(word + 1, 0);
+        }
+        if self.bits[word] & (1 << bit) == 0 {
+            self.bits[word] |= 1 << bit;
+            self.count += 1;
+        }
+    }
+
+    /// Check if a line number has been completed.
+    #[must_use]
+    pub fn contains(&self, line: usize) -> bool {
+        self.bits
+            .get(line / 64)
+            .is_some_and(|word| word & (1 << (line % 64)) != 0)
+    }
+
+    /// Number of completed lines.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Check if no lines have been completed.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+/// Append-only writer for a checkpoint file.
+#[derive(Debug)]
+pub struct Checkpoint {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    unsynced: usize,
+}
+
+impl Checkpoint {
+    /// Get the checkpoint path for an output file.
+    #[must_use]
+    pub fn path_for(output: &Path) -> PathBuf {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".checkpoint");
+        PathBuf::from(path)
+    }
+
+    /// Create a new checkpoint for an input file, replacing any existing one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the input file metadata cannot be read or the
+    /// checkpoint cannot be written.
+    pub fn create(path: &Path, input: &Path) -> Result<Self> {
+        let header = CheckpointHeader::for_input(input)?;
+        let file = File::create(path).map_err(|e| BlazeError::OutputFileWrite {
+            path: path.to_path_buf(),
+            source: e,
+        })?;
+
+        let mut checkpoint = Self {
+            path: path.to_path_buf(),
+            writer: BufWriter::with_capacity(WRITER_CAPACITY, file),
+            unsynced: 0,
+        };
+        let line = serde_json::to_string(&header)?;
+        writeln!(checkpoint.writer, "{line}").map_err(|e| checkpoint.write_error(e))?;
+        checkpoint.sync()?;
+        Ok(checkpoint)
+    }
+
+    /// Open an existing checkpoint to resume from, returning the completed lines.
+    ///
+    /// Starts a fresh checkpoint if none exists yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::CheckpointMismatch`] if the checkpoint was created
+    /// for a different input file, or an I/O error if it cannot be read.
+    pub fn resume(path: &Path, input: &Path) -> Result<(Self, CompletedLines)> {
+        if !path.exists() {
+            return Ok((Self::create(path, input)?, CompletedLines::default()));
+        }
+
+        let read_error = |e| BlazeError::InputFileRead {
+            path: path.to_path_buf(),
+            source: e,
+        };
+        let mut lines = BufReader::new(File::open(path).map_err(read_error)?).lines();
+
+        let header: Option<CheckpointHeader> = lines
+            .next()
+            .transpose()
+            .map_err(read_error)?
+            .and_then(|line| serde_json::from_str(&line).ok());
+        if header != Some(CheckpointHeader::for_input(input)?) {
+            return Err(BlazeError::CheckpointMismatch {
+                path: path.to_path_buf(),
+                input: input.to_path_buf(),
+            });
+        }
+
+        // A torn trailing line from a crash is simply ignored
+        let mut completed = CompletedLines::default();
+        for line in lines {
+            if let Ok(line_number) = line.map_err(read_error)?.trim().parse() {
+                completed.insert(line_number);
+            }
+        }
+
+        terminate_partial_line(path).map_err(read_error)?;
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(|e| BlazeError::OutputFileWrite {
+                path: path.to_path_buf(),
+                source: e,
+            })?;
+
+        let checkpoint = Self {
+            path: path.to_path_buf(),
+            writer: BufWriter::with_capacity(WRITER_CAPACITY, file),
+            unsynced: 0,
+        };
+        Ok((checkpoint, completed))
+    }
+
+    /// Record a completed line number.
+    ///
+    /// Returns `true` once [`CHECKPOINT_SYNC_INTERVAL`] lines have been recorded
+    /// since the last sync, signalling the caller to sync the output and then
+    /// this checkpoint.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::OutputFileWrite`] if the line cannot be written.
+    pub fn record(&mut self, line_number: usize) -> Result<bool> {
+        writeln!(self.writer, "{line_number}").map_err(|e| self.write_error(e))?;
+        self.unsynced += 1;
+        Ok(self.unsynced >= CHECKPOINT_SYNC_INTERVAL)
+    }
+
+    /// Flush and fsync the checkpoint to disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::OutputFileWrite`] if the flush or sync fails.
+    pub fn sync(&mut self) -> Result<()> {
+        self.writer.flush().map_err(|e| self.write_error(e))?;
+        self.writer
+            .get_ref()
+            .sync_data()
+            .map_err(|e| self.write_error(e))?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    fn write_error(&self, source: std::io::Error) -> BlazeError {
+        BlazeError::OutputFileWrite {
+            path: self.path.clone(),
+            source,
+        }
+    }
+}
+
+/// Append a newline to a file whose last line was torn by a crash, so that
+/// appended records start on their own line.
+///
+/// # Errors
+///
+/// Returns an I/O error if the file cannot be read or written.
+pub fn terminate_partial_line(path: &Path) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().read(true).append(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        return Ok(());
+    }
+
+    let mut last = [0u8; 1];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut last)?;
+    if last[0] != b'\n' {
+        file.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_resume_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.jsonl");
+        std::fs::write(&input, "{}\n{}\n{}\n").unwrap();
+        let path = Checkpoint::path_for(&dir.path().join("output.jsonl"));
+
+        let mut checkpoint = Checkpoint::create(&path, &input).unwrap();
+        checkpoint.record(1).unwrap();
+        checkpoint.record(3).unwrap();
+        checkpoint.sync().unwrap();
+        drop(checkpoint);
+
+        // Simulate a torn write from a crash
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "2x").unwrap();
+
+        let (mut checkpoint, completed) = Checkpoint::resume(&path, &input).unwrap();
+        assert_eq!(completed.len(), 2);
+        assert!(completed.contains(1));
+        assert!(!completed.contains(2));
+        assert!(completed.contains(3));
+
+        checkpoint.record(2).unwrap();
+        checkpoint.sync().unwrap();
+        drop(checkpoint);
+        let (_, completed) = Checkpoint::resume(&path, &input).unwrap();
+        assert!(completed.contains(2));
+
+        // An input edited in place to the same size must not reuse it either
+        std::fs::write(&input, "{}\n[]\n{}\n").unwrap();
+        assert!(matches!(
+            Checkpoint::resume(&path, &input),
+            Err(BlazeError::CheckpointMismatch { .. })
+        ));
+
+        // A different input file must not silently reuse the checkpoint
+        std::fs::write(&input, "{}\n").unwrap();
+        assert!(matches!(
+            Checkpoint::resume(&path, &input),
+            Err(BlazeError::CheckpointMismatch { .. })
+        ));
+    }
+}
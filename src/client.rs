@@ -3,21 +3,118 @@
 //! This module provides a high-performance HTTP client optimized for
 //! high-throughput API requests with automatic retries.
 
-use crate::config::Config;
-use crate::endpoint::Endpoint;
+use crate::batch::{BatchItem, Batcher, Joined};
+use crate::config::{AuthConfig, Config, HttpVersion, JitterKind, ProxyConfig};
+use crate::endpoint::{CircuitState, Endpoint, EndpointPermit, LoadBalancer};
 use crate::error::{BlazeError, Result};
-use crate::request::{ApiRequest, ApiResponse, ErrorResponse, RequestResult, ResponseMetadata};
-use reqwest::{header, Client};
+use crate::events::{EventSender, ProcessEvent};
+use crate::keys::PooledKey;
+use crate::random::RandomSource;
+use crate::request::{
+    ApiRequest, ApiResponse, BodyEncoding, ErrorKind, ErrorResponse, RequestResult,
+    ResponseMetadata, TokenUsage,
+};
+use crate::sse::{StreamAssembler, StreamError};
+use crate::tracker::StatsTracker;
+use crate::validation::ResponseValidator;
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use futures::future::{self, Either};
+use governor::DefaultDirectRateLimiter;
+use rand::Rng;
+use reqwest::{header, Client, RequestBuilder, Response, StatusCode};
+use std::collections::BTreeMap;
+use std::pin::pin;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
 use tokio::time::sleep;
-use tracing::{debug, trace, warn};
+use tracing::{debug, field, info_span, trace, warn, Instrument};
+
+/// Longest part of an error response body kept in the error message.
+const ERROR_BODY_LIMIT: usize = 500;
+
+/// Outcome of probing an endpoint for reachability.
+#[derive(Debug, Clone)]
+pub struct ProbeResult {
+    /// The endpoint URL.
+    pub url: String,
+    /// HTTP status of the response, if one was received.
+    pub status: Option<u16>,
+    /// Time until the response arrived or the probe failed.
+    pub latency: Duration,
+    /// Why no response was received.
+    pub error: Option<String>,
+}
+
+impl ProbeResult {
+    /// Check whether the endpoint answered at all, whatever the status.
+    #[must_use]
+    pub fn is_reachable(&self) -> bool {
+        self.status.is_some()
+    }
+}
+
+/// A successful response to a single send attempt.
+#[derive(Debug)]
+struct Received {
+    /// Parsed response body, assembled from its chunks if it was streamed.
+    body: serde_json::Value,
+    /// HTTP status code.
+    status: u16,
+    /// Time until the first streamed content arrived.
+    first_token: Option<Duration>,
+}
+
+/// Failure details from a single send attempt.
+#[derive(Debug)]
+struct SendError {
+    /// Human-readable error message.
+    message: String,
+    /// HTTP status code (if a response was received).
+    status: Option<u16>,
+    /// Category of the failure.
+    kind: ErrorKind,
+    /// Delay requested by the server via `Retry-After`.
+    retry_after: Option<Duration>,
+}
+
+impl SendError {
+    fn new(message: String, status: Option<u16>, kind: ErrorKind) -> Self {
+        Self {
+            message,
+            status,
+            kind,
+            retry_after: None,
+        }
+    }
+
+    /// Check whether the endpoint answered, so the failure is not a connection error.
+    fn responded(&self) -> bool {
+        self.status.is_some()
+            || matches!(
+                self.kind,
+                ErrorKind::Validation | ErrorKind::ResponseTooLarge
+            )
+    }
+}
 
 /// HTTP client wrapper with retry logic.
 #[derive(Debug, Clone)]
 pub struct ApiClient {
     client: Client,
     config: Arc<Config>,
+    validator: ResponseValidator,
+    /// Randomness for backoff jitter.
+    rng: Arc<RandomSource>,
+    /// Holds the retry budget shared with the rest of the run.
+    stats: Arc<StatsTracker>,
+    /// Where endpoint events are reported.
+    events: EventSender,
+    /// Picks the endpoints of retries, if retries switch endpoints.
+    load_balancer: Option<Arc<LoadBalancer>>,
+    /// Rate limit that hedges and retries moved to another endpoint take a
+    /// token from, since they skip the wait of a first attempt.
+    rate_limiter: Option<Arc<DefaultDirectRateLimiter>>,
 }
 
 impl ApiClient {
@@ -33,160 +130,1257 @@ impl ApiClient {
             header::HeaderValue::from_static("application/json"),
         );
 
-        let client = Client::builder()
+        let mut builder = Client::builder();
+        // Without an explicit proxy, reqwest picks up HTTPS_PROXY/NO_PROXY from the environment
+        if let Some(proxy) = &config.request.proxy {
+            builder = builder.proxy(build_proxy(proxy)?);
+        }
+
+        if config.request.accept_invalid_certs {
+            warn!("TLS certificate verification is DISABLED; connections can be intercepted. Never use accept_invalid_certs in production");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        builder = match config.request.http_version {
+            HttpVersion::Auto => builder,
+            HttpVersion::Http1 => builder.http1_only(),
+            HttpVersion::Http2 => builder.http2_prior_knowledge(),
+        };
+
+        let client = builder
             .timeout(config.request.timeout)
-            .pool_max_idle_per_host(config.request.workers)
-            .pool_idle_timeout(Duration::from_secs(90))
+            .pool_max_idle_per_host(config.pool_size())
+            .pool_idle_timeout(config.request.pool_idle_timeout)
+            .http2_keep_alive_interval(config.request.http2_keep_alive_interval)
+            .http2_keep_alive_timeout(config.request.http2_keep_alive_timeout)
+            .http2_keep_alive_while_idle(config.request.http2_keep_alive_while_idle)
             .tcp_keepalive(Duration::from_secs(60))
             .tcp_nodelay(true)
+            .user_agent(config.request.user_agent.as_str())
             .default_headers(headers)
             .gzip(true)
             .brotli(true)
             .build()
             .map_err(BlazeError::HttpRequest)?;
 
+        let validator = ResponseValidator::new(&config.request.validation)?;
+        let rng = Arc::new(RandomSource::new(config.seed));
+        let stats = Arc::new(StatsTracker::new().with_retry_budget(config.retry.budget_ratio));
+
         Ok(Self {
             client,
             config: config,
+            validator,
+            rng,
+            stats,
+            events: EventSender::default(),
+            load_balancer: None,
+            rate_limiter: None,
         })
     }
 
-    /// Send a request to an endpoint with retries.
+    /// Draw retries from the retry budget of `stats` instead of the client's own.
+    #[must_use]
+    pub fn with_stats(mut self, stats: Arc<StatsTracker>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Report endpoints opening their circuit or rate limiting requests to `events`.
+    #[must_use]
+    pub fn with_events(mut self, events: EventSender) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Pick the endpoints of retries from `load_balancer` when the retry config
+    /// switches endpoints.
+    #[must_use]
+    pub fn with_load_balancer(mut self, load_balancer: Arc<LoadBalancer>) -> Self {
+        self.load_balancer = Some(load_balancer);
+        self
+    }
+
+    /// Only send hedges and retries moved to another endpoint when
+    /// `rate_limiter` has a token for them right away.
+    #[must_use]
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<DefaultDirectRateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Take a token from the rate limiter without waiting, if there is one.
+    fn try_rate_limit(&self) -> bool {
+        self.rate_limiter
+            .as_ref()
+            .map_or(true, |limiter| limiter.check().is_ok())
+    }
+
+    /// Send a request to an endpoint with retries, holding `slot` on it until done.
+    ///
+    /// If retries switch endpoints, a retry that finds a free slot on another
+    /// endpoint moves there and releases `slot`; the metadata of the result
+    /// names the endpoint the last attempt went to.
+    ///
+    /// Runs in a `send_request` span recording the endpoint, attempt count,
+    /// outcome and latency, so the request shows up in exported traces.
     pub async fn send_with_retry(
         &self,
         request: &ApiRequest,
-        endpoint: Arc<Endpoint>,
+        slot: EndpointPermit,
+    ) -> RequestResult {
+        let span = info_span!(
+            "send_request",
+            endpoint = slot.endpoint().url(),
+            line = request.line_number,
+            attempts = field::Empty,
+            latency_ms = field::Empty,
+            "http.response.status_code" = field::Empty,
+            "otel.status_code" = field::Empty,
+        );
+        let start = Instant::now();
+        let result = self
+            .send_request(request, slot)
+            .instrument(span.clone())
+            .await;
+
+        span.record("latency_ms", start.elapsed().as_secs_f64() * 1000.0);
+        match &result {
+            RequestResult::Success(response) => {
+                if let Some(metadata) = &response.metadata {
+                    span.record("attempts", metadata.attempts);
+                }
+                span.record("otel.status_code", "OK");
+            }
+            RequestResult::Failure(error) => {
+                span.record("attempts", error.attempts);
+                if let Some(status) = error.status_code {
+                    span.record("http.response.status_code", status);
+                }
+                span.record("otel.status_code", "ERROR");
+            }
+            RequestResult::Skipped { .. } => {}
+        }
+        result
+    }
+
+    /// Send a request, racing a second copy on another endpoint if it is slow.
+    ///
+    /// If the request has not finished after `delay`, `select_hedge` picks a
+    /// different endpoint for a hedged copy and whichever finishes first with
+    /// a success wins; the other is cancelled and its endpoint slot released.
+    /// The hedge only goes out if its endpoint has a free slot and the rate
+    /// limit a token right away.
+    /// A request sent as a half-open circuit probe is never hedged, since the
+    /// probe has to finish to settle the circuit.
+    pub async fn send_hedged(
+        &self,
+        request: &ApiRequest,
+        slot: EndpointPermit,
+        delay: Duration,
+        select_hedge: impl FnOnce(&Endpoint) -> Option<Arc<Endpoint>>,
+    ) -> RequestResult {
+        let endpoint = Arc::clone(slot.endpoint());
+        if !endpoint.is_healthy() {
+            return self.send_with_retry(request, slot).await;
+        }
+
+        let mut primary = pin!(self.send_with_retry(request, slot));
+        tokio::select! {
+            result = &mut primary => return result,
+            () = sleep(delay) => {}
+        }
+
+        let hedge_slot = select_hedge(&endpoint)
+            .and_then(|e| e.try_acquire())
+            .filter(|_| self.try_rate_limit());
+        let Some(hedge_slot) = hedge_slot else {
+            return primary.await;
+        };
+        debug!(
+            line = request.line_number,
+            endpoint = endpoint.url(),
+            hedge = hedge_slot.endpoint().url(),
+            "Request slow, sending hedge"
+        );
+        let hedge = pin!(self.send_with_retry(request, hedge_slot));
+
+        match future::select(primary, hedge).await {
+            Either::Left((result, other)) | Either::Right((result, other)) => {
+                if result.is_success() {
+                    result
+                } else {
+                    other.await
+                }
+            }
+        }
+    }
+
+    /// Send a request on its own or, if its endpoint batches, as part of a batch.
+    async fn send_request(&self, request: &ApiRequest, slot: EndpointPermit) -> RequestResult {
+        let endpoint = Arc::clone(slot.endpoint());
+        match endpoint.batcher() {
+            Some(batcher) => self.send_batched(request, &endpoint, batcher).await,
+            None => self.send_attempts(request, &endpoint, Some(slot)).await,
+        }
+    }
+
+    /// Add a request to its endpoint's pending batch and wait for its result.
+    ///
+    /// Requests without a body are sent on their own. The batch is sent from
+    /// a task of its own, so it still goes out if the request that fills or
+    /// opened it is cancelled.
+    async fn send_batched(
+        &self,
+        request: &ApiRequest,
+        endpoint: &Arc<Endpoint>,
+        batcher: &Batcher,
+    ) -> RequestResult {
+        let failure = |message: String| {
+            RequestResult::Failure(
+                ErrorResponse::new(request, message, 0).with_endpoint(endpoint.url()),
+            )
+        };
+        let body = match self.request_body(request, endpoint) {
+            Ok(Some(body)) => body,
+            Ok(None) => return self.send_attempts(request, endpoint, None).await,
+            Err(e) => return failure(e.to_string()),
+        };
+
+        let (reply, result) = oneshot::channel();
+        let item = BatchItem {
+            request: request.clone(),
+            body,
+            reply,
+        };
+        match batcher.join(item) {
+            Joined::Opened(generation) => {
+                let (client, endpoint) = (self.clone(), Arc::clone(endpoint));
+                let wait = batcher.wait();
+                tokio::spawn(async move {
+                    sleep(wait).await;
+                    let items = endpoint.batcher().and_then(|b| b.take(generation));
+                    if let Some(items) = items {
+                        client.send_batch(items, &endpoint).await;
+                    }
+                });
+            }
+            Joined::Filled(items) => {
+                let (client, endpoint) = (self.clone(), Arc::clone(endpoint));
+                tokio::spawn(async move { client.send_batch(items, &endpoint).await });
+            }
+            Joined::Joined => {}
+        }
+        result
+            .await
+            .unwrap_or_else(|_| failure("Batch was dropped before it was sent".to_string()))
+    }
+
+    /// Send a batch as one request with an array body, and answer each item.
+    ///
+    /// The request goes out with the path and headers of the batch's first
+    /// item and is retried as a whole. A failed batch fails every item.
+    async fn send_batch(&self, items: Vec<BatchItem>, endpoint: &Arc<Endpoint>) {
+        debug!(
+            endpoint = endpoint.url(),
+            size = items.len(),
+            "Sending batch"
+        );
+        let bodies = items.iter().map(|item| item.body.clone()).collect();
+        let batch = ApiRequest {
+            input: None,
+            body: Some(serde_json::Value::Array(bodies)),
+            ..items[0].request.clone()
+        };
+        let results = match self.send_attempts(&batch, endpoint, None).await {
+            RequestResult::Success(response) => self.split_batch(response, &items, endpoint),
+            RequestResult::Failure(error) => items
+                .iter()
+                .map(|item| RequestResult::Failure(item_failure(&error, item)))
+                .collect(),
+            RequestResult::Skipped { reason } => items
+                .iter()
+                .map(|_| RequestResult::Skipped {
+                    reason: reason.clone(),
+                })
+                .collect(),
+        };
+        for (item, result) in items.into_iter().zip(results) {
+            // The request may have been cancelled meanwhile, such as a losing hedge
+            let _ = item.reply.send(result);
+        }
+    }
+
+    /// Split the response to a batch into the results of its items.
+    ///
+    /// The response must be an array with one element per item, in order.
+    /// An element with an `error` field fails its item alone, as does one
+    /// failing validation; such failures are not retried.
+    fn split_batch(
+        &self,
+        response: ApiResponse,
+        items: &[BatchItem],
+        endpoint: &Endpoint,
+    ) -> Vec<RequestResult> {
+        let metadata = response
+            .metadata
+            .expect("responses to sent requests carry metadata");
+        let fail = |item: &BatchItem, message: String, status: Option<u16>, kind: ErrorKind| {
+            let mut error = ErrorResponse::new(&item.request, message, metadata.attempts)
+                .with_class(self.config.retry.failure_class(status))
+                .with_kind(kind)
+                .with_endpoint(endpoint.url());
+            error.status_code = status;
+            error.request_id.clone_from(&metadata.request_id);
+            RequestResult::Failure(error)
+        };
+
+        let elements = match response.response {
+            serde_json::Value::Array(elements) if elements.len() == items.len() => elements,
+            _ => {
+                let message = format!("Batch response is not an array of {} results", items.len());
+                return items
+                    .iter()
+                    .map(|item| {
+                        fail(
+                            item,
+                            message.clone(),
+                            Some(metadata.status),
+                            ErrorKind::ParseError,
+                        )
+                    })
+                    .collect();
+            }
+        };
+
+        items
+            .iter()
+            .zip(elements)
+            .map(|(item, element)| {
+                if let Some(error) = element.get("error").filter(|e| !e.is_null()) {
+                    let status = error
+                        .get("code")
+                        .or_else(|| element.get("status"))
+                        .and_then(serde_json::Value::as_u64)
+                        .and_then(|code| u16::try_from(code).ok());
+                    let message = error
+                        .get("message")
+                        .and_then(serde_json::Value::as_str)
+                        .map_or_else(|| error.to_string(), str::to_string);
+                    let kind = status.map_or(ErrorKind::Other, ErrorKind::from_status);
+                    return fail(item, format!("Batch item failed: {message}"), status, kind);
+                }
+                if let Err(reason) = self.validator.check(&element) {
+                    let message = format!("Response failed validation: {reason}");
+                    return fail(item, message, None, ErrorKind::Validation);
+                }
+                let (usage, estimated_cost) = priced_usage(&element, endpoint);
+                let response = ApiResponse::new(item.request.input.clone(), element).with_metadata(
+                    ResponseMetadata {
+                        model: body_model(Some(&item.body)),
+                        usage,
+                        estimated_cost,
+                        source: item.request.source.as_deref().map(str::to_string),
+                        ..metadata.clone()
+                    },
+                );
+                RequestResult::Success(response.with_request_metadata(&item.request))
+            })
+            .collect()
+    }
+
+    /// Attempt to send a request, backing off between attempts.
+    ///
+    /// Retries stop at the attempts allowed for the last failure, which
+    /// depend on whether it got a response. With the `slot` held on the
+    /// endpoint, retries may move to another endpoint.
+    async fn send_attempts(
+        &self,
+        request: &ApiRequest,
+        endpoint: &Arc<Endpoint>,
+        mut slot: Option<EndpointPermit>,
     ) -> RequestResult {
         let mut attempts = 0;
-        let mut last_error: Option<String> = None;
-        let mut last_status: Option<u16> = None;
+        let mut endpoint = Arc::clone(endpoint);
+        let mut tried = vec![Arc::clone(&endpoint)];
 
-        let body = request.build_llm_body(endpoint.model());
+        let mut body = match self.request_body(request, &endpoint) {
+            Ok(body) => body,
+            Err(e) => {
+                return RequestResult::Failure(
+                    ErrorResponse::new(request, e.to_string(), 0).with_endpoint(endpoint.url()),
+                );
+            }
+        };
+        let mut headers = self.custom_headers(request, &endpoint);
+        let request_id = self.tag_request(&mut headers);
         let start = Instant::now();
+        let mut last_backoff = None;
 
-        while attempts < self.config.retry.max_attempts {
+        let error = loop {
             attempts += 1;
 
-            match self.send_once(&body, &endpoint).await {
-                Ok(response) => {
+            match self
+                .send_once(request, body.as_ref(), &headers, &endpoint)
+                .await
+            {
+                Ok(Received {
+                    body: response,
+                    status,
+                    first_token,
+                }) => {
                     let latency = start.elapsed();
                     endpoint.record_success(latency);
-                    endpoint.release();
+                    self.stats.retry_budget().record_success();
+
+                    let (usage, estimated_cost) = priced_usage(&response, &endpoint);
 
                     let api_response = ApiResponse::new(request.input.clone(), response)
                         .with_metadata(ResponseMetadata {
                             endpoint: endpoint.url().to_string(),
-                            latency_ms: latency.as_millis() as u64,
+                            model: body_model(body.as_ref()),
+                            status,
+                            latency_ms: whole_millis(latency),
+                            ttft_ms: first_token.map(whole_millis),
                             attempts,
-                        });
+                            usage,
+                            estimated_cost,
+                            source: request.source.as_deref().map(str::to_string),
+                            request_id,
+                            cached: false,
+                        })
+                        .with_request_metadata(request);
 
                     return RequestResult::Success(api_response);
                 }
-                Err((error, status)) => {
-                    last_error = Some(error.clone());
-                    last_status = status;
-
+                Err(error) => {
                     // Don't retry on certain status codes
-                    if let Some(code) = status {
-                        if code == 400 || code == 401 || code == 403 || code == 404 {
+                    if let Some(code) = error.status {
+                        if !self.config.retry.is_retryable(code) {
                             warn!(
                                 endpoint = endpoint.url(),
                                 status = code,
                                 "Non-retryable error"
                             );
-                            break;
+                            break error;
                         }
                     }
 
-                    if attempts < self.config.retry.max_attempts {
-                        let backoff = self.calculate_backoff(attempts);
-                        debug!(
-                            attempt = attempts,
-                            max_attempts = self.config.retry.max_attempts,
-                            backoff_ms = backoff.as_millis(),
-                            error = %error,
-                            "Request failed, retrying"
-                        );
-                        sleep(backoff).await;
+                    if attempts >= self.config.retry.max_attempts_for(error.responded()) {
+                        break error;
+                    }
+                    let Some(backoff) =
+                        self.retry_backoff(&error, attempts, last_backoff, &endpoint)
+                    else {
+                        break error;
+                    };
+                    last_backoff = Some(backoff);
+
+                    // A retry on another endpoint has no reason to wait on this one
+                    let next = slot
+                        .as_ref()
+                        .and_then(|_| self.retry_target(request, &tried, &headers));
+                    match next {
+                        Some((next_slot, next_body, next_headers)) => {
+                            self.record_endpoint_failure(&endpoint, error.status).await;
+                            endpoint = Arc::clone(next_slot.endpoint());
+                            tried.push(Arc::clone(&endpoint));
+                            (slot, body, headers) = (Some(next_slot), next_body, next_headers);
+                        }
+                        None => sleep(backoff).await,
                     }
                 }
             }
-        }
+        };
 
-        endpoint.record_failure();
-        endpoint.release();
+        self.record_endpoint_failure(&endpoint, error.status).await;
 
-        let error_response =
-            ErrorResponse::new(request, last_error.unwrap_or_else(|| "Unknown error".to_string()), attempts);
+        let error_response = ErrorResponse::new(request, error.message, attempts)
+            .with_class(self.config.retry.failure_class(error.status))
+            .with_kind(error.kind)
+            .with_endpoint(endpoint.url());
 
-        let error_response = if let Some(status) = last_status {
+        let mut error_response = if let Some(status) = error.status {
             error_response.with_status(status)
         } else {
             error_response
         };
+        error_response.request_id = request_id;
+        if self.config.processing.capture_request {
+            error_response.sent_body = body;
+            error_response.sent_headers = Some(captured_headers(&headers, &endpoint));
+        }
 
         RequestResult::Failure(error_response)
     }
 
-    /// Send a single request without retries.
-    async fn send_once(
+    /// Record a failed request against an endpoint, reporting it if its circuit opens.
+    async fn record_endpoint_failure(&self, endpoint: &Endpoint, status: Option<u16>) {
+        let was_open = endpoint.circuit_state() == CircuitState::Open;
+        endpoint.record_failure();
+        endpoint.record_failure_status(status);
+        if !was_open && endpoint.circuit_state() == CircuitState::Open {
+            let url = endpoint.url().to_string();
+            self.events
+                .send(ProcessEvent::EndpointMarkedUnhealthy { url })
+                .await;
+        }
+    }
+
+    /// Find another endpoint for the retry of a request that failed on each of `tried`.
+    ///
+    /// Returns a slot on the endpoint with the body and headers built for it,
+    /// keeping the request id of the `sent` headers. Returns `None` unless
+    /// retries switch endpoints, an untried endpoint has a free slot and the
+    /// rate limit has a token.
+    fn retry_target(
         &self,
-        body: &serde_json::Value,
+        request: &ApiRequest,
+        tried: &[Arc<Endpoint>],
+        sent: &header::HeaderMap,
+    ) -> Option<(EndpointPermit, Option<serde_json::Value>, header::HeaderMap)> {
+        if !self.config.retry.switch_endpoints {
+            return None;
+        }
+        let load_balancer = self.load_balancer.as_ref()?;
+        let slot = load_balancer
+            .select_untried(tried)?
+            .try_acquire()
+            .filter(|_| self.try_rate_limit())?;
+        let endpoint = slot.endpoint();
+        let body = self.request_body(request, endpoint).ok()?;
+        let mut headers = self.custom_headers(request, endpoint);
+        // Every attempt of the request carries the same id
+        let id = self
+            .config
+            .request
+            .request_id_header
+            .as_deref()
+            .and_then(|name| {
+                let id = sent.get(name)?.clone();
+                Some((header::HeaderName::from_bytes(name.as_bytes()).ok()?, id))
+            });
+        if let Some((name, id)) = id {
+            headers.insert(name, id);
+        }
+        debug!(
+            line = request.line_number,
+            endpoint = endpoint.url(),
+            "Retrying on another endpoint"
+        );
+        Some((slot, body, headers))
+    }
+
+    /// Build the body to send to an endpoint, if the request has one.
+    ///
+    /// Streaming endpoints are asked for an event stream with `"stream": true`.
+    fn request_body(
+        &self,
+        request: &ApiRequest,
         endpoint: &Endpoint,
-    ) -> std::result::Result<serde_json::Value, (String, Option<u16>)> {
-        let mut request = self.client.post(endpoint.url()).json(body);
+    ) -> Result<Option<serde_json::Value>> {
+        if !request.method().has_body() {
+            return Ok(None);
+        }
+        let template = self.config.request.body_template.as_ref();
+        let encoding = endpoint.config.body_encoding;
+        if encoding == BodyEncoding::Text && request.body.is_none() && template.is_none() {
+            let input = request.input.clone().unwrap_or_default();
+            return Ok(Some(serde_json::Value::String(input)));
+        }
+        let mut body = request.build_llm_body(
+            endpoint.model(),
+            endpoint.request_format(),
+            template,
+            self.config.request.strict_template,
+        )?;
+        if encoding == BodyEncoding::Form && !body.is_object() {
+            return Err(BlazeError::FormBody {
+                line: request.line_number,
+            });
+        }
+        if let Some(fields) = body.as_object_mut().filter(|_| endpoint.config.stream) {
+            fields.insert("stream".to_string(), serde_json::Value::Bool(true));
+        }
+        Ok(Some(body))
+    }
 
-        // Add authorization header if API key is configured
-        if let Some(api_key) = endpoint.api_key() {
-            request = request.header(header::AUTHORIZATION, format!("Bearer {}", api_key));
+    /// Collect the custom headers of a request, its own over its endpoint's.
+    ///
+    /// Invalid headers are left off and counted rather than failing the request.
+    fn custom_headers(&self, request: &ApiRequest, endpoint: &Endpoint) -> header::HeaderMap {
+        let mut headers = header::HeaderMap::new();
+        let layers = endpoint.config.headers.iter();
+        for (name, value) in layers.chain(request.headers.iter().flatten()) {
+            let parsed = (
+                header::HeaderName::from_bytes(name.as_bytes()),
+                header::HeaderValue::from_str(value),
+            );
+            if let (Ok(name), Ok(value)) = parsed {
+                headers.insert(name, value);
+            } else {
+                warn!(
+                    line_number = request.line_number,
+                    header = name.as_str(),
+                    "Leaving off invalid header"
+                );
+                self.stats.record_invalid_header();
+            }
         }
+        headers
+    }
+
+    /// Add the request id header, if configured, returning the id sent.
+    ///
+    /// An id the request already carries in its own headers is kept. Ids are
+    /// random even in seeded runs, as they must not repeat across runs.
+    fn tag_request(&self, headers: &mut header::HeaderMap) -> Option<String> {
+        let name = self.config.request.request_id_header.as_deref()?;
+        let name = header::HeaderName::from_bytes(name.as_bytes()).ok()?;
+        let id = headers.entry(name).or_insert_with(|| {
+            header::HeaderValue::from_str(&new_request_id()).expect("UUIDs are valid header values")
+        });
+        Some(String::from_utf8_lossy(id.as_bytes()).into_owned())
+    }
+
+    /// Send a single request without retries, returning the body and status of a success.
+    async fn send_once(
+        &self,
+        api_request: &ApiRequest,
+        body: Option<&serde_json::Value>,
+        headers: &header::HeaderMap,
+        endpoint: &Endpoint,
+    ) -> std::result::Result<Received, SendError> {
+        let url = api_request.url(endpoint.url());
+        let build = || {
+            let mut request = with_timeout(
+                self.client.request(api_request.method().into(), &url),
+                endpoint,
+            );
+            if endpoint.config.stream {
+                request = request.header(header::ACCEPT, "text/event-stream");
+            }
+            match body {
+                Some(body) => with_body(request, body, endpoint),
+                None => request,
+            }
+        };
+        let sent = Instant::now();
 
         trace!(endpoint = endpoint.url(), "Sending request");
 
-        let response = request.send().await.map_err(|e| {
-            let error = format!("Request failed: {}", e);
-            (error, e.status().map(|s| s.as_u16()))
-        })?;
+        // Each attempt takes its own key, so a retry after a 429 can go out on another
+        let key = endpoint.key_pool().pick();
+        let api_key = key.map(PooledKey::key);
+        let (mut response, token) = self
+            .send_authorized(build(), headers, endpoint, api_key)
+            .await?;
+        // A token can be revoked before it expires, so fetch a new one and try once more
+        if let Some(token) = token.filter(|_| response.status() == StatusCode::UNAUTHORIZED) {
+            debug!(
+                endpoint = endpoint.url(),
+                "Access token rejected, fetching a new one"
+            );
+            if let Some(cache) = endpoint.token_cache() {
+                cache.invalidate(&token);
+            }
+            response = self
+                .send_authorized(build(), headers, endpoint, api_key)
+                .await?
+                .0;
+        }
 
         let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        if let Some(key) = key {
+            key.record(status.as_u16(), retry_after);
+        }
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let url = endpoint.url().to_string();
+            self.events
+                .send(ProcessEvent::RateLimited { url, retry_after })
+                .await;
+        }
+
+        if endpoint.is_success(status.as_u16()) {
+            // Endpoints may ignore "stream": true, so go by what was actually sent back
+            let (body, first_token) = if is_event_stream(&response) {
+                self.read_event_stream(response, endpoint, sent).await?
+            } else {
+                let bytes = self.read_body(response, endpoint).await?;
+                let body = serde_json::from_slice(&bytes).map_err(|e| {
+                    SendError::new(
+                        format!("Failed to parse response: {e}"),
+                        Some(status.as_u16()),
+                        ErrorKind::ParseError,
+                    )
+                })?;
+                (body, None)
+            };
+            // A soft failure inside a 2xx body is retried like a transient error.
+            // Batches are validated per item once split instead.
+            if endpoint.batcher().is_none() {
+                self.validator.check(&body).map_err(|reason| {
+                    SendError::new(
+                        format!("Response failed validation: {reason}"),
+                        None,
+                        ErrorKind::Validation,
+                    )
+                })?;
+            }
+            Ok(Received {
+                body,
+                status: status.as_u16(),
+                first_token,
+            })
+        } else {
+            let error_body = read_error_body(response).await;
+            Err(SendError {
+                message: format!("HTTP {}: {}", status.as_u16(), error_body),
+                status: Some(status.as_u16()),
+                kind: ErrorKind::from_status(status.as_u16()),
+                retry_after,
+            })
+        }
+    }
+
+    /// Read a response body, failing once it grows past `max_response_bytes`.
+    ///
+    /// An oversized body is treated as transient and retried, since a
+    /// misbehaving endpoint may well answer normally the next time.
+    async fn read_body(
+        &self,
+        mut response: Response,
+        endpoint: &Endpoint,
+    ) -> std::result::Result<Vec<u8>, SendError> {
+        let limit = self.config.request.max_response_bytes;
+        let too_large = || {
+            let error = BlazeError::InvalidResponse {
+                message: format!(
+                    "response from '{}' exceeds the {} byte limit",
+                    endpoint.url(),
+                    limit
+                ),
+            };
+            SendError::new(error.to_string(), None, ErrorKind::ResponseTooLarge)
+        };
+
+        // Compressed bodies can still grow past the limit once decoded, so this is only a shortcut
+        let declared = response.content_length().unwrap_or(0);
+        if usize::try_from(declared).map_or(true, |declared| declared > limit) {
+            return Err(too_large());
+        }
+
+        // Without a status, a body cut off midway is retried like a dropped connection
+        let mut body = Vec::with_capacity(usize::try_from(declared).unwrap_or(0));
+        while let Some(chunk) = response.chunk().await.map_err(|e| {
+            let error = format!("Failed to read response: {e}");
+            SendError::new(error, None, classify_error(&e))
+        })? {
+            if body.len() + chunk.len() > limit {
+                return Err(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+
+    /// Read an event stream, assembling its chunks into one chat completion.
+    ///
+    /// Also returns the time from `sent` until the first content arrived. The
+    /// stream counts towards `max_response_bytes` like a buffered body.
+    async fn read_event_stream(
+        &self,
+        mut response: Response,
+        endpoint: &Endpoint,
+        sent: Instant,
+    ) -> std::result::Result<(serde_json::Value, Option<Duration>), SendError> {
+        let limit = self.config.request.max_response_bytes;
+        let status = response.status().as_u16();
+        let invalid =
+            |message: String| SendError::new(message, Some(status), ErrorKind::ParseError);
+
+        let mut assembler = StreamAssembler::new();
+        let (mut received, mut first_token) = (0, None);
+        while let Some(chunk) = response.chunk().await.map_err(|e| {
+            let error = format!("Failed to read event stream: {e}");
+            SendError::new(error, None, classify_error(&e))
+        })? {
+            received += chunk.len();
+            if received > limit {
+                let error = BlazeError::InvalidResponse {
+                    message: format!(
+                        "event stream from '{}' exceeds the {} byte limit",
+                        endpoint.url(),
+                        limit
+                    ),
+                };
+                return Err(SendError::new(
+                    error.to_string(),
+                    None,
+                    ErrorKind::ResponseTooLarge,
+                ));
+            }
+            assembler.push(&chunk).map_err(invalid)?;
+            if first_token.is_none() && assembler.has_content() {
+                first_token = Some(sent.elapsed());
+            }
+        }
+        let response = assembler.finish().map_err(|e| match e {
+            StreamError::Invalid(message) => invalid(message),
+            // Without a status, a cut-off stream is retried like a dropped connection
+            StreamError::Truncated => SendError::new(e.to_string(), None, ErrorKind::Other),
+        })?;
+        Ok((response, first_token))
+    }
+
+    /// Add the endpoint's credentials, with `api_key` from its pool, to a
+    /// request and send it.
+    ///
+    /// Also returns the access token that was sent, for endpoints using OAuth.
+    async fn send_authorized(
+        &self,
+        request: RequestBuilder,
+        headers: &header::HeaderMap,
+        endpoint: &Endpoint,
+        api_key: Option<&str>,
+    ) -> std::result::Result<(Response, Option<Arc<str>>), SendError> {
+        let token = self
+            .access_token(endpoint)
+            .await
+            .map_err(|e| SendError::new(e.to_string(), None, ErrorKind::Other))?;
 
-        if status.is_success() {
-            let body: serde_json::Value = response.json().await.map_err(|e| {
-                (format!("Failed to parse response: {}", e), Some(status.as_u16()))
+        // Custom headers go last, so they replace generated ones of the same name
+        let response = authorize(request, endpoint, api_key, token.as_deref())
+            .headers(headers.clone())
+            .send()
+            .await
+            .map_err(|e| {
+                let error = format!("Request failed: {e}");
+                SendError::new(error, e.status().map(|s| s.as_u16()), classify_error(&e))
             })?;
-            Ok(body)
+        Ok((response, token))
+    }
+
+    /// Open pooled connections to an endpoint, returning how many answered.
+    ///
+    /// Sends as many concurrent `HEAD` requests as the pool keeps idle
+    /// connections, up to the endpoint's `max_concurrent`. Whatever the
+    /// status, each response leaves its connection in the pool for the run;
+    /// over HTTP/2 they share fewer connections.
+    pub async fn prewarm(&self, endpoint: &Endpoint) -> usize {
+        let count = self
+            .config
+            .pool_size()
+            .min(endpoint.config.max_concurrent as usize);
+        let opens =
+            (0..count).map(|_| with_timeout(self.client.head(endpoint.url()), endpoint).send());
+        future::join_all(opens)
+            .await
+            .iter()
+            .filter(|response| response.is_ok())
+            .count()
+    }
+
+    /// Send a `HEAD` request to an endpoint to check that it is reachable.
+    ///
+    /// Any HTTP response counts as reachable; the status is reported so
+    /// wrong paths (404) and rejected keys (401) can be told apart.
+    pub async fn probe(&self, endpoint: &Endpoint) -> ProbeResult {
+        let start = Instant::now();
+        let (status, error) = match self.access_token(endpoint).await {
+            Ok(token) => {
+                let request = with_timeout(self.client.head(endpoint.url()), endpoint);
+                let api_key = endpoint.key_pool().pick().map(PooledKey::key);
+                let request = authorize(request, endpoint, api_key, token.as_deref());
+                match request.send().await {
+                    Ok(response) => (Some(response.status().as_u16()), None),
+                    Err(e) => {
+                        // The innermost cause says why, e.g. a refused connection or unknown host
+                        let mut cause: &dyn std::error::Error = &e;
+                        while let Some(source) = cause.source() {
+                            cause = source;
+                        }
+                        (None, Some(cause.to_string()))
+                    }
+                }
+            }
+            Err(e) => (None, Some(e.to_string())),
+        };
+        ProbeResult {
+            url: endpoint.url().to_string(),
+            status,
+            latency: start.elapsed(),
+            error,
+        }
+    }
+
+    /// Get the endpoint's current access token, for endpoints using OAuth.
+    async fn access_token(&self, endpoint: &Endpoint) -> Result<Option<Arc<str>>> {
+        match endpoint.token_cache() {
+            Some(cache) => cache.token(&self.client).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the wait before retrying a failed attempt, or `None` if the retry
+    /// budget is exhausted and the request should fail straight away.
+    ///
+    /// `previous` is the wait before the failed attempt, if it was a retry.
+    fn retry_backoff(
+        &self,
+        error: &SendError,
+        attempt: u32,
+        previous: Option<Duration>,
+        endpoint: &Endpoint,
+    ) -> Option<Duration> {
+        if !self.stats.retry_budget().try_retry() {
+            debug!(
+                attempt,
+                endpoint = endpoint.url(),
+                "Retry budget exhausted, not retrying"
+            );
+            return None;
+        }
+
+        // Prefer the server's Retry-After, capped so it can't stall the batch
+        let backoff = match error.retry_after {
+            Some(delay) => delay.min(self.config.retry.max_backoff),
+            None => self.calculate_backoff(attempt, previous),
+        };
+        debug!(
+            attempt,
+            max_attempts = self.config.retry.max_attempts_for(error.responded()),
+            backoff_ms = backoff.as_millis(),
+            retry_after = error.retry_after.is_some(),
+            error = %error.message,
+            "Request failed, retrying"
+        );
+        Some(backoff)
+    }
+
+    /// Calculate backoff duration for a given attempt.
+    ///
+    /// `previous` is the last wait of the same request, which decorrelated
+    /// jitter grows from instead of the attempt number.
+    fn calculate_backoff(&self, attempt: u32, previous: Option<Duration>) -> Duration {
+        let retry = &self.config.retry;
+        let base = retry.initial_backoff.as_secs_f64() * 1000.0;
+        let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+        let max_ms = retry.max_backoff.as_secs_f64() * 1000.0;
+        // Cap before jitter: the growth can overflow to infinity (or NaN for a
+        // zero base), which `min` maps back to the cap
+        let backoff_ms = (base * retry.multiplier.powi(exponent)).min(max_ms);
+
+        let final_ms = match retry.jitter {
+            JitterKind::None => backoff_ms,
+            // Add jitter (±25%)
+            JitterKind::Equal => backoff_ms * (1.0 + (self.random() - 0.5) * 0.5),
+            // Spread over the whole range
+            JitterKind::Full => self.random() * backoff_ms,
+            // Between the base and three times the previous wait
+            JitterKind::Decorrelated => {
+                let previous_ms = previous.map_or(base, |previous| previous.as_secs_f64() * 1000.0);
+                let upper = (previous_ms * 3.0).clamp(base.min(max_ms), max_ms);
+                base + self.random() * (upper - base)
+            }
+        };
+
+        // `min` maps a NaN wait to the cap, and `max` a negative one to zero,
+        // so the conversion can't panic
+        Duration::from_secs_f64(final_ms.min(max_ms).max(0.0) / 1000.0)
+    }
+
+    /// Draw a random number in `[0, 1)` for jitter.
+    fn random(&self) -> f64 {
+        self.rng.with(|rng| rng.random())
+    }
+}
+
+/// Build a proxy from its configuration.
+fn build_proxy(config: &ProxyConfig) -> Result<reqwest::Proxy> {
+    let invalid = |reason: String| BlazeError::InvalidProxy {
+        url: config.url.clone(),
+        reason,
+    };
+
+    // reqwest defers unknown schemes to request time, so check them up front
+    let url = reqwest::Url::parse(&config.url).map_err(|e| invalid(e.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https" | "socks5" | "socks5h") {
+        return Err(invalid(format!(
+            "unsupported scheme '{}', expected http, https, socks5 or socks5h",
+            url.scheme()
+        )));
+    }
+
+    let mut proxy = reqwest::Proxy::all(url).map_err(|e| invalid(e.to_string()))?;
+    if let Some(username) = &config.username {
+        proxy = proxy.basic_auth(username, config.password.as_deref().unwrap_or_default());
+    }
+    let no_proxy = match &config.no_proxy {
+        Some(hosts) => reqwest::NoProxy::from_string(hosts),
+        None => reqwest::NoProxy::from_env(),
+    };
+    Ok(proxy.no_proxy(no_proxy))
+}
+
+/// Add the endpoint's credentials to a request: an API key from its pool,
+/// then each of its `auth` entries in order, an access token included.
+///
+/// An entry replaces the value of any earlier one sent in the same header.
+fn authorize(
+    request: RequestBuilder,
+    endpoint: &Endpoint,
+    api_key: Option<&str>,
+    token: Option<&str>,
+) -> RequestBuilder {
+    let config = &endpoint.config;
+    let with_scheme = |credential: &str| {
+        if config.auth_scheme.is_empty() {
+            credential.to_string()
         } else {
-            let error_body = response.text().await.unwrap_or_default();
-            let truncated = if error_body.len() > 500 {
-                format!("{}...", &error_body[..500])
+            format!("{} {}", config.auth_scheme, credential)
+        }
+    };
+    let mut headers = header::HeaderMap::new();
+    // Names and values are checked when the config is validated
+    let mut set = |name: &str, value: String| {
+        if let (Ok(name), Ok(mut value)) = (
+            header::HeaderName::from_bytes(name.as_bytes()),
+            header::HeaderValue::try_from(value),
+        ) {
+            value.set_sensitive(true);
+            headers.insert(name, value);
+        }
+    };
+
+    if let Some(key) = api_key {
+        set(&config.auth_header, with_scheme(key));
+    }
+    for auth in &config.auth {
+        match auth {
+            AuthConfig::None => {}
+            AuthConfig::Bearer { token } => set("authorization", format!("Bearer {token}")),
+            AuthConfig::Basic { username, password } => {
+                let credentials = format!("{username}:{}", password.as_deref().unwrap_or_default());
+                set(
+                    "authorization",
+                    format!("Basic {}", BASE64_STANDARD.encode(credentials)),
+                );
+            }
+            AuthConfig::Header { name, value } => set(name, value.clone()),
+            AuthConfig::OAuth2(_) => {
+                if let Some(token) = token {
+                    set(&config.auth_header, with_scheme(token));
+                }
+            }
+        }
+    }
+    request.headers(headers)
+}
+
+/// Describe the custom headers of a request for the errors file.
+///
+/// Values of headers the endpoint's credentials go in are masked, so keys
+/// set as custom headers don't leak.
+fn captured_headers(headers: &header::HeaderMap, endpoint: &Endpoint) -> BTreeMap<String, String> {
+    let config = &endpoint.config;
+    let is_credential = |name: &header::HeaderName| {
+        *name == header::AUTHORIZATION
+            || name.as_str().eq_ignore_ascii_case(&config.auth_header)
+            || config.auth.iter().any(|auth| {
+                matches!(auth, AuthConfig::Header { name: auth_name, .. }
+                    if name.as_str().eq_ignore_ascii_case(auth_name))
+            })
+    };
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if value.is_sensitive() || is_credential(name) {
+                "********".to_string()
             } else {
-                error_body
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
             };
-            Err((
-                format!("HTTP {}: {}", status.as_u16(), truncated),
-                Some(status.as_u16()),
-            ))
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// Get a duration in whole milliseconds, saturating at `u64::MAX`.
+fn whole_millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Get the model named in a request body, which is the one the API bills.
+fn body_model(body: Option<&serde_json::Value>) -> Option<String> {
+    let model = body?.get("model")?.as_str()?;
+    Some(model.to_string())
+}
+
+/// Read the token usage of a response and price it at the endpoint's rates.
+///
+/// Token usage is optional; responses without it simply don't count.
+fn priced_usage(
+    response: &serde_json::Value,
+    endpoint: &Endpoint,
+) -> (Option<TokenUsage>, Option<f64>) {
+    let usage = TokenUsage::from_response(response);
+    let estimated_cost = usage
+        .map(|usage| {
+            usage.cost(
+                endpoint.config.input_price_per_1k,
+                endpoint.config.output_price_per_1k,
+            )
+        })
+        .filter(|&cost| cost > 0.0);
+    (usage, estimated_cost)
+}
+
+/// Give a batch item the failure of its whole batch.
+fn item_failure(error: &ErrorResponse, item: &BatchItem) -> ErrorResponse {
+    let own = ErrorResponse::new(&item.request, String::new(), 0);
+    ErrorResponse {
+        input: own.input,
+        body: own.body,
+        method: own.method,
+        path: own.path,
+        headers: own.headers,
+        priority: own.priority,
+        model: own.model,
+        line_number: own.line_number,
+        source: own.source,
+        request_metadata: own.request_metadata,
+        sent_body: error.sent_body.as_ref().map(|_| item.body.clone()),
+        ..error.clone()
+    }
+}
+
+/// Attach a body to a request in its endpoint's encoding.
+fn with_body(
+    request: RequestBuilder,
+    body: &serde_json::Value,
+    endpoint: &Endpoint,
+) -> RequestBuilder {
+    let config = &endpoint.config;
+    let content_type = match &config.content_type {
+        Some(content_type) => content_type.as_str(),
+        None => config.body_encoding.content_type(),
+    };
+    let request = request.header(header::CONTENT_TYPE, content_type);
+    let as_text = |value: &serde_json::Value| match value {
+        serde_json::Value::String(text) => text.clone(),
+        value => value.to_string(),
+    };
+    match config.body_encoding {
+        BodyEncoding::Json => request.json(body),
+        BodyEncoding::Form => {
+            let fields: Vec<_> = body
+                .as_object()
+                .into_iter()
+                .flatten()
+                .map(|(name, value)| (name, as_text(value)))
+                .collect();
+            request.form(&fields)
         }
+        BodyEncoding::Text => request.body(as_text(body)),
     }
+}
 
-    /// Calculate backoff duration for a given attempt.
-    fn calculate_backoff(&self, attempt: u32) -> Duration {
-        let base = self.config.retry.initial_backoff.as_millis() as f64;
-        let multiplier = self.config.retry.multiplier.powi(attempt as i32 - 1);
-        let backoff_ms = base * multiplier;
+/// Generate a random (version 4) UUID to tag a request with.
+fn new_request_id() -> String {
+    let bits: u128 = rand::random();
+    // Set the version nibble to 4 and the variant bits to 10
+    let bits = bits & !(0xF << 76) & !(0x3 << 62) | (0x4 << 76) | (0x2 << 62);
+    let hex = format!("{bits:032x}");
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Check whether a response is a server-sent event stream.
+fn is_event_stream(response: &Response) -> bool {
+    response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/event-stream"))
+}
+
+/// Apply the endpoint's own timeout, if set, in place of the client-wide one.
+fn with_timeout(request: RequestBuilder, endpoint: &Endpoint) -> RequestBuilder {
+    match endpoint.config.timeout {
+        Some(timeout) => request.timeout(timeout),
+        None => request,
+    }
+}
+
+/// Categorize a failure to send a request or receive its response.
+fn classify_error(error: &reqwest::Error) -> ErrorKind {
+    if error.is_timeout() {
+        ErrorKind::Timeout
+    } else if error.is_connect() {
+        ErrorKind::ConnectionRefused
+    } else if error.is_decode() {
+        ErrorKind::ParseError
+    } else if let Some(status) = error.status() {
+        ErrorKind::from_status(status.as_u16())
+    } else {
+        ErrorKind::Other
+    }
+}
 
-        // Add jitter (±25%)
-        let jitter = 1.0 + (rand::random::<f64>() - 0.5) * 0.5;
-        let final_ms = (backoff_ms * jitter) as u64;
+/// Read the start of an error response body for its message.
+///
+/// Stops after `ERROR_BODY_LIMIT` bytes, so a large error page is never
+/// buffered whole, and cuts the text on a character boundary.
+async fn read_error_body(mut response: Response) -> String {
+    let mut body = Vec::new();
+    while body.len() <= ERROR_BODY_LIMIT {
+        match response.chunk().await {
+            Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+            Ok(None) | Err(_) => break,
+        }
+    }
 
-        Duration::from_millis(final_ms.min(self.config.retry.max_backoff.as_millis() as u64))
+    let text = String::from_utf8_lossy(&body);
+    if text.len() <= ERROR_BODY_LIMIT {
+        return text.into_owned();
     }
+    let mut end = ERROR_BODY_LIMIT;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}...", &text[..end])
+}
+
+/// Parse a `Retry-After` header value (delta-seconds or HTTP-date).
+///
+/// Returns `None` if the value is unparseable or the date is in the past.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{EndpointConfig, RequestConfig, RetryConfig};
+    use crate::config::EndpointConfig;
+    use crate::request::FailureClass;
 
     fn test_config() -> Config {
         Config {
@@ -196,9 +1390,9 @@ mod tests {
                 api_key: None,
                 model: None,
                 max_concurrent: 100,
+                ..Default::default()
             }],
-            request: RequestConfig::default(),
-            retry: RetryConfig::default(),
+            ..Default::default()
         }
     }
 
@@ -207,13 +1401,1120 @@ mod tests {
         let config = Arc::new(test_config());
         let client = ApiClient::new(config).unwrap();
 
-        let b1 = client.calculate_backoff(1);
-        let b2 = client.calculate_backoff(2);
-        let b3 = client.calculate_backoff(3);
+        let b1 = client.calculate_backoff(1, None);
+        let b2 = client.calculate_backoff(2, None);
+        let b3 = client.calculate_backoff(3, None);
 
         // Backoff should generally increase (allowing for jitter)
         assert!(b1 < Duration::from_secs(1));
         assert!(b2 < Duration::from_secs(2));
         assert!(b3 < Duration::from_secs(5));
     }
+
+    #[test]
+    fn test_backoff_jitter() {
+        let backoffs = |jitter, attempt| {
+            let mut config = test_config();
+            config.retry.jitter = jitter;
+            let client = ApiClient::new(Arc::new(config)).unwrap();
+            (0..200)
+                .map(|_| client.calculate_backoff(attempt, None))
+                .collect::<Vec<_>>()
+        };
+        let ms = Duration::from_millis;
+
+        // The third attempt backs off 400ms before jitter
+        assert!(backoffs(JitterKind::None, 3).iter().all(|&b| b == ms(400)));
+        let equal = backoffs(JitterKind::Equal, 3);
+        assert!(equal.iter().all(|&b| b >= ms(300) && b <= ms(500)));
+        let full = backoffs(JitterKind::Full, 3);
+        assert!(full.iter().all(|&b| b <= ms(400)));
+        assert!(full.iter().any(|&b| b < ms(300)));
+
+        // Every mode stays within the 10 second cap
+        let none = backoffs(JitterKind::None, 20);
+        assert!(none.iter().all(|&b| b == ms(10_000)));
+        let full = backoffs(JitterKind::Full, 20);
+        assert!(full.iter().all(|&b| b <= ms(10_000)));
+        assert!(full.iter().any(|&b| b < ms(5_000)));
+
+        // Steep growth lands exactly on the cap, even past f64 overflow
+        let steep = |jitter, attempt| {
+            let mut config = test_config();
+            config.retry.multiplier = 10.0;
+            config.retry.jitter = jitter;
+            let client = ApiClient::new(Arc::new(config)).unwrap();
+            (0..50)
+                .map(|_| client.calculate_backoff(attempt, None))
+                .collect::<Vec<_>>()
+        };
+        let max = test_config().retry.max_backoff;
+        assert_eq!(steep(JitterKind::None, 20)[0], max);
+        assert_eq!(steep(JitterKind::None, 400)[0], max);
+        let equal = steep(JitterKind::Equal, 400);
+        assert!(equal.iter().all(|&b| b >= max * 3 / 4 && b <= max));
+
+        // Decorrelated jitter grows from the previous wait, between the base and the cap
+        let mut config = test_config();
+        config.retry.jitter = JitterKind::Decorrelated;
+        let base = config.retry.initial_backoff;
+        let client = ApiClient::new(Arc::new(config)).unwrap();
+        let retries: Vec<Vec<_>> = (0..200)
+            .map(|_| {
+                let mut previous = None;
+                (1..=8)
+                    .map(|attempt| {
+                        let backoff = client.calculate_backoff(attempt, previous);
+                        previous = Some(backoff);
+                        backoff
+                    })
+                    .collect()
+            })
+            .collect();
+        assert!(retries.iter().flatten().all(|&b| b >= base && b <= max));
+        let mean = |retry: usize| retries.iter().map(|r| r[retry]).sum::<Duration>() / 200;
+        assert!(mean(0) < mean(2) && mean(2) < mean(7));
+
+        // A seeded client repeats its jitter
+        let seeded = || {
+            let mut config = test_config();
+            config.seed = Some(42);
+            let client = ApiClient::new(Arc::new(config)).unwrap();
+            (1..10)
+                .map(|attempt| client.calculate_backoff(attempt, None))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(seeded(), seeded());
+    }
+
+    #[tokio::test]
+    async fn test_probe() {
+        use wiremock::matchers::{header, method};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .and(header("Authorization", "Bearer secret"))
+            .respond_with(ResponseTemplate::new(405))
+            .mount(&server)
+            .await;
+
+        let client = ApiClient::new(Arc::new(test_config())).unwrap();
+        let endpoint = Endpoint::new(EndpointConfig {
+            url: server.uri(),
+            api_key: Some("secret".to_string()),
+            ..Default::default()
+        });
+        let result = client.probe(&endpoint).await;
+        assert!(result.is_reachable());
+        assert_eq!(result.status, Some(405));
+
+        // Nothing listens on port 9
+        let endpoint = Endpoint::new(EndpointConfig {
+            url: "http://127.0.0.1:9".to_string(),
+            ..Default::default()
+        });
+        let result = client.probe(&endpoint).await;
+        assert!(!result.is_reachable());
+        assert!(result.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_prewarm() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .respond_with(ResponseTemplate::new(405))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        // One connection per pooled slot, capped by the endpoint's concurrency
+        let mut config = test_config();
+        config.request.pool_max_idle_per_host = Some(4);
+        let client = ApiClient::new(Arc::new(config)).unwrap();
+        let endpoint = Endpoint::new(EndpointConfig {
+            url: server.uri(),
+            max_concurrent: 2,
+            ..Default::default()
+        });
+        assert_eq!(client.prewarm(&endpoint).await, 2);
+        server.verify().await;
+
+        let endpoint = Endpoint::new(EndpointConfig {
+            url: "http://127.0.0.1:9".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(client.prewarm(&endpoint).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_oauth2_token() {
+        use crate::config::OAuth2Config;
+        use wiremock::matchers::{body_string_contains, header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        // The first token is revoked early, the second one is accepted
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("grant_type=client_credentials"))
+            .and(body_string_contains("scope=read+write"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"access_token": "t1", "expires_in": 900})),
+            )
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"access_token": "t2", "expires_in": 900})),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v1"))
+            .and(header("Authorization", "Bearer t2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v1"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+
+        let client = ApiClient::new(Arc::new(test_config())).unwrap();
+        let endpoint = Arc::new(Endpoint::new(EndpointConfig {
+            url: format!("{}/v1", server.uri()),
+            auth: vec![AuthConfig::OAuth2(OAuth2Config {
+                token_url: format!("{}/token", server.uri()),
+                client_id: "client".to_string(),
+                client_secret: "secret".to_string(),
+                scopes: vec!["read".to_string(), "write".to_string()],
+            })],
+            ..Default::default()
+        }));
+
+        let requests: Vec<_> = (0..10)
+            .map(|i| ApiRequest::simple(format!("request {i}")))
+            .collect();
+        let results = futures::future::join_all(
+            requests
+                .iter()
+                .map(|request| client.send_with_retry(request, endpoint.try_acquire().unwrap())),
+        )
+        .await;
+        assert!(results.iter().all(RequestResult::is_success));
+
+        // All requests shared one fetch of each token
+        let token_requests = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|request| request.url.path() == "/token")
+            .count();
+        assert_eq!(token_requests, 2);
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_timeout() {
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(wiremock::matchers::any())
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({}))
+                    .set_delay(Duration::from_millis(500)),
+            )
+            .mount(&server)
+            .await;
+
+        let mut config = test_config();
+        config.retry.max_attempts = 1;
+        let client = ApiClient::new(Arc::new(config)).unwrap();
+        let endpoint: EndpointConfig =
+            serde_json::from_value(serde_json::json!({"url": server.uri(), "timeout": "100ms"}))
+                .unwrap();
+        assert_eq!(endpoint.timeout, Some(Duration::from_millis(100)));
+        let endpoint = Arc::new(Endpoint::new(endpoint));
+
+        // The endpoint's timeout wins over the client's 30 seconds
+        let request = ApiRequest::simple("slow");
+        let start = Instant::now();
+        match client
+            .send_with_retry(&request, endpoint.acquire().await)
+            .await
+        {
+            RequestResult::Failure(error) => {
+                assert_eq!(error.error_kind, ErrorKind::Timeout);
+            }
+            RequestResult::Success(_) | RequestResult::Skipped { .. } => {
+                panic!("request should have timed out")
+            }
+        }
+        assert!(start.elapsed() < Duration::from_millis(450));
+    }
+
+    #[tokio::test]
+    async fn test_max_response_bytes() {
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let padding = "x".repeat(2000);
+        Mock::given(wiremock::matchers::any())
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "text": padding })),
+            )
+            .mount(&server)
+            .await;
+
+        let send = |max_response_bytes| {
+            let mut config = test_config();
+            config.retry.max_attempts = 1;
+            config.request.max_response_bytes = max_response_bytes;
+            let client = ApiClient::new(Arc::new(config)).unwrap();
+            let endpoint = Arc::new(Endpoint::new(EndpointConfig {
+                url: server.uri(),
+                ..Default::default()
+            }));
+            async move {
+                client
+                    .send_with_retry(&ApiRequest::simple("big"), endpoint.acquire().await)
+                    .await
+            }
+        };
+
+        // A body over the limit fails as transient, naming the limit and endpoint
+        match send(1024).await {
+            RequestResult::Failure(error) => {
+                assert_eq!(error.error_kind, ErrorKind::ResponseTooLarge);
+                assert_eq!(error.failure_class, FailureClass::Transient);
+                assert!(error.error.contains("1024 byte limit"), "{}", error.error);
+                assert!(error.error.contains(&server.uri()), "{}", error.error);
+            }
+            RequestResult::Success(_) | RequestResult::Skipped { .. } => {
+                panic!("oversized response should fail")
+            }
+        }
+        assert!(send(4096).await.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_cut_off_body() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // The connection closes before the promised body has arrived
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = [0; 4096];
+                let _ = socket.read(&mut request).await;
+                let response = "HTTP/1.1 200 OK\r\ncontent-length: 100\r\n\r\n{\"text\":";
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+
+        let mut config = test_config();
+        config.retry.initial_backoff = Duration::from_millis(1);
+        let client = ApiClient::new(Arc::new(config)).unwrap();
+        let endpoint = Arc::new(Endpoint::new(EndpointConfig {
+            url,
+            ..Default::default()
+        }));
+
+        // Despite the 200, a body cut off midway is retried like a dropped connection
+        match client
+            .send_with_retry(&ApiRequest::simple("hello"), endpoint.acquire().await)
+            .await
+        {
+            RequestResult::Failure(error) => {
+                assert!(
+                    error.error.contains("Failed to read response"),
+                    "{}",
+                    error.error
+                );
+                assert_eq!(error.failure_class, FailureClass::Transient);
+                assert_eq!(error.attempts, 3);
+            }
+            RequestResult::Success(_) => panic!("a cut-off body should fail"),
+            RequestResult::Skipped { .. } => unreachable!("the client never skips"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_error_body() {
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(wiremock::matchers::any())
+            .respond_with(ResponseTemplate::new(400).set_body_string("é".repeat(1000)))
+            .mount(&server)
+            .await;
+        let client = ApiClient::new(Arc::new(test_config())).unwrap();
+        let endpoint = Arc::new(Endpoint::new(EndpointConfig {
+            url: server.uri(),
+            ..Default::default()
+        }));
+
+        // Only the start of the body is kept, cut between characters
+        match client
+            .send_with_retry(&ApiRequest::simple("hello"), endpoint.acquire().await)
+            .await
+        {
+            RequestResult::Failure(error) => {
+                assert_eq!(error.error, format!("HTTP 400: {}...", "é".repeat(250)));
+            }
+            RequestResult::Success(_) => panic!("a 400 should fail"),
+            RequestResult::Skipped { .. } => unreachable!("the client never skips"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_budget() {
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(wiremock::matchers::any())
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+        let mut config = test_config();
+        config.retry.max_attempts = 3;
+        config.retry.initial_backoff = Duration::from_millis(1);
+        config.retry.budget_ratio = 0.0;
+        let client = ApiClient::new(Arc::new(config)).unwrap();
+        let endpoint = Arc::new(Endpoint::new(EndpointConfig {
+            url: server.uri(),
+            ..Default::default()
+        }));
+
+        // Without successes only the minimum retries are allowed, then requests fail after one attempt
+        let mut attempts = Vec::new();
+        for _ in 0..6 {
+            match client
+                .send_with_retry(&ApiRequest::simple("hello"), endpoint.acquire().await)
+                .await
+            {
+                RequestResult::Failure(error) => attempts.push(error.attempts),
+                RequestResult::Success(_) | RequestResult::Skipped { .. } => {
+                    panic!("request should fail")
+                }
+            }
+        }
+        assert_eq!(attempts, [3, 3, 3, 3, 3, 1]);
+        assert_eq!(client.stats.retry_budget().denied(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_attempts_by_failure() {
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(wiremock::matchers::any())
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+        let mut config = test_config();
+        config.retry.initial_backoff = Duration::from_millis(1);
+        config.retry.connection_max_attempts = Some(1);
+        config.retry.http_max_attempts = Some(4);
+        let client = ApiClient::new(Arc::new(config)).unwrap();
+
+        // A dead endpoint fails fast, an erroring one is retried patiently
+        let mut attempts = Vec::new();
+        for url in ["http://127.0.0.1:9".to_string(), server.uri()] {
+            let endpoint = Arc::new(Endpoint::new(EndpointConfig {
+                url,
+                ..Default::default()
+            }));
+            match client
+                .send_with_retry(&ApiRequest::simple("hello"), endpoint.acquire().await)
+                .await
+            {
+                RequestResult::Failure(error) => attempts.push(error.attempts),
+                RequestResult::Success(_) | RequestResult::Skipped { .. } => {
+                    panic!("request should fail")
+                }
+            }
+        }
+        assert_eq!(attempts, [1, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_stream_response() {
+        use wiremock::matchers::body_partial_json;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let events = concat!(
+            "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi \"}}]}\n\n",
+            "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"there\"}}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        Mock::given(body_partial_json(serde_json::json!({"stream": true})))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(events, "text/event-stream"))
+            .mount(&server)
+            .await;
+
+        let client = ApiClient::new(Arc::new(test_config())).unwrap();
+        let endpoint = Arc::new(Endpoint::new(EndpointConfig {
+            url: server.uri(),
+            stream: true,
+            ..Default::default()
+        }));
+
+        // The chunks are assembled into one message, with the time to first token recorded
+        match client
+            .send_with_retry(&ApiRequest::simple("hello"), endpoint.acquire().await)
+            .await
+        {
+            RequestResult::Success(response) => {
+                assert_eq!(
+                    response.response["choices"][0]["message"]["content"],
+                    "Hi there"
+                );
+                assert!(response.metadata.unwrap().ttft_ms.is_some());
+            }
+            RequestResult::Failure(error) => panic!("stream should succeed: {}", error.error),
+            RequestResult::Skipped { .. } => unreachable!("the client never skips"),
+        }
+
+        // A stream cut off before it finished is retried as a transient failure
+        let cut_off = MockServer::start().await;
+        let events =
+            "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi \"}}]}\n\n";
+        Mock::given(body_partial_json(serde_json::json!({"stream": true})))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(events, "text/event-stream"))
+            .mount(&cut_off)
+            .await;
+        let endpoint = Arc::new(Endpoint::new(EndpointConfig {
+            url: cut_off.uri(),
+            stream: true,
+            ..Default::default()
+        }));
+        match client
+            .send_with_retry(&ApiRequest::simple("hello"), endpoint.acquire().await)
+            .await
+        {
+            RequestResult::Failure(error) => {
+                assert!(error.attempts > 1);
+                assert_eq!(error.failure_class, FailureClass::Transient);
+            }
+            RequestResult::Success(_) => panic!("a cut-off stream should fail"),
+            RequestResult::Skipped { .. } => unreachable!("the client never skips"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_model() {
+        use wiremock::matchers::body_partial_json;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        for model in ["gpt-4o", "gpt-4o-mini"] {
+            Mock::given(body_partial_json(serde_json::json!({ "model": model })))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+                .mount(&server)
+                .await;
+        }
+
+        let client = ApiClient::new(Arc::new(test_config())).unwrap();
+        let endpoint = Arc::new(Endpoint::new(EndpointConfig {
+            url: server.uri(),
+            model: Some("gpt-4o-mini".to_string()),
+            ..Default::default()
+        }));
+
+        // A row's own model wins over the endpoint's, and each is recorded
+        let hard = ApiRequest {
+            model: Some("gpt-4o".to_string()),
+            ..ApiRequest::simple("hard")
+        };
+        for (request, model) in [
+            (hard, "gpt-4o"),
+            (ApiRequest::simple("easy"), "gpt-4o-mini"),
+        ] {
+            let RequestResult::Success(response) = client
+                .send_with_retry(&request, endpoint.acquire().await)
+                .await
+            else {
+                panic!("request for {model} should succeed");
+            };
+            assert_eq!(response.metadata.unwrap().model.as_deref(), Some(model));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_success_statuses() {
+        use wiremock::{matchers::any, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(202).set_body_json(serde_json::json!({})))
+            .mount(&server)
+            .await;
+
+        let mut config = test_config();
+        config.retry.max_attempts = 1;
+        let client = ApiClient::new(Arc::new(config)).unwrap();
+        let send = |success_statuses: Vec<u16>| {
+            let endpoint = Arc::new(Endpoint::new(EndpointConfig {
+                url: server.uri(),
+                success_statuses,
+                ..Default::default()
+            }));
+            let client = client.clone();
+            async move {
+                client
+                    .send_with_retry(&ApiRequest::simple("hello"), endpoint.acquire().await)
+                    .await
+            }
+        };
+
+        // Any 2xx succeeds by default
+        assert!(send(Vec::new()).await.is_success());
+
+        // A 202 the endpoint doesn't list is a failure, like an HTTP error
+        let RequestResult::Failure(error) = send(vec![200, 201]).await else {
+            panic!("a queued job should not count as done");
+        };
+        assert_eq!(error.status_code, Some(202));
+        assert!(error.error.starts_with("HTTP 202"));
+    }
+
+    #[tokio::test]
+    async fn test_body_encoding() {
+        use wiremock::matchers::{body_string, header, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/form"))
+            .and(header("content-type", "application/x-www-form-urlencoded"))
+            .and(body_string("age=36&name=Ada+L"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/text"))
+            .and(header("content-type", "text/csv"))
+            .and(body_string("a,b\n1,2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = ApiClient::new(Arc::new(test_config())).unwrap();
+        let send = |url: String, body_encoding, content_type: Option<&str>, request| {
+            let client = &client;
+            let endpoint = Arc::new(Endpoint::new(EndpointConfig {
+                url,
+                body_encoding,
+                content_type: content_type.map(str::to_string),
+                ..Default::default()
+            }));
+            async move {
+                client
+                    .send_with_retry(&request, endpoint.acquire().await)
+                    .await
+            }
+        };
+
+        // Object fields are form-encoded, non-strings as their JSON text
+        let request = ApiRequest {
+            body: Some(serde_json::json!({ "name": "Ada L", "age": 36 })),
+            ..ApiRequest::simple("hello")
+        };
+        let url = format!("{}/form", server.uri());
+        let result = send(url.clone(), BodyEncoding::Form, None, request).await;
+        assert!(result.is_success(), "{result:?}");
+
+        // Only objects can be form-encoded
+        let request = ApiRequest {
+            body: Some(serde_json::json!(["Ada L"])),
+            ..ApiRequest::simple("hello")
+        };
+        let RequestResult::Failure(error) = send(url, BodyEncoding::Form, None, request).await
+        else {
+            panic!("expected a failure");
+        };
+        assert!(error.error.contains("form-encoded"), "{error:?}");
+
+        // Plain text sends the input itself, with the endpoint's content type
+        let url = format!("{}/text", server.uri());
+        let request = ApiRequest::simple("a,b\n1,2");
+        let result = send(url, BodyEncoding::Text, Some("text/csv"), request).await;
+        assert!(result.is_success(), "{result:?}");
+        server.verify().await;
+    }
+
+    #[tokio::test]
+    async fn test_layered_auth() {
+        use wiremock::matchers::header;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(header("authorization", "Basic Z2F0ZXdheTpwYXNz"))
+            .and(header("x-api-key", "provider-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // Basic auth to the gateway replaces the bearer token, the provider key goes alongside
+        let client = ApiClient::new(Arc::new(test_config())).unwrap();
+        let endpoint = Arc::new(Endpoint::new(EndpointConfig {
+            url: server.uri(),
+            api_key: Some("replaced".to_string()),
+            auth: vec![
+                AuthConfig::Basic {
+                    username: "gateway".to_string(),
+                    password: Some("pass".to_string()),
+                },
+                AuthConfig::Header {
+                    name: "x-api-key".to_string(),
+                    value: "provider-key".to_string(),
+                },
+            ],
+            ..Default::default()
+        }));
+        let result = client
+            .send_with_retry(&ApiRequest::simple("hello"), endpoint.acquire().await)
+            .await;
+        assert!(result.is_success(), "{result:?}");
+        server.verify().await;
+    }
+
+    #[tokio::test]
+    async fn test_custom_headers() {
+        use wiremock::matchers::header;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(header("x-tenant", "acme"))
+            .and(header("x-team", "search"))
+            .and(header("Authorization", "Bearer row-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .mount(&server)
+            .await;
+
+        let client = ApiClient::new(Arc::new(test_config())).unwrap();
+        let endpoint = Arc::new(Endpoint::new(EndpointConfig {
+            url: server.uri(),
+            api_key: Some("endpoint-key".to_string()),
+            headers: [("x-tenant", "acme"), ("x-team", "default")]
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .into(),
+            ..Default::default()
+        }));
+
+        // A request's headers replace its endpoint's, generated auth included
+        let headers = [("X-Team", "search"), ("authorization", "Bearer row-key")];
+        let request = ApiRequest {
+            headers: Some(
+                headers
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .into(),
+            ),
+            ..ApiRequest::simple("hello")
+        };
+        let result = client
+            .send_with_retry(&request, endpoint.acquire().await)
+            .await;
+        assert!(result.is_success(), "{result:?}");
+
+        // Invalid headers are left off and counted, the rest still go out
+        let headers = [
+            ("x team", "search"),
+            ("X-Team", "search"),
+            ("x-tenant", "a\nb"),
+            ("authorization", "Bearer row-key"),
+        ];
+        let request = ApiRequest {
+            headers: Some(
+                headers
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .into(),
+            ),
+            ..ApiRequest::simple("hello")
+        };
+        assert_eq!(client.stats.snapshot().invalid_headers, 0);
+        let result = client
+            .send_with_retry(&request, endpoint.acquire().await)
+            .await;
+        assert!(result.is_success(), "{result:?}");
+        assert_eq!(client.stats.snapshot().invalid_headers, 2);
+    }
+
+    #[tokio::test]
+    async fn test_capture_request() {
+        use wiremock::matchers::any;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(400))
+            .mount(&server)
+            .await;
+
+        let mut config = test_config();
+        config.processing.capture_request = true;
+        let client = ApiClient::new(Arc::new(config)).unwrap();
+        let endpoint = Arc::new(Endpoint::new(EndpointConfig {
+            url: server.uri(),
+            api_key: Some("endpoint-key".to_string()),
+            model: Some("gpt-4o".to_string()),
+            auth_header: "x-api-key".to_string(),
+            headers: [("x-tenant", "acme"), ("X-Api-Key", "header-key")]
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .into(),
+            ..Default::default()
+        }));
+        let request = ApiRequest {
+            headers: Some([("authorization".to_string(), "Bearer row-key".to_string())].into()),
+            ..ApiRequest::simple("hello")
+        };
+
+        // The body is captured as rendered, with credentials in custom headers masked
+        let RequestResult::Failure(error) = client
+            .send_with_retry(&request, endpoint.acquire().await)
+            .await
+        else {
+            panic!("expected a failure");
+        };
+        let body = error.sent_body.unwrap();
+        assert_eq!(body["model"], "gpt-4o");
+        assert_eq!(body["messages"][0]["content"], "hello");
+        let headers = error.sent_headers.unwrap();
+        assert_eq!(headers["x-tenant"], "acme");
+        assert_eq!(headers["x-api-key"], "********");
+        assert_eq!(headers["authorization"], "********");
+
+        // Nothing is captured unless asked for
+        let client = ApiClient::new(Arc::new(test_config())).unwrap();
+        let RequestResult::Failure(error) = client
+            .send_with_retry(&request, endpoint.acquire().await)
+            .await
+        else {
+            panic!("expected a failure");
+        };
+        assert!(error.sent_body.is_none() && error.sent_headers.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_batch_requests() {
+        use crate::request::RequestFormat;
+        use wiremock::matchers::any;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let results = serde_json::json!([
+            { "embedding": [1.0], "usage": { "prompt_tokens": 4 } },
+            { "error": { "message": "input too long", "code": 400 } },
+            { "embedding": [3.0] },
+        ]);
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(results))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = ApiClient::new(Arc::new(test_config())).unwrap();
+        let endpoint = Arc::new(Endpoint::new(EndpointConfig {
+            url: server.uri(),
+            request_format: RequestFormat::Embedding,
+            batch_size: 3,
+            batch_wait: Duration::from_secs(5),
+            ..Default::default()
+        }));
+        let requests: Vec<_> = (1..=3)
+            .map(|line| ApiRequest {
+                line_number: line,
+                ..ApiRequest::simple(format!("text {line}"))
+            })
+            .collect();
+
+        // The three requests go out as one array and get their own results back
+        let results = future::join_all(requests.iter().map(|request| {
+            let endpoint = Arc::clone(&endpoint);
+            let client = &client;
+            async move {
+                client
+                    .send_with_retry(request, endpoint.acquire().await)
+                    .await
+            }
+        }))
+        .await;
+        let sent: serde_json::Value =
+            serde_json::from_slice(&server.received_requests().await.unwrap()[0].body).unwrap();
+        assert_eq!(sent[2], serde_json::json!({ "input": "text 3" }));
+
+        let RequestResult::Success(first) = &results[0] else {
+            panic!("expected the first item to succeed");
+        };
+        assert_eq!(first.input.as_deref(), Some("text 1"));
+        assert_eq!(first.response["embedding"][0], 1.0);
+        assert_eq!(
+            first
+                .metadata
+                .as_ref()
+                .unwrap()
+                .usage
+                .unwrap()
+                .prompt_tokens,
+            4
+        );
+        let RequestResult::Failure(error) = &results[1] else {
+            panic!("expected the second item to fail");
+        };
+        assert_eq!(error.line_number, 2);
+        assert_eq!(error.status_code, Some(400));
+        assert!(error.error.contains("input too long"));
+        assert!(results[2].is_success());
+    }
+
+    #[tokio::test]
+    async fn test_request_id() {
+        use wiremock::matchers::{body_string_contains, header, header_exists};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(body_string_contains("bad"))
+            .respond_with(ResponseTemplate::new(400))
+            .mount(&server)
+            .await;
+        Mock::given(header_exists("x-request-id"))
+            .and(header("user-agent", "batch-job/1.0"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .mount(&server)
+            .await;
+
+        let mut config = test_config();
+        config.request.user_agent = "batch-job/1.0".to_string();
+        config.request.request_id_header = Some("X-Request-Id".to_string());
+        let client = ApiClient::new(Arc::new(config)).unwrap();
+        let endpoint = Arc::new(Endpoint::new(EndpointConfig {
+            url: server.uri(),
+            ..Default::default()
+        }));
+        let (client, endpoint) = (&client, &endpoint);
+        let send = |request: ApiRequest| async move {
+            let slot = endpoint.acquire().await;
+            client.send_with_retry(&request, slot).await
+        };
+
+        // Each request gets a fresh id, recorded in its result
+        let ids: Vec<_> = [
+            send(ApiRequest::simple("hello")).await,
+            send(ApiRequest::simple("hello")).await,
+        ]
+        .into_iter()
+        .map(|result| match result {
+            RequestResult::Success(response) => response.metadata.unwrap().request_id.unwrap(),
+            other => panic!("request should succeed: {other:?}"),
+        })
+        .collect();
+        assert_eq!(ids[0].len(), 36);
+        assert_eq!(&ids[0][14..15], "4");
+        assert_ne!(ids[0], ids[1]);
+
+        // Failures carry the id too, and an id the request brings is kept
+        let request = ApiRequest {
+            headers: Some([("x-request-id".to_string(), "row-7".to_string())].into()),
+            ..ApiRequest::simple("bad")
+        };
+        match send(request).await {
+            RequestResult::Failure(error) => assert_eq!(error.request_id.as_deref(), Some("row-7")),
+            other => panic!("request should fail: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_hedged() {
+        use std::sync::atomic::Ordering;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let respond_after = |delay: u64| {
+            Mock::given(wiremock::matchers::any()).respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({}))
+                    .set_delay(Duration::from_millis(delay)),
+            )
+        };
+        let slow = MockServer::start().await;
+        respond_after(1500).mount(&slow).await;
+        let fast = MockServer::start().await;
+        respond_after(0).mount(&fast).await;
+
+        let client = ApiClient::new(Arc::new(test_config())).unwrap();
+        let endpoint = |server: &MockServer| {
+            Arc::new(Endpoint::new(EndpointConfig {
+                url: server.uri(),
+                ..Default::default()
+            }))
+        };
+        let (primary, hedge) = (endpoint(&slow), endpoint(&fast));
+
+        // The hedge to the fast endpoint wins and the slow primary is cancelled
+        let request = ApiRequest::simple("hedge me");
+        let start = Instant::now();
+        let result = client
+            .send_hedged(
+                &request,
+                primary.acquire().await,
+                Duration::from_millis(50),
+                |_| Some(Arc::clone(&hedge)),
+            )
+            .await;
+        match result {
+            RequestResult::Success(response) => {
+                assert_eq!(response.metadata.unwrap().endpoint, fast.uri());
+            }
+            RequestResult::Failure(error) => panic!("hedge should have succeeded: {}", error.error),
+            RequestResult::Skipped { .. } => unreachable!("the client never skips"),
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(primary.in_flight.load(Ordering::Relaxed), 0);
+        assert_eq!(hedge.in_flight.load(Ordering::Relaxed), 0);
+
+        // Without a rate limit token the hedge stays home and the primary answers
+        let limiter =
+            governor::RateLimiter::direct(governor::Quota::per_minute(std::num::NonZeroU32::MIN));
+        assert!(limiter.check().is_ok());
+        let client = client.with_rate_limiter(Arc::new(limiter));
+        let result = client
+            .send_hedged(
+                &request,
+                primary.acquire().await,
+                Duration::from_millis(50),
+                |_| Some(Arc::clone(&hedge)),
+            )
+            .await;
+        match result {
+            RequestResult::Success(response) => {
+                assert_eq!(response.metadata.unwrap().endpoint, slow.uri());
+            }
+            RequestResult::Failure(error) => {
+                panic!("primary should have succeeded: {}", error.error)
+            }
+            RequestResult::Skipped { .. } => unreachable!("the client never skips"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_other_endpoints() {
+        use std::sync::atomic::Ordering;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let failing = MockServer::start().await;
+        Mock::given(wiremock::matchers::any())
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&failing)
+            .await;
+        let healthy = MockServer::start().await;
+        Mock::given(wiremock::matchers::any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .mount(&healthy)
+            .await;
+
+        for switch_endpoints in [false, true] {
+            let mut config = test_config();
+            config.endpoints = [&failing, &healthy]
+                .map(|server| EndpointConfig {
+                    url: server.uri(),
+                    ..Default::default()
+                })
+                .to_vec();
+            config.retry.initial_backoff = Duration::from_millis(1);
+            config.retry.switch_endpoints = switch_endpoints;
+            let load_balancer = Arc::new(LoadBalancer::new(config.endpoints.clone()).unwrap());
+            let client = ApiClient::new(Arc::new(config))
+                .unwrap()
+                .with_load_balancer(Arc::clone(&load_balancer));
+
+            // The first attempt goes to the endpoint it was given, the retry elsewhere
+            let first = &load_balancer.endpoints()[0];
+            let result = client
+                .send_with_retry(&ApiRequest::simple("hello"), first.acquire().await)
+                .await;
+            match result {
+                RequestResult::Success(response) => {
+                    assert!(switch_endpoints);
+                    let metadata = response.metadata.unwrap();
+                    assert_eq!(metadata.endpoint, healthy.uri());
+                    assert_eq!(metadata.attempts, 2);
+                }
+                RequestResult::Failure(error) => {
+                    assert!(!switch_endpoints);
+                    assert_eq!(error.endpoint.as_deref(), Some(failing.uri().as_str()));
+                    assert_eq!(error.attempts, 3);
+                }
+                RequestResult::Skipped { .. } => unreachable!("the client never skips"),
+            }
+            for endpoint in load_balancer.endpoints() {
+                assert_eq!(endpoint.in_flight.load(Ordering::Relaxed), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_proxy() {
+        let with_proxy = |proxy: ProxyConfig| {
+            let mut config = test_config();
+            config.request.proxy = Some(proxy);
+            ApiClient::new(Arc::new(config))
+        };
+
+        assert!(with_proxy(ProxyConfig::new("http://proxy.internal:3128")).is_ok());
+        assert!(with_proxy(ProxyConfig {
+            username: Some("user".to_string()),
+            password: Some("secret".to_string()),
+            no_proxy: Some("localhost,.internal".to_string()),
+            ..ProxyConfig::new("socks5://proxy.internal:1080")
+        })
+        .is_ok());
+
+        for url in ["ftp://proxy.internal", "proxy.internal:3128", "not a url"] {
+            assert!(matches!(
+                with_proxy(ProxyConfig::new(url)),
+                Err(BlazeError::InvalidProxy { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_connection_options() {
+        for http_version in [HttpVersion::Auto, HttpVersion::Http1, HttpVersion::Http2] {
+            let mut config = test_config();
+            config.request.http_version = http_version;
+            config.request.accept_invalid_certs = true;
+            config.request.pool_max_idle_per_host = Some(4);
+            config.request.pool_idle_timeout = Duration::from_secs(5);
+            assert!(ApiClient::new(Arc::new(config.clone())).is_ok());
+            config.request.http2_keep_alive_interval = None;
+            config.request.http2_keep_alive_while_idle = false;
+            assert!(ApiClient::new(Arc::new(config)).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+        assert_eq!(parse_retry_after("soon"), None);
+
+        let future = (chrono::Utc::now() + chrono::Duration::seconds(60)).to_rfc2822();
+        let delay = parse_retry_after(&future).unwrap();
+        assert!(delay > Duration::from_secs(55) && delay <= Duration::from_secs(60));
+    }
 }
@@ -0,0 +1,207 @@
+//! Transparent gzip and zstd compression for input and output files.
+//!
+//! The format is picked from the file extension: `.gz` for gzip, `.zst`
+//! for zstd, anything else is read and written as plain text.
+
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
+use std::io;
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::{AsyncBufRead, AsyncWriteExt, BufReader, BufWriter};
+
+/// Compression format of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Plain, uncompressed file.
+    None,
+    /// Gzip (`.gz`).
+    Gzip,
+    /// Zstandard (`.zst`).
+    Zstd,
+}
+
+impl Compression {
+    /// Detect the compression format from a file extension.
+    #[must_use]
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Self::Gzip,
+            Some("zst") => Self::Zstd,
+            _ => Self::None,
+        }
+    }
+
+    /// Check whether this format compresses data.
+    #[must_use]
+    pub fn is_compressed(self) -> bool {
+        self != Self::None
+    }
+}
+
+/// A buffered reader that decompresses according to the file extension.
+pub type FileReader = Box<dyn AsyncBufRead + Send + Unpin>;
+
+/// Open a file for reading, decompressing it if needed.
+///
+/// Concatenated gzip members and zstd frames are read as one stream.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened.
+pub async fn open_reader(path: &Path) -> io::Result<FileReader> {
+    let file = BufReader::new(File::open(path).await?);
+
+    Ok(match Compression::from_path(path) {
+        Compression::None => Box::new(file),
+        Compression::Gzip => {
+            let mut decoder = GzipDecoder::new(file);
+            decoder.multiple_members(true);
+            Box::new(BufReader::new(decoder))
+        }
+        Compression::Zstd => {
+            let mut decoder = ZstdDecoder::new(file);
+            decoder.multiple_members(true);
+            Box::new(BufReader::new(decoder))
+        }
+    })
+}
+
+/// Check that a compressed file holds complete streams, reading it through.
+///
+/// A stream a killed run never finished can be decoded up to its last
+/// flush, but ends without its trailer.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read, or its last stream is cut off.
+pub async fn check_complete(path: &Path) -> io::Result<()> {
+    if tokio::fs::metadata(path).await?.len() == 0 {
+        return Ok(());
+    }
+    let mut reader = open_reader(path).await?;
+    tokio::io::copy_buf(&mut reader, &mut tokio::io::sink()).await?;
+    Ok(())
+}
+
+/// A buffered file writer that compresses according to the file extension.
+#[derive(Debug)]
+pub enum FileWriter {
+    /// Plain text.
+    Plain(BufWriter<File>),
+    /// Gzip compressed.
+    Gzip(GzipEncoder<BufWriter<File>>),
+    /// Zstandard compressed.
+    Zstd(ZstdEncoder<BufWriter<File>>),
+}
+
+impl FileWriter {
+    /// Wrap an open file, compressing according to the extension of `path`.
+    #[must_use]
+    pub fn new(file: File, path: &Path) -> Self {
+        let file = BufWriter::new(file);
+        match Compression::from_path(path) {
+            Compression::None => Self::Plain(file),
+            Compression::Gzip => Self::Gzip(GzipEncoder::new(file)),
+            Compression::Zstd => Self::Zstd(ZstdEncoder::new(file)),
+        }
+    }
+
+    /// Write a buffer, compressing it if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the underlying file cannot be written.
+    pub async fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Plain(w) => w.write_all(buf).await,
+            Self::Gzip(w) => w.write_all(buf).await,
+            Self::Zstd(w) => w.write_all(buf).await,
+        }
+    }
+
+    /// Flush buffered data to the file, without waiting for it to reach the disk.
+    ///
+    /// What was written survives the process being killed, though not a
+    /// crash of the machine. Compressed data written so far can be decoded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data cannot be flushed.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(w) => w.flush().await,
+            Self::Gzip(w) => w.flush().await,
+            Self::Zstd(w) => w.flush().await,
+        }
+    }
+
+    /// Flush buffered data and sync it to disk.
+    ///
+    /// Compressed data written so far can be decoded after a sync, but the
+    /// stream stays open until [`FileWriter::finish`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data cannot be flushed or synced.
+    pub async fn sync(&mut self) -> io::Result<()> {
+        self.flush().await?;
+        self.file().sync_data().await
+    }
+
+    /// Write the end of the compressed stream and sync the file to disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data cannot be flushed or synced.
+    pub async fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(w) => w.flush().await?,
+            Self::Gzip(w) => w.shutdown().await?,
+            Self::Zstd(w) => w.shutdown().await?,
+        }
+        self.file().sync_data().await
+    }
+
+    /// Get the underlying file.
+    fn file(&self) -> &File {
+        match self {
+            Self::Plain(w) => w.get_ref(),
+            Self::Gzip(w) => w.get_ref().get_ref(),
+            Self::Zstd(w) => w.get_ref().get_ref(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncBufReadExt;
+
+    #[tokio::test]
+    async fn test_compressed_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+
+        for name in ["out.jsonl", "out.jsonl.gz", "out.jsonl.zst"] {
+            let path = dir.path().join(name);
+            let mut writer = FileWriter::new(File::create(&path).await.unwrap(), &path);
+            writer.write_all(b"{\"n\":1}\n").await.unwrap();
+            writer.sync().await.unwrap();
+            writer.write_all(b"{\"n\":2}\n").await.unwrap();
+            writer.finish().await.unwrap();
+
+            let raw = std::fs::read(&path).unwrap();
+            assert_eq!(
+                raw.starts_with(b"{"),
+                !Compression::from_path(&path).is_compressed()
+            );
+
+            let mut lines = open_reader(&path).await.unwrap().lines();
+            let mut read = Vec::new();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                read.push(line);
+            }
+            assert_eq!(read, ["{\"n\":1}", "{\"n\":2}"], "{name}");
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Adaptive concurrency control.
+//!
+//! An AIMD (additive increase, multiplicative decrease) controller adjusts
+//! how many requests may be in flight. After each window of completions the
+//! limit grows by one while latency and errors stay stable, and is halved
+//! when the window's p95 latency spikes above its running baseline or the
+//! error rate climbs.
+
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tracing::debug;
+
+/// Minimum number of completions in a window before adjusting the limit.
+const MIN_WINDOW: usize = 20;
+/// Error rate above which the limit is decreased.
+const MAX_ERROR_RATE: f64 = 0.1;
+/// How far above the baseline p95 latency may rise before the limit is decreased.
+const LATENCY_TOLERANCE: f64 = 2.0;
+/// Weight of the newest window in the baseline p95 latency.
+const BASELINE_WEIGHT: f64 = 0.2;
+
+/// Completions observed since the last adjustment.
+#[derive(Debug, Default)]
+struct Window {
+    /// Latencies of successful requests.
+    latencies: Vec<Duration>,
+    /// Failed requests.
+    errors: usize,
+    /// Smoothed p95 latency of previous windows.
+    baseline_p95: Option<Duration>,
+}
+
+/// Concurrency limiter whose limit adapts to observed latency and errors.
+#[derive(Debug)]
+pub struct AdaptiveLimiter {
+    semaphore: Semaphore,
+    limit: AtomicUsize,
+    min_limit: usize,
+    max_limit: usize,
+    /// Permits to discard as they are released, after a decrease.
+    debt: AtomicUsize,
+    window: Mutex<Window>,
+}
+
+impl AdaptiveLimiter {
+    /// Create a limiter starting at `initial`, bounded by `min_limit..=max_limit`.
+    #[must_use]
+    pub fn new(initial: usize, min_limit: usize, max_limit: usize) -> Self {
+        let min_limit = min_limit.max(1);
+        let max_limit = max_limit.max(min_limit);
+        let initial = initial.clamp(min_limit, max_limit);
+
+        Self {
+            semaphore: Semaphore::new(initial),
+            limit: AtomicUsize::new(initial),
+            min_limit,
+            max_limit,
+            debt: AtomicUsize::new(0),
+            window: Mutex::new(Window::default()),
+        }
+    }
+
+    /// Get the current concurrency limit.
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Wait for a slot under the current limit.
+    pub async fn acquire(&self) -> AdaptivePermit<'_> {
+        // The semaphore is never closed, so acquiring only fails in theory
+        AdaptivePermit {
+            limiter: self,
+            permit: self.semaphore.acquire().await.ok(),
+        }
+    }
+
+    /// Record a completed request: its latency on success, or `None` on failure.
+    pub fn record(&self, latency: Option<Duration>) {
+        let mut window = self.window.lock();
+        match latency {
+            Some(latency) => window.latencies.push(latency),
+            None => window.errors += 1,
+        }
+
+        let total = window.latencies.len() + window.errors;
+        if total < self.limit().max(MIN_WINDOW) {
+            return;
+        }
+
+        #[allow(clippy::cast_precision_loss)]
+        let error_rate = window.errors as f64 / total as f64;
+        let p95 = percentile(&mut window.latencies, 0.95);
+        let baseline = window.baseline_p95.unwrap_or(p95);
+        let congested = error_rate > MAX_ERROR_RATE
+            || p95.as_secs_f64() > baseline.as_secs_f64() * LATENCY_TOLERANCE;
+
+        window.baseline_p95 =
+            Some(baseline.mul_f64(1.0 - BASELINE_WEIGHT) + p95.mul_f64(BASELINE_WEIGHT));
+        window.latencies.clear();
+        window.errors = 0;
+
+        if congested {
+            self.decrease();
+        } else {
+            self.increase();
+        }
+        debug!(
+            limit = self.limit(),
+            p95_ms = p95.as_millis(),
+            error_rate,
+            "Adjusted concurrency limit"
+        );
+    }
+
+    /// Grow the limit by one.
+    fn increase(&self) {
+        let limit = self.limit();
+        if limit >= self.max_limit {
+            return;
+        }
+        self.limit.store(limit + 1, Ordering::Relaxed);
+
+        // Cancel outstanding debt before handing out a new permit
+        let repaid = self
+            .debt
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |debt| {
+                debt.checked_sub(1)
+            })
+            .is_ok();
+        if !repaid {
+            self.semaphore.add_permits(1);
+        }
+    }
+
+    /// Halve the limit.
+    fn decrease(&self) {
+        let limit = self.limit();
+        let reduced = (limit / 2).max(self.min_limit);
+        if reduced == limit {
+            return;
+        }
+        self.limit.store(reduced, Ordering::Relaxed);
+
+        // Drop idle permits now and the rest as in-flight requests release them
+        let excess = limit - reduced;
+        let forgotten = self.semaphore.forget_permits(excess);
+        self.debt.fetch_add(excess - forgotten, Ordering::AcqRel);
+    }
+}
+
+/// A slot held under an [`AdaptiveLimiter`], released on drop.
+#[derive(Debug)]
+pub struct AdaptivePermit<'a> {
+    limiter: &'a AdaptiveLimiter,
+    permit: Option<SemaphorePermit<'a>>,
+}
+
+impl Drop for AdaptivePermit<'_> {
+    fn drop(&mut self) {
+        let Some(permit) = self.permit.take() else {
+            return;
+        };
+        let in_debt = self
+            .limiter
+            .debt
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |debt| {
+                debt.checked_sub(1)
+            })
+            .is_ok();
+        if in_debt {
+            permit.forget();
+        }
+    }
+}
+
+/// Get a percentile of a set of latencies, sorting them in place.
+fn percentile(latencies: &mut [Duration], quantile: f64) -> Duration {
+    if latencies.is_empty() {
+        return Duration::ZERO;
+    }
+    latencies.sort_unstable();
+
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    let index = ((latencies.len() as f64 * quantile).ceil() as usize).clamp(1, latencies.len()) - 1;
+    latencies[index]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_adaptive_limit() {
+        let limiter = AdaptiveLimiter::new(4, 1, 8);
+        assert_eq!(limiter.limit(), 4);
+
+        // Stable latency grows the limit by one per window
+        for _ in 0..MIN_WINDOW {
+            limiter.record(Some(Duration::from_millis(10)));
+        }
+        assert_eq!(limiter.limit(), 5);
+
+        // A latency spike halves it, reclaiming permits held in flight
+        let held: Vec<_> = futures::future::join_all((0..5).map(|_| limiter.acquire())).await;
+        for _ in 0..MIN_WINDOW {
+            limiter.record(Some(Duration::from_millis(100)));
+        }
+        assert_eq!(limiter.limit(), 2);
+        drop(held);
+        assert_eq!(limiter.semaphore.available_permits(), 2);
+
+        // Errors also back off, but never below the minimum
+        for _ in 0..MIN_WINDOW * 2 {
+            limiter.record(None);
+        }
+        assert_eq!(limiter.limit(), 1);
+        assert_eq!(limiter.semaphore.available_permits(), 1);
+    }
+}
@@ -3,13 +3,27 @@
 //! Supports configuration via CLI arguments, environment variables,
 //! and configuration files with sensible defaults.
 
+use crate::endpoint::{LoadBalanceStrategy, DEFAULT_LATENCY_DECAY};
 use crate::error::{BlazeError, Result};
-use clap::Parser;
+use crate::input::FieldMapping;
+use crate::keys::KeyRotation;
+use crate::projection::Projection;
+use crate::request::{BodyEncoding, FailureClass, RequestFormat};
+use crate::tracker::{DEFAULT_RETRY_BUDGET_RATIO, DEFAULT_RPS_WINDOW};
+use crate::validation::ResponseValidator;
+use clap::{Parser, Subcommand, ValueEnum};
+use governor::Quota;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+#[cfg(feature = "metrics")]
+use std::net::SocketAddr;
 use std::num::NonZeroU32;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+/// Shown in place of secrets in redacted configs and `Debug` output.
+const MASK: &str = "********";
+
 /// CLI arguments for the Blaze API client.
 #[derive(Parser, Debug, Clone)]
 #[command(
@@ -23,12 +37,26 @@ use std::time::Duration;
     after_help = "EXAMPLES:\n    \
         blaze --input requests.jsonl --output results.jsonl\n    \
         blaze -i data.jsonl -o out.jsonl --rate 5000 --workers 100\n    \
-        blaze --config endpoints.json --input batch.jsonl"
+        blaze --config endpoints.json --input batch.jsonl\n    \
+        blaze -i 'shards/*.jsonl' -o out.jsonl\n    \
+        blaze check --input batch.jsonl\n    \
+        blaze init config.json"
 )]
 pub struct Args {
-    /// Path to the JSONL file containing requests
-    #[arg(short, long, env = "BLAZE_INPUT")]
-    pub input: PathBuf,
+    /// Run a subcommand instead of processing the input
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Paths or glob patterns of the JSONL files containing requests
+    #[arg(
+        short,
+        long,
+        env = "BLAZE_INPUT",
+        global = true,
+        num_args = 1..,
+        value_delimiter = ','
+    )]
+    pub input: Vec<PathBuf>,
 
     /// Path to save successful responses (optional)
     #[arg(short, long, env = "BLAZE_OUTPUT")]
@@ -38,24 +66,122 @@ pub struct Args {
     #[arg(short, long, default_value = "errors.jsonl", env = "BLAZE_ERRORS")]
     pub errors: PathBuf,
 
-    /// Maximum requests per second
-    #[arg(short, long, default_value = "1000", env = "BLAZE_RATE")]
-    pub rate: u32,
+    /// Maximum requests per --rate-period [default: 1000]
+    #[arg(short, long, env = "BLAZE_RATE")]
+    pub rate: Option<u32>,
 
-    /// Maximum retry attempts per request
-    #[arg(short = 'a', long, default_value = "3", env = "BLAZE_MAX_ATTEMPTS")]
-    pub max_attempts: u32,
+    /// Period --rate is counted over (e.g. 1m) [default: 1s]
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, env = "BLAZE_RATE_PERIOD")]
+    pub rate_period: Option<Duration>,
+
+    /// Requests that may go out at once after a quiet spell [default: --rate]
+    #[arg(long, value_name = "N", env = "BLAZE_RATE_BURST")]
+    pub rate_burst: Option<u32>,
+
+    /// Attempts per request, including the first (1 disables retries) [default: 3]
+    #[arg(short = 'a', long, env = "BLAZE_MAX_ATTEMPTS")]
+    pub max_attempts: Option<u32>,
+
+    /// Attempts for requests failing without a response, like refused connections [default: --max-attempts]
+    #[arg(long, value_name = "N", env = "BLAZE_CONNECTION_MAX_ATTEMPTS")]
+    pub connection_max_attempts: Option<u32>,
+
+    /// Attempts for requests failing with an HTTP error status [default: --max-attempts]
+    #[arg(long, value_name = "N", env = "BLAZE_HTTP_MAX_ATTEMPTS")]
+    pub http_max_attempts: Option<u32>,
+
+    /// Retries allowed per successful request over the last 10 seconds [default: 0.1]
+    #[arg(long, value_name = "RATIO", env = "BLAZE_RETRY_BUDGET")]
+    pub retry_budget: Option<f64>,
+
+    /// HTTP statuses to retry and requeue like server errors (e.g. 409)
+    #[arg(
+        long,
+        value_name = "CODES",
+        env = "BLAZE_RETRYABLE_STATUSES",
+        value_delimiter = ','
+    )]
+    pub retryable_statuses: Option<Vec<u16>>,
+
+    /// HTTP statuses that fail a request without retrying [default: 400,401,403,404]
+    #[arg(
+        long,
+        value_name = "CODES",
+        env = "BLAZE_NON_RETRYABLE_STATUSES",
+        value_delimiter = ','
+    )]
+    pub non_retryable_statuses: Option<Vec<u16>>,
+
+    /// Send each retry to another endpoint than the ones the request failed on, when one is free
+    #[arg(long, env = "BLAZE_RETRY_OTHER_ENDPOINTS")]
+    pub retry_other_endpoints: bool,
+
+    /// Ramp the rate up from 10% of --rate to the full rate over this period (e.g. 30s)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, env = "BLAZE_RAMP_UP")]
+    pub ramp_up: Option<Duration>,
+
+    /// Send a second copy of a request to another endpoint if it has not finished after this delay (e.g. 500ms)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, env = "BLAZE_HEDGE_AFTER")]
+    pub hedge_after: Option<Duration>,
+
+    /// Number of concurrent workers [default: the endpoints' combined capacity]
+    #[arg(short, long, env = "BLAZE_WORKERS")]
+    pub workers: Option<usize>,
+
+    /// Adapt concurrency to observed latency and errors, starting from --workers
+    #[arg(long, env = "BLAZE_ADAPTIVE_CONCURRENCY")]
+    pub adaptive_concurrency: bool,
+
+    /// Upper bound on concurrency when adapting
+    #[arg(long, env = "BLAZE_MAX_WORKERS")]
+    pub max_workers: Option<usize>,
 
-    /// Number of concurrent workers
-    #[arg(short, long, default_value = "50", env = "BLAZE_WORKERS")]
-    pub workers: usize,
+    /// How requests are distributed across endpoints
+    #[arg(long, value_enum, env = "BLAZE_LOAD_BALANCING")]
+    pub load_balancing: Option<LoadBalanceStrategy>,
 
-    /// Request timeout in seconds
+    /// Weight of the newest sample in endpoint latency averages (0-1), for latency-weighted balancing
+    #[arg(long, env = "BLAZE_LATENCY_DECAY")]
+    pub latency_decay: Option<f64>,
+
+    /// Request field whose value picks the endpoint, for consistent-hash balancing
+    #[arg(long, env = "BLAZE_HASH_KEY")]
+    pub hash_key: Option<String>,
+
+    /// Seed for endpoint selection and backoff jitter, to make runs reproducible
+    #[arg(long, value_name = "N", env = "BLAZE_SEED")]
+    pub seed: Option<u64>,
+
+    /// Request timeout in seconds, for endpoints without their own timeout
     #[arg(short, long, default_value = "30", env = "BLAZE_TIMEOUT")]
     pub timeout: u64,
 
-    /// Path to endpoint configuration file (JSON)
-    #[arg(short, long, env = "BLAZE_CONFIG")]
+    /// Proxy URL for all requests (http://, https:// or socks5://)
+    #[arg(long, env = "BLAZE_PROXY")]
+    pub proxy: Option<String>,
+
+    /// HTTP version to speak to endpoints
+    #[arg(long, value_enum, env = "BLAZE_HTTP_VERSION")]
+    pub http_version: Option<HttpVersion>,
+
+    /// Skip TLS certificate verification (insecure, for self-signed test endpoints only)
+    #[arg(long, env = "BLAZE_ACCEPT_INVALID_CERTS")]
+    pub accept_invalid_certs: bool,
+
+    /// Open pooled connections to every endpoint before sending the first request
+    #[arg(long, env = "BLAZE_PREWARM")]
+    pub prewarm: bool,
+
+    /// User-Agent sent with every request [default: blaze/VERSION]
+    #[arg(long, env = "BLAZE_USER_AGENT")]
+    pub user_agent: Option<String>,
+
+    /// Send a unique id for each request in this header, such as X-Request-Id, and record it in its result
+    #[arg(long, value_name = "NAME", env = "BLAZE_REQUEST_ID_HEADER")]
+    pub request_id_header: Option<String>,
+
+    /// Path to endpoint configuration file (JSON, YAML or TOML)
+    #[arg(short, long, env = "BLAZE_CONFIG", global = true)]
     pub config: Option<PathBuf>,
 
     /// Enable verbose logging
@@ -66,6 +192,14 @@ pub struct Args {
     #[arg(long, env = "BLAZE_JSON_LOGS")]
     pub json_logs: bool,
 
+    /// Log one line per completed request with its endpoint, status, attempts and latency
+    #[arg(long, env = "BLAZE_LOG_OUTCOMES")]
+    pub log_outcomes: bool,
+
+    /// Write the body and custom headers each failed request was sent with to the errors file, credentials masked
+    #[arg(long, env = "BLAZE_CAPTURE_REQUEST")]
+    pub capture_request: bool,
+
     /// Disable progress bar
     #[arg(long, env = "BLAZE_NO_PROGRESS")]
     pub no_progress: bool,
@@ -73,6 +207,184 @@ pub struct Args {
     /// Dry run - validate config without sending requests
     #[arg(long)]
     pub dry_run: bool,
+
+    /// With --dry-run, send a HEAD request to each endpoint and report whether it answers
+    #[arg(long, requires = "dry_run")]
+    pub dry_run_probe: bool,
+
+    /// Resume from the checkpoint of a previous run, skipping completed requests
+    #[arg(long, env = "BLAZE_RESUME")]
+    pub resume: bool,
+
+    /// Write transient failures straight to the errors file instead of retrying them after the main pass
+    #[arg(long, env = "BLAZE_NO_REQUEUE")]
+    pub no_requeue: bool,
+
+    /// Send rows with neither input nor body instead of writing them to the errors file
+    #[arg(long, env = "BLAZE_ALLOW_BLANK_INPUT")]
+    pub allow_blank_input: bool,
+
+    /// Write input lines longer than this (e.g. 16MB) to the errors file instead of reading them [default: 64MiB]
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, env = "BLAZE_MAX_LINE_LENGTH")]
+    pub max_line_length: Option<u64>,
+
+    /// Parse input lines on this many threads at once [default: number of CPUs]
+    #[arg(long, value_name = "N", env = "BLAZE_PARSE_WORKERS")]
+    pub parse_workers: Option<usize>,
+
+    /// Send each distinct request once, reusing its response for duplicates
+    #[arg(long, env = "BLAZE_DEDUP")]
+    pub dedup: bool,
+
+    /// Maximum number of responses kept for deduplication
+    #[arg(long, env = "BLAZE_DEDUP_CACHE_SIZE")]
+    pub dedup_cache_size: Option<usize>,
+
+    /// Cache successful responses in this directory and answer identical requests from it
+    #[arg(long, value_name = "DIR", env = "BLAZE_CACHE_DIR")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Ignore cached responses older than this (e.g. 1h) [default: 24h]
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, env = "BLAZE_CACHE_TTL")]
+    pub cache_ttl: Option<Duration>,
+
+    /// Neither read nor write the response cache, even if a cache directory is configured
+    #[arg(long, env = "BLAZE_NO_CACHE")]
+    pub no_cache: bool,
+
+    /// Abort the run once this many requests have failed
+    #[arg(long, env = "BLAZE_MAX_FAILURES")]
+    pub max_failures: Option<u64>,
+
+    /// Abort the run once this fraction of requests has failed (0.0-1.0)
+    #[arg(long, env = "BLAZE_MAX_FAILURE_RATE")]
+    pub max_failure_rate: Option<f64>,
+
+    /// Requests read ahead to admit the highest priority first (1 keeps input order)
+    #[arg(long, value_name = "N", env = "BLAZE_PRIORITY_WINDOW")]
+    pub priority_window: Option<usize>,
+
+    /// Cancel requests still in flight this long after the input runs out, failing them as timeouts (e.g. 120s)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, env = "BLAZE_DRAIN_TIMEOUT")]
+    pub drain_timeout: Option<Duration>,
+
+    /// Stop taking new requests once the run has taken this long, finishing like Ctrl-C (e.g. 2h)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, env = "BLAZE_MAX_DURATION")]
+    pub max_duration: Option<Duration>,
+
+    /// Average the live requests per second over this window (e.g. 5s) [default: 1s]
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, env = "BLAZE_RPS_WINDOW")]
+    pub rps_window: Option<Duration>,
+
+    /// Write results in input order, holding completed ones until those before them are written
+    #[arg(long, env = "BLAZE_ORDERED_OUTPUT")]
+    pub ordered_output: bool,
+
+    /// With --ordered-output, requests that may be sent or held ahead of the oldest unwritten one [default: 10000]
+    #[arg(long, value_name = "N", env = "BLAZE_REORDER_WINDOW")]
+    pub reorder_window: Option<usize>,
+
+    /// Pass over this many requests at the start of the input, keeping their line numbers
+    #[arg(long, value_name = "N", env = "BLAZE_SKIP")]
+    pub skip: Option<usize>,
+
+    /// Process at most this many requests, after any skipped
+    #[arg(long, value_name = "N", env = "BLAZE_TAKE")]
+    pub take: Option<usize>,
+
+    /// Flush the output and errors files after every N results, so they survive a crash
+    #[arg(long, value_name = "N", env = "BLAZE_FLUSH_EVERY")]
+    pub flush_every: Option<usize>,
+
+    /// Flush the output and errors files at this interval (e.g. 5s), so they survive a crash
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, env = "BLAZE_FLUSH_INTERVAL")]
+    pub flush_interval: Option<Duration>,
+
+    /// Roll the output and errors files over to numbered files once one reaches this size (e.g. 1GB)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, env = "BLAZE_OUTPUT_ROTATE_SIZE")]
+    pub output_rotate_size: Option<u64>,
+
+    /// Roll the output and errors files over to numbered files once one holds N lines
+    #[arg(long, value_name = "N", env = "BLAZE_OUTPUT_ROTATE_LINES")]
+    pub output_rotate_lines: Option<u64>,
+
+    /// Write only this fraction of successful responses to the output file (0.0-1.0), for spot checks
+    #[arg(long, value_name = "FRACTION", env = "BLAZE_SAMPLE_OUTPUT")]
+    pub sample_output: Option<f64>,
+
+    /// Only count results, writing neither output nor errors, to benchmark raw request throughput
+    #[arg(long, env = "BLAZE_COUNT_ONLY", conflicts_with_all = ["output", "resume"])]
+    pub count_only: bool,
+
+    /// Send at most N requests to each endpoint, ending the run once all have taken theirs
+    #[arg(long, value_name = "N", env = "BLAZE_LIMIT_PER_ENDPOINT")]
+    pub limit_per_endpoint: Option<u64>,
+
+    /// Write a JSON report of the run to this path
+    #[arg(long, env = "BLAZE_REPORT")]
+    pub report: Option<PathBuf>,
+
+    /// Write the latency histograms of the run, overall and per endpoint, to this JSON file
+    #[arg(long, value_name = "PATH", env = "BLAZE_LATENCY_HISTOGRAM")]
+    pub latency_histogram: Option<PathBuf>,
+
+    /// Start from the endpoint health saved in this file, and save it there when the run ends
+    #[arg(long, value_name = "PATH", env = "BLAZE_HEALTH_FILE")]
+    pub health_file: Option<PathBuf>,
+
+    /// Ignore saved endpoint health older than this (e.g. 5m) [default: 10m]
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, env = "BLAZE_HEALTH_TTL")]
+    pub health_ttl: Option<Duration>,
+
+    /// Write only these response fields, as [KEY=]PATH (e.g. content=choices[0].message.content)
+    #[arg(
+        long,
+        value_name = "[KEY=]PATH",
+        env = "BLAZE_OUTPUT_JSONPATH",
+        value_delimiter = ','
+    )]
+    pub output_jsonpath: Vec<String>,
+
+    /// Format of the output file; csv and parquet write one column per --output-jsonpath key [default: jsonl]
+    #[arg(long, value_name = "FORMAT", value_enum, env = "BLAZE_OUTPUT_FORMAT")]
+    pub output_format: Option<OutputFormat>,
+
+    /// Serve Prometheus metrics at /metrics on this address (e.g. 127.0.0.1:9100)
+    #[cfg(feature = "metrics")]
+    #[arg(long, env = "BLAZE_METRICS_ADDR")]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Export traces over OTLP to the collector set by the standard OTEL environment variables
+    #[cfg(feature = "otel")]
+    #[arg(long, env = "BLAZE_OTEL")]
+    pub otel: bool,
+}
+
+/// Subcommands of the CLI.
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Parse every request of the input without sending anything, listing malformed lines
+    Check {
+        /// List at most this many malformed requests per file
+        #[arg(long, value_name = "N", default_value = "100")]
+        max_errors: usize,
+    },
+    /// Send the requests in errors files of earlier runs again, keeping their line numbers
+    Replay {
+        /// Errors files to replay, instead of --input
+        #[arg(long = "errors", value_name = "FILE", required = true, num_args = 1.., value_delimiter = ',')]
+        files: Vec<PathBuf>,
+    },
+    /// Write a starter config file with a placeholder endpoint and the default settings
+    Init {
+        /// Where to write the config
+        #[arg(value_name = "FILE", default_value = "config.json")]
+        path: PathBuf,
+
+        /// Overwrite the file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
 }
 
 impl Args {
@@ -80,10 +392,171 @@ impl Args {
     pub fn parse_args() -> Self {
         Self::parse()
     }
+
+    /// Expand the `--input` values into input files, in the order given.
+    ///
+    /// Values containing `*`, `?` or `[` are glob patterns, each expanding to
+    /// its matches in sorted order. Other values are kept as they are. The
+    /// `replay` command reads the errors files it is given instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::InvalidConfig`] if no input is given, if a
+    /// pattern is malformed or matches no files, or if a replayed errors file
+    /// is also where new errors would be written.
+    pub fn input_paths(&self) -> Result<Vec<PathBuf>> {
+        if let Some(Command::Replay { files }) = &self.command {
+            if !self.input.is_empty() {
+                return Err(BlazeError::InvalidConfig(
+                    "replay reads the errors files given to it, not --input".to_string(),
+                ));
+            }
+            if files.iter().any(|file| is_same_file(file, &self.errors)) {
+                return Err(BlazeError::InvalidConfig(format!(
+                    "cannot replay {} while writing new errors to it, pass another --errors before replay",
+                    self.errors.display()
+                )));
+            }
+            return Ok(files.clone());
+        }
+        // Global arguments can't be required, so clap leaves this check to us
+        if self.input.is_empty() {
+            return Err(BlazeError::InvalidConfig(
+                "no input given, pass it with --input".to_string(),
+            ));
+        }
+        let mut paths = Vec::new();
+        for input in &self.input {
+            let pattern = input.to_string_lossy();
+            if !pattern.contains(['*', '?', '[']) {
+                paths.push(input.clone());
+                continue;
+            }
+            let matches = glob::glob(&pattern)
+                .map_err(|e| {
+                    BlazeError::InvalidConfig(format!("invalid input pattern '{pattern}': {e}"))
+                })?
+                .filter_map(std::result::Result::ok)
+                .collect::<Vec<_>>();
+            if matches.is_empty() {
+                return Err(BlazeError::InvalidConfig(format!(
+                    "input pattern '{pattern}' matched no files"
+                )));
+            }
+            paths.extend(matches);
+        }
+        Ok(paths)
+    }
+
+    /// Map `--output-jsonpath` values to output keys, keying bare paths by themselves.
+    fn response_projection(&self) -> BTreeMap<String, String> {
+        self.output_jsonpath
+            .iter()
+            .map(|spec| match spec.split_once('=') {
+                Some((key, path)) => (key.to_string(), path.to_string()),
+                None => (spec.clone(), spec.clone()),
+            })
+            .collect()
+    }
+
+    /// Override the processing settings of a config file with any given on the command line.
+    fn override_processing(&self, processing: &mut ProcessingConfig) {
+        processing.resume |= self.resume;
+        processing.requeue &= !self.no_requeue;
+        processing.reject_blank_input &= !self.allow_blank_input;
+        processing.max_line_length = self.max_line_length.unwrap_or(processing.max_line_length);
+        processing.parse_workers = self.parse_workers.or(processing.parse_workers);
+        processing.dedup |= self.dedup;
+        processing.log_outcomes |= self.log_outcomes;
+        processing.capture_request |= self.capture_request;
+        processing.dedup_cache_size = self.dedup_cache_size.unwrap_or(processing.dedup_cache_size);
+        if self.cache_dir.is_some() {
+            processing.cache_dir.clone_from(&self.cache_dir);
+        }
+        if self.no_cache {
+            processing.cache_dir = None;
+        }
+        processing.cache_ttl = self.cache_ttl.unwrap_or(processing.cache_ttl);
+        processing.max_failures = self.max_failures.or(processing.max_failures);
+        processing.max_failure_rate = self.max_failure_rate.or(processing.max_failure_rate);
+        processing.priority_window = self.priority_window.unwrap_or(processing.priority_window);
+        processing.drain_timeout = self.drain_timeout.or(processing.drain_timeout);
+        processing.max_duration = self.max_duration.or(processing.max_duration);
+        processing.rps_window = self.rps_window.unwrap_or(processing.rps_window);
+        processing.ordered_output |= self.ordered_output;
+        processing.reorder_window = self.reorder_window.unwrap_or(processing.reorder_window);
+        processing.skip = self.skip.unwrap_or(processing.skip);
+        processing.take = self.take.or(processing.take);
+        processing.flush_every = self.flush_every.or(processing.flush_every);
+        processing.flush_interval = self.flush_interval.or(processing.flush_interval);
+        processing.output_rotate_size = self.output_rotate_size.or(processing.output_rotate_size);
+        processing.output_rotate_lines =
+            self.output_rotate_lines.or(processing.output_rotate_lines);
+        processing.sample_output = self.sample_output.or(processing.sample_output);
+        processing.count_only |= self.count_only;
+        if self.report.is_some() {
+            processing.report.clone_from(&self.report);
+        }
+        if self.latency_histogram.is_some() {
+            processing
+                .latency_histogram
+                .clone_from(&self.latency_histogram);
+        }
+        if self.health_file.is_some() {
+            processing.health_file.clone_from(&self.health_file);
+        }
+        processing.health_ttl = self.health_ttl.unwrap_or(processing.health_ttl);
+        if !self.output_jsonpath.is_empty() {
+            processing.response_projection = self.response_projection();
+        }
+        processing.output_format = self.output_format.unwrap_or(processing.output_format);
+        #[cfg(feature = "metrics")]
+        if self.metrics_addr.is_some() {
+            processing.metrics_addr = self.metrics_addr;
+        }
+    }
+
+    /// Cap the requests of every endpoint if a limit is given on the command line.
+    fn override_endpoint_limits(&self, endpoints: &mut [EndpointConfig]) {
+        if let Some(limit) = self.limit_per_endpoint {
+            for endpoint in endpoints {
+                endpoint.max_requests = Some(limit);
+            }
+        }
+    }
+
+    /// Replace the request tagging options with any given on the command line.
+    fn override_request_tags(&self, request: &mut RequestConfig) {
+        if let Some(user_agent) = &self.user_agent {
+            request.user_agent.clone_from(user_agent);
+        }
+        if self.request_id_header.is_some() {
+            request
+                .request_id_header
+                .clone_from(&self.request_id_header);
+        }
+    }
+
+    /// Replace the retry attempts, status lists, budget and endpoint switching with any given on the command line.
+    fn override_retry(&self, retry: &mut RetryConfig) {
+        retry.max_attempts = self.max_attempts.unwrap_or(retry.max_attempts);
+        retry.budget_ratio = self.retry_budget.unwrap_or(retry.budget_ratio);
+        retry.switch_endpoints |= self.retry_other_endpoints;
+        retry.connection_max_attempts = self
+            .connection_max_attempts
+            .or(retry.connection_max_attempts);
+        retry.http_max_attempts = self.http_max_attempts.or(retry.http_max_attempts);
+        if let Some(statuses) = &self.retryable_statuses {
+            retry.retryable_statuses.clone_from(statuses);
+        }
+        if let Some(statuses) = &self.non_retryable_statuses {
+            retry.non_retryable_statuses.clone_from(statuses);
+        }
+    }
 }
 
 /// Configuration for a single API endpoint.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct EndpointConfig {
     /// The endpoint URL.
     pub url: String,
@@ -96,105 +569,1365 @@ pub struct EndpointConfig {
     #[serde(default)]
     pub api_key: Option<String>,
 
+    /// More keys for the same provider, rotated with `api_key` to multiply
+    /// the rate quota.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub api_keys: Vec<String>,
+
+    /// How a key is picked from the pool for each request.
+    #[serde(default)]
+    pub key_rotation: KeyRotation,
+
     /// Model identifier (for LLM endpoints).
     #[serde(default)]
     pub model: Option<String>,
 
+    /// Body shape built from `input` when a request has no `body` of its own.
+    #[serde(default)]
+    pub request_format: RequestFormat,
+
+    /// How bodies are encoded: `json`, `form` or `text`.
+    #[serde(default)]
+    pub body_encoding: BodyEncoding,
+
+    /// Content type of request bodies, overriding the one of `body_encoding`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+
+    /// Ask for a streamed (server-sent events) response and assemble its chunks.
+    #[serde(default)]
+    pub stream: bool,
+
+    /// Requests sent together as one JSON array body, for endpoints that
+    /// accept arrays and answer with one result per element (1 = no batching).
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+
+    /// How long a batch waits to fill before it is sent anyway.
+    #[serde(with = "humantime_serde", default = "default_batch_wait")]
+    pub batch_wait: Duration,
+
     /// Maximum concurrent requests to this endpoint.
     #[serde(default = "default_max_concurrent")]
     pub max_concurrent: u32,
+
+    /// Requests the endpoint takes in the whole run, counting a request and
+    /// its retries once. Unset sends it as many as its weight gets it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_requests: Option<u64>,
+
+    /// Consecutive failures that mark the endpoint unhealthy, opening its circuit.
+    #[serde(default = "default_unhealthy_after")]
+    pub unhealthy_after: u32,
+
+    /// Also mark the endpoint unhealthy once this fraction of its last
+    /// `failure_rate_window` requests has failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unhealthy_failure_rate: Option<f64>,
+
+    /// Number of recent requests `unhealthy_failure_rate` is measured over.
+    #[serde(default = "default_failure_rate_window")]
+    pub failure_rate_window: usize,
+
+    /// Header used to send the API key.
+    #[serde(default = "default_auth_header")]
+    pub auth_header: String,
+
+    /// Scheme prefixed to the API key (empty to send the raw key).
+    #[serde(default = "default_auth_scheme")]
+    pub auth_scheme: String,
+
+    /// Headers sent with every request to this endpoint, such as an
+    /// organization or API version. A request's own headers take precedence.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+
+    /// Request timeout for this endpoint, overriding `request.timeout`.
+    #[serde(
+        with = "humantime_serde::option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timeout: Option<Duration>,
+
+    /// Statuses that count as a successful response, such as `[200, 201]`
+    /// for a gateway whose 202 means the job is still queued. Any other
+    /// status fails the attempt like an HTTP error. Empty accepts any 2xx.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub success_statuses: Vec<u16>,
+
+    /// Credentials added to every request, applied in order after `api_key`.
+    /// Takes a single entry or a list, such as basic auth to a gateway plus
+    /// a provider key in a header of its own.
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub auth: Vec<AuthConfig>,
+
+    /// Price per 1,000 prompt tokens, for cost estimates.
+    #[serde(default)]
+    pub input_price_per_1k: f64,
+
+    /// Price per 1,000 completion tokens, for cost estimates.
+    #[serde(default)]
+    pub output_price_per_1k: f64,
+}
+
+impl Default for EndpointConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            weight: default_weight(),
+            api_key: None,
+            api_keys: Vec::new(),
+            key_rotation: KeyRotation::default(),
+            model: None,
+            request_format: RequestFormat::default(),
+            body_encoding: BodyEncoding::default(),
+            content_type: None,
+            stream: false,
+            batch_size: default_batch_size(),
+            batch_wait: default_batch_wait(),
+            max_concurrent: default_max_concurrent(),
+            max_requests: None,
+            unhealthy_after: default_unhealthy_after(),
+            unhealthy_failure_rate: None,
+            failure_rate_window: default_failure_rate_window(),
+            auth_header: default_auth_header(),
+            auth_scheme: default_auth_scheme(),
+            headers: BTreeMap::new(),
+            timeout: None,
+            success_statuses: Vec::new(),
+            auth: Vec::new(),
+            input_price_per_1k: 0.0,
+            output_price_per_1k: 0.0,
+        }
+    }
+}
+
+impl EndpointConfig {
+    /// Validate the endpoint's settings.
+    fn validate(&self) -> Result<()> {
+        if self.url.is_empty() {
+            return Err(BlazeError::InvalidConfig(
+                "endpoint URL cannot be empty".to_string(),
+            ));
+        }
+        if self.weight == 0 {
+            return Err(BlazeError::InvalidConfig(
+                "endpoint weight must be greater than 0".to_string(),
+            ));
+        }
+        if self.max_concurrent == 0 {
+            return Err(BlazeError::InvalidConfig(
+                "endpoint max_concurrent must be greater than 0".to_string(),
+            ));
+        }
+        if self.unhealthy_after == 0 {
+            return Err(BlazeError::InvalidConfig(
+                "endpoint unhealthy_after must be at least 1".to_string(),
+            ));
+        }
+        if self.max_requests == Some(0) {
+            return Err(BlazeError::InvalidConfig(
+                "endpoint max_requests must be at least 1".to_string(),
+            ));
+        }
+        if let Some(rate) = self.unhealthy_failure_rate {
+            if !(rate > 0.0 && rate <= 1.0) {
+                return Err(BlazeError::InvalidConfig(
+                    "endpoint unhealthy_failure_rate must be greater than 0 and at most 1"
+                        .to_string(),
+                ));
+            }
+            if self.failure_rate_window == 0 {
+                return Err(BlazeError::InvalidConfig(
+                    "endpoint failure_rate_window must be at least 1".to_string(),
+                ));
+            }
+        }
+        if self.batch_size == 0 {
+            return Err(BlazeError::InvalidConfig(
+                "endpoint batch_size must be at least 1".to_string(),
+            ));
+        }
+        if self.batch_size > 1 && (self.stream || self.body_encoding != BodyEncoding::Json) {
+            return Err(BlazeError::InvalidConfig(format!(
+                "endpoint '{}' can only batch JSON bodies without streaming",
+                self.url
+            )));
+        }
+        if reqwest::header::HeaderName::from_bytes(self.auth_header.as_bytes()).is_err() {
+            return Err(BlazeError::InvalidConfig(format!(
+                "invalid auth header name '{}'",
+                self.auth_header
+            )));
+        }
+        if let Some(status) = self
+            .success_statuses
+            .iter()
+            .find(|status| !(100..=599).contains(*status))
+        {
+            return Err(BlazeError::InvalidConfig(format!(
+                "invalid success status {status} for endpoint '{}'",
+                self.url
+            )));
+        }
+        if let Some(content_type) = &self.content_type {
+            if reqwest::header::HeaderValue::from_str(content_type).is_err() {
+                return Err(BlazeError::InvalidConfig(format!(
+                    "invalid content type '{content_type}' for endpoint '{}'",
+                    self.url
+                )));
+            }
+        }
+        for (name, value) in &self.headers {
+            if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err()
+                || reqwest::header::HeaderValue::from_str(value).is_err()
+            {
+                return Err(BlazeError::InvalidConfig(format!(
+                    "invalid header '{name}' for endpoint '{}'",
+                    self.url
+                )));
+            }
+        }
+        self.validate_auth()
+    }
+
+    /// Validate the endpoint's `auth` entries.
+    fn validate_auth(&self) -> Result<()> {
+        let invalid = |reason: &str| {
+            Err(BlazeError::InvalidConfig(format!(
+                "endpoint '{}' {reason}",
+                self.url
+            )))
+        };
+        let oauth_count = self
+            .auth
+            .iter()
+            .filter(|auth| matches!(auth, AuthConfig::OAuth2(_)))
+            .count();
+        if oauth_count > 1 {
+            return invalid("sets more than one oauth2 auth");
+        }
+        let has_keys = self.api_key.is_some() || !self.api_keys.is_empty();
+        if oauth_count > 0 && has_keys {
+            return invalid("sets both api_key and oauth2 auth");
+        }
+        if self.auth.contains(&AuthConfig::None) && (self.auth.len() > 1 || has_keys) {
+            return invalid("combines auth type none with other credentials");
+        }
+
+        for auth in &self.auth {
+            match auth {
+                AuthConfig::OAuth2(oauth) => {
+                    if reqwest::Url::parse(&oauth.token_url).is_err() {
+                        return Err(BlazeError::InvalidConfig(format!(
+                            "invalid oauth2 token URL '{}'",
+                            oauth.token_url
+                        )));
+                    }
+                }
+                AuthConfig::Header { name, value } => {
+                    if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err()
+                        || reqwest::header::HeaderValue::from_str(value).is_err()
+                    {
+                        return invalid(&format!("has an invalid auth header '{name}'"));
+                    }
+                }
+                AuthConfig::Bearer { token } => {
+                    if reqwest::header::HeaderValue::from_str(token).is_err() {
+                        return invalid("has an invalid bearer token");
+                    }
+                }
+                AuthConfig::None | AuthConfig::Basic { .. } => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check whether two paths name the same file, comparing them as given if
+/// either does not exist yet.
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Replace `${VAR}` references in every string of a config with the
+/// environment variable's value, skipping the body template.
+///
+/// Returns the name of the first variable that is not set.
+fn expand_env(value: &mut serde_json::Value) -> std::result::Result<(), String> {
+    match value {
+        serde_json::Value::String(text) if text.contains("${") => {
+            let mut expanded = String::with_capacity(text.len());
+            let mut rest = text.as_str();
+            while let Some(start) = rest.find("${") {
+                let Some(len) = rest[start + 2..].find('}') else {
+                    break;
+                };
+                let name = &rest[start + 2..start + 2 + len];
+                expanded.push_str(&rest[..start]);
+                expanded.push_str(&std::env::var(name).map_err(|_| name.to_string())?);
+                rest = &rest[start + 3 + len..];
+            }
+            expanded.push_str(rest);
+            *text = expanded;
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                expand_env(item)?;
+            }
+        }
+        serde_json::Value::Object(fields) => {
+            for (key, field) in fields {
+                if key != "body_template" {
+                    expand_env(field)?;
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Accept either a single value or a list of them.
+fn one_or_many<'de, D, T>(deserializer: D) -> std::result::Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+fn default_latency_decay() -> f64 {
+    DEFAULT_LATENCY_DECAY
+}
+
+pub(crate) fn default_hash_key() -> String {
+    "user_id".to_string()
 }
 
 fn default_weight() -> u32 {
     1
 }
 
-fn default_max_concurrent() -> u32 {
-    100
-}
+fn default_max_concurrent() -> u32 {
+    100
+}
+
+fn default_unhealthy_after() -> u32 {
+    3
+}
+
+fn default_failure_rate_window() -> usize {
+    20
+}
+
+fn default_batch_size() -> usize {
+    1
+}
+
+fn default_batch_wait() -> Duration {
+    Duration::from_millis(10)
+}
+
+fn default_auth_header() -> String {
+    "Authorization".to_string()
+}
+
+fn default_auth_scheme() -> String {
+    "Bearer".to_string()
+}
+
+/// Full application configuration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    /// API endpoints for load balancing.
+    pub endpoints: Vec<EndpointConfig>,
+
+    /// How requests are distributed across endpoints.
+    #[serde(default)]
+    pub load_balancing: LoadBalanceStrategy,
+
+    /// Weight of the newest sample in endpoint latency averages.
+    #[serde(default = "default_latency_decay")]
+    pub latency_decay: f64,
+
+    /// Request metadata field used as the key for consistent hashing.
+    #[serde(default = "default_hash_key")]
+    pub hash_key: String,
+
+    /// Seed for load balancing and backoff jitter. Unset draws fresh randomness each run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+
+    /// Request settings.
+    #[serde(default)]
+    pub request: RequestConfig,
+
+    /// Retry settings.
+    #[serde(default)]
+    pub retry: RetryConfig,
+
+    /// Batch processing settings.
+    #[serde(default)]
+    pub processing: ProcessingConfig,
+}
+
+/// Request-specific configuration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct RequestConfig {
+    /// Request timeout, unless the endpoint sets its own.
+    #[serde(with = "humantime_serde", default = "default_timeout")]
+    pub timeout: Duration,
+
+    /// Maximum requests per `rate_period`.
+    #[serde(default = "default_rate")]
+    pub rate_limit: u32,
+
+    /// Period `rate_limit` is counted over. Requests are let through evenly
+    /// across it, one every `rate_period / rate_limit`.
+    #[serde(with = "humantime_serde", default = "default_rate_period")]
+    pub rate_period: Duration,
+
+    /// Requests that may go out at once when the limiter has been idle,
+    /// unused capacity building up to this many. Defaults to `rate_limit`,
+    /// a full period's worth.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_burst: Option<u32>,
+
+    /// Period over which the rate ramps up to `rate_limit`. Zero starts at the full rate.
+    #[serde(with = "humantime_serde", default)]
+    pub ramp_up: Duration,
+
+    /// Delay after which a slow request is hedged to a second endpoint. Unset disables hedging.
+    #[serde(
+        with = "humantime_serde::option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub hedge_after: Option<Duration>,
+
+    /// Number of concurrent workers (defaults to the endpoints' combined `max_concurrent`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workers: Option<usize>,
+
+    /// Adapt the number of concurrent requests to observed latency and errors.
+    #[serde(default)]
+    pub adaptive_concurrency: bool,
+
+    /// Upper bound on concurrent requests when adapting.
+    #[serde(default = "default_max_workers")]
+    pub max_workers: usize,
+
+    /// Request body template with `${field}` placeholders filled from each request.
+    #[serde(default)]
+    pub body_template: Option<serde_json::Value>,
+
+    /// Fail requests whose template fields are missing instead of leaving the placeholder.
+    #[serde(default)]
+    pub strict_template: bool,
+
+    /// Rules a 2xx response body must pass to count as a success.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub validation: Vec<ValidationRule>,
+
+    /// Largest response body to read, in bytes. Longer responses fail instead of being buffered.
+    #[serde(default = "default_max_response_bytes")]
+    pub max_response_bytes: usize,
+
+    /// Proxy to route requests through. Falls back to `HTTPS_PROXY`/`NO_PROXY` when unset.
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+
+    /// How long idle pooled connections are kept open.
+    #[serde(with = "humantime_serde", default = "default_pool_idle_timeout")]
+    pub pool_idle_timeout: Duration,
+
+    /// Maximum idle connections kept per host (defaults to `workers`).
+    #[serde(default)]
+    pub pool_max_idle_per_host: Option<usize>,
+
+    /// How often to ping HTTP/2 connections, so ones dropped along the way
+    /// are noticed before a request is sent on them. `null` sends no pings.
+    #[serde(
+        with = "humantime_serde::option",
+        default = "default_http2_keep_alive_interval"
+    )]
+    pub http2_keep_alive_interval: Option<Duration>,
+
+    /// How long a ping may go unanswered before its connection is closed.
+    #[serde(with = "humantime_serde", default = "default_http2_keep_alive_timeout")]
+    pub http2_keep_alive_timeout: Duration,
+
+    /// Ping connections with no requests in flight too, keeping idle pooled
+    /// connections alive between bursts.
+    #[serde(default = "default_http2_keep_alive_while_idle")]
+    pub http2_keep_alive_while_idle: bool,
+
+    /// Open up to `pool_max_idle_per_host` connections to each endpoint
+    /// before the run starts, so the first requests don't pay for the
+    /// handshakes.
+    #[serde(default)]
+    pub prewarm: bool,
+
+    /// Skip TLS certificate verification.
+    #[serde(default)]
+    pub accept_invalid_certs: bool,
+
+    /// HTTP version to speak to endpoints.
+    #[serde(default)]
+    pub http_version: HttpVersion,
+
+    /// `User-Agent` sent with every request.
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+
+    /// Header to send a unique id for each request in, such as `X-Request-Id`.
+    /// The id is also written to the request's result or error line.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id_header: Option<String>,
+}
+
+/// A condition a successful response body must meet.
+///
+/// Paths use the same syntax as output projections, such as
+/// `choices[0].message.content`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum ValidationRule {
+    /// The field must be present.
+    Present {
+        /// Path to the field.
+        path: String,
+    },
+    /// The field must be missing, such as an `error` object.
+    Absent {
+        /// Path to the field.
+        path: String,
+    },
+    /// The field must not equal a value. A missing field passes.
+    NotEqual {
+        /// Path to the field.
+        path: String,
+        /// Value the field must not have.
+        value: serde_json::Value,
+    },
+}
+
+/// HTTP protocol version used for requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum HttpVersion {
+    /// Negotiate HTTP/2 via ALPN over TLS, HTTP/1.1 otherwise.
+    #[default]
+    Auto,
+    /// Always use HTTP/1.1.
+    #[value(name = "http1")]
+    Http1,
+    /// Always use HTTP/2, without negotiation.
+    #[value(name = "http2")]
+    Http2,
+}
+
+/// Format of the output file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// One JSON object per line.
+    #[default]
+    Jsonl,
+    /// Comma-separated values with a header row, one row per response.
+    Csv,
+    /// An Apache Parquet file, written in row groups.
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl OutputFormat {
+    /// Check whether an output file in this format can be resumed, which
+    /// appends to it, or rotated. A Parquet file is only complete once its
+    /// footer is written.
+    #[must_use]
+    pub fn is_resumable(self) -> bool {
+        match self {
+            Self::Jsonl | Self::Csv => true,
+            #[cfg(feature = "parquet")]
+            Self::Parquet => false,
+        }
+    }
+}
+
+/// Credentials added to an endpoint's requests.
+///
+/// Secrets are masked in its `Debug` output.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AuthConfig {
+    /// No credentials, for open endpoints.
+    None,
+
+    /// A bearer token, sent as `Authorization: Bearer <token>`.
+    Bearer {
+        /// The token.
+        token: String,
+    },
+
+    /// HTTP Basic auth, sent as `Authorization: Basic <credentials>`.
+    Basic {
+        /// Username.
+        username: String,
+        /// Password, if any.
+        #[serde(default)]
+        password: Option<String>,
+    },
+
+    /// A credential sent in a header of its own, such as `x-api-key`.
+    Header {
+        /// Header name.
+        name: String,
+        /// Header value, sent as is.
+        value: String,
+    },
+
+    /// OAuth client credentials grant.
+    OAuth2(OAuth2Config),
+}
+
+impl std::fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => f.write_str("None"),
+            Self::Bearer { .. } => f.debug_struct("Bearer").field("token", &MASK).finish(),
+            Self::Basic { username, password } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &password.as_ref().map(|_| MASK))
+                .finish(),
+            Self::Header { name, .. } => f
+                .debug_struct("Header")
+                .field("name", name)
+                .field("value", &MASK)
+                .finish(),
+            Self::OAuth2(oauth) => f.debug_tuple("OAuth2").field(oauth).finish(),
+        }
+    }
+}
+
+/// OAuth client credentials used to fetch access tokens.
+///
+/// Tokens are sent in the endpoint's `auth_header` with its `auth_scheme`,
+/// like a static API key. The client secret is masked in its `Debug` output.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct OAuth2Config {
+    /// URL of the authorization server's token endpoint.
+    pub token_url: String,
+
+    /// OAuth client ID.
+    pub client_id: String,
+
+    /// OAuth client secret.
+    pub client_secret: String,
+
+    /// Scopes to request, if any.
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+impl std::fmt::Debug for OAuth2Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OAuth2Config")
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .field("client_secret", &MASK)
+            .field("scopes", &self.scopes)
+            .finish()
+    }
+}
+
+/// Proxy configuration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    /// Proxy URL (`http://`, `https://` or `socks5://`).
+    pub url: String,
+
+    /// Username for proxy basic auth.
+    #[serde(default)]
+    pub username: Option<String>,
+
+    /// Password for proxy basic auth.
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// Comma-separated hosts to connect to directly. Defaults to `NO_PROXY`.
+    #[serde(default)]
+    pub no_proxy: Option<String>,
+}
+
+impl ProxyConfig {
+    /// Create a proxy configuration for a URL without credentials.
+    #[must_use]
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            username: None,
+            password: None,
+            no_proxy: None,
+        }
+    }
+}
+
+impl Default for RequestConfig {
+    fn default() -> Self {
+        Self {
+            timeout: default_timeout(),
+            rate_limit: default_rate(),
+            rate_period: default_rate_period(),
+            rate_burst: None,
+            ramp_up: Duration::ZERO,
+            hedge_after: None,
+            workers: None,
+            adaptive_concurrency: false,
+            max_workers: default_max_workers(),
+            body_template: None,
+            strict_template: false,
+            validation: Vec::new(),
+            max_response_bytes: default_max_response_bytes(),
+            proxy: None,
+            pool_idle_timeout: default_pool_idle_timeout(),
+            pool_max_idle_per_host: None,
+            http2_keep_alive_interval: default_http2_keep_alive_interval(),
+            http2_keep_alive_timeout: default_http2_keep_alive_timeout(),
+            http2_keep_alive_while_idle: default_http2_keep_alive_while_idle(),
+            prewarm: false,
+            accept_invalid_certs: false,
+            http_version: HttpVersion::default(),
+            user_agent: default_user_agent(),
+            request_id_header: None,
+        }
+    }
+}
+
+impl RequestConfig {
+    /// Validate the request settings.
+    fn validate(&self) -> Result<()> {
+        if self.workers == Some(0) {
+            return Err(BlazeError::InvalidConfig(
+                "workers must be greater than 0".to_string(),
+            ));
+        }
+
+        if reqwest::header::HeaderValue::from_str(&self.user_agent).is_err() {
+            return Err(BlazeError::InvalidConfig(format!(
+                "invalid user_agent '{}'",
+                self.user_agent
+            )));
+        }
+        if let Some(name) = &self.request_id_header {
+            if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                return Err(BlazeError::InvalidConfig(format!(
+                    "invalid request_id_header '{name}'"
+                )));
+            }
+        }
+
+        if self.rate_period.is_zero() || self.rate_burst == Some(0) {
+            return Err(BlazeError::InvalidConfig(
+                "rate_period and rate_burst must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.max_response_bytes == 0 {
+            return Err(BlazeError::InvalidConfig(
+                "max_response_bytes must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.http2_keep_alive_interval == Some(Duration::ZERO)
+            || self.http2_keep_alive_timeout.is_zero()
+        {
+            return Err(BlazeError::InvalidConfig(
+                "http2_keep_alive_interval and http2_keep_alive_timeout must be greater than 0"
+                    .to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Build the rate limiter's quota: `rate_limit` requests per `rate_period`,
+    /// with room for `rate_burst` at once.
+    #[must_use]
+    pub fn rate_quota(&self) -> Quota {
+        let rate = NonZeroU32::new(self.rate_limit).unwrap_or(NonZeroU32::MIN);
+        let burst = self.rate_burst.and_then(NonZeroU32::new).unwrap_or(rate);
+        Quota::with_period(self.rate_period / rate.get())
+            // Under a nanosecond apart is as good as no limit
+            .unwrap_or_else(|| Quota::per_second(rate))
+            .allow_burst(burst)
+    }
+
+    /// Get the sustained rate in requests per second.
+    #[must_use]
+    pub fn rate_per_second(&self) -> f64 {
+        f64::from(self.rate_limit.max(1)) / self.rate_period.as_secs_f64()
+    }
+}
+
+fn default_user_agent() -> String {
+    format!("blaze/{}", env!("CARGO_PKG_VERSION"))
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_max_response_bytes() -> usize {
+    16 * 1024 * 1024
+}
+
+fn default_pool_idle_timeout() -> Duration {
+    Duration::from_secs(90)
+}
+
+// serde defaults have the field's type, and `null` turns the pings off
+#[allow(clippy::unnecessary_wraps)]
+fn default_http2_keep_alive_interval() -> Option<Duration> {
+    Some(Duration::from_secs(30))
+}
+
+fn default_http2_keep_alive_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_http2_keep_alive_while_idle() -> bool {
+    true
+}
+
+fn default_rate() -> u32 {
+    1000
+}
+
+fn default_rate_period() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_max_workers() -> usize {
+    500
+}
+
+/// Retry configuration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Attempts per request, including the first; 1 disables retries.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+
+    /// Attempts for requests that fail without a response, such as refused
+    /// or reset connections and timeouts. Defaults to `max_attempts`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection_max_attempts: Option<u32>,
+
+    /// Attempts for requests that get an error response, such as a 500 or a
+    /// 2xx that fails validation. Defaults to `max_attempts`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_max_attempts: Option<u32>,
+
+    /// Initial backoff duration.
+    #[serde(with = "humantime_serde", default = "default_initial_backoff")]
+    pub initial_backoff: Duration,
+
+    /// Maximum backoff duration.
+    #[serde(with = "humantime_serde", default = "default_max_backoff")]
+    pub max_backoff: Duration,
+
+    /// Backoff multiplier.
+    #[serde(default = "default_multiplier")]
+    pub multiplier: f64,
+
+    /// How backoff durations are randomized.
+    #[serde(default)]
+    pub jitter: JitterKind,
+
+    /// HTTP statuses retried and requeued like server errors.
+    #[serde(default)]
+    pub retryable_statuses: Vec<u16>,
+
+    /// HTTP statuses that fail a request immediately, without retries.
+    #[serde(default = "default_non_retryable_statuses")]
+    pub non_retryable_statuses: Vec<u16>,
+
+    /// Retries allowed per successful request over a rolling window, so an
+    /// outage can't multiply the load through retries.
+    #[serde(default = "default_budget_ratio")]
+    pub budget_ratio: f64,
+
+    /// Send each retry to an endpoint the request has not failed on yet,
+    /// picked by the load balancer, instead of the same endpoint.
+    #[serde(default)]
+    pub switch_endpoints: bool,
+}
+
+impl RetryConfig {
+    /// Get the attempts allowed for a request, by whether its last failed
+    /// attempt got a response.
+    #[must_use]
+    pub fn max_attempts_for(&self, responded: bool) -> u32 {
+        let attempts = if responded {
+            self.http_max_attempts
+        } else {
+            self.connection_max_attempts
+        };
+        attempts.unwrap_or(self.max_attempts)
+    }
+
+    /// Check whether a request that got this status should be retried.
+    #[must_use]
+    pub fn is_retryable(&self, status: u16) -> bool {
+        !self.non_retryable_statuses.contains(&status)
+    }
+
+    /// Classify a failure by its HTTP status, honoring the configured status lists.
+    #[must_use]
+    pub fn failure_class(&self, status: Option<u16>) -> FailureClass {
+        match status {
+            Some(code) if self.retryable_statuses.contains(&code) => FailureClass::Transient,
+            Some(code) if self.non_retryable_statuses.contains(&code) => FailureClass::Permanent,
+            _ => FailureClass::from_status(status),
+        }
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            connection_max_attempts: None,
+            http_max_attempts: None,
+            initial_backoff: default_initial_backoff(),
+            max_backoff: default_max_backoff(),
+            multiplier: default_multiplier(),
+            jitter: JitterKind::default(),
+            retryable_statuses: Vec::new(),
+            non_retryable_statuses: default_non_retryable_statuses(),
+            budget_ratio: default_budget_ratio(),
+            switch_endpoints: false,
+        }
+    }
+}
+
+/// Randomization applied to retry backoff, so workers don't retry in lockstep.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JitterKind {
+    /// Wait exactly the computed backoff.
+    None,
+    /// Wait within 25% either side of the computed backoff.
+    #[default]
+    Equal,
+    /// Wait anywhere from zero up to the computed backoff.
+    Full,
+    /// Wait anywhere from the initial backoff up to three times the previous
+    /// wait, ignoring the exponential growth.
+    Decorrelated,
+}
+
+/// Batch processing configuration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct ProcessingConfig {
+    /// Skip requests recorded as completed in the output's checkpoint file.
+    #[serde(default)]
+    pub resume: bool,
+
+    /// Retry transient failures in a second pass after the main run drains.
+    #[serde(default = "default_requeue")]
+    pub requeue: bool,
+
+    /// Write rows with neither `input` nor `body` to the errors file instead of sending them.
+    #[serde(default = "default_reject_blank_input")]
+    pub reject_blank_input: bool,
+
+    /// Longest input line to read, in bytes; longer lines are written to the
+    /// errors file instead. Given as a number of bytes or a size like `"16MB"`.
+    #[serde(
+        default = "default_max_line_length",
+        deserialize_with = "size_serde::deserialize_required"
+    )]
+    pub max_line_length: u64,
+
+    /// Threads parsing input lines at once. Unset uses one per CPU, and 1
+    /// parses on the task reading the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parse_workers: Option<usize>,
+
+    /// Send each distinct request once and reuse its response for duplicates.
+    #[serde(default)]
+    pub dedup: bool,
+
+    /// Maximum number of responses kept in memory for deduplication.
+    #[serde(default = "default_dedup_cache_size")]
+    pub dedup_cache_size: usize,
+
+    /// Directory to cache successful responses in across runs. Requests
+    /// identical to a cached one get its response without an API call.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// How long cached responses are reused.
+    #[serde(with = "humantime_serde", default = "default_cache_ttl")]
+    pub cache_ttl: Duration,
+
+    /// Abort the run once this many requests have failed.
+    #[serde(default)]
+    pub max_failures: Option<u64>,
+
+    /// Abort the run once this fraction of requests has failed.
+    #[serde(default)]
+    pub max_failure_rate: Option<f64>,
+
+    /// Requests read ahead and admitted highest priority first. Larger windows
+    /// reorder across more of the input but hold more requests in memory.
+    #[serde(default = "default_priority_window")]
+    pub priority_window: usize,
+
+    /// How long requests still in flight may take once the input runs out,
+    /// before they are cancelled and failed as timeouts. Unset waits for them.
+    #[serde(
+        with = "humantime_serde::option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub drain_timeout: Option<Duration>,
+
+    /// How long the whole run may take. Once it is up, no new requests are
+    /// sent and in-flight ones finish as on Ctrl-C, so a resumed run picks up
+    /// the rest. Unset runs until the input is done.
+    #[serde(
+        with = "humantime_serde::option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_duration: Option<Duration>,
+
+    /// Window the live requests per second are averaged over. Longer windows
+    /// give a steadier rate in the progress bar.
+    #[serde(with = "humantime_serde", default = "default_rps_window")]
+    pub rps_window: Duration,
+
+    /// Write results in input order instead of completion order. Transient
+    /// failures are then retried in place rather than in a requeue pass.
+    #[serde(default)]
+    pub ordered_output: bool,
+
+    /// With ordered output, how many requests may be in flight or waiting to
+    /// be written at once. Larger windows let more requests pass a slow one
+    /// but hold more responses in memory.
+    #[serde(default = "default_reorder_window")]
+    pub reorder_window: usize,
+
+    /// Requests at the start of the input to pass over without parsing them.
+    /// Counted in requests, so blank lines don't shift the slice.
+    #[serde(default)]
+    pub skip: usize,
+
+    /// Process at most this many requests after those skipped. Results keep
+    /// the line numbers of the requests in the input.
+    #[serde(default)]
+    pub take: Option<usize>,
+
+    /// Flush the output and errors files after this many results. Unset
+    /// leaves results in the write buffers until they fill or the run ends.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flush_every: Option<usize>,
+
+    /// Flush the output and errors files at this interval, if anything was
+    /// written since the last flush.
+    #[serde(
+        with = "humantime_serde::option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub flush_interval: Option<Duration>,
+
+    /// Roll the output and errors files over to numbered files once one
+    /// holds this many bytes, counted before compression. Given as a number
+    /// of bytes or a size like `"1GB"`.
+    #[serde(
+        deserialize_with = "size_serde::deserialize",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub output_rotate_size: Option<u64>,
+
+    /// Roll the output and errors files over to numbered files once one
+    /// holds this many lines.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_rotate_lines: Option<u64>,
+
+    /// Fraction of successful responses written to the output file, picked
+    /// at random. Failures are all written and every request still counts in
+    /// the stats. Unset writes every response.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_output: Option<f64>,
+
+    /// Tally results in the stats without serializing or writing any of
+    /// them, so a benchmark measures requests alone. No output, errors or
+    /// checkpoint file is written.
+    #[serde(default)]
+    pub count_only: bool,
+
+    /// Path to write a JSON report of the run to.
+    #[serde(default)]
+    pub report: Option<PathBuf>,
+
+    /// Path to write the latency histograms of the run to, with the bucket
+    /// bounds and counts overall and for each endpoint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_histogram: Option<PathBuf>,
+
+    /// File to carry endpoint health over between runs in. Each run starts
+    /// from the health saved there and saves its own when it ends.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_file: Option<PathBuf>,
+
+    /// How long saved endpoint health is trusted.
+    #[serde(with = "humantime_serde", default = "default_health_ttl")]
+    pub health_ttl: Duration,
+
+    /// Log the outcome of every completed request, successes included.
+    #[serde(default)]
+    pub log_outcomes: bool,
+
+    /// Record the body and custom headers a failed request was last sent
+    /// with in the errors file, so it can be replayed as sent. Headers that
+    /// carry the endpoint's credentials are masked.
+    #[serde(default)]
+    pub capture_request: bool,
+
+    /// Response fields to write instead of the full response, as output key to path.
+    #[serde(default)]
+    pub response_projection: BTreeMap<String, String>,
+
+    /// Format of the output file. CSV and Parquet files have a column for
+    /// each key of `response_projection`, which they require.
+    #[serde(default)]
+    pub output_format: OutputFormat,
+
+    /// Input fields to rename before parsing, as source field to request field.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub input_fields: BTreeMap<String, String>,
+
+    /// Address to serve Prometheus metrics on while processing.
+    #[cfg(feature = "metrics")]
+    #[serde(default)]
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+impl ProcessingConfig {
+    /// Validate the processing settings.
+    fn validate(&self) -> Result<()> {
+        Projection::new(&self.response_projection)?;
+        FieldMapping::new(&self.input_fields)?;
+
+        if self.rps_window.is_zero() {
+            return Err(BlazeError::InvalidConfig(
+                "rps_window must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.priority_window == 0 {
+            return Err(BlazeError::InvalidConfig(
+                "priority_window must be at least 1".to_string(),
+            ));
+        }
+
+        if self.reorder_window == 0 {
+            return Err(BlazeError::InvalidConfig(
+                "reorder_window must be at least 1".to_string(),
+            ));
+        }
+
+        if self.take == Some(0) {
+            return Err(BlazeError::InvalidConfig(
+                "take must be at least 1".to_string(),
+            ));
+        }
+
+        if self.flush_every == Some(0) {
+            return Err(BlazeError::InvalidConfig(
+                "flush_every must be at least 1".to_string(),
+            ));
+        }
+
+        if self.flush_interval == Some(Duration::ZERO) {
+            return Err(BlazeError::InvalidConfig(
+                "flush_interval must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.max_line_length == 0 {
+            return Err(BlazeError::InvalidConfig(
+                "max_line_length must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.parse_workers == Some(0) {
+            return Err(BlazeError::InvalidConfig(
+                "parse_workers must be at least 1".to_string(),
+            ));
+        }
 
-/// Full application configuration.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Config {
-    /// API endpoints for load balancing.
-    pub endpoints: Vec<EndpointConfig>,
+        if self.output_rotate_size == Some(0) || self.output_rotate_lines == Some(0) {
+            return Err(BlazeError::InvalidConfig(
+                "output_rotate_size and output_rotate_lines must be greater than 0".to_string(),
+            ));
+        }
 
-    /// Request settings.
-    #[serde(default)]
-    pub request: RequestConfig,
+        if self.dedup && self.dedup_cache_size == 0 {
+            return Err(BlazeError::InvalidConfig(
+                "dedup_cache_size must be at least 1".to_string(),
+            ));
+        }
 
-    /// Retry settings.
-    #[serde(default)]
-    pub retry: RetryConfig,
-}
+        if let Some(rate) = self.max_failure_rate {
+            if !(rate > 0.0 && rate <= 1.0) {
+                return Err(BlazeError::InvalidConfig(
+                    "max_failure_rate must be greater than 0 and at most 1".to_string(),
+                ));
+            }
+        }
 
-/// Request-specific configuration.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RequestConfig {
-    /// Request timeout.
-    #[serde(with = "humantime_serde", default = "default_timeout")]
-    pub timeout: Duration,
+        if self.output_format != OutputFormat::Jsonl && self.response_projection.is_empty() {
+            return Err(BlazeError::InvalidConfig(
+                "csv and parquet output need response_projection (or --output-jsonpath) to choose their columns".to_string(),
+            ));
+        }
+        if !self.output_format.is_resumable()
+            && (self.resume
+                || self.output_rotate_size.is_some()
+                || self.output_rotate_lines.is_some())
+        {
+            return Err(BlazeError::InvalidConfig(
+                "parquet output can't be resumed or rotated".to_string(),
+            ));
+        }
 
-    /// Maximum requests per second.
-    #[serde(default = "default_rate")]
-    pub rate_limit: u32,
+        if let Some(rate) = self.sample_output {
+            if !(rate > 0.0 && rate <= 1.0) {
+                return Err(BlazeError::InvalidConfig(
+                    "sample_output must be greater than 0 and at most 1".to_string(),
+                ));
+            }
+        }
+
+        if self.count_only && self.resume {
+            return Err(BlazeError::InvalidConfig(
+                "count_only runs keep no checkpoint to resume from".to_string(),
+            ));
+        }
 
-    /// Number of concurrent workers.
-    #[serde(default = "default_workers")]
-    pub workers: usize,
+        Ok(())
+    }
 }
 
-impl Default for RequestConfig {
+impl Default for ProcessingConfig {
     fn default() -> Self {
         Self {
-            timeout: default_timeout(),
-            rate_limit: default_rate(),
-            workers: default_workers(),
+            resume: false,
+            requeue: default_requeue(),
+            reject_blank_input: default_reject_blank_input(),
+            max_line_length: default_max_line_length(),
+            parse_workers: None,
+            dedup: false,
+            dedup_cache_size: default_dedup_cache_size(),
+            cache_dir: None,
+            cache_ttl: default_cache_ttl(),
+            max_failures: None,
+            max_failure_rate: None,
+            priority_window: default_priority_window(),
+            drain_timeout: None,
+            max_duration: None,
+            rps_window: DEFAULT_RPS_WINDOW,
+            ordered_output: false,
+            reorder_window: default_reorder_window(),
+            skip: 0,
+            take: None,
+            flush_every: None,
+            flush_interval: None,
+            output_rotate_size: None,
+            output_rotate_lines: None,
+            sample_output: None,
+            count_only: false,
+            report: None,
+            latency_histogram: None,
+            health_file: None,
+            health_ttl: default_health_ttl(),
+            log_outcomes: false,
+            capture_request: false,
+            response_projection: BTreeMap::new(),
+            output_format: OutputFormat::default(),
+            input_fields: BTreeMap::new(),
+            #[cfg(feature = "metrics")]
+            metrics_addr: None,
         }
     }
 }
 
-fn default_timeout() -> Duration {
-    Duration::from_secs(30)
+fn default_rps_window() -> Duration {
+    DEFAULT_RPS_WINDOW
 }
 
-fn default_rate() -> u32 {
-    1000
+fn default_requeue() -> bool {
+    true
 }
 
-fn default_workers() -> usize {
-    50
+fn default_reject_blank_input() -> bool {
+    true
 }
 
-/// Retry configuration.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RetryConfig {
-    /// Maximum number of retry attempts.
-    #[serde(default = "default_max_attempts")]
-    pub max_attempts: u32,
+fn default_max_line_length() -> u64 {
+    64 << 20
+}
 
-    /// Initial backoff duration.
-    #[serde(with = "humantime_serde", default = "default_initial_backoff")]
-    pub initial_backoff: Duration,
+fn default_budget_ratio() -> f64 {
+    DEFAULT_RETRY_BUDGET_RATIO
+}
 
-    /// Maximum backoff duration.
-    #[serde(with = "humantime_serde", default = "default_max_backoff")]
-    pub max_backoff: Duration,
+fn default_priority_window() -> usize {
+    1000
+}
 
-    /// Backoff multiplier.
-    #[serde(default = "default_multiplier")]
-    pub multiplier: f64,
+fn default_reorder_window() -> usize {
+    10_000
 }
 
-impl Default for RetryConfig {
-    fn default() -> Self {
-        Self {
-            max_attempts: default_max_attempts(),
-            initial_backoff: default_initial_backoff(),
-            max_backoff: default_max_backoff(),
-            multiplier: default_multiplier(),
-        }
-    }
+fn default_dedup_cache_size() -> usize {
+    10_000
+}
+
+fn default_health_ttl() -> Duration {
+    Duration::from_secs(600)
+}
+
+fn default_cache_ttl() -> Duration {
+    Duration::from_secs(24 * 60 * 60)
 }
 
 fn default_max_attempts() -> u32 {
@@ -213,56 +1946,277 @@ fn default_multiplier() -> f64 {
     2.0
 }
 
+fn default_non_retryable_statuses() -> Vec<u16> {
+    vec![400, 401, 403, 404]
+}
+
 impl Config {
-    /// Load configuration from a file.
+    /// Build the configuration written by `blaze init`: a placeholder
+    /// endpoint, with every other setting at its default.
+    #[must_use]
+    pub fn starter() -> Self {
+        Self {
+            endpoints: vec![EndpointConfig {
+                url: "https://api.openai.com/v1/chat/completions".to_string(),
+                api_key: Some("sk-your-api-key".to_string()),
+                model: Some("gpt-4o-mini".to_string()),
+                ..Default::default()
+            }],
+            load_balancing: LoadBalanceStrategy::default(),
+            latency_decay: default_latency_decay(),
+            hash_key: default_hash_key(),
+            seed: None,
+            request: RequestConfig::default(),
+            retry: RetryConfig::default(),
+            processing: ProcessingConfig::default(),
+        }
+    }
+
+    /// Render the starter configuration as JSON, with its `endpoints`,
+    /// `request` and `retry` sections explained in `_comment` fields.
+    ///
+    /// JSON has no comments, but the loader ignores unknown fields, so the
+    /// file loads as it is. Sections left out keep their defaults.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::JsonSerialize`] if the configuration cannot be serialized.
+    pub fn starter_json() -> Result<String> {
+        let comment = |text: &str| serde_json::Value::String(text.to_string());
+        let serde_json::Value::Object(mut sections) = serde_json::to_value(Self::starter())? else {
+            unreachable!("the configuration serializes to an object");
+        };
+        let mut starter = serde_json::Map::new();
+        starter.insert(
+            "_comment".to_string(),
+            comment("Starter blaze config. Set your endpoint below, then run: blaze --config config.json --input requests.jsonl"),
+        );
+        let notes = [
+            ("endpoints", "Requests are spread across these by weight. Replace the url, api_key and model with your own; values like ${OPENAI_API_KEY} are read from the environment."),
+            ("request", "Rate limit (requests per second), timeout, workers and connection settings shared by all endpoints."),
+            ("retry", "Failed requests are retried with exponential backoff; statuses in non_retryable_statuses fail right away."),
+        ];
+        for (name, note) in notes {
+            let Some(mut section) = sections.remove(name) else {
+                continue;
+            };
+            let targets: Vec<_> = match &mut section {
+                serde_json::Value::Array(items) => items.iter_mut().collect(),
+                other => vec![other],
+            };
+            for target in targets {
+                if let serde_json::Value::Object(fields) = target {
+                    fields.insert("_comment".to_string(), comment(note));
+                }
+            }
+            starter.insert(name.to_string(), section);
+        }
+        Ok(serde_json::to_string_pretty(&starter)?)
+    }
+
+    /// Load configuration from a JSON, YAML or TOML file, based on its extension.
+    ///
+    /// `${VAR}` references in string values are replaced with the value of
+    /// the environment variable, so secrets can stay out of the file. The
+    /// body template is left as is, as its `${field}` placeholders are filled
+    /// from each request.
     pub fn from_file(path: &PathBuf) -> Result<Self> {
         let content = std::fs::read_to_string(path).map_err(|e| BlazeError::InputFileRead {
             path: path.clone(),
             source: e,
         })?;
 
-        serde_json::from_str(&content).map_err(|e| BlazeError::JsonParse { line: 0, source: e })
+        let parse_error = |e: &dyn std::fmt::Display| {
+            BlazeError::InvalidConfig(format!("failed to parse '{}': {}", path.display(), e))
+        };
+
+        let config: Self = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&content)
+                .map_err(|e| BlazeError::JsonParse { line: 0, source: e }),
+            Some("yaml" | "yml") => serde_yaml::from_str(&content).map_err(|e| parse_error(&e)),
+            Some("toml") => toml::from_str(&content).map_err(|e| parse_error(&e)),
+            _ => Err(BlazeError::InvalidConfig(format!(
+                "unsupported config file '{}', expected a .json, .yaml, .yml or .toml extension",
+                path.display()
+            ))),
+        }?;
+        if !content.contains("${") {
+            return Ok(config);
+        }
+
+        // Parsing first keeps the format's own error locations; the strings are then
+        // expanded on a generic copy, which works the same for every format
+        let mut value = serde_json::to_value(config)?;
+        expand_env(&mut value).map_err(|name| BlazeError::UnsetEnvVar {
+            name,
+            path: path.clone(),
+        })?;
+        serde_json::from_value(value).map_err(|e| parse_error(&e))
     }
 
     /// Create configuration from CLI arguments.
     pub fn from_args(args: &Args) -> Result<Self> {
-        let config = if let Some(config_path) = &args.config {
+        let mut config = if let Some(config_path) = &args.config {
             let mut config = Self::from_file(config_path)?;
             // Override with CLI args
-            config.request.rate_limit = args.rate;
-            config.request.workers = args.workers;
+            config.request.rate_limit = args.rate.unwrap_or(config.request.rate_limit);
+            config.request.rate_period = args.rate_period.unwrap_or(config.request.rate_period);
+            config.request.rate_burst = args.rate_burst.or(config.request.rate_burst);
+            config.request.ramp_up = args.ramp_up.unwrap_or(config.request.ramp_up);
+            config.request.hedge_after = args.hedge_after.or(config.request.hedge_after);
+            config.request.workers = args.workers.or(config.request.workers);
+            config.request.adaptive_concurrency |= args.adaptive_concurrency;
+            config.request.max_workers = args.max_workers.unwrap_or(config.request.max_workers);
             config.request.timeout = Duration::from_secs(args.timeout);
-            config.retry.max_attempts = args.max_attempts;
+            if let Some(proxy) = &args.proxy {
+                config.request.proxy = Some(ProxyConfig::new(proxy));
+            }
+            if let Some(http_version) = args.http_version {
+                config.request.http_version = http_version;
+            }
+            config.request.accept_invalid_certs |= args.accept_invalid_certs;
+            config.request.prewarm |= args.prewarm;
+            config.load_balancing = args.load_balancing.unwrap_or(config.load_balancing);
+            config.latency_decay = args.latency_decay.unwrap_or(config.latency_decay);
+            config.seed = args.seed.or(config.seed);
+            if let Some(key) = &args.hash_key {
+                config.hash_key.clone_from(key);
+            }
             config
         } else {
             // Use default endpoint from environment or error
             let endpoint = EndpointConfig {
                 url: std::env::var("BLAZE_ENDPOINT_URL")
                     .unwrap_or_else(|_| "http://localhost:8080/v1/completions".to_string()),
-                weight: 1,
                 api_key: std::env::var("BLAZE_API_KEY").ok(),
                 model: std::env::var("BLAZE_MODEL").ok(),
-                max_concurrent: 100,
+                ..Default::default()
             };
 
             Self {
                 endpoints: vec![endpoint],
+                load_balancing: args.load_balancing.unwrap_or_default(),
+                latency_decay: args.latency_decay.unwrap_or(DEFAULT_LATENCY_DECAY),
+                hash_key: args.hash_key.clone().unwrap_or_else(default_hash_key),
+                seed: args.seed,
                 request: RequestConfig {
                     timeout: Duration::from_secs(args.timeout),
-                    rate_limit: args.rate,
+                    rate_limit: args.rate.unwrap_or_else(default_rate),
+                    rate_period: args.rate_period.unwrap_or_else(default_rate_period),
+                    rate_burst: args.rate_burst,
+                    ramp_up: args.ramp_up.unwrap_or_default(),
+                    hedge_after: args.hedge_after,
                     workers: args.workers,
-                },
-                retry: RetryConfig {
-                    max_attempts: args.max_attempts,
+                    adaptive_concurrency: args.adaptive_concurrency,
+                    max_workers: args.max_workers.unwrap_or_else(default_max_workers),
+                    proxy: args.proxy.as_deref().map(ProxyConfig::new),
+                    accept_invalid_certs: args.accept_invalid_certs,
+                    prewarm: args.prewarm,
+                    http_version: args.http_version.unwrap_or_default(),
                     ..Default::default()
                 },
+                retry: RetryConfig::default(),
+                processing: ProcessingConfig::default(),
             }
         };
+        args.override_processing(&mut config.processing);
+        args.override_retry(&mut config.retry);
+        args.override_request_tags(&mut config.request);
+        args.override_endpoint_limits(&mut config.endpoints);
 
         config.validate()?;
         Ok(config)
     }
 
+    /// Get a copy of the configuration with secrets masked, safe to log or archive.
+    #[must_use]
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        for endpoint in &mut config.endpoints {
+            if endpoint.api_key.is_some() {
+                endpoint.api_key = Some(MASK.to_string());
+            }
+            for key in &mut endpoint.api_keys {
+                *key = MASK.to_string();
+            }
+            // Endpoint headers often carry credentials of their own
+            for value in endpoint.headers.values_mut() {
+                *value = MASK.to_string();
+            }
+            for auth in &mut endpoint.auth {
+                match auth {
+                    AuthConfig::Bearer { token: secret }
+                    | AuthConfig::Basic {
+                        password: Some(secret),
+                        ..
+                    }
+                    | AuthConfig::Header { value: secret, .. }
+                    | AuthConfig::OAuth2(OAuth2Config {
+                        client_secret: secret,
+                        ..
+                    }) => *secret = MASK.to_string(),
+                    AuthConfig::None | AuthConfig::Basic { password: None, .. } => {}
+                }
+            }
+        }
+        if let Some(proxy) = config.request.proxy.as_mut() {
+            if proxy.password.is_some() {
+                proxy.password = Some(MASK.to_string());
+            }
+            // Credentials can also be embedded in the proxy URL
+            if let Ok(mut url) = reqwest::Url::parse(&proxy.url) {
+                if url.password().is_some() && url.set_password(Some(MASK)).is_ok() {
+                    proxy.url = url.to_string();
+                }
+            }
+        }
+        config
+    }
+
+    /// Get the combined `max_concurrent` of all endpoints.
+    #[must_use]
+    pub fn endpoint_capacity(&self) -> usize {
+        self.endpoints
+            .iter()
+            .map(|e| e.max_concurrent as usize)
+            .sum()
+    }
+
+    /// Get the most idle connections kept open per host: `pool_max_idle_per_host`, or `workers`.
+    #[must_use]
+    pub fn pool_size(&self) -> usize {
+        self.request
+            .pool_max_idle_per_host
+            .unwrap_or_else(|| self.workers())
+    }
+
+    /// Get the number of requests to keep in flight at once.
+    ///
+    /// Without an explicit `workers`, this is the endpoints' combined
+    /// capacity, so no endpoint slot goes unused. When adapting, that default
+    /// is capped at `max_workers`.
+    #[must_use]
+    pub fn workers(&self) -> usize {
+        self.request.workers.unwrap_or_else(|| {
+            let capacity = self.endpoint_capacity().max(1);
+            if self.request.adaptive_concurrency {
+                capacity.min(self.request.max_workers)
+            } else {
+                capacity
+            }
+        })
+    }
+
+    /// Get the most requests kept in flight at once: `workers`, or `max_workers` when adapting.
+    #[must_use]
+    pub fn max_in_flight(&self) -> usize {
+        if self.request.adaptive_concurrency {
+            self.request.max_workers
+        } else {
+            self.workers()
+        }
+    }
+
     /// Validate the configuration.
     pub fn validate(&self) -> Result<()> {
         if self.endpoints.is_empty() {
@@ -270,21 +2224,51 @@ impl Config {
         }
 
         for endpoint in &self.endpoints {
-            if endpoint.url.is_empty() {
-                return Err(BlazeError::InvalidConfig(
-                    "endpoint URL cannot be empty".to_string(),
-                ));
-            }
-            if endpoint.weight == 0 {
-                return Err(BlazeError::InvalidConfig(
-                    "endpoint weight must be greater than 0".to_string(),
-                ));
-            }
+            endpoint.validate()?;
         }
 
-        if self.request.workers == 0 {
+        self.request.validate()?;
+
+        if self.request.adaptive_concurrency && self.request.max_workers < self.workers() {
             return Err(BlazeError::InvalidConfig(
-                "workers must be greater than 0".to_string(),
+                "max_workers must be at least workers".to_string(),
+            ));
+        }
+
+        // Attempts include the first, so 1 sends each request once without retries
+        if self.retry.max_attempts == 0
+            || self.retry.connection_max_attempts == Some(0)
+            || self.retry.http_max_attempts == Some(0)
+        {
+            return Err(BlazeError::InvalidConfig(
+                "max_attempts, connection_max_attempts and http_max_attempts must be at least 1"
+                    .to_string(),
+            ));
+        }
+
+        if let Some(status) = self
+            .retry
+            .retryable_statuses
+            .iter()
+            .find(|status| self.retry.non_retryable_statuses.contains(status))
+        {
+            return Err(BlazeError::InvalidConfig(format!(
+                "status {status} is listed as both retryable and non-retryable"
+            )));
+        }
+
+        ResponseValidator::new(&self.request.validation)?;
+        self.processing.validate()?;
+
+        if !(self.latency_decay > 0.0 && self.latency_decay <= 1.0) {
+            return Err(BlazeError::InvalidConfig(
+                "latency_decay must be greater than 0 and at most 1".to_string(),
+            ));
+        }
+
+        if self.retry.budget_ratio.is_nan() || self.retry.budget_ratio < 0.0 {
+            return Err(BlazeError::InvalidConfig(
+                "retry budget_ratio must be at least 0".to_string(),
             ));
         }
 
@@ -297,6 +2281,85 @@ impl Config {
     }
 }
 
+/// Parse a duration such as "30s", "100ms", "5m", "1h", or a bare number of seconds.
+fn parse_duration(s: &str) -> std::result::Result<Duration, std::num::ParseIntError> {
+    if let Some(ms) = s.strip_suffix("ms") {
+        ms.parse().map(Duration::from_millis)
+    } else if let Some(secs) = s.strip_suffix('s') {
+        secs.parse().map(Duration::from_secs)
+    } else if let Some(mins) = s.strip_suffix('m') {
+        mins.parse()
+            .map(|mins: u64| Duration::from_secs(mins.saturating_mul(60)))
+    } else if let Some(hours) = s.strip_suffix('h') {
+        hours
+            .parse()
+            .map(|hours: u64| Duration::from_secs(hours.saturating_mul(3600)))
+    } else {
+        s.parse().map(Duration::from_secs)
+    }
+}
+
+/// Parse a size such as "1GB", "512MiB", or a bare number of bytes.
+///
+/// `KB`, `MB`, `GB` and `TB` are powers of 1000, and `KiB`, `MiB`, `GiB`
+/// and `TiB` powers of 1024.
+fn parse_size(s: &str) -> std::result::Result<u64, String> {
+    let s = s.trim();
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let number: u64 = number.parse().map_err(|_| format!("invalid size '{s}'"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" | "k" => 1_000,
+        "mb" | "m" => 1_000_000,
+        "gb" | "g" => 1_000_000_000,
+        "tb" | "t" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return Err(format!("unknown unit in size '{s}'")),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size '{s}' is too large"))
+}
+
+/// Serde module for optional sizes, given as a number of bytes or a string like "1GB".
+mod size_serde {
+    use serde::{Deserialize, Deserializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<Size>::deserialize(deserializer)?
+            .map(bytes)
+            .transpose()
+            .map_err(serde::de::Error::custom)
+    }
+
+    pub fn deserialize_required<'de, D>(deserializer: D) -> Result<u64, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        bytes(Size::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+
+    fn bytes(size: Size) -> Result<u64, String> {
+        match size {
+            Size::Bytes(bytes) => Ok(bytes),
+            Size::Text(s) => super::parse_size(&s),
+        }
+    }
+}
+
 /// Custom serde module for humantime Duration parsing.
 mod humantime_serde {
     use serde::{Deserialize, Deserializer, Serializer};
@@ -306,7 +2369,11 @@ mod humantime_serde {
     where
         S: Serializer,
     {
-        serializer.serialize_str(&format!("{}s", duration.as_secs()))
+        if duration.subsec_millis() == 0 {
+            serializer.serialize_str(&format!("{}s", duration.as_secs()))
+        } else {
+            serializer.serialize_str(&format!("{}ms", duration.as_millis()))
+        }
     }
 
     pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
@@ -314,19 +2381,549 @@ mod humantime_serde {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        // Simple parsing: support "30s", "100ms", or just seconds as number
-        if let Some(secs) = s.strip_suffix('s') {
-            secs.parse::<u64>()
-                .map(Duration::from_secs)
-                .map_err(serde::de::Error::custom)
-        } else if let Some(ms) = s.strip_suffix("ms") {
-            ms.parse::<u64>()
-                .map(Duration::from_millis)
-                .map_err(serde::de::Error::custom)
-        } else {
-            s.parse::<u64>()
-                .map(Duration::from_secs)
-                .map_err(serde::de::Error::custom)
+        super::parse_duration(&s).map_err(serde::de::Error::custom)
+    }
+
+    /// The same format for optional durations.
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+        use std::time::Duration;
+
+        // serde's `with` passes a reference to the field itself
+        #[allow(clippy::ref_option)]
+        pub fn serialize<S>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match duration {
+                Some(duration) => super::serialize(duration, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            Option::<String>::deserialize(deserializer)?
+                .map(|s| super::super::parse_duration(&s).map_err(serde::de::Error::custom))
+                .transpose()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1GB"), Ok(1_000_000_000));
+        assert_eq!(parse_size("512MiB"), Ok(512 << 20));
+        assert_eq!(parse_size("10 kb"), Ok(10_000));
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert!(parse_size("1.5GB").is_err());
+        assert!(parse_size("GB").is_err());
+        assert!(parse_size("99999999TB").is_err());
+
+        let processing: ProcessingConfig =
+            serde_json::from_str(r#"{"output_rotate_size": "2MB"}"#).unwrap();
+        assert_eq!(processing.output_rotate_size, Some(2_000_000));
+        let processing: ProcessingConfig =
+            serde_json::from_str(r#"{"output_rotate_size": 100}"#).unwrap();
+        assert_eq!(processing.output_rotate_size, Some(100));
+    }
+
+    #[test]
+    fn test_workers() {
+        let endpoint = |max_concurrent| EndpointConfig {
+            url: "http://localhost:8080".to_string(),
+            max_concurrent,
+            ..Default::default()
+        };
+        let mut config = Config {
+            endpoints: vec![endpoint(100), endpoint(50)],
+            ..Default::default()
+        };
+
+        // Without workers, concurrency matches the endpoints' combined capacity
+        assert_eq!(config.endpoint_capacity(), 150);
+        assert_eq!(config.workers(), 150);
+        assert_eq!(config.max_in_flight(), 150);
+
+        // Adapting starts from that capacity, capped at max_workers
+        config.request.adaptive_concurrency = true;
+        config.request.max_workers = 120;
+        assert_eq!(config.workers(), 120);
+        assert_eq!(config.max_in_flight(), 120);
+        assert!(config.validate().is_ok());
+
+        // An explicit worker count wins
+        config.request.workers = Some(20);
+        assert_eq!(config.workers(), 20);
+        assert_eq!(config.max_in_flight(), 120);
+        config.request.workers = Some(0);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_from_file_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            (
+                "config.json",
+                r#"{
+                    "endpoints": [
+                        {"url": "http://a.example/v1", "weight": 2, "api_key": "key-a"},
+                        {"url": "http://b.example/v1", "model": "gpt-4", "max_concurrent": 10}
+                    ],
+                    "request": {"timeout": "45s", "rate_limit": 500},
+                    "retry": {"max_attempts": 5, "initial_backoff": "250ms"}
+                }"#,
+            ),
+            (
+                "config.yaml",
+                r"
+endpoints:
+  - url: http://a.example/v1
+    weight: 2
+    api_key: key-a
+  - url: http://b.example/v1
+    model: gpt-4
+    max_concurrent: 10
+request:
+  timeout: 45s
+  rate_limit: 500
+retry:
+  max_attempts: 5
+  initial_backoff: 250ms
+",
+            ),
+            (
+                "config.toml",
+                r#"
+[[endpoints]]
+url = "http://a.example/v1"
+weight = 2
+api_key = "key-a"
+
+[[endpoints]]
+url = "http://b.example/v1"
+model = "gpt-4"
+max_concurrent = 10
+
+[request]
+timeout = "45s"
+rate_limit = 500
+
+[retry]
+max_attempts = 5
+initial_backoff = "250ms"
+"#,
+            ),
+        ];
+
+        let configs: Vec<Config> = files
+            .iter()
+            .map(|(name, content)| {
+                let path = dir.path().join(name);
+                std::fs::write(&path, content).unwrap();
+                Config::from_file(&path).unwrap()
+            })
+            .collect();
+
+        assert_eq!(configs[0].endpoints.len(), 2);
+        assert_eq!(configs[0].request.timeout, Duration::from_secs(45));
+        assert_eq!(configs[0].retry.initial_backoff, Duration::from_millis(250));
+        assert_eq!(configs[0].retry.max_backoff, default_max_backoff());
+        assert_eq!(configs[0], configs[1]);
+        assert_eq!(configs[0], configs[2]);
+
+        // Serialized durations must survive a round trip in every format
+        let yaml = serde_yaml::to_string(&configs[0]).unwrap();
+        assert_eq!(serde_yaml::from_str::<Config>(&yaml).unwrap(), configs[0]);
+        let toml = toml::to_string(&configs[0]).unwrap();
+        assert_eq!(toml::from_str::<Config>(&toml).unwrap(), configs[0]);
+
+        assert_eq!(configs[0].endpoints[0].auth_header, "Authorization");
+        assert_eq!(configs[0].endpoints[0].auth_scheme, "Bearer");
+
+        let path = dir.path().join("config.ini");
+        std::fs::write(&path, "").unwrap();
+        assert!(matches!(
+            Config::from_file(&path),
+            Err(BlazeError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_command() {
+        let args =
+            Args::try_parse_from(["blaze", "check", "-i", "a.jsonl", "--max-errors", "5"]).unwrap();
+        assert_eq!(args.command, Some(Command::Check { max_errors: 5 }));
+        assert_eq!(args.input_paths().unwrap(), [PathBuf::from("a.jsonl")]);
+
+        let args = Args::try_parse_from(["blaze", "-i", "a.jsonl"]).unwrap();
+        assert_eq!(args.command, None);
+        let args = Args::try_parse_from(["blaze", "check"]).unwrap();
+        assert!(args.input_paths().is_err());
+    }
+
+    #[test]
+    fn test_replay_command() {
+        let args = Args::try_parse_from([
+            "blaze",
+            "-e",
+            "errors-2.jsonl",
+            "replay",
+            "--errors",
+            "errors.jsonl",
+        ])
+        .unwrap();
+        assert_eq!(args.input_paths().unwrap(), [PathBuf::from("errors.jsonl")]);
+
+        // New errors can't overwrite the file being replayed, which --errors defaults to
+        let args = Args::try_parse_from(["blaze", "replay", "--errors", "errors.jsonl"]).unwrap();
+        assert!(args.input_paths().is_err());
+        let args = Args::try_parse_from([
+            "blaze", "-e", "e2.jsonl", "replay", "--errors", "e.jsonl", "-i", "a.jsonl",
+        ])
+        .unwrap();
+        assert!(args.input_paths().is_err());
+    }
+
+    #[test]
+    fn test_init_command() {
+        let args = Args::try_parse_from(["blaze", "init", "--force"]).unwrap();
+        assert_eq!(
+            args.command,
+            Some(Command::Init {
+                path: PathBuf::from("config.json"),
+                force: true
+            })
+        );
+
+        // The starter file loads back as the starter configuration, comments and all
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        std::fs::write(&path, Config::starter_json().unwrap()).unwrap();
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config, Config::starter());
+        config.validate().unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&Config::starter_json().unwrap()).unwrap();
+        assert!(json["endpoints"][0]["_comment"].is_string());
+        assert!(json["retry"]["_comment"].is_string());
+        assert!(json.get("processing").is_none());
+    }
+
+    #[test]
+    fn test_config_env_vars() {
+        std::env::set_var("BLAZE_TEST_CONFIG_KEY", "sk-from-env");
+        std::env::set_var("BLAZE_TEST_CONFIG_HOST", "api.example.com");
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        std::fs::write(
+            &path,
+            r#"
+endpoints:
+  - url: https://${BLAZE_TEST_CONFIG_HOST}/v1
+    api_key: ${BLAZE_TEST_CONFIG_KEY}
+    auth_scheme: literal $ sign
+request:
+  body_template: { "prompt": "${input}" }
+"#,
+        )
+        .unwrap();
+        let config = Config::from_file(&path).unwrap();
+        let endpoint = &config.endpoints[0];
+        assert_eq!(endpoint.url, "https://api.example.com/v1");
+        assert_eq!(endpoint.api_key.as_deref(), Some("sk-from-env"));
+        assert_eq!(endpoint.auth_scheme, "literal $ sign");
+        // The body template's placeholders are filled per request, not from the environment
+        let template = config.request.body_template.unwrap();
+        assert_eq!(template["prompt"], "${input}");
+
+        std::fs::write(&path, "endpoints:\n  - url: ${BLAZE_TEST_CONFIG_UNSET}\n").unwrap();
+        assert!(matches!(
+            Config::from_file(&path),
+            Err(BlazeError::UnsetEnvVar { name, .. }) if name == "BLAZE_TEST_CONFIG_UNSET"
+        ));
+    }
+
+    #[test]
+    fn test_validate_auth_header() {
+        let mut config = Config {
+            endpoints: vec![EndpointConfig {
+                url: "http://localhost:8080".to_string(),
+                auth_header: "api-key".to_string(),
+                auth_scheme: String::new(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        config.endpoints[0].auth_header = "x api key".to_string();
+        assert!(matches!(
+            config.validate(),
+            Err(BlazeError::InvalidConfig(_))
+        ));
+
+        // Custom headers are checked the same way
+        config.endpoints[0].auth_header = "api-key".to_string();
+        config.endpoints[0]
+            .headers
+            .insert("anthropic-version".to_string(), "2023-06-01".to_string());
+        assert!(config.validate().is_ok());
+        config.endpoints[0]
+            .headers
+            .insert("x-tenant".to_string(), "acme\n".to_string());
+        assert!(matches!(
+            config.validate(),
+            Err(BlazeError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_auth_entries() {
+        // A single entry or a list of them
+        let one: EndpointConfig = serde_json::from_value(serde_json::json!({
+            "url": "http://localhost:8080",
+            "auth": { "type": "bearer", "token": "t" }
+        }))
+        .unwrap();
+        assert_eq!(
+            one.auth,
+            [AuthConfig::Bearer {
+                token: "t".to_string()
+            }]
+        );
+        let mut config = Config {
+            endpoints: vec![serde_json::from_value(serde_json::json!({
+                "url": "http://localhost:8080",
+                "auth": [
+                    { "type": "basic", "username": "gateway", "password": "pass" },
+                    { "type": "header", "name": "x-api-key", "value": "key" }
+                ]
+            }))
+            .unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(config.endpoints[0].auth.len(), 2);
+        assert!(config.validate().is_ok());
+
+        config.endpoints[0]
+            .headers
+            .insert("x-org".to_string(), "org-secret".to_string());
+        let redacted = config.redacted();
+        assert!(matches!(
+            &redacted.endpoints[0].auth[..],
+            [AuthConfig::Basic { password: Some(p), .. }, AuthConfig::Header { value: v, .. }]
+                if p == "********" && v == "********"
+        ));
+        assert_eq!(redacted.endpoints[0].headers["x-org"], "********");
+        let debug = format!("{:?}", config.endpoints[0].auth);
+        assert!(debug.contains("gateway"));
+        assert!(!debug.contains("\"pass\"") && !debug.contains("\"key\""));
+
+        // No credentials can't be combined with others
+        config.endpoints[0].auth.push(AuthConfig::None);
+        assert!(matches!(
+            config.validate(),
+            Err(BlazeError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_http2_keep_alive() {
+        let request: RequestConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(
+            request.http2_keep_alive_interval,
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(request.http2_keep_alive_timeout, Duration::from_secs(10));
+        assert!(request.http2_keep_alive_while_idle);
+
+        // null turns the pings off
+        let request: RequestConfig = serde_json::from_str(
+            r#"{"http2_keep_alive_interval": null, "http2_keep_alive_timeout": "5s"}"#,
+        )
+        .unwrap();
+        assert_eq!(request.http2_keep_alive_interval, None);
+        assert_eq!(request.http2_keep_alive_timeout, Duration::from_secs(5));
+        assert!(request.validate().is_ok());
+
+        let mut config = Config {
+            endpoints: vec![EndpointConfig {
+                url: "http://localhost:8080".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        config.request.http2_keep_alive_interval = Some(Duration::ZERO);
+        assert!(config.validate().is_err());
+        config.request.http2_keep_alive_interval = None;
+        config.request.http2_keep_alive_timeout = Duration::ZERO;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_output_format() {
+        let args = Args::try_parse_from([
+            "blaze",
+            "-i",
+            "in.jsonl",
+            "--output-format",
+            "csv",
+            "--output-jsonpath",
+            "content=choices[0].text",
+        ])
+        .unwrap();
+        let mut processing = ProcessingConfig::default();
+        args.override_processing(&mut processing);
+        assert_eq!(processing.output_format, OutputFormat::Csv);
+        assert!(processing.validate().is_ok());
+
+        // Tabular output takes its columns from the projection
+        processing.response_projection.clear();
+        assert!(processing.validate().is_err());
+        let processing: ProcessingConfig =
+            serde_json::from_str(r#"{"output_format": "jsonl"}"#).unwrap();
+        assert!(processing.validate().is_ok());
+    }
+
+    #[test]
+    fn test_rate_quota() {
+        let request = RequestConfig::default();
+        let quota = request.rate_quota();
+        assert_eq!(quota.replenish_interval(), Duration::from_millis(1));
+        assert_eq!(quota.burst_size().get(), 1000);
+
+        // A minute's budget can go out at once, refilling at the same average rate
+        let args = Args::try_parse_from([
+            "blaze",
+            "-i",
+            "in.jsonl",
+            "--rate",
+            "60000",
+            "--rate-period",
+            "1m",
+        ])
+        .unwrap();
+        let mut config = Config::from_args(&args).unwrap();
+        let quota = config.request.rate_quota();
+        assert_eq!(quota.replenish_interval(), Duration::from_millis(1));
+        assert_eq!(quota.burst_size().get(), 60000);
+        assert!((config.request.rate_per_second() - 1000.0).abs() < 1e-9);
+
+        config.request.rate_burst = Some(5000);
+        assert_eq!(config.request.rate_quota().burst_size().get(), 5000);
+        config.request.rate_burst = Some(0);
+        assert!(config.validate().is_err());
+
+        // A config file's rate is kept unless --rate is given
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        std::fs::write(
+            &path,
+            r#"{
+                "endpoints": [{"url": "http://a.example/v1"}],
+                "request": {"rate_limit": 50, "rate_period": "1m"}
+            }"#,
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+        let args = Args::try_parse_from(["blaze", "-i", "in.jsonl", "-c", path]).unwrap();
+        let config = Config::from_args(&args).unwrap();
+        assert_eq!(config.request.rate_limit, 50);
+        assert_eq!(config.request.rate_period, Duration::from_secs(60));
+        let args =
+            Args::try_parse_from(["blaze", "-i", "in.jsonl", "-c", path, "-r", "10"]).unwrap();
+        let config = Config::from_args(&args).unwrap();
+        assert_eq!(config.request.rate_limit, 10);
+        assert_eq!(config.request.rate_period, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_count_only() {
+        let args = Args::try_parse_from(["blaze", "-i", "in.jsonl", "--count-only"]).unwrap();
+        let mut processing = ProcessingConfig::default();
+        args.override_processing(&mut processing);
+        assert!(processing.count_only);
+
+        // Counting only writes nothing, so there is no output to name or resume
+        let args = ["blaze", "-i", "in.jsonl", "--count-only", "-o", "out.jsonl"];
+        assert!(Args::try_parse_from(args).is_err());
+        processing.resume = true;
+        assert!(processing.validate().is_err());
+    }
+
+    #[test]
+    fn test_retry_statuses() {
+        let mut config = Config {
+            endpoints: vec![EndpointConfig {
+                url: "http://localhost:8080".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let retry = &config.retry;
+        assert!(!retry.is_retryable(404));
+        assert!(retry.is_retryable(409));
+        assert_eq!(retry.failure_class(Some(409)), FailureClass::Permanent);
+        assert_eq!(retry.failure_class(Some(503)), FailureClass::Transient);
+
+        config.retry.retryable_statuses = vec![409];
+        config.retry.non_retryable_statuses = vec![400, 401, 403, 503];
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.retry.failure_class(Some(409)),
+            FailureClass::Transient
+        );
+        assert_eq!(
+            config.retry.failure_class(Some(503)),
+            FailureClass::Permanent
+        );
+        assert!(config.retry.is_retryable(404));
+
+        config.retry.retryable_statuses.push(403);
+        assert!(matches!(
+            config.validate(),
+            Err(BlazeError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_max_attempts() {
+        let mut config = Config {
+            endpoints: vec![EndpointConfig {
+                url: "http://localhost:8080".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        config.retry.max_attempts = 1;
+        assert!(config.validate().is_ok());
+        assert_eq!(config.retry.max_attempts_for(true), 1);
+
+        config.retry.max_attempts = 0;
+        assert!(matches!(
+            config.validate(),
+            Err(BlazeError::InvalidConfig(_))
+        ));
+
+        // A config file's attempts are kept unless --max-attempts is given
+        let mut retry = RetryConfig {
+            max_attempts: 5,
+            ..Default::default()
+        };
+        let args = Args::try_parse_from(["blaze", "-i", "in.jsonl"]).unwrap();
+        args.override_retry(&mut retry);
+        assert_eq!(retry.max_attempts, 5);
+        let args = Args::try_parse_from(["blaze", "-i", "in.jsonl", "-a", "2"]).unwrap();
+        args.override_retry(&mut retry);
+        assert_eq!(retry.max_attempts, 2);
+        assert_eq!(RetryConfig::default().max_attempts, 3);
+    }
+}
@@ -0,0 +1,189 @@
+//! Request deduplication by content.
+//!
+//! Requests are keyed by their method, path, headers and rendered body,
+//! compared byte for byte. The first request with a given key is sent; duplicates wait for it
+//! and reuse its response instead of calling the API again.
+//!
+//! Successful responses are held in memory until the cache is full, after
+//! which the oldest entries are evicted. A duplicate of an evicted or failed
+//! request is sent like any other request.
+
+use crate::request::{ApiRequest, ApiResponse, RequestFormat, RequestResult};
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+/// Response of the first request with a key, or `None` if it failed.
+type Slot = Arc<OnceCell<Option<ApiResponse>>>;
+
+/// Tracks request keys and caches their responses.
+#[derive(Debug)]
+pub struct Deduplicator {
+    entries: Mutex<Entries>,
+    capacity: usize,
+}
+
+/// Cache slots with their insertion order, for eviction.
+#[derive(Debug, Default)]
+struct Entries {
+    slots: HashMap<Arc<str>, Slot>,
+    order: VecDeque<Arc<str>>,
+}
+
+impl Deduplicator {
+    /// Create a deduplicator caching at most `capacity` responses.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(Entries::default()),
+            capacity,
+        }
+    }
+
+    /// Compute the deduplication key of a request from its rendered body.
+    ///
+    /// The key is the request's canonical encoding rather than a hash of it,
+    /// so different requests never share a key.
+    ///
+    /// Returns `None` if the body cannot be rendered, so the request is sent
+    /// and fails on its own.
+    #[must_use]
+    pub fn key(request: &ApiRequest, template: Option<&Value>, strict: bool) -> Option<String> {
+        let body = if request.method().has_body() {
+            Some(
+                request
+                    .build_llm_body(None, RequestFormat::Chat, template, strict)
+                    .ok()?,
+            )
+        } else {
+            None
+        };
+        let headers: Option<BTreeMap<_, _>> = request.headers.as_ref().map(|h| h.iter().collect());
+
+        // Object keys are sorted, so equal requests serialize identically
+        Some(json!([request.method(), request.path, headers, body]).to_string())
+    }
+
+    /// Run `send` for the first request with `key`, or reuse its response.
+    ///
+    /// Duplicates that arrive while the first request is in flight wait for
+    /// it to finish. Reused responses are marked as deduplicated.
+    pub async fn run<F, Fut>(&self, key: &str, request: &ApiRequest, send: F) -> RequestResult
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = RequestResult>,
+    {
+        let slot = self.slot(key);
+        let mut send = Some(send);
+        let mut first_result = None;
+
+        let cached = slot
+            .get_or_init(|| {
+                let send = send.take();
+                let first_result = &mut first_result;
+                async move {
+                    let result = match send {
+                        Some(send) => send().await,
+                        None => unreachable!("a slot is only initialized once"),
+                    };
+                    let response = match &result {
+                        RequestResult::Success(response) => Some(response.clone()),
+                        RequestResult::Failure(_) | RequestResult::Skipped { .. } => None,
+                    };
+                    *first_result = Some(result);
+                    response
+                }
+            })
+            .await;
+
+        if let Some(result) = first_result {
+            return result;
+        }
+        match (cached, send) {
+            (Some(response), _) => {
+                let mut metadata = response.metadata.clone();
+                if let Some(metadata) = &mut metadata {
+                    metadata.source = request.source.as_deref().map(str::to_string);
+                }
+                RequestResult::Success(ApiResponse {
+                    input: request.input.clone(),
+                    metadata,
+                    request_metadata: request.metadata_fields(),
+                    deduped: true,
+                    ..response.clone()
+                })
+            }
+            (None, Some(send)) => send().await,
+            (None, None) => unreachable!("send is only taken by the request that fills the slot"),
+        }
+    }
+
+    /// Get the cache slot for a key, evicting the oldest entry if full.
+    fn slot(&self, key: &str) -> Slot {
+        let mut entries = self.entries.lock();
+        if let Some(slot) = entries.slots.get(key) {
+            return Arc::clone(slot);
+        }
+
+        while entries.slots.len() >= self.capacity {
+            let Some(oldest) = entries.order.pop_front() else {
+                break;
+            };
+            entries.slots.remove(&oldest);
+        }
+
+        let key: Arc<str> = key.into();
+        let slot = Slot::default();
+        entries.slots.insert(Arc::clone(&key), Arc::clone(&slot));
+        entries.order.push_back(key);
+        slot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::ErrorResponse;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_dedup() {
+        let first = ApiRequest::simple("hello");
+        let same = ApiRequest::simple("hello");
+        let other = ApiRequest::simple("world");
+        let key = Deduplicator::key(&first, None, false).unwrap();
+        assert_eq!(Deduplicator::key(&same, None, false).as_ref(), Some(&key));
+        assert_ne!(Deduplicator::key(&other, None, false).as_ref(), Some(&key));
+
+        let dedup = Deduplicator::new(1);
+        let sends = AtomicUsize::new(0);
+        let send = |request: &ApiRequest| {
+            sends.fetch_add(1, Ordering::Relaxed);
+            let response = ApiResponse::new(request.input.clone(), json!({"ok": true}));
+            async move { RequestResult::Success(response) }
+        };
+
+        let result = dedup.run(&key, &first, || send(&first)).await;
+        assert!(matches!(result, RequestResult::Success(r) if !r.deduped));
+        let result = dedup.run(&key, &same, || send(&same)).await;
+        assert!(matches!(result, RequestResult::Success(r) if r.deduped));
+        assert_eq!(sends.load(Ordering::Relaxed), 1);
+
+        // The cache holds one entry, so the first key is evicted
+        let other_key = Deduplicator::key(&other, None, false).unwrap();
+        dedup.run(&other_key, &other, || send(&other)).await;
+        dedup.run(&key, &same, || send(&same)).await;
+        assert_eq!(sends.load(Ordering::Relaxed), 3);
+
+        // Failures are not reused
+        let dedup = Deduplicator::new(10);
+        let fail = || async { RequestResult::Failure(ErrorResponse::new(&first, "boom", 1)) };
+        assert!(!dedup.run(&key, &first, fail).await.is_success());
+        let result = dedup.run(&key, &same, || send(&same)).await;
+        assert!(matches!(result, RequestResult::Success(r) if !r.deduped));
+    }
+}
@@ -3,13 +3,51 @@
 //! This module provides a load balancer that distributes requests
 //! across multiple endpoints based on configurable weights.
 
-use crate::config::EndpointConfig;
+use crate::batch::Batcher;
+use crate::config::{AuthConfig, EndpointConfig};
 use crate::error::{BlazeError, Result};
-use parking_lot::RwLock;
-use rand::prelude::*;
+use crate::health::{EndpointHealth, HealthState};
+use crate::histogram::LatencyHistogram;
+use crate::keys::{KeyPool, KeyStats};
+use crate::oauth::TokenCache;
+use crate::random::RandomSource;
+use crate::request::RequestFormat;
+use crate::tracker::{FailureStatuses, StatusCounter};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use parking_lot::{Mutex, RwLock};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{info, warn};
+
+/// FNV-1a parameters, which keep the hash ring the same across builds and platforms.
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Default weight of the newest sample in an endpoint's latency average.
+pub const DEFAULT_LATENCY_DECAY: f64 = 0.1;
+
+/// Floor for latency averages when scoring endpoints, to avoid dividing by zero.
+const MIN_SCORED_LATENCY_MS: f64 = 0.1;
+
+/// Points each unit of endpoint weight gets on the consistent hash ring.
+const RING_POINTS_PER_WEIGHT: u32 = 40;
+
+/// Circuit breaker state of an endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum CircuitState {
+    /// Normal operation, all traffic is allowed.
+    Closed,
+    /// The endpoint is failing, traffic is blocked until the cooldown elapses.
+    Open,
+    /// The cooldown has elapsed, a single probe request is allowed through.
+    HalfOpen,
+}
 
 /// A single API endpoint with health tracking.
 #[derive(Debug)]
@@ -18,32 +56,92 @@ pub struct Endpoint {
     pub config: EndpointConfig,
     /// Current number of in-flight requests.
     pub in_flight: AtomicUsize,
+    /// Highest number of requests in flight at once.
+    pub max_in_flight: AtomicUsize,
+    /// Slots for requests in flight, `max_concurrent` in total.
+    slots: Arc<Semaphore>,
+    /// Requests waiting for a slot.
+    waiting: AtomicUsize,
     /// Total successful requests.
     pub success_count: AtomicU64,
     /// Total failed requests.
     pub failure_count: AtomicU64,
+    /// Requests the endpoint was selected for, counted against `max_requests`.
+    requests_taken: AtomicU64,
     /// Total latency in microseconds.
     pub total_latency_us: AtomicU64,
-    /// Whether the endpoint is healthy.
-    healthy: RwLock<bool>,
-    /// Last health check time.
-    last_health_check: RwLock<Option<Instant>>,
+    /// Latencies of successful requests.
+    latency_histogram: LatencyHistogram,
+    /// Moving average of latency in microseconds, as `f64` bits (0 before the first success).
+    ewma_latency_us: AtomicU64,
+    /// Weight of the newest sample in the moving average, as `f64` bits.
+    latency_decay: AtomicU64,
+    /// Circuit breaker state.
+    circuit: RwLock<CircuitState>,
+    /// When the circuit was last opened.
+    opened_at: RwLock<Option<Instant>>,
+    /// Id of the half-open probe request currently in flight, 0 if there is none.
+    probe_in_flight: AtomicU64,
+    /// Id of the latest probe claimed.
+    last_probe: AtomicU64,
     /// Consecutive failures.
     consecutive_failures: AtomicUsize,
+    /// When the latest failure happened.
+    last_failure: RwLock<Option<DateTime<Utc>>>,
+    /// Outcomes of the most recent requests, `true` for failures, when the
+    /// endpoint trips on a failure rate.
+    recent_outcomes: Mutex<VecDeque<bool>>,
+    /// Access token shared by all requests, for endpoints using OAuth.
+    token_cache: Option<TokenCache>,
+    /// API keys rotated across requests.
+    key_pool: KeyPool,
+    /// Pending batch, for endpoints sending requests in batches.
+    batcher: Option<Batcher>,
+    /// Failed requests by the HTTP status they ended with.
+    failure_statuses: StatusCounter,
 }
 
 impl Endpoint {
     /// Create a new endpoint from configuration.
     pub fn new(config: EndpointConfig) -> Self {
+        let token_cache = config.auth.iter().find_map(|auth| match auth {
+            AuthConfig::OAuth2(oauth) => Some(TokenCache::new(oauth.clone())),
+            _ => None,
+        });
+        let keys = config
+            .api_key
+            .iter()
+            .chain(&config.api_keys)
+            .cloned()
+            .collect();
+        let key_pool = KeyPool::new(keys, config.key_rotation);
+        let slots = Arc::new(Semaphore::new(config.max_concurrent as usize));
+        let batcher =
+            (config.batch_size > 1).then(|| Batcher::new(config.batch_size, config.batch_wait));
         Self {
             config,
             in_flight: AtomicUsize::new(0),
+            max_in_flight: AtomicUsize::new(0),
+            slots,
+            waiting: AtomicUsize::new(0),
             success_count: AtomicU64::new(0),
             failure_count: AtomicU64::new(0),
+            requests_taken: AtomicU64::new(0),
             total_latency_us: AtomicU64::new(0),
-            healthy: RwLock::new(true),
-            last_health_check: RwLock::new(None),
+            latency_histogram: LatencyHistogram::new(),
+            ewma_latency_us: AtomicU64::new(0),
+            latency_decay: AtomicU64::new(DEFAULT_LATENCY_DECAY.to_bits()),
+            circuit: RwLock::new(CircuitState::Closed),
+            opened_at: RwLock::new(None),
+            probe_in_flight: AtomicU64::new(0),
+            last_probe: AtomicU64::new(0),
             consecutive_failures: AtomicUsize::new(0),
+            last_failure: RwLock::new(None),
+            recent_outcomes: Mutex::new(VecDeque::new()),
+            token_cache,
+            key_pool,
+            batcher,
+            failure_statuses: StatusCounter::default(),
         }
     }
 
@@ -57,79 +155,342 @@ impl Endpoint {
         self.config.api_key.as_deref()
     }
 
+    /// Get the endpoint's API keys.
+    #[must_use]
+    pub fn key_pool(&self) -> &KeyPool {
+        &self.key_pool
+    }
+
+    /// Get the pending batch if the endpoint sends requests in batches.
+    #[must_use]
+    pub fn batcher(&self) -> Option<&Batcher> {
+        self.batcher.as_ref()
+    }
+
+    /// Get the access token cache if the endpoint uses OAuth.
+    pub fn token_cache(&self) -> Option<&TokenCache> {
+        self.token_cache.as_ref()
+    }
+
     /// Get the model if configured.
     pub fn model(&self) -> Option<&str> {
         self.config.model.as_deref()
     }
 
-    /// Check if the endpoint is healthy.
+    /// Get the body format built for requests to this endpoint.
+    pub fn request_format(&self) -> RequestFormat {
+        self.config.request_format
+    }
+
+    /// Check whether a response status counts as a success, which is any
+    /// 2xx unless the endpoint lists its success statuses.
+    #[must_use]
+    pub fn is_success(&self, status: u16) -> bool {
+        if self.config.success_statuses.is_empty() {
+            (200..300).contains(&status)
+        } else {
+            self.config.success_statuses.contains(&status)
+        }
+    }
+
+    /// Get the current circuit breaker state.
+    pub fn circuit_state(&self) -> CircuitState {
+        *self.circuit.read()
+    }
+
+    /// Check if the endpoint is healthy (circuit closed).
     pub fn is_healthy(&self) -> bool {
-        *self.healthy.read()
+        self.circuit_state() == CircuitState::Closed
     }
 
-    /// Mark the endpoint as healthy.
+    /// Mark the endpoint as healthy, closing the circuit.
     pub fn mark_healthy(&self) {
-        *self.healthy.write() = true;
+        let mut circuit = self.circuit.write();
+        if *circuit != CircuitState::Closed {
+            info!(endpoint = self.url(), "Circuit closed");
+            // Failures from before the circuit opened shouldn't trip it again
+            self.recent_outcomes.lock().clear();
+        }
+        *circuit = CircuitState::Closed;
+        self.probe_in_flight.store(0, Ordering::Release);
         self.consecutive_failures.store(0, Ordering::Relaxed);
     }
 
-    /// Mark the endpoint as unhealthy.
+    /// Mark the endpoint as unhealthy, opening the circuit and restarting the cooldown.
     pub fn mark_unhealthy(&self) {
-        *self.healthy.write() = false;
-        *self.last_health_check.write() = Some(Instant::now());
+        let mut circuit = self.circuit.write();
+        if *circuit != CircuitState::Open {
+            warn!(endpoint = self.url(), "Circuit opened");
+        }
+        *circuit = CircuitState::Open;
+        *self.opened_at.write() = Some(Instant::now());
+        self.probe_in_flight.store(0, Ordering::Release);
     }
 
-    /// Check if the endpoint should be retried (after cooldown).
+    /// Check if the endpoint can take traffic, either because the circuit is
+    /// closed or because a half-open probe is available.
+    ///
+    /// This does not claim the probe; use [`Endpoint::try_begin_probe`] for that.
     pub fn should_retry(&self, cooldown: Duration) -> bool {
-        if self.is_healthy() {
-            return true;
+        match self.circuit_state() {
+            CircuitState::Closed => true,
+            CircuitState::Open => self.cooldown_elapsed(cooldown),
+            CircuitState::HalfOpen => self.probe_in_flight.load(Ordering::Acquire) == 0,
+        }
+    }
+
+    /// Try to claim the single half-open probe slot.
+    ///
+    /// Moves an open circuit to half-open once the cooldown has elapsed. Returns
+    /// `true` if the caller may send the probe request (or the circuit is closed).
+    pub fn try_begin_probe(&self, cooldown: Duration) -> bool {
+        let mut circuit = self.circuit.write();
+        match *circuit {
+            CircuitState::Closed => true,
+            CircuitState::Open => {
+                if !self.cooldown_elapsed(cooldown) {
+                    return false;
+                }
+                *circuit = CircuitState::HalfOpen;
+                self.probe_in_flight
+                    .store(self.next_probe_id(), Ordering::Release);
+                info!(endpoint = self.url(), "Circuit half-open, sending probe");
+                true
+            }
+            CircuitState::HalfOpen => self
+                .probe_in_flight
+                .compare_exchange(0, self.next_probe_id(), Ordering::AcqRel, Ordering::Acquire)
+                .is_ok(),
         }
+    }
+
+    fn next_probe_id(&self) -> u64 {
+        self.last_probe.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Take charge of the half-open probe in flight, if the endpoint is probing.
+    ///
+    /// Call this right after the endpoint was selected, so that a probe whose
+    /// request is dropped before recording an outcome, such as one cancelled
+    /// at the drain timeout, is released for the next request instead of
+    /// holding the circuit half-open for good.
+    #[must_use]
+    pub fn guard_probe(self: &Arc<Self>) -> Option<ProbeGuard> {
+        if self.circuit_state() != CircuitState::HalfOpen {
+            return None;
+        }
+        let probe = self.probe_in_flight.load(Ordering::Acquire);
+        (probe != 0).then(|| ProbeGuard {
+            endpoint: Arc::clone(self),
+            probe,
+        })
+    }
 
-        let last_check = self.last_health_check.read();
-        match *last_check {
+    /// Check if the cooldown since the circuit opened has elapsed.
+    fn cooldown_elapsed(&self, cooldown: Duration) -> bool {
+        match *self.opened_at.read() {
             Some(instant) => instant.elapsed() >= cooldown,
             None => true,
         }
     }
 
+    /// Get the endpoint's health, to carry over to a later run.
+    ///
+    /// An unhealthy endpoint is checked as of when its circuit opened, and a
+    /// healthy one as of its latest failure, so saving health again doesn't
+    /// restart the cooldown or keep old failures from expiring.
+    #[must_use]
+    pub fn health(&self) -> EndpointHealth {
+        let healthy = self.is_healthy();
+        let opened_at = (!healthy).then(|| *self.opened_at.read()).flatten();
+        let last_check = match opened_at {
+            Some(instant) => chrono::Duration::from_std(instant.elapsed())
+                .map_or_else(|_| Utc::now(), |age| Utc::now() - age),
+            None => self.last_failure.read().unwrap_or_else(Utc::now),
+        };
+        EndpointHealth {
+            url: self.url().to_string(),
+            healthy,
+            consecutive_failures: self.consecutive_failures.load(Ordering::Relaxed),
+            last_check,
+        }
+    }
+
+    /// Start from health saved by an earlier run.
+    ///
+    /// An endpoint saved as unhealthy starts with its circuit open, with the
+    /// cooldown counted from when it opened in the earlier run.
+    pub fn restore_health(&self, health: &EndpointHealth) {
+        self.consecutive_failures
+            .store(health.consecutive_failures, Ordering::Relaxed);
+        if health.consecutive_failures > 0 {
+            *self.last_failure.write() = Some(health.last_check);
+        }
+        if !health.healthy {
+            let age = (Utc::now() - health.last_check)
+                .to_std()
+                .unwrap_or_default();
+            *self.circuit.write() = CircuitState::Open;
+            *self.opened_at.write() = Instant::now().checked_sub(age);
+        }
+    }
+
     /// Record a successful request.
     pub fn record_success(&self, latency: Duration) {
         self.success_count.fetch_add(1, Ordering::Relaxed);
         self.total_latency_us
             .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+        self.latency_histogram.record(latency);
+        self.record_latency(latency);
         self.consecutive_failures.store(0, Ordering::Relaxed);
+        self.record_outcome(false);
         self.mark_healthy();
     }
 
+    /// Fold a latency sample into the moving average.
+    fn record_latency(&self, latency: Duration) {
+        let sample = latency.as_secs_f64() * 1_000_000.0;
+        let decay = f64::from_bits(self.latency_decay.load(Ordering::Relaxed));
+        let _ = self
+            .ewma_latency_us
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                let average = f64::from_bits(bits);
+                let updated = if bits == 0 {
+                    sample
+                } else {
+                    average + decay * (sample - average)
+                };
+                Some(updated.to_bits())
+            });
+    }
+
+    /// Set the weight of the newest sample in the latency moving average.
+    pub fn set_latency_decay(&self, decay: f64) {
+        self.latency_decay.store(decay.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Get the moving average of latency in milliseconds, if any request has succeeded.
+    #[must_use]
+    pub fn ewma_latency_ms(&self) -> Option<f64> {
+        match self.ewma_latency_us.load(Ordering::Relaxed) {
+            0 => None,
+            bits => Some(f64::from_bits(bits) / 1000.0),
+        }
+    }
+
     /// Record a failed request.
     pub fn record_failure(&self) {
         self.failure_count.fetch_add(1, Ordering::Relaxed);
         let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        *self.last_failure.write() = Some(Utc::now());
+        let too_frequent = self.record_outcome(true);
 
-        // Mark unhealthy after 3 consecutive failures
-        if failures >= 3 {
+        // A failed probe re-opens the circuit; otherwise trip after enough failures
+        if self.circuit_state() == CircuitState::HalfOpen
+            || failures >= self.config.unhealthy_after as usize
+            || too_frequent
+        {
             self.mark_unhealthy();
         }
     }
 
+    /// Count a failed request under the HTTP status it ended with, if any.
+    pub fn record_failure_status(&self, status: Option<u16>) {
+        self.failure_statuses.record(status);
+    }
+
+    /// Add an outcome to the recent window, returning whether the failure
+    /// rate over a full window reached `unhealthy_failure_rate`.
+    #[allow(clippy::cast_precision_loss)]
+    fn record_outcome(&self, failed: bool) -> bool {
+        let Some(max_rate) = self.config.unhealthy_failure_rate else {
+            return false;
+        };
+        let window = self.config.failure_rate_window;
+        let mut outcomes = self.recent_outcomes.lock();
+        if outcomes.len() >= window {
+            outcomes.pop_front();
+        }
+        outcomes.push_back(failed);
+        let failures = outcomes.iter().filter(|&&failed| failed).count();
+        outcomes.len() >= window && failures as f64 / window as f64 >= max_rate
+    }
+
     /// Check if we can send more requests to this endpoint.
     pub fn can_accept(&self) -> bool {
         self.in_flight.load(Ordering::Relaxed) < self.config.max_concurrent as usize
+            && !self.is_exhausted()
     }
 
-    /// Acquire a slot for sending a request.
-    pub fn acquire(&self) -> bool {
-        let current = self.in_flight.load(Ordering::Relaxed);
-        if current >= self.config.max_concurrent as usize {
-            return false;
+    /// Count a request against the endpoint's request limit, returning
+    /// whether it was still under it.
+    pub fn try_take_request(&self) -> bool {
+        let Some(max) = self.config.max_requests else {
+            self.requests_taken.fetch_add(1, Ordering::Relaxed);
+            return true;
+        };
+        self.requests_taken
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |taken| {
+                (taken < max).then_some(taken + 1)
+            })
+            .is_ok()
+    }
+
+    /// Check whether the endpoint has taken as many requests as it may.
+    #[must_use]
+    pub fn is_exhausted(&self) -> bool {
+        self.config
+            .max_requests
+            .is_some_and(|max| self.requests_taken.load(Ordering::Relaxed) >= max)
+    }
+
+    /// Wait for a slot for sending a request.
+    ///
+    /// Waiting requests get slots in the order they asked for them.
+    pub async fn acquire(self: &Arc<Self>) -> EndpointPermit {
+        let waiting = Waiting::new(&self.waiting);
+        // The semaphore is never closed, so acquiring only fails in theory
+        let permit = Arc::clone(&self.slots).acquire_owned().await.ok();
+        drop(waiting);
+        self.permit(permit)
+    }
+
+    /// Take a slot for sending a request if one is free right away.
+    pub fn try_acquire(self: &Arc<Self>) -> Option<EndpointPermit> {
+        let permit = Arc::clone(&self.slots).try_acquire_owned().ok()?;
+        Some(self.permit(Some(permit)))
+    }
+
+    /// Count a newly taken slot as in flight.
+    fn permit(self: &Arc<Self>, permit: Option<OwnedSemaphorePermit>) -> EndpointPermit {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::Relaxed);
+        EndpointPermit {
+            endpoint: Arc::clone(self),
+            _permit: permit,
         }
-        self.in_flight.fetch_add(1, Ordering::Relaxed);
-        true
     }
 
-    /// Release a slot after completing a request.
-    pub fn release(&self) {
-        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    /// Check whether the endpoint is less loaded than `other` for its
+    /// capacity, counting requests in flight and waiting for a slot.
+    fn less_utilized_than(&self, other: &Self) -> bool {
+        let load = |e: &Self| {
+            (e.in_flight.load(Ordering::Relaxed) + e.waiting.load(Ordering::Relaxed)) as u64
+        };
+        // Compare load / max_concurrent without rounding
+        load(self) * u64::from(other.config.max_concurrent)
+            < load(other) * u64::from(self.config.max_concurrent)
+    }
+
+    /// Get the highest number of requests that were in flight at once.
+    #[must_use]
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Get the latency distribution of successful requests.
+    pub fn latency_histogram(&self) -> &LatencyHistogram {
+        &self.latency_histogram
     }
 
     /// Get average latency in milliseconds.
@@ -142,14 +503,145 @@ impl Endpoint {
             (total as f64 / count as f64) / 1000.0
         }
     }
+
+    /// Get a snapshot of this endpoint's statistics.
+    #[must_use]
+    pub fn stats(&self) -> EndpointStats {
+        EndpointStats {
+            url: self.url().to_string(),
+            success_count: self.success_count.load(Ordering::Relaxed),
+            failure_count: self.failure_count.load(Ordering::Relaxed),
+            avg_latency_ms: self.avg_latency_ms(),
+            max_in_flight: self.max_in_flight(),
+            max_concurrent: self.config.max_concurrent,
+            requests_taken: self.requests_taken.load(Ordering::Relaxed),
+            max_requests: self.config.max_requests,
+            circuit_state: self.circuit_state(),
+            keys: self.key_pool.stats(),
+            failure_statuses: self.failure_statuses.snapshot(),
+        }
+    }
+}
+
+/// A slot on an endpoint for one request, released when dropped.
+#[derive(Debug)]
+pub struct EndpointPermit {
+    endpoint: Arc<Endpoint>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl EndpointPermit {
+    /// Get the endpoint the slot is on.
+    #[must_use]
+    pub fn endpoint(&self) -> &Arc<Endpoint> {
+        &self.endpoint
+    }
+}
+
+impl Drop for EndpointPermit {
+    fn drop(&mut self) {
+        self.endpoint.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Releases a half-open probe when dropped, unless its outcome was recorded first.
+#[derive(Debug)]
+pub struct ProbeGuard {
+    endpoint: Arc<Endpoint>,
+    probe: u64,
+}
+
+impl Drop for ProbeGuard {
+    fn drop(&mut self) {
+        // A recorded outcome already cleared the probe, and a later probe keeps its own id
+        let _ = self.endpoint.probe_in_flight.compare_exchange(
+            self.probe,
+            0,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+    }
+}
+
+/// Counts a request as waiting for a slot until dropped, so a wait that is
+/// cancelled stops counting too.
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    fn new(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::Relaxed);
+        Self(count)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A snapshot of a single endpoint's statistics.
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointStats {
+    /// The endpoint URL.
+    pub url: String,
+    /// Successful requests.
+    pub success_count: u64,
+    /// Failed requests.
+    pub failure_count: u64,
+    /// Average latency of successful requests in milliseconds.
+    pub avg_latency_ms: f64,
+    /// Highest number of requests in flight at once.
+    pub max_in_flight: usize,
+    /// Cap on requests in flight; a peak at the cap means it held requests back.
+    pub max_concurrent: u32,
+    /// Requests the endpoint was selected for.
+    pub requests_taken: u64,
+    /// Cap on the requests the endpoint takes in the run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_requests: Option<u64>,
+    /// Circuit breaker state.
+    pub circuit_state: CircuitState,
+    /// Responses to each of the endpoint's API keys.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<KeyStats>,
+    /// Failed requests by the HTTP status they ended with.
+    pub failure_statuses: FailureStatuses,
+}
+
+/// Algorithm used to pick an endpoint for each request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadBalanceStrategy {
+    /// Pick an endpoint at random, proportionally to its weight.
+    #[default]
+    WeightedRandom,
+    /// Sample two endpoints by weight and pick the one with fewer requests in flight.
+    PowerOfTwoChoices,
+    /// Pick an endpoint at random, proportionally to its weight divided by its recent latency.
+    LatencyWeighted,
+    /// Route requests with the same hash key to the same endpoint, using a weighted hash ring.
+    ConsistentHash,
+    /// Cycle through endpoints in proportion to their weight, interleaving picks (nginx's smooth round-robin).
+    WeightedRoundRobin,
+    /// Pick the endpoint using the smallest share of its `max_concurrent`, counting requests waiting for a slot.
+    LeastUtilized,
 }
 
 /// Weighted load balancer for distributing requests across endpoints.
 #[derive(Debug)]
 pub struct LoadBalancer {
     endpoints: Vec<Arc<Endpoint>>,
-    #[allow(dead_code)]
+    /// Running sum of endpoint weights, for sampling without a full scan.
+    cumulative_weights: Vec<u32>,
     total_weight: u32,
+    strategy: LoadBalanceStrategy,
+    /// Hash ring of `(point, endpoint index)`, sorted by point, for consistent hashing.
+    ring: Vec<(u64, usize)>,
+    /// Current weight of each endpoint for smooth weighted round-robin.
+    current_weights: Mutex<Vec<i64>>,
+    /// Randomness for the random selection strategies.
+    rng: RandomSource,
 }
 
 impl LoadBalancer {
@@ -164,21 +656,150 @@ impl LoadBalancer {
             .map(|c| Arc::new(Endpoint::new(c)))
             .collect();
 
-        let total_weight = endpoints.iter().map(|e| e.config.weight).sum();
+        let cumulative_weights: Vec<u32> = endpoints
+            .iter()
+            .scan(0, |total, e| {
+                *total += e.config.weight;
+                Some(*total)
+            })
+            .collect();
+        let total_weight = cumulative_weights.last().copied().unwrap_or(0);
+        let current_weights = Mutex::new(vec![0; endpoints.len()]);
 
         Ok(Self {
             endpoints,
+            cumulative_weights,
             total_weight,
+            strategy: LoadBalanceStrategy::default(),
+            ring: Vec::new(),
+            current_weights,
+            rng: RandomSource::default(),
         })
     }
 
+    /// Set the selection strategy.
+    #[must_use]
+    pub fn with_strategy(mut self, strategy: LoadBalanceStrategy) -> Self {
+        self.strategy = strategy;
+        self.ring = if strategy == LoadBalanceStrategy::ConsistentHash {
+            self.build_ring()
+        } else {
+            Vec::new()
+        };
+        self
+    }
+
+    /// Place each endpoint on the hash ring, with points in proportion to its weight.
+    fn build_ring(&self) -> Vec<(u64, usize)> {
+        let mut ring: Vec<_> = self
+            .endpoints
+            .iter()
+            .enumerate()
+            .flat_map(|(index, endpoint)| {
+                (0..endpoint.config.weight * RING_POINTS_PER_WEIGHT)
+                    // The point goes first so that each one mixes through the whole URL
+                    .map(move |point| {
+                        let point = point.to_le_bytes();
+                        (hash_of(&[&point, endpoint.url().as_bytes()]), index)
+                    })
+            })
+            .collect();
+        ring.sort_unstable();
+        ring
+    }
+
+    /// Seed the random selection strategies, so the same seed picks the same endpoints.
+    #[must_use]
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        self.rng = RandomSource::new(seed);
+        self
+    }
+
+    /// Set how strongly each new latency sample moves an endpoint's moving
+    /// average, between 0 (never) and 1 (only the latest sample counts).
+    #[must_use]
+    pub fn with_latency_decay(self, decay: f64) -> Self {
+        for endpoint in &self.endpoints {
+            endpoint.set_latency_decay(decay);
+        }
+        self
+    }
+
+    /// Start each endpoint from the health an earlier run saved for its URL.
+    #[must_use]
+    pub fn with_health(self, state: &HealthState) -> Self {
+        for endpoint in &self.endpoints {
+            if let Some(health) = state.get(endpoint.url()) {
+                endpoint.restore_health(health);
+                if !health.healthy {
+                    info!(
+                        endpoint = endpoint.url(),
+                        "Starting with circuit open, as saved by an earlier run"
+                    );
+                }
+            }
+        }
+        self
+    }
+
     /// Select an endpoint using weighted random selection.
     pub fn select(&self) -> Result<Arc<Endpoint>> {
         self.select_with_cooldown(Duration::from_secs(30))
     }
 
+    /// Select an endpoint for a request with the given hash key.
+    ///
+    /// With the consistent hash strategy, a key maps to the same endpoint as
+    /// long as it is healthy and has capacity; otherwise the next endpoint on
+    /// the ring is used. Requests without a key, or when every endpoint is
+    /// unavailable, fall back to [`LoadBalancer::select`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::AllEndpointsUnhealthy`] if no endpoint can take the request.
+    pub fn select_for_key(&self, key: Option<&str>) -> Result<Arc<Endpoint>> {
+        let ring = key.and_then(|key| self.ring_select(key));
+        match ring.filter(|endpoint| endpoint.try_take_request()) {
+            Some(endpoint) => Ok(endpoint),
+            None => self.select(),
+        }
+    }
+
+    /// Check whether every endpoint has taken as many requests as it may.
+    #[must_use]
+    pub fn is_exhausted(&self) -> bool {
+        self.endpoints.iter().all(|e| e.is_exhausted())
+    }
+
     /// Select an endpoint with a custom cooldown for unhealthy endpoints.
+    ///
+    /// The request is counted against the endpoint's request limit.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::EndpointsExhausted`] if every endpoint has
+    /// reached its limit, or [`BlazeError::AllEndpointsUnhealthy`] if no
+    /// endpoint can take the request.
     pub fn select_with_cooldown(&self, cooldown: Duration) -> Result<Arc<Endpoint>> {
+        loop {
+            let endpoint = self.pick(cooldown)?;
+            // Another request may have taken the endpoint's last one since it was picked
+            if endpoint.try_take_request() {
+                return Ok(endpoint);
+            }
+            // Let the next request probe the endpoint if this one claimed its probe
+            drop(endpoint.guard_probe());
+        }
+    }
+
+    /// Pick an endpoint without counting the request against it.
+    fn pick(&self, cooldown: Duration) -> Result<Arc<Endpoint>> {
+        if self.strategy == LoadBalanceStrategy::PowerOfTwoChoices {
+            if let Some(endpoint) = self.power_of_two_select() {
+                return Ok(endpoint);
+            }
+        }
+
         // First, try to find a healthy endpoint with capacity
         let available: Vec<_> = self
             .endpoints
@@ -187,28 +808,203 @@ impl LoadBalancer {
             .collect();
 
         if !available.is_empty() {
-            return Ok(self.weighted_select(&available));
+            return Ok(self.strategy_select(&available));
         }
 
-        // If no healthy endpoints, try endpoints past their cooldown
-        let recovering: Vec<_> = self
+        // If no healthy endpoints, let a single probe through to an endpoint past its cooldown
+        let mut recovering: Vec<_> = self
             .endpoints
             .iter()
             .filter(|e| e.should_retry(cooldown) && e.can_accept())
             .collect();
 
-        if !recovering.is_empty() {
-            return Ok(self.weighted_select(&recovering));
+        while !recovering.is_empty() {
+            let candidate = self.weighted_select(&recovering);
+            if candidate.try_begin_probe(cooldown) {
+                return Ok(candidate);
+            }
+            // Another worker claimed the probe first
+            recovering.retain(|e| !Arc::ptr_eq(e, &candidate));
         }
 
+        // Every healthy endpoint is at capacity, so pick one to wait on for a slot
+        let busy: Vec<_> = self
+            .endpoints
+            .iter()
+            .filter(|e| e.is_healthy() && !e.is_exhausted())
+            .collect();
+        if !busy.is_empty() {
+            return Ok(self.strategy_select(&busy));
+        }
+
+        if self.is_exhausted() {
+            return Err(BlazeError::EndpointsExhausted);
+        }
         Err(BlazeError::AllEndpointsUnhealthy)
     }
 
+    /// Walk the hash ring clockwise from the key to the first available endpoint.
+    fn ring_select(&self, key: &str) -> Option<Arc<Endpoint>> {
+        let hash = hash_of(&[key.as_bytes()]);
+        let start = self.ring.partition_point(|&(point, _)| point < hash);
+        self.ring
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(self.ring.len())
+            .map(|&(_, index)| &self.endpoints[index])
+            .find(|e| e.is_healthy() && e.can_accept())
+            .cloned()
+    }
+
+    /// Sample two endpoints by weight and pick the less loaded one.
+    ///
+    /// Returns `None` if neither sample can take a request, leaving the
+    /// caller to fall back to scanning all endpoints.
+    fn power_of_two_select(&self) -> Option<Arc<Endpoint>> {
+        if self.total_weight == 0 {
+            return None;
+        }
+        let sample = || {
+            let pick = self.rng.with(|rng| rng.random_range(0..self.total_weight));
+            let index = self.cumulative_weights.partition_point(|&w| w <= pick);
+            Some(&self.endpoints[index]).filter(|e| e.is_healthy() && e.can_accept())
+        };
+
+        match (sample(), sample()) {
+            (Some(a), Some(b)) => {
+                let load = |e: &Endpoint| e.in_flight.load(Ordering::Relaxed);
+                Some(Arc::clone(if load(b) < load(a) { b } else { a }))
+            }
+            (Some(e), None) | (None, Some(e)) => Some(Arc::clone(e)),
+            (None, None) => None,
+        }
+    }
+
+    /// Perform weighted random selection, scaling weights down by recent latency.
+    ///
+    /// Endpoints without a latency sample yet are scored like the fastest
+    /// endpoint, so they still get traffic.
+    fn latency_weighted_select(&self, endpoints: &[&Arc<Endpoint>]) -> Arc<Endpoint> {
+        let latencies: Vec<_> = endpoints.iter().map(|e| e.ewma_latency_ms()).collect();
+        let fastest = latencies
+            .iter()
+            .flatten()
+            .copied()
+            .reduce(f64::min)
+            .unwrap_or(1.0);
+        let scores: Vec<f64> = endpoints
+            .iter()
+            .zip(&latencies)
+            .map(|(e, latency)| {
+                f64::from(e.config.weight) / latency.unwrap_or(fastest).max(MIN_SCORED_LATENCY_MS)
+            })
+            .collect();
+
+        let total: f64 = scores.iter().sum();
+        let mut pick = self.rng.with(|rng| rng.random_range(0.0..total));
+        for (endpoint, score) in endpoints.iter().zip(&scores) {
+            if pick < *score {
+                return Arc::clone(endpoint);
+            }
+            pick -= score;
+        }
+
+        // Floating point rounding can leave a sliver past the last endpoint
+        Arc::clone(endpoints[endpoints.len() - 1])
+    }
+
+    /// Select an endpoint other than `exclude` for a hedged copy of a request.
+    ///
+    /// Only healthy endpoints with capacity are considered, so a hedge never
+    /// lands on a circuit that is probing for recovery.
+    pub fn select_other(&self, exclude: &Endpoint) -> Option<Arc<Endpoint>> {
+        self.select_available(|e| !std::ptr::eq(e, exclude))
+    }
+
+    /// Select an endpoint for the retry of a request that failed on each of `tried`.
+    ///
+    /// Like [`LoadBalancer::select_other`], only healthy endpoints with
+    /// capacity are considered, and the retry counts against the endpoint's
+    /// request limit.
+    pub fn select_untried(&self, tried: &[Arc<Endpoint>]) -> Option<Arc<Endpoint>> {
+        self.select_available(|e| !tried.iter().any(|t| std::ptr::eq(t.as_ref(), e)))
+    }
+
+    /// Pick one of the healthy endpoints with capacity that `include` accepts.
+    fn select_available(&self, include: impl Fn(&Endpoint) -> bool) -> Option<Arc<Endpoint>> {
+        let available: Vec<_> = self
+            .endpoints
+            .iter()
+            .filter(|e| include(e) && e.is_healthy() && e.can_accept())
+            .collect();
+
+        (!available.is_empty())
+            .then(|| self.strategy_select(&available))
+            .filter(|endpoint| endpoint.try_take_request())
+    }
+
+    /// Pick one of the available endpoints with the configured strategy.
+    fn strategy_select(&self, available: &[&Arc<Endpoint>]) -> Arc<Endpoint> {
+        match self.strategy {
+            LoadBalanceStrategy::LatencyWeighted => self.latency_weighted_select(available),
+            LoadBalanceStrategy::WeightedRoundRobin => self.round_robin_select(available),
+            LoadBalanceStrategy::LeastUtilized => self.least_utilized_select(available),
+            _ => self.weighted_select(available),
+        }
+    }
+
+    /// Pick the endpoint with the most free capacity relative to its size.
+    ///
+    /// Endpoints whose queues are equally full are picked from by weight, so
+    /// idle endpoints still split traffic by weight.
+    fn least_utilized_select(&self, available: &[&Arc<Endpoint>]) -> Arc<Endpoint> {
+        let mut least: Vec<&Arc<Endpoint>> = Vec::new();
+        for &endpoint in available {
+            match least.first() {
+                Some(first) if endpoint.less_utilized_than(first) => least = vec![endpoint],
+                Some(first) if first.less_utilized_than(endpoint) => {}
+                _ => least.push(endpoint),
+            }
+        }
+        self.weighted_select(&least)
+    }
+
+    /// Perform smooth weighted round-robin selection.
+    ///
+    /// Each available endpoint's current weight grows by its weight, and the
+    /// endpoint with the highest current weight is picked and set back by the
+    /// total. Over any run of picks every endpoint gets its share by weight,
+    /// spread out rather than in bursts.
+    fn round_robin_select(&self, available: &[&Arc<Endpoint>]) -> Arc<Endpoint> {
+        let mut current = self.current_weights.lock();
+        let mut total = 0;
+        let mut best: Option<usize> = None;
+        for (index, endpoint) in self.endpoints.iter().enumerate() {
+            if !available.iter().any(|e| Arc::ptr_eq(e, endpoint)) {
+                continue;
+            }
+            let weight = i64::from(endpoint.config.weight);
+            current[index] += weight;
+            total += weight;
+            if best.map_or(true, |best| current[index] > current[best]) {
+                best = Some(index);
+            }
+        }
+
+        match best {
+            Some(index) => {
+                current[index] -= total;
+                Arc::clone(&self.endpoints[index])
+            }
+            None => Arc::clone(available[0]),
+        }
+    }
+
     /// Perform weighted random selection.
     fn weighted_select(&self, endpoints: &[&Arc<Endpoint>]) -> Arc<Endpoint> {
         let total: u32 = endpoints.iter().map(|e| e.config.weight).sum();
-        let mut rng = rand::rng();
-        let mut pick = rng.random_range(0..total);
+        let mut pick = self.rng.with(|rng| rng.random_range(0..total));
 
         for endpoint in endpoints {
             if pick < endpoint.config.weight {
@@ -240,6 +1036,19 @@ impl LoadBalancer {
     }
 }
 
+/// Hash byte strings onto the consistent hash ring with FNV-1a.
+fn hash_of(parts: &[&[u8]]) -> u64 {
+    let mut hash = FNV_OFFSET;
+    for part in parts {
+        // A byte no string contains keeps the parts apart
+        for &byte in part.iter().chain(&[0xff]) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+    hash
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -251,6 +1060,7 @@ mod tests {
             api_key: None,
             model: None,
             max_concurrent: 100,
+            ..Default::default()
         }
     }
 
@@ -277,6 +1087,31 @@ mod tests {
         assert_eq!(endpoint.avg_latency_ms(), 150.0);
     }
 
+    #[tokio::test]
+    async fn test_endpoint_slots() {
+        let endpoint = Arc::new(Endpoint::new(EndpointConfig {
+            max_concurrent: 2,
+            ..test_endpoint()
+        }));
+        let first = endpoint.try_acquire().unwrap();
+        let _second = endpoint.acquire().await;
+        assert!(endpoint.try_acquire().is_none());
+        assert!(!endpoint.can_accept());
+
+        // A waiting request gets the slot as soon as one is dropped
+        let waiting = tokio::spawn({
+            let endpoint = Arc::clone(&endpoint);
+            async move { endpoint.acquire().await.endpoint().url().to_string() }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        drop(first);
+        assert_eq!(waiting.await.unwrap(), "http://localhost:8080");
+        assert_eq!(endpoint.in_flight.load(Ordering::Relaxed), 1);
+        assert_eq!(endpoint.max_in_flight(), 2);
+        assert_eq!(endpoint.stats().max_concurrent, 2);
+    }
+
     #[test]
     fn test_load_balancer() {
         let configs = vec![
@@ -286,6 +1121,7 @@ mod tests {
                 api_key: None,
                 model: None,
                 max_concurrent: 100,
+                ..Default::default()
             },
             EndpointConfig {
                 url: "http://b.test".to_string(),
@@ -293,11 +1129,313 @@ mod tests {
                 api_key: None,
                 model: None,
                 max_concurrent: 100,
+                ..Default::default()
             },
         ];
 
-        let lb = LoadBalancer::new(configs).unwrap();
+        let lb = LoadBalancer::new(configs.clone()).unwrap();
         assert_eq!(lb.endpoints().len(), 2);
         assert_eq!(lb.healthy_count(), 2);
+
+        // Load balancers with the same seed pick the same endpoints
+        let picks = |seed| {
+            let lb = LoadBalancer::new(configs.clone())
+                .unwrap()
+                .with_seed(Some(seed));
+            (0..32)
+                .map(|_| lb.select().unwrap().url().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(picks(7), picks(7));
+        assert_ne!(picks(7), picks(8));
+    }
+
+    #[test]
+    fn test_power_of_two_choices() {
+        let configs = (0..3)
+            .map(|i| EndpointConfig {
+                url: format!("http://{i}.test"),
+                ..test_endpoint()
+            })
+            .collect();
+        // Seeded, so an unlucky run of draws can't fail the count below
+        let lb = LoadBalancer::new(configs)
+            .unwrap()
+            .with_strategy(LoadBalanceStrategy::PowerOfTwoChoices)
+            .with_seed(Some(2));
+
+        // Load up the first two endpoints so the idle one wins any pairing it is in
+        let _slots: Vec<_> = (0..10)
+            .flat_map(|_| {
+                [
+                    lb.endpoints()[0].try_acquire(),
+                    lb.endpoints()[1].try_acquire(),
+                ]
+            })
+            .collect();
+        let idle_picks = (0..300)
+            .filter(|_| lb.select().unwrap().url() == "http://2.test")
+            .count();
+        assert!(idle_picks > 150, "idle endpoint picked {idle_picks} times");
+
+        // Unavailable endpoints are never picked
+        lb.endpoints()[2].mark_unhealthy();
+        for _ in 0..50 {
+            assert_ne!(lb.select().unwrap().url(), "http://2.test");
+        }
+    }
+
+    #[test]
+    fn test_latency_weighted() {
+        let endpoint = Endpoint::new(test_endpoint());
+        assert_eq!(endpoint.ewma_latency_ms(), None);
+        endpoint.set_latency_decay(0.5);
+        endpoint.record_success(Duration::from_millis(100));
+        endpoint.record_success(Duration::from_millis(200));
+        assert_eq!(endpoint.ewma_latency_ms(), Some(150.0));
+
+        let configs = (0..2)
+            .map(|i| EndpointConfig {
+                url: format!("http://{i}.test"),
+                ..test_endpoint()
+            })
+            .collect();
+        let lb = LoadBalancer::new(configs)
+            .unwrap()
+            .with_strategy(LoadBalanceStrategy::LatencyWeighted)
+            .with_latency_decay(1.0);
+
+        // The fast endpoint should take roughly nine picks in ten
+        lb.endpoints()[0].record_success(Duration::from_millis(10));
+        lb.endpoints()[1].record_success(Duration::from_millis(90));
+        let fast_picks = (0..500)
+            .filter(|_| lb.select().unwrap().url() == "http://0.test")
+            .count();
+        assert!(fast_picks > 400, "fast endpoint picked {fast_picks} times");
+    }
+
+    #[test]
+    fn test_weighted_round_robin() {
+        let configs = [5, 1, 1]
+            .into_iter()
+            .enumerate()
+            .map(|(i, weight)| EndpointConfig {
+                url: format!("http://{i}.test"),
+                weight,
+                ..test_endpoint()
+            })
+            .collect();
+        let lb = LoadBalancer::new(configs)
+            .unwrap()
+            .with_strategy(LoadBalanceStrategy::WeightedRoundRobin);
+
+        // Picks follow the weights exactly, with the light endpoints spread out
+        let picks: String = (0..14)
+            .map(|_| lb.select().unwrap().url().chars().nth(7).unwrap())
+            .collect();
+        assert_eq!(picks, "00102000010200");
+
+        // An unavailable endpoint is skipped without skewing the others
+        lb.endpoints()[0].mark_unhealthy();
+        let picks: String = (0..4)
+            .map(|_| lb.select().unwrap().url().chars().nth(7).unwrap())
+            .collect();
+        assert_eq!(picks.matches('1').count(), 2);
+        assert_eq!(picks.matches('2').count(), 2);
+    }
+
+    #[test]
+    fn test_consistent_hash() {
+        let configs = (0..3)
+            .map(|i| EndpointConfig {
+                url: format!("http://{i}.test"),
+                ..test_endpoint()
+            })
+            .collect();
+        let lb = LoadBalancer::new(configs)
+            .unwrap()
+            .with_strategy(LoadBalanceStrategy::ConsistentHash);
+
+        // The same key always lands on the same endpoint, and keys spread out
+        let owners: Vec<_> = (0..300)
+            .map(|i| lb.select_for_key(Some(&format!("user-{i}"))).unwrap())
+            .collect();
+        for (i, owner) in owners.iter().enumerate() {
+            let again = lb.select_for_key(Some(&format!("user-{i}"))).unwrap();
+            assert!(Arc::ptr_eq(owner, &again));
+        }
+        for endpoint in lb.endpoints() {
+            let keys = owners.iter().filter(|o| Arc::ptr_eq(o, endpoint)).count();
+            assert!(keys > 50, "{} owns {keys} keys", endpoint.url());
+        }
+
+        // Keys of an unhealthy endpoint move elsewhere, the rest stay put
+        lb.endpoints()[0].mark_unhealthy();
+        for (i, owner) in owners.iter().enumerate() {
+            let now = lb.select_for_key(Some(&format!("user-{i}"))).unwrap();
+            if Arc::ptr_eq(owner, &lb.endpoints()[0]) {
+                assert!(!Arc::ptr_eq(&now, owner));
+            } else {
+                assert!(Arc::ptr_eq(&now, owner));
+            }
+        }
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let endpoint = Endpoint::new(test_endpoint());
+        assert_eq!(endpoint.circuit_state(), CircuitState::Closed);
+
+        for _ in 0..3 {
+            endpoint.record_failure();
+        }
+        assert_eq!(endpoint.circuit_state(), CircuitState::Open);
+        assert!(!endpoint.try_begin_probe(Duration::from_secs(60)));
+
+        // Only one probe is let through once the cooldown has elapsed
+        assert!(endpoint.try_begin_probe(Duration::ZERO));
+        assert_eq!(endpoint.circuit_state(), CircuitState::HalfOpen);
+        assert!(!endpoint.try_begin_probe(Duration::ZERO));
+
+        // A failed probe re-opens the circuit
+        endpoint.record_failure();
+        assert_eq!(endpoint.circuit_state(), CircuitState::Open);
+
+        // A successful probe closes it
+        assert!(endpoint.try_begin_probe(Duration::ZERO));
+        endpoint.record_success(Duration::from_millis(10));
+        assert_eq!(endpoint.circuit_state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_dropped_probe() {
+        let endpoint = Arc::new(Endpoint::new(test_endpoint()));
+        endpoint.mark_unhealthy();
+        assert!(endpoint.try_begin_probe(Duration::ZERO));
+        let probe = endpoint.guard_probe().unwrap();
+        assert!(!endpoint.should_retry(Duration::ZERO));
+
+        // A probe dropped without an outcome lets the next request probe
+        drop(probe);
+        assert!(endpoint.should_retry(Duration::ZERO));
+        assert!(endpoint.try_begin_probe(Duration::ZERO));
+
+        // Once the probe failed, dropping its guard leaves a later probe alone
+        let probe = endpoint.guard_probe().unwrap();
+        endpoint.record_failure();
+        assert!(endpoint.try_begin_probe(Duration::ZERO));
+        drop(probe);
+        assert!(!endpoint.try_begin_probe(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_unhealthy_thresholds() {
+        let endpoint = Endpoint::new(EndpointConfig {
+            unhealthy_after: 5,
+            ..test_endpoint()
+        });
+        for _ in 0..4 {
+            endpoint.record_failure();
+        }
+        assert!(endpoint.is_healthy());
+        endpoint.record_failure();
+        assert!(!endpoint.is_healthy());
+
+        // Half of the last four requests failing trips the circuit, but only once four are in
+        let endpoint = Endpoint::new(EndpointConfig {
+            unhealthy_after: 10,
+            unhealthy_failure_rate: Some(0.5),
+            failure_rate_window: 4,
+            ..test_endpoint()
+        });
+        endpoint.record_failure();
+        endpoint.record_success(Duration::from_millis(10));
+        endpoint.record_failure();
+        assert!(endpoint.is_healthy());
+        endpoint.record_success(Duration::from_millis(10));
+        assert!(endpoint.is_healthy());
+        endpoint.record_failure();
+        assert!(!endpoint.is_healthy());
+    }
+
+    #[test]
+    fn test_load_balancer_single_probe() {
+        let lb = LoadBalancer::new(vec![test_endpoint()]).unwrap();
+        lb.endpoints()[0].mark_unhealthy();
+
+        assert!(lb.select_with_cooldown(Duration::ZERO).is_ok());
+        assert!(lb.select_with_cooldown(Duration::ZERO).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_least_utilized() {
+        let configs = vec![
+            EndpointConfig {
+                url: "http://big.test".to_string(),
+                weight: 9,
+                max_concurrent: 8,
+                ..test_endpoint()
+            },
+            EndpointConfig {
+                url: "http://small.test".to_string(),
+                max_concurrent: 2,
+                ..test_endpoint()
+            },
+        ];
+        let lb = LoadBalancer::new(configs)
+            .unwrap()
+            .with_strategy(LoadBalanceStrategy::LeastUtilized);
+        let (big, small) = (&lb.endpoints()[0], &lb.endpoints()[1]);
+
+        // A quarter of the big endpoint is busier than none of the small one, despite the weights
+        let _big: Vec<_> = (0..2).map(|_| big.try_acquire().unwrap()).collect();
+        assert_eq!(lb.select().unwrap().url(), "http://small.test");
+        let small_slot = small.try_acquire().unwrap();
+        assert_eq!(lb.select().unwrap().url(), "http://big.test");
+
+        // Once both are full, requests queue where the queue is shortest for the capacity
+        let _big: Vec<_> = (0..6).map(|_| big.try_acquire().unwrap()).collect();
+        let _small = small.try_acquire().unwrap();
+        let waiting = tokio::spawn({
+            let small = Arc::clone(small);
+            async move { small.acquire().await.endpoint().url().to_string() }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(lb.select().unwrap().url(), "http://big.test");
+        drop(small_slot);
+        assert_eq!(waiting.await.unwrap(), "http://small.test");
+        assert_eq!(small.waiting.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_request_limits() {
+        let configs = vec![
+            EndpointConfig {
+                url: "http://a.test".to_string(),
+                weight: 9,
+                max_requests: Some(2),
+                ..test_endpoint()
+            },
+            EndpointConfig {
+                url: "http://b.test".to_string(),
+                max_requests: Some(3),
+                ..test_endpoint()
+            },
+        ];
+        let lb = LoadBalancer::new(configs).unwrap();
+
+        // Weights stop mattering once the heavier endpoint is capped
+        let picks: Vec<_> = (0..5)
+            .map(|_| lb.select().unwrap().url().to_string())
+            .collect();
+        assert_eq!(
+            picks.iter().filter(|url| *url == "http://a.test").count(),
+            2
+        );
+        assert!(lb.is_exhausted());
+        assert!(matches!(lb.select(), Err(BlazeError::EndpointsExhausted)));
+
+        let stats = lb.endpoints()[0].stats();
+        assert_eq!((stats.requests_taken, stats.max_requests), (2, Some(2)));
     }
 }
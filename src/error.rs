@@ -30,16 +30,42 @@ pub enum BlazeError {
         source: std::io::Error,
     },
 
+    /// Failed to create the response cache directory.
+    #[error("failed to create cache directory '{path}': {source}")]
+    CacheDir {
+        /// Path to the directory that could not be created.
+        path: PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        source: std::io::Error,
+    },
+
     /// Failed to parse JSON from the input file.
     #[error("failed to parse JSON at line {line}: {source}")]
     JsonParse {
-        /// Line number where the error occurred.
+        /// Line number where the error occurred, or the element index for JSON array input.
         line: usize,
         /// The underlying JSON parsing error.
         #[source]
         source: serde_json::Error,
     },
 
+    /// A line of the input file is not valid UTF-8.
+    #[error("line {line} is not valid UTF-8")]
+    InvalidUtf8 {
+        /// Line number of the request, or the element index for JSON array input.
+        line: usize,
+    },
+
+    /// A line of the input file is longer than the configured limit.
+    #[error("line {line} is longer than {max} bytes")]
+    LineTooLong {
+        /// Line number of the request, or the element index for JSON array input.
+        line: usize,
+        /// The length limit, in bytes.
+        max: usize,
+    },
+
     /// Failed to serialize JSON for output.
     #[error("failed to serialize JSON: {0}")]
     JsonSerialize(#[from] serde_json::Error),
@@ -56,6 +82,10 @@ pub enum BlazeError {
     #[error("all endpoints are currently unhealthy")]
     AllEndpointsUnhealthy,
 
+    /// Every endpoint has taken as many requests as it may.
+    #[error("every endpoint has reached its request limit")]
+    EndpointsExhausted,
+
     /// Invalid configuration.
     #[error("invalid configuration: {0}")]
     InvalidConfig(String),
@@ -81,6 +111,72 @@ pub enum BlazeError {
         message: String,
     },
 
+    /// Checkpoint file does not belong to the input file being processed.
+    #[error("checkpoint '{path}' was created for a different input file than '{input}'")]
+    CheckpointMismatch {
+        /// Path to the checkpoint file.
+        path: PathBuf,
+        /// Path to the input file being processed.
+        input: PathBuf,
+    },
+
+    /// A config file references an environment variable that is not set.
+    #[error("config file '{path}' references environment variable '{name}', which is not set")]
+    UnsetEnvVar {
+        /// Name of the variable.
+        name: String,
+        /// Path to the config file.
+        path: PathBuf,
+    },
+
+    /// A body template references a field the request does not have.
+    #[error("body template field '{field}' is missing at line {line}")]
+    MissingTemplateField {
+        /// Name of the missing field.
+        field: String,
+        /// Line number of the request.
+        line: usize,
+    },
+
+    /// A body to be form-encoded is not a JSON object.
+    #[error("body at line {line} must be a JSON object to be form-encoded")]
+    FormBody {
+        /// Line number of the request.
+        line: usize,
+    },
+
+    /// Failed to bind the metrics server.
+    #[error("failed to bind metrics server to '{addr}': {source}")]
+    MetricsBind {
+        /// Address the server tried to bind.
+        addr: std::net::SocketAddr,
+        /// The underlying I/O error.
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// The configured proxy could not be used.
+    #[error("invalid proxy '{url}': {reason}")]
+    InvalidProxy {
+        /// The proxy URL.
+        url: String,
+        /// Why the proxy was rejected.
+        reason: String,
+    },
+
+    /// An access token could not be fetched.
+    #[error("failed to fetch token from '{url}': {reason}")]
+    TokenRequest {
+        /// The token endpoint URL.
+        url: String,
+        /// Why no token was issued.
+        reason: String,
+    },
+
+    /// Trace export could not be set up.
+    #[error("failed to set up trace export: {0}")]
+    Telemetry(String),
+
     /// Endpoint returned an error status.
     #[error("endpoint returned error status {status}: {body}")]
     EndpointError {
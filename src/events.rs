@@ -0,0 +1,81 @@
+//! Events for following a run from library code.
+//!
+//! The CLI reports progress with its progress bar and summary. Programs that
+//! embed Blaze can pass a channel to [`Processor::with_events`] instead, and
+//! get an event as each request completes, when an endpoint's circuit opens,
+//! when an endpoint rate limits a request, and when a run finishes or fails.
+//! Events are sent as they happen: a consumer that falls behind slows the run
+//! down rather than missing events, and a dropped receiver is ignored.
+//!
+//! [`Processor::with_events`]: crate::Processor::with_events
+
+use crate::request::RequestResult;
+use crate::tracker::StatsSnapshot;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Something that happened during a run.
+// Nearly every event is a completed request, so boxing its result would only add an allocation
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum ProcessEvent {
+    /// A request finished, whether it succeeded, failed or was skipped.
+    RequestCompleted {
+        /// Line number of the request in its input.
+        line_number: usize,
+        /// What became of the request.
+        result: RequestResult,
+    },
+    /// An endpoint failed often enough for its circuit to open, taking it out of rotation.
+    EndpointMarkedUnhealthy {
+        /// The endpoint URL.
+        url: String,
+    },
+    /// An endpoint answered a request with 429 Too Many Requests.
+    RateLimited {
+        /// The endpoint URL.
+        url: String,
+        /// Delay the endpoint asked for with `Retry-After`.
+        retry_after: Option<Duration>,
+    },
+    /// A run of [`Processor::process_files`] finished, with its final statistics.
+    ///
+    /// [`Processor::process_files`]: crate::Processor::process_files
+    RunFinished(Box<StatsSnapshot>),
+    /// A run of [`Processor::process_files`] stopped with an error, such as
+    /// malformed input, after letting its in-flight requests finish.
+    ///
+    /// [`Processor::process_files`]: crate::Processor::process_files
+    RunFailed {
+        /// Why the run failed.
+        error: String,
+        /// Statistics of the requests processed before it stopped.
+        stats: Box<StatsSnapshot>,
+    },
+}
+
+/// Sends events to the channel given to the processor, if there is one.
+#[derive(Debug, Clone, Default)]
+pub struct EventSender(Option<mpsc::Sender<ProcessEvent>>);
+
+impl EventSender {
+    /// Send events to `sender`.
+    #[must_use]
+    pub fn new(sender: mpsc::Sender<ProcessEvent>) -> Self {
+        Self(Some(sender))
+    }
+
+    /// Check whether events are being sent anywhere, to skip building ones that would not be.
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Send an event, waiting for room in the channel.
+    pub async fn send(&self, event: ProcessEvent) {
+        if let Some(sender) = &self.0 {
+            // The receiver may have been dropped, which leaves nobody to tell
+            let _ = sender.send(event).await;
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Endpoint health carried over between runs.
+//!
+//! Short, frequent runs against the same endpoints would each rediscover a
+//! down endpoint by failing their first requests. With a health file, a run
+//! saves the health of its endpoints when it finishes, and the next run
+//! starts from it. Saved health older than a TTL is ignored, as the
+//! endpoint may well have recovered since.
+
+use crate::endpoint::Endpoint;
+use crate::error::{BlazeError, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+/// Health of an endpoint when it was saved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EndpointHealth {
+    /// The endpoint URL, which saved health is matched by.
+    pub url: String,
+    /// Whether the endpoint's circuit was closed.
+    pub healthy: bool,
+    /// Failures since the endpoint's last success.
+    pub consecutive_failures: usize,
+    /// When the endpoint's circuit opened, if it was unhealthy, or else when
+    /// it last failed, falling back to when the health was recorded.
+    pub last_check: DateTime<Utc>,
+}
+
+/// Saved health of a run's endpoints.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HealthState {
+    /// Health of each endpoint, in configuration order.
+    pub endpoints: Vec<EndpointHealth>,
+}
+
+impl HealthState {
+    /// Record the current health of `endpoints`.
+    #[must_use]
+    pub fn capture(endpoints: &[Arc<Endpoint>]) -> Self {
+        Self {
+            endpoints: endpoints.iter().map(|e| e.health()).collect(),
+        }
+    }
+
+    /// Load saved health, leaving out endpoints last checked more than `ttl` ago.
+    ///
+    /// A missing file gives an empty state, as does an unreadable one, with a
+    /// warning: saved health only saves warm-up failures, so it is not worth
+    /// failing a run over.
+    #[must_use]
+    pub fn load(path: &Path, ttl: Duration) -> Self {
+        let content = match std::fs::read(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                warn!(path = %path.display(), "Ignoring unreadable health file: {}", e);
+                return Self::default();
+            }
+        };
+        let mut state: Self = match serde_json::from_slice(&content) {
+            Ok(state) => state,
+            Err(e) => {
+                warn!(path = %path.display(), "Ignoring malformed health file: {}", e);
+                return Self::default();
+            }
+        };
+        let now = Utc::now();
+        state.endpoints.retain(|health| {
+            (now - health.last_check)
+                .to_std()
+                .map_or(true, |age| age <= ttl)
+        });
+        state
+    }
+
+    /// Get the saved health of the endpoint at `url`.
+    #[must_use]
+    pub fn get(&self, url: &str) -> Option<&EndpointHealth> {
+        self.endpoints.iter().find(|health| health.url == url)
+    }
+
+    /// Write the health as JSON.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::OutputFileWrite`] if the file cannot be written.
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_vec_pretty(self)?;
+        tokio::fs::write(path, json)
+            .await
+            .map_err(|e| BlazeError::OutputFileWrite {
+                path: path.to_path_buf(),
+                source: e,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EndpointConfig;
+    use crate::endpoint::{CircuitState, LoadBalancer};
+
+    #[tokio::test]
+    async fn test_health_across_runs() {
+        let configs: Vec<_> = ["http://up", "http://down", "http://flaky"]
+            .iter()
+            .map(|url| EndpointConfig {
+                url: (*url).to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let lb = LoadBalancer::new(configs.clone()).unwrap();
+        lb.endpoints()[1].mark_unhealthy();
+        lb.endpoints()[2].record_failure();
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("health.json");
+        HealthState::capture(lb.endpoints())
+            .save(&path)
+            .await
+            .unwrap();
+
+        // The next run starts with the down endpoint's circuit open
+        let state = HealthState::load(&path, Duration::from_secs(600));
+        let lb = LoadBalancer::new(configs).unwrap().with_health(&state);
+        let endpoints = lb.endpoints();
+        assert!(endpoints[0].is_healthy());
+        assert_eq!(endpoints[1].circuit_state(), CircuitState::Open);
+        assert!(!endpoints[1].should_retry(Duration::from_secs(30)));
+        assert_eq!(endpoints[2].health().consecutive_failures, 1);
+
+        // Saving again keeps when the circuit opened and when the endpoint failed
+        let mut opened = state.get("http://down").unwrap().clone();
+        opened.last_check -= chrono::Duration::minutes(5);
+        endpoints[1].restore_health(&opened);
+        let resaved = endpoints[1].health().last_check;
+        assert!((resaved - opened.last_check).num_seconds().abs() < 1);
+        let failed = state.get("http://flaky").unwrap().last_check;
+        assert_eq!(endpoints[2].health().last_check, failed);
+
+        // Stale health is ignored, and so is a missing file
+        let mut aged = state.clone();
+        aged.endpoints[1].last_check -= chrono::Duration::minutes(20);
+        aged.save(&path).await.unwrap();
+        let state = HealthState::load(&path, Duration::from_secs(600));
+        assert!(state.get("http://down").is_none());
+        assert!(state.get("http://up").is_some());
+        assert!(
+            HealthState::load(&dir.path().join("missing.json"), Duration::MAX)
+                .endpoints
+                .is_empty()
+        );
+    }
+}
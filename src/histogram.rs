@@ -0,0 +1,243 @@
+//! Lock-free latency histogram.
+//!
+//! Latencies are recorded in microseconds into log-linear buckets (16
+//! sub-buckets per power of two, so roughly 6% relative error). Each bucket
+//! is an atomic counter, so recording is a single `fetch_add` and never
+//! takes a lock on the hot path.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Number of bits used for sub-buckets within each power of two.
+const SUB_BUCKET_BITS: u32 = 4;
+/// Number of sub-buckets within each power of two.
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+/// Largest tracked power of two (2^40 µs is roughly 12 days).
+const MAX_EXPONENT: u32 = 40;
+/// Largest recordable value in microseconds; larger values are clamped.
+const MAX_VALUE_US: u64 = (1 << (MAX_EXPONENT + 1)) - 1;
+/// Total number of buckets.
+const BUCKET_COUNT: usize = (MAX_EXPONENT - SUB_BUCKET_BITS + 2) as usize * SUB_BUCKETS;
+
+/// A bucket of recorded latencies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Bucket {
+    /// Lowest latency in the bucket, in microseconds.
+    pub lower_us: u64,
+    /// Exclusive upper bound of the bucket, in microseconds.
+    pub upper_us: u64,
+    /// Latencies recorded in the bucket.
+    pub count: u64,
+}
+
+/// Recorded latencies as counts per bucket, for plotting the distribution.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct HistogramSnapshot {
+    /// Total number of recorded latencies.
+    pub count: u64,
+    /// Buckets holding at least one latency, from the lowest up.
+    pub buckets: Vec<Bucket>,
+}
+
+/// A concurrent latency histogram.
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    buckets: Box<[AtomicU64]>,
+    count: AtomicU64,
+}
+
+impl LatencyHistogram {
+    /// Create an empty histogram.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            buckets: (0..BUCKET_COUNT).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    /// Record a latency.
+    pub fn record(&self, latency: Duration) {
+        let us = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[bucket_index(us)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Total number of recorded latencies.
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Count the recorded latencies at or below a bound.
+    ///
+    /// Buckets straddling the bound are excluded, so the count is accurate to
+    /// the bucket resolution.
+    pub fn count_at_most(&self, bound: Duration) -> u64 {
+        let bound_us = u64::try_from(bound.as_micros()).unwrap_or(u64::MAX);
+        self.buckets
+            .iter()
+            .enumerate()
+            .take_while(|&(index, _)| bucket_upper(index) - 1 <= bound_us)
+            .map(|(_, bucket)| bucket.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Get the latency at a quantile (`0.0..=1.0`), or zero if nothing was recorded.
+    ///
+    /// The result is the highest value of the bucket containing the quantile.
+    pub fn quantile(&self, quantile: f64) -> Duration {
+        self.quantiles(&[quantile])[0]
+    }
+
+    /// Get the latencies at several quantiles in a single pass over the buckets.
+    pub fn quantiles(&self, quantiles: &[f64]) -> Vec<Duration> {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return vec![Duration::ZERO; quantiles.len()];
+        }
+
+        quantiles
+            .iter()
+            .map(|quantile| {
+                #[allow(
+                    clippy::cast_possible_truncation,
+                    clippy::cast_precision_loss,
+                    clippy::cast_sign_loss
+                )]
+                let rank = ((quantile.clamp(0.0, 1.0) * total as f64).ceil() as u64).max(1);
+
+                let mut seen = 0;
+                let index = counts
+                    .iter()
+                    .position(|count| {
+                        seen += count;
+                        seen >= rank
+                    })
+                    .unwrap_or(BUCKET_COUNT - 1);
+                Duration::from_micros(bucket_upper(index) - 1)
+            })
+            .collect()
+    }
+
+    /// Get the counts of the buckets holding at least one latency.
+    #[must_use]
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let buckets: Vec<Bucket> = self
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(index, bucket)| Bucket {
+                lower_us: index.checked_sub(1).map_or(0, bucket_upper),
+                upper_us: bucket_upper(index),
+                count: bucket.load(Ordering::Relaxed),
+            })
+            .filter(|bucket| bucket.count > 0)
+            .collect();
+        HistogramSnapshot {
+            count: buckets.iter().map(|bucket| bucket.count).sum(),
+            buckets,
+        }
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Get the bucket index for a value in microseconds.
+fn bucket_index(us: u64) -> usize {
+    let us = us.min(MAX_VALUE_US);
+    if us < SUB_BUCKETS as u64 {
+        return us as usize;
+    }
+
+    let exponent = us.ilog2();
+    let sub = (us >> (exponent - SUB_BUCKET_BITS)) as usize & (SUB_BUCKETS - 1);
+    (exponent - SUB_BUCKET_BITS + 1) as usize * SUB_BUCKETS + sub
+}
+
+/// Get the exclusive upper bound of a bucket in microseconds.
+fn bucket_upper(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64 + 1;
+    }
+
+    let shift = index / SUB_BUCKETS - 1;
+    let sub = (index % SUB_BUCKETS) as u64;
+    (SUB_BUCKETS as u64 + sub + 1) << shift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_bounds() {
+        for us in [0, 1, 15, 16, 17, 100, 1_000, 123_456, MAX_VALUE_US] {
+            let index = bucket_index(us);
+            assert!(index < BUCKET_COUNT);
+            assert!(us < bucket_upper(index), "{us} not below upper bound");
+            if index > 0 {
+                assert!(us >= bucket_upper(index - 1), "{us} not above lower bound");
+            }
+        }
+    }
+
+    #[test]
+    fn test_quantiles() {
+        let histogram = LatencyHistogram::new();
+        assert_eq!(histogram.quantile(0.5), Duration::ZERO);
+
+        for ms in 1..=100 {
+            histogram.record(Duration::from_millis(ms));
+        }
+        assert_eq!(histogram.count(), 100);
+
+        let within = |actual: Duration, expected_ms: f64| {
+            let actual_ms = actual.as_secs_f64() * 1000.0;
+            (actual_ms - expected_ms).abs() / expected_ms < 0.07
+        };
+        assert!(within(histogram.quantile(0.50), 50.0));
+        assert!(within(histogram.quantile(0.95), 95.0));
+        assert!(within(histogram.quantile(0.99), 99.0));
+
+        assert_eq!(histogram.count_at_most(Duration::ZERO), 0);
+        assert_eq!(histogram.count_at_most(Duration::from_secs(1)), 100);
+        let below_half = histogram.count_at_most(Duration::from_millis(50));
+        assert!((46..=50).contains(&below_half), "{below_half}");
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let histogram = LatencyHistogram::new();
+        assert_eq!(histogram.snapshot(), HistogramSnapshot::default());
+
+        for us in [3, 3, 100, 1_000] {
+            histogram.record(Duration::from_micros(us));
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 4);
+        assert_eq!(snapshot.buckets.len(), 3);
+        assert_eq!(
+            snapshot.buckets[0],
+            Bucket {
+                lower_us: 3,
+                upper_us: 4,
+                count: 2
+            }
+        );
+        for bucket in &snapshot.buckets[1..] {
+            assert_eq!(bucket.count, 1);
+            assert!(bucket.lower_us < bucket.upper_us);
+        }
+        assert!((snapshot.buckets[2].lower_us..snapshot.buckets[2].upper_us).contains(&1_000));
+    }
+}
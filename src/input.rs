@@ -0,0 +1,1049 @@
+//! Streaming input reading for request files.
+//!
+//! Requests are parsed lazily line by line so memory stays bounded
+//! regardless of the size of the input file. Files whose first
+//! non-whitespace byte is `[` are read as a single JSON array instead,
+//! one element at a time.
+//!
+//! Lines that are not valid UTF-8 or are longer than the line length limit
+//! don't stop the run: they become requests that fail validation, so they
+//! end up in the errors file with their line numbers.
+
+use crate::compression::{self, FileReader};
+use crate::config::ProcessingConfig;
+use crate::error::{BlazeError, Result};
+use crate::request::ApiRequest;
+use futures::future;
+use futures::stream::{self, BoxStream, StreamExt};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncBufReadExt;
+
+/// A stream of parsed requests in file order.
+///
+/// Streams returned by this module end after yielding their first error.
+pub type RequestStream = BoxStream<'static, Result<ApiRequest>>;
+
+/// Layout of a request file.
+enum Format {
+    /// One request per line, after the given number of leading blank lines.
+    Lines { skipped_lines: usize },
+    /// A single JSON array of requests, with the opening `[` consumed.
+    Array,
+}
+
+/// Skip leading whitespace and detect whether the file holds a JSON array.
+async fn detect_format(reader: &mut FileReader) -> io::Result<Format> {
+    let mut skipped_lines = 0;
+
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            return Ok(Format::Lines { skipped_lines });
+        }
+
+        let start = buf.iter().position(|b| !b.is_ascii_whitespace());
+        let whitespace = start.unwrap_or(buf.len());
+        skipped_lines += buf[..whitespace].split(|&b| b == b'\n').count() - 1;
+
+        match start {
+            Some(start) if buf[start] == b'[' => {
+                reader.consume(start + 1);
+                return Ok(Format::Array);
+            }
+            Some(start) => {
+                reader.consume(start);
+                return Ok(Format::Lines { skipped_lines });
+            }
+            None => reader.consume(whitespace),
+        }
+    }
+}
+
+/// A raw request from a file, not yet parsed.
+enum Element {
+    /// The request's bytes.
+    Bytes(Vec<u8>),
+    /// A request longer than the length limit, passed over without keeping it.
+    TooLong,
+}
+
+impl Element {
+    /// Add a chunk of the request, dropping what was kept once it is over `max_len` bytes.
+    fn extend(&mut self, chunk: &[u8], max_len: usize) {
+        if let Self::Bytes(bytes) = self {
+            if bytes.len() + chunk.len() <= max_len {
+                bytes.extend_from_slice(chunk);
+            } else {
+                *self = Self::TooLong;
+            }
+        }
+    }
+}
+
+/// Read the next line, without its line ending, or `None` at the end of the file.
+///
+/// A line longer than `max_len` bytes is read to its end without being kept.
+async fn read_line(reader: &mut FileReader, max_len: usize) -> io::Result<Option<Element>> {
+    let mut line = Element::Bytes(Vec::new());
+    let mut empty = true;
+
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            break;
+        }
+        empty = false;
+
+        let newline = buf.iter().position(|&b| b == b'\n');
+        line.extend(&buf[..newline.unwrap_or(buf.len())], max_len);
+        let used = newline.map_or(buf.len(), |i| i + 1);
+        reader.consume(used);
+        if newline.is_some() {
+            break;
+        }
+    }
+
+    if empty {
+        return Ok(None);
+    }
+    if let Element::Bytes(bytes) = &mut line {
+        if bytes.last() == Some(&b'\r') {
+            bytes.pop();
+        }
+    }
+    Ok(Some(line))
+}
+
+/// Splits a JSON array into the raw bytes of its elements without parsing them.
+struct ArrayElements {
+    reader: FileReader,
+    done: bool,
+    /// Length limit of an element, in bytes.
+    max_len: usize,
+}
+
+impl ArrayElements {
+    /// Read the next element, or `None` after the closing `]`.
+    async fn next_element(&mut self) -> io::Result<Option<Element>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        let mut element = Element::Bytes(Vec::new());
+        let mut blank = true;
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+
+        loop {
+            let buf = self.reader.fill_buf().await?;
+            if buf.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "unterminated JSON array",
+                ));
+            }
+
+            let mut end = None;
+            for (i, &byte) in buf.iter().enumerate() {
+                if in_string {
+                    match byte {
+                        _ if escaped => escaped = false,
+                        b'\\' => escaped = true,
+                        b'"' => in_string = false,
+                        _ => {}
+                    }
+                    continue;
+                }
+                match byte {
+                    b'"' => in_string = true,
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' if depth > 0 => depth -= 1,
+                    b']' => {
+                        self.done = true;
+                        end = Some(i);
+                        break;
+                    }
+                    b',' if depth == 0 => {
+                        end = Some(i);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+
+            let chunk = &buf[..end.unwrap_or(buf.len())];
+            blank &= chunk.iter().all(u8::is_ascii_whitespace);
+            element.extend(chunk, self.max_len);
+            let used = end.map_or(buf.len(), |end| end + 1);
+            self.reader.consume(used);
+            if end.is_none() {
+                continue;
+            }
+            if self.done {
+                self.expect_end().await?;
+            }
+
+            // An empty slot before the closing bracket is the end of the array
+            if self.done && blank {
+                return Ok(None);
+            }
+            return Ok(Some(element));
+        }
+    }
+
+    /// Check that nothing but whitespace follows the closing `]`.
+    async fn expect_end(&mut self) -> io::Result<()> {
+        loop {
+            let buf = self.reader.fill_buf().await?;
+            if buf.is_empty() {
+                return Ok(());
+            }
+            if !buf.iter().all(u8::is_ascii_whitespace) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "data after the end of the JSON array",
+                ));
+            }
+            let read = buf.len();
+            self.reader.consume(read);
+        }
+    }
+}
+
+/// Open a request file and detect its format.
+async fn open(path: &Path) -> Result<(FileReader, Format)> {
+    let read_error = |e| BlazeError::InputFileRead {
+        path: path.to_path_buf(),
+        source: e,
+    };
+    let mut reader = compression::open_reader(path).await.map_err(read_error)?;
+    let format = detect_format(&mut reader).await.map_err(read_error)?;
+    Ok((reader, format))
+}
+
+/// Fields of [`ApiRequest`] that input fields can be renamed to.
+const MAPPABLE_FIELDS: [&str; 8] = [
+    "input", "body", "headers", "method", "path", "priority", "model", "metadata",
+];
+
+/// Renames of input fields to the names Blaze expects, applied before parsing.
+///
+/// Mapping a field to `metadata` spreads its object's fields to the top
+/// level, where body templates and the hash key read extra fields from.
+/// A spread field may not replace a request field or another field of the
+/// row, so such rows fail to parse.
+#[derive(Debug, Clone, Default)]
+pub struct FieldMapping {
+    /// Source field names with the field each one becomes.
+    renames: Arc<[(String, String)]>,
+}
+
+impl FieldMapping {
+    /// Build a mapping from source field names to [`ApiRequest`] fields.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::InvalidConfig`] if a target is not a request field.
+    pub fn new(renames: &BTreeMap<String, String>) -> Result<Self> {
+        if let Some(target) = renames
+            .values()
+            .find(|target| !MAPPABLE_FIELDS.contains(&target.as_str()))
+        {
+            return Err(BlazeError::InvalidConfig(format!(
+                "cannot map an input field to '{target}', expected one of {}",
+                MAPPABLE_FIELDS.join(", ")
+            )));
+        }
+        Ok(Self {
+            renames: renames
+                .iter()
+                .map(|(source, target)| (source.clone(), target.clone()))
+                .collect(),
+        })
+    }
+
+    /// Parse a request, renaming its fields first.
+    fn parse(&self, text: &[u8]) -> serde_json::Result<ApiRequest> {
+        // Without renames, skip the intermediate value
+        if self.renames.is_empty() {
+            return serde_json::from_slice(text);
+        }
+        let mut value: Value = serde_json::from_slice(text)?;
+        if let Value::Object(fields) = &mut value {
+            for (source, target) in self.renames.iter() {
+                let Some(field) = fields.remove(source) else {
+                    continue;
+                };
+                match field {
+                    Value::Object(nested) if target == "metadata" => {
+                        let collision = nested.keys().find(|key| {
+                            MAPPABLE_FIELDS.contains(&key.as_str()) || fields.contains_key(*key)
+                        });
+                        if let Some(key) = collision {
+                            return Err(serde::de::Error::custom(format!(
+                                "metadata field '{key}' would replace a field of the request"
+                            )));
+                        }
+                        fields.extend(nested);
+                    }
+                    field => {
+                        fields.insert(target.clone(), field);
+                    }
+                }
+            }
+        }
+        serde_json::from_value(value)
+    }
+}
+
+/// How requests are read from input files.
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    /// Renames applied to each request's fields before it is parsed.
+    pub fields: FieldMapping,
+    /// Longest line read, in bytes; longer lines become unreadable requests.
+    pub max_line_length: usize,
+    /// Threads parsing requests at once. With 1, requests are parsed on the
+    /// task reading the file.
+    pub parse_workers: usize,
+    /// Read errors files of earlier runs, rebuilding the requests that failed
+    /// with their original line numbers.
+    pub replay: bool,
+}
+
+impl ReadOptions {
+    /// Read requests as the processing configuration says.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::InvalidConfig`] if an input field is mapped to
+    /// something that is not a request field.
+    pub fn new(config: &ProcessingConfig) -> Result<Self> {
+        Ok(Self {
+            fields: FieldMapping::new(&config.input_fields)?,
+            max_line_length: usize::try_from(config.max_line_length).unwrap_or(usize::MAX),
+            parse_workers: config.parse_workers.unwrap_or_else(|| {
+                std::thread::available_parallelism().map_or(1, NonZeroUsize::get)
+            }),
+            replay: false,
+        })
+    }
+
+    /// Parse a raw request read from line `line_number`.
+    fn parse(&self, (line_number, element): (usize, Element)) -> Result<ApiRequest> {
+        let text = match element {
+            Element::Bytes(text) => text,
+            Element::TooLong => {
+                return Err(BlazeError::LineTooLong {
+                    line: line_number,
+                    max: self.max_line_length,
+                })
+            }
+        };
+        if std::str::from_utf8(&text).is_err() {
+            return Err(BlazeError::InvalidUtf8 { line: line_number });
+        }
+        let parsed = if self.replay {
+            serde_json::from_slice(&text).map(ApiRequest::replay)
+        } else {
+            self.fields.parse(&text).map(|mut request| {
+                request.line_number = line_number;
+                request
+            })
+        };
+        parsed.map_err(|e| BlazeError::JsonParse {
+            line: line_number,
+            source: e,
+        })
+    }
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            fields: FieldMapping::default(),
+            max_line_length: usize::MAX,
+            parse_workers: 1,
+            replay: false,
+        }
+    }
+}
+
+/// Open a JSONL or JSON array file and stream its requests one at a time.
+///
+/// Gzip (`.gz`) and zstd (`.zst`) files are decompressed on the fly. For
+/// JSON arrays, each request's line number is its index in the array.
+/// Fields are renamed and lines limited in length as `options` say, and
+/// requests come out in file order however many threads parse them.
+///
+/// # Errors
+///
+/// Returns [`BlazeError::InputFileRead`] if the file cannot be opened.
+pub async fn stream_requests(path: &Path, options: &ReadOptions) -> Result<RequestStream> {
+    stream_slice(path, options, InputSlice::default()).await
+}
+
+/// A run of consecutive requests in a file, counted in requests, not lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputSlice {
+    /// Requests to pass over before the first one streamed.
+    pub skip: usize,
+    /// Requests to stream at most, or every remaining one if unset.
+    pub take: Option<usize>,
+}
+
+impl InputSlice {
+    /// Get how many of `total` requests fall in the slice.
+    #[must_use]
+    pub fn len_of(&self, total: usize) -> usize {
+        let remaining = total.saturating_sub(self.skip);
+        self.take.map_or(remaining, |take| remaining.min(take))
+    }
+}
+
+/// Stream only the requests of a file that fall in `slice`.
+///
+/// Requests keep their line numbers in the file, and those passed over are
+/// not parsed, so a malformed one before the slice doesn't stop the stream.
+///
+/// # Errors
+///
+/// Returns [`BlazeError::InputFileRead`] if the file cannot be opened.
+pub async fn stream_slice(
+    path: &Path,
+    options: &ReadOptions,
+    slice: InputSlice,
+) -> Result<RequestStream> {
+    let requests = parse_requests(path, options, slice).await?;
+    Ok(until_error(keep_unreadable(requests)))
+}
+
+/// Stream the requests of several files one after another.
+///
+/// Each request is tagged with the path of its file as its `source`, unless
+/// it was replayed and already names the file it was first read from. Files
+/// are opened as they are reached, and the stream ends after the first error
+/// in any of them.
+#[must_use]
+pub fn stream_files(paths: Vec<PathBuf>, options: &ReadOptions) -> RequestStream {
+    let options = options.clone();
+    let requests = stream::iter(paths)
+        .then(move |path| {
+            let options = options.clone();
+            async move {
+                let source: Arc<str> = path.display().to_string().into();
+                match stream_requests(&path, &options).await {
+                    Ok(requests) => requests
+                        .map(move |request| {
+                            request.map(|mut request| {
+                                request.source.get_or_insert_with(|| Arc::clone(&source));
+                                request
+                            })
+                        })
+                        .boxed(),
+                    Err(e) => stream::once(future::ready(Err(e))).boxed(),
+                }
+            }
+        })
+        .flatten();
+    until_error(requests.boxed())
+}
+
+/// Replace lines that could not be read with requests that fail validation.
+fn keep_unreadable(requests: RequestStream) -> RequestStream {
+    requests
+        .map(|request| match request {
+            Err(e @ (BlazeError::InvalidUtf8 { line } | BlazeError::LineTooLong { line, .. })) => {
+                Ok(ApiRequest::unreadable(line, e.to_string()))
+            }
+            request => request,
+        })
+        .boxed()
+}
+
+/// End a stream of requests after its first error.
+fn until_error(requests: RequestStream) -> RequestStream {
+    requests
+        .scan(false, |failed, request| {
+            if *failed {
+                return future::ready(None);
+            }
+            *failed = request.is_err();
+            future::ready(Some(request))
+        })
+        .boxed()
+}
+
+/// Outcome of parsing every request of an input file without sending any.
+#[derive(Debug, Default)]
+pub struct InputCheck {
+    /// Requests that parsed.
+    pub requests: usize,
+    /// The first parse errors and unreadable lines, in file order.
+    pub errors: Vec<BlazeError>,
+    /// Parse errors and unreadable lines in total, including those past the reporting limit.
+    pub error_count: usize,
+}
+
+impl InputCheck {
+    /// Check whether every request parsed.
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.error_count == 0
+    }
+}
+
+/// Parse every request of a file, collecting up to `max_errors` parse errors.
+///
+/// Unlike [`stream_requests`], a malformed request doesn't stop the check,
+/// so all of them are found in one pass over the file.
+///
+/// # Errors
+///
+/// Returns [`BlazeError::InputFileRead`] if the file cannot be read.
+pub async fn check_requests(
+    path: &Path,
+    options: &ReadOptions,
+    max_errors: usize,
+) -> Result<InputCheck> {
+    let mut requests = parse_requests(path, options, InputSlice::default()).await?;
+    let mut check = InputCheck::default();
+    while let Some(request) = requests.next().await {
+        match request {
+            Ok(_) => check.requests += 1,
+            Err(
+                e @ (BlazeError::JsonParse { .. }
+                | BlazeError::InvalidUtf8 { .. }
+                | BlazeError::LineTooLong { .. }),
+            ) => {
+                check.error_count += 1;
+                if check.errors.len() < max_errors {
+                    check.errors.push(e);
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(check)
+}
+
+/// Stream the requests of a file, carrying on past malformed and unreadable ones.
+///
+/// Requests outside `slice` are passed over without being parsed. The stream
+/// ends after an error reading the file.
+async fn parse_requests(
+    path: &Path,
+    options: &ReadOptions,
+    slice: InputSlice,
+) -> Result<RequestStream> {
+    let (reader, format) = open(path).await?;
+    let path = path.to_path_buf();
+
+    let max_len = options.max_line_length;
+    let elements = match format {
+        Format::Lines { skipped_lines } => stream_lines(reader, path, skipped_lines, max_len),
+        Format::Array => stream_array(reader, path, max_len),
+    };
+    // Read errors are never skipped, so they still end the stream
+    let mut skip = slice.skip;
+    let elements = elements
+        .filter(move |element| {
+            let skipped = element.is_ok() && skip > 0;
+            skip -= usize::from(skipped);
+            future::ready(!skipped)
+        })
+        .take(slice.take.unwrap_or(usize::MAX));
+
+    let options = options.clone();
+    if options.parse_workers <= 1 {
+        return Ok(elements.map(move |element| options.parse(element?)).boxed());
+    }
+
+    // Handing lines to other threads in batches keeps the overhead per line
+    // small, and buffering the batches keeps them in file order
+    let workers = options.parse_workers;
+    let stream = elements
+        .ready_chunks(PARSE_BATCH_SIZE)
+        .map(move |batch| {
+            let options = options.clone();
+            tokio::task::spawn_blocking(move || {
+                batch
+                    .into_iter()
+                    .map(|element| options.parse(element?))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .buffered(workers)
+        .flat_map(|parsed| {
+            let parsed = parsed.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+            stream::iter(parsed)
+        });
+
+    Ok(stream.boxed())
+}
+
+/// Requests handed to a parsing thread at once.
+const PARSE_BATCH_SIZE: usize = 256;
+
+/// Raw requests of a file with their line numbers, not yet parsed.
+type ElementStream = BoxStream<'static, Result<(usize, Element)>>;
+
+/// Stream the non-empty lines of a JSONL file, numbering them from 1.
+fn stream_lines(
+    reader: FileReader,
+    path: PathBuf,
+    skipped_lines: usize,
+    max_len: usize,
+) -> ElementStream {
+    let stream = stream::unfold(Some((reader, skipped_lines)), move |state| {
+        let path = path.clone();
+        async move {
+            let (mut reader, mut line_number) = state?;
+            loop {
+                let line = match read_line(&mut reader, max_len).await {
+                    Ok(Some(line)) => line,
+                    Ok(None) => return None,
+                    Err(e) => {
+                        let error = BlazeError::InputFileRead { path, source: e };
+                        return Some((Err(error), None));
+                    }
+                };
+                line_number += 1;
+
+                // Skip empty lines
+                if matches!(&line, Element::Bytes(bytes) if bytes.iter().all(u8::is_ascii_whitespace))
+                {
+                    continue;
+                }
+                return Some((Ok((line_number, line)), Some((reader, line_number))));
+            }
+        }
+    });
+
+    stream.boxed()
+}
+
+/// Stream the elements of a JSON array, numbering them by index.
+fn stream_array(reader: FileReader, path: PathBuf, max_len: usize) -> ElementStream {
+    let elements = ArrayElements {
+        reader,
+        done: false,
+        max_len,
+    };
+
+    let stream = stream::unfold(Some((elements, 0)), move |state| {
+        let path = path.clone();
+        async move {
+            let (mut elements, index) = state?;
+            let element = match elements.next_element().await {
+                Ok(Some(element)) => element,
+                Ok(None) => return None,
+                Err(e) => {
+                    let error = BlazeError::InputFileRead { path, source: e };
+                    return Some((Err(error), None));
+                }
+            };
+            Some((Ok((index, element)), Some((elements, index + 1))))
+        }
+    });
+
+    stream.boxed()
+}
+
+/// Count the requests in a JSONL or JSON array file without parsing them.
+///
+/// # Errors
+///
+/// Returns [`BlazeError::InputFileRead`] if the file cannot be read.
+pub async fn count_requests(path: &Path) -> Result<usize> {
+    let (mut reader, format) = open(path).await?;
+
+    if let Format::Array = format {
+        // Elements are only counted, so none is kept
+        let mut elements = ArrayElements {
+            reader,
+            done: false,
+            max_len: 0,
+        };
+        let mut count = 0;
+        while elements
+            .next_element()
+            .await
+            .map_err(|e| BlazeError::InputFileRead {
+                path: path.to_path_buf(),
+                source: e,
+            })?
+            .is_some()
+        {
+            count += 1;
+        }
+        return Ok(count);
+    }
+
+    // Lines are scanned in place rather than collected, however long they are
+    let mut count = 0;
+    let mut blank = true;
+
+    loop {
+        let buf = reader
+            .fill_buf()
+            .await
+            .map_err(|e| BlazeError::InputFileRead {
+                path: path.to_path_buf(),
+                source: e,
+            })?;
+        if buf.is_empty() {
+            break;
+        }
+        for &byte in buf {
+            if byte == b'\n' {
+                count += usize::from(!blank);
+                blank = true;
+            } else {
+                blank &= byte.is_ascii_whitespace();
+            }
+        }
+        let read = buf.len();
+        reader.consume(read);
+    }
+
+    Ok(count + usize::from(!blank))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn test_stream_requests() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, r#"{{"input": "first"}}"#).unwrap();
+        writeln!(file).unwrap();
+        writeln!(file, r#"{{"input": "second"}}"#).unwrap();
+        writeln!(file, "not json").unwrap();
+        writeln!(file, r#"{{"input": "never reached"}}"#).unwrap();
+
+        assert_eq!(count_requests(file.path()).await.unwrap(), 4);
+
+        let results: Vec<_> = stream_requests(file.path(), &ReadOptions::default())
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap().line_number, 1);
+        assert_eq!(results[1].as_ref().unwrap().line_number, 3);
+        assert!(matches!(
+            results[2],
+            Err(BlazeError::JsonParse { line: 4, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stream_slice() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "not json").unwrap();
+        writeln!(file).unwrap();
+        for input in ["first", "second", "third", "fourth"] {
+            writeln!(file, r#"{{"input": "{input}"}}"#).unwrap();
+        }
+
+        // Malformed requests before the slice are passed over unparsed, and blank lines aren't counted
+        let slice = InputSlice {
+            skip: 2,
+            take: Some(2),
+        };
+        let requests: Vec<_> = stream_slice(file.path(), &ReadOptions::default(), slice)
+            .await
+            .unwrap()
+            .map(|request| request.unwrap())
+            .collect()
+            .await;
+        let lines: Vec<_> = requests.iter().map(|request| request.line_number).collect();
+        assert_eq!(lines, [4, 5]);
+        assert_eq!(requests[0].input.as_deref(), Some("second"));
+
+        assert_eq!(slice.len_of(5), 2);
+        assert_eq!(slice.len_of(3), 1);
+        let rest = InputSlice {
+            skip: 4,
+            take: None,
+        };
+        assert_eq!(rest.len_of(5), 1);
+    }
+
+    #[tokio::test]
+    async fn test_check_requests() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, r#"{{"input": "first"}}"#).unwrap();
+        writeln!(file, "not json").unwrap();
+        writeln!(file, r#"{{"input": "second"}}"#).unwrap();
+        writeln!(file, r#"{{"input": "truncated"#).unwrap();
+        writeln!(file).unwrap();
+        writeln!(file, r#"{{"input": "third"}}"#).unwrap();
+        writeln!(file, "{{").unwrap();
+
+        // Every malformed line is counted, but only the first are kept
+        let check = check_requests(file.path(), &ReadOptions::default(), 2)
+            .await
+            .unwrap();
+        assert!(!check.is_valid());
+        assert_eq!(check.requests, 3);
+        assert_eq!(check.error_count, 3);
+        assert!(matches!(
+            check.errors[..],
+            [
+                BlazeError::JsonParse { line: 2, .. },
+                BlazeError::JsonParse { line: 4, .. }
+            ]
+        ));
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            r#"[{{"input": "a"}}, {{"input": 1}}, {{"input": "c"}}]"#
+        )
+        .unwrap();
+        let check = check_requests(file.path(), &ReadOptions::default(), 10)
+            .await
+            .unwrap();
+        assert_eq!(check.requests, 2);
+        assert!(matches!(
+            check.errors[..],
+            [BlazeError::JsonParse { line: 1, .. }]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stream_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("part-00.jsonl");
+        let second = dir.path().join("part-01.jsonl");
+        let third = dir.path().join("part-02.jsonl");
+        std::fs::write(&first, "{\"input\": \"a\"}\n{\"input\": \"b\"}\n").unwrap();
+        std::fs::write(&second, "{\"input\": \"c\"}\nnot json\n").unwrap();
+        std::fs::write(&third, "{\"input\": \"never reached\"}\n").unwrap();
+
+        let paths = vec![first.clone(), second.clone(), third];
+        let results: Vec<_> = stream_files(paths, &ReadOptions::default()).collect().await;
+        assert_eq!(results.len(), 4);
+        let request = results[1].as_ref().unwrap();
+        assert_eq!(request.line_number, 2);
+        assert_eq!(
+            request.source.as_deref(),
+            Some(&*first.display().to_string())
+        );
+        let request = results[2].as_ref().unwrap();
+        assert_eq!(request.line_number, 1);
+        assert_eq!(
+            request.source.as_deref(),
+            Some(&*second.display().to_string())
+        );
+        assert!(matches!(
+            results[3],
+            Err(BlazeError::JsonParse { line: 2, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_field_mapping() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            r#"{{"prompt": "hi", "meta": {{"user_id": "u1"}}, "id": 7}}"#
+        )
+        .unwrap();
+        writeln!(file, r#"{{"prompt": "no meta", "meta": "not an object"}}"#).unwrap();
+
+        let renames = BTreeMap::from([
+            ("prompt".to_string(), "input".to_string()),
+            ("meta".to_string(), "metadata".to_string()),
+        ]);
+        let options = ReadOptions {
+            fields: FieldMapping::new(&renames).unwrap(),
+            ..Default::default()
+        };
+        let results: Vec<_> = stream_requests(file.path(), &options)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+
+        let first = results[0].as_ref().unwrap();
+        assert_eq!(first.input.as_deref(), Some("hi"));
+        assert_eq!(first.metadata["user_id"], "u1");
+        assert_eq!(first.metadata["id"], 7);
+        assert!(!first.metadata.contains_key("prompt"));
+        // A non-object can't be spread, so it is kept under the target name
+        let second = results[1].as_ref().unwrap();
+        assert_eq!(second.input.as_deref(), Some("no meta"));
+        assert_eq!(second.metadata["metadata"], "not an object");
+        // Spread fields can't replace the request's own
+        let fields = &options.fields;
+        assert!(fields
+            .parse(br#"{"prompt": "hi", "meta": {"input": "other"}}"#)
+            .is_err());
+        assert!(fields
+            .parse(br#"{"prompt": "hi", "meta": {"id": 8}, "id": 7}"#)
+            .is_err());
+
+        let renames = BTreeMap::from([("prompt".to_string(), "line_number".to_string())]);
+        assert!(FieldMapping::new(&renames).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stream_json_array() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            r#"
+  [{{"input": "first, [with] {{brackets}}"}},
+   {{"input": "quote \" and ]"}} ,
+   {{"input": 3}}, {{"input": "never reached"}}]"#
+        )
+        .unwrap();
+
+        assert_eq!(count_requests(file.path()).await.unwrap(), 4);
+
+        let results: Vec<_> = stream_requests(file.path(), &ReadOptions::default())
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(results.len(), 3);
+        let first = results[0].as_ref().unwrap();
+        assert_eq!(first.line_number, 0);
+        assert_eq!(first.input.as_deref(), Some("first, [with] {brackets}"));
+        let second = results[1].as_ref().unwrap();
+        assert_eq!(second.line_number, 1);
+        assert_eq!(second.input.as_deref(), Some("quote \" and ]"));
+        assert!(matches!(
+            results[2],
+            Err(BlazeError::JsonParse { line: 2, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_data_after_json_array() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, r#"[{{"input": "first"}}] {{"input": "stray"}}"#).unwrap();
+
+        assert!(matches!(
+            count_requests(file.path()).await,
+            Err(BlazeError::InputFileRead { .. })
+        ));
+        let results: Vec<_> = stream_requests(file.path(), &ReadOptions::default())
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert!(matches!(
+            results.last(),
+            Some(Err(BlazeError::InputFileRead { .. }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_lines() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, r#"{{"input": "first"}}"#).unwrap();
+        file.write_all(b"{\"input\": \"bad \xff byte\"}\r\n")
+            .unwrap();
+        writeln!(file, r#"{{"input": "{}"}}"#, "x".repeat(100)).unwrap();
+        write!(file, r#"{{"input": "last"}}"#).unwrap();
+
+        assert_eq!(count_requests(file.path()).await.unwrap(), 4);
+
+        // Unreadable lines become requests of their own, and reading carries on past them
+        let short = ReadOptions {
+            max_line_length: 64,
+            ..Default::default()
+        };
+        let requests: Vec<_> = stream_requests(file.path(), &short)
+            .await
+            .unwrap()
+            .map(|request| request.unwrap())
+            .collect()
+            .await;
+        let lines: Vec<_> = requests.iter().map(|request| request.line_number).collect();
+        assert_eq!(lines, [1, 2, 3, 4]);
+        assert!(requests[0].unreadable.is_none());
+        assert_eq!(
+            requests[1].unreadable.as_deref(),
+            Some("line 2 is not valid UTF-8")
+        );
+        assert_eq!(
+            requests[2].unreadable.as_deref(),
+            Some("line 3 is longer than 64 bytes")
+        );
+        assert_eq!(requests[3].input.as_deref(), Some("last"));
+
+        let check = check_requests(file.path(), &short, 10).await.unwrap();
+        assert_eq!(check.requests, 2);
+        assert!(matches!(
+            check.errors[..],
+            [
+                BlazeError::InvalidUtf8 { line: 2 },
+                BlazeError::LineTooLong { line: 3, max: 64 }
+            ]
+        ));
+
+        // Elements of a JSON array are limited too
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let long = "x".repeat(100);
+        write!(file, r#"[{{"input": "{long}"}}, {{"input": "short"}}]"#).unwrap();
+        let requests: Vec<_> = stream_requests(file.path(), &short)
+            .await
+            .unwrap()
+            .map(|request| request.unwrap())
+            .collect()
+            .await;
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].unreadable.is_some());
+        assert_eq!(requests[1].input.as_deref(), Some("short"));
+    }
+
+    #[tokio::test]
+    async fn test_parse_workers() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for n in 1..=1000 {
+            if n == 700 {
+                writeln!(file, "not json").unwrap();
+            } else {
+                writeln!(file, r#"{{"input": "prompt {n}"}}"#).unwrap();
+            }
+        }
+
+        // Batches parsed on several threads still come out in file order
+        let options = ReadOptions {
+            parse_workers: 4,
+            ..Default::default()
+        };
+        let requests: Vec<_> = stream_requests(file.path(), &options)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(requests.len(), 700);
+        for (request, n) in requests.iter().zip(1..700) {
+            let request = request.as_ref().unwrap();
+            assert_eq!(request.line_number, n);
+            assert_eq!(request.input, Some(format!("prompt {n}")));
+        }
+        assert!(matches!(
+            requests[699],
+            Err(BlazeError::JsonParse { line: 700, .. })
+        ));
+
+        let check = check_requests(file.path(), &options, 10).await.unwrap();
+        assert_eq!(check.requests, 999);
+        assert_eq!(check.error_count, 1);
+    }
+}
@@ -0,0 +1,206 @@
+//! Rotation across several API keys for the same endpoint.
+//!
+//! Providers rate limit per key, so spreading requests over a pool of keys
+//! multiplies the quota. Each request picks a key in turn (or at random), and
+//! a key answered with 429 sits out until its `Retry-After` has passed, while
+//! the other keys keep the endpoint busy. Each key counts the responses it got,
+//! so a revoked key shows up as one with only failures.
+
+use parking_lot::Mutex;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// How long a rate-limited key sits out when the response has no `Retry-After`.
+pub const DEFAULT_KEY_SIDELINE: Duration = Duration::from_secs(30);
+
+/// How an endpoint picks a key from its pool for each request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyRotation {
+    /// Take the keys in turn.
+    #[default]
+    RoundRobin,
+    /// Pick a key at random.
+    Random,
+}
+
+/// An API key in a pool, with the responses it got.
+#[derive(Debug)]
+pub struct PooledKey {
+    key: String,
+    success_count: AtomicU64,
+    failure_count: AtomicU64,
+    /// Until when the key sits out after a 429.
+    sidelined_until: Mutex<Option<Instant>>,
+}
+
+impl PooledKey {
+    fn new(key: String) -> Self {
+        Self {
+            key,
+            success_count: AtomicU64::new(0),
+            failure_count: AtomicU64::new(0),
+            sidelined_until: Mutex::new(None),
+        }
+    }
+
+    /// Get the key itself.
+    #[must_use]
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Record the status of a response to a request sent with this key.
+    ///
+    /// A 429 sidelines the key for `retry_after`, or [`DEFAULT_KEY_SIDELINE`].
+    pub fn record(&self, status: u16, retry_after: Option<Duration>) {
+        if (200..300).contains(&status) {
+            self.success_count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.failure_count.fetch_add(1, Ordering::Relaxed);
+        if status == 429 {
+            let until = Instant::now() + retry_after.unwrap_or(DEFAULT_KEY_SIDELINE);
+            *self.sidelined_until.lock() = Some(until);
+        }
+    }
+
+    fn is_sidelined(&self, now: Instant) -> bool {
+        self.sidelined_until.lock().is_some_and(|until| until > now)
+    }
+
+    /// Get the key's counts, with the key masked.
+    #[must_use]
+    pub fn stats(&self) -> KeyStats {
+        KeyStats {
+            key: mask_key(&self.key),
+            success_count: self.success_count.load(Ordering::Relaxed),
+            failure_count: self.failure_count.load(Ordering::Relaxed),
+            sidelined: self.is_sidelined(Instant::now()),
+        }
+    }
+}
+
+/// The API keys of an endpoint.
+#[derive(Debug)]
+pub struct KeyPool {
+    keys: Vec<PooledKey>,
+    rotation: KeyRotation,
+    next: AtomicUsize,
+}
+
+impl KeyPool {
+    /// Create a pool of `keys`, which may be empty.
+    #[must_use]
+    pub fn new(keys: Vec<String>, rotation: KeyRotation) -> Self {
+        Self {
+            keys: keys.into_iter().map(PooledKey::new).collect(),
+            rotation,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Get the number of keys in the pool.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Check whether the pool has no keys.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Pick a key for the next request, if there are any.
+    ///
+    /// Sidelined keys are passed over while another is available. If every
+    /// key is sidelined, the one that comes back first is used.
+    pub fn pick(&self) -> Option<&PooledKey> {
+        let count = self.keys.len();
+        if count == 0 {
+            return None;
+        }
+        let start = match self.rotation {
+            KeyRotation::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed),
+            KeyRotation::Random => rand::rng().random_range(0..count),
+        };
+        let now = Instant::now();
+        (0..count)
+            .map(|offset| &self.keys[(start + offset) % count])
+            .find(|key| !key.is_sidelined(now))
+            .or_else(|| {
+                self.keys
+                    .iter()
+                    .min_by_key(|key| *key.sidelined_until.lock())
+            })
+    }
+
+    /// Get the counts of every key in the pool.
+    #[must_use]
+    pub fn stats(&self) -> Vec<KeyStats> {
+        self.keys.iter().map(PooledKey::stats).collect()
+    }
+}
+
+/// A snapshot of a single key's responses.
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyStats {
+    /// The key, masked to its last four characters.
+    pub key: String,
+    /// Successful responses.
+    pub success_count: u64,
+    /// Error responses, rate limits included.
+    pub failure_count: u64,
+    /// Whether the key is sitting out a rate limit.
+    pub sidelined: bool,
+}
+
+/// Mask a key for display, keeping the last four characters of longer keys.
+fn mask_key(key: &str) -> String {
+    let count = key.chars().count();
+    if count < 12 {
+        return "****".to_string();
+    }
+    let tail: String = key.chars().skip(count - 4).collect();
+    format!("…{tail}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_pool() {
+        let keys = ["sk-first-0001", "sk-second-002", "sk-third-0003"];
+        let pool = KeyPool::new(keys.map(str::to_string).to_vec(), KeyRotation::RoundRobin);
+        let picks: Vec<_> = (0..4).map(|_| pool.pick().unwrap().key()).collect();
+        assert_eq!(picks, [keys[0], keys[1], keys[2], keys[0]]);
+
+        // A rate-limited key sits out while the others take its turns
+        pool.pick()
+            .unwrap()
+            .record(429, Some(Duration::from_secs(60)));
+        let picks: Vec<_> = (0..4).map(|_| pool.pick().unwrap().key()).collect();
+        assert_eq!(picks, [keys[2], keys[0], keys[2], keys[2]]);
+
+        pool.pick().unwrap().record(200, None);
+        let stats = pool.stats();
+        assert_eq!(stats[1].key, "…-002");
+        assert_eq!(stats[1].failure_count, 1);
+        assert!(stats[1].sidelined);
+        assert_eq!(stats[0].success_count, 1);
+
+        // With every key sidelined, the one back first is used
+        let pool = KeyPool::new(vec!["a".to_string(), "b".to_string()], KeyRotation::Random);
+        pool.keys[0].record(429, Some(Duration::from_secs(60)));
+        pool.keys[1].record(429, Some(Duration::from_secs(10)));
+        assert_eq!(pool.pick().unwrap().key(), "b");
+        assert_eq!(pool.stats()[0].key, "****");
+        assert!(KeyPool::new(Vec::new(), KeyRotation::RoundRobin)
+            .pick()
+            .is_none());
+    }
+}
@@ -27,6 +27,7 @@
 //!             api_key: Some("your-api-key".to_string()),
 //!             model: Some("gpt-4".to_string()),
 //!             max_concurrent: 100,
+//!             ..Default::default()
 //!         }],
 //!         ..Default::default()
 //!     };
@@ -49,7 +50,7 @@
 //! Blaze supports configuration via:
 //! - Command-line arguments
 //! - Environment variables (prefixed with `BLAZE_`)
-//! - JSON configuration files
+//! - JSON, YAML or TOML configuration files
 //!
 //! See [`Config`] for all available options.
 
@@ -58,20 +59,57 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::module_name_repetitions)]
 
+pub mod batch;
+pub mod cache;
+pub mod checkpoint;
 pub mod client;
+pub mod compression;
+pub mod concurrency;
 pub mod config;
+pub mod dedup;
 pub mod endpoint;
 pub mod error;
+pub mod events;
+pub mod health;
+pub mod histogram;
+pub mod input;
+pub mod keys;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod oauth;
+pub mod priority;
 pub mod processor;
+pub mod projection;
+pub mod ramp;
+pub mod random;
+pub mod reorder;
+pub mod report;
 pub mod request;
+pub mod sse;
+pub mod table;
+#[cfg(feature = "otel")]
+pub mod telemetry;
 pub mod tracker;
+pub mod validation;
+pub mod writer;
 
 // Re-exports for convenience
-pub use config::{Args, Config, EndpointConfig, RequestConfig, RetryConfig};
-pub use endpoint::{Endpoint, LoadBalancer};
+pub use client::ProbeResult;
+pub use config::{
+    Args, AuthConfig, Command, Config, EndpointConfig, JitterKind, OAuth2Config, OutputFormat,
+    ProcessingConfig, ProxyConfig, RequestConfig, RetryConfig, ValidationRule,
+};
+pub use endpoint::{
+    CircuitState, Endpoint, EndpointPermit, EndpointStats, LoadBalanceStrategy, LoadBalancer,
+};
 pub use error::{BlazeError, Result};
+pub use events::ProcessEvent;
 pub use processor::{ProcessingResult, Processor};
-pub use request::{ApiRequest, ApiResponse, ErrorResponse, RequestResult};
+pub use report::{RunReport, RunStatus};
+pub use request::{
+    ApiRequest, ApiResponse, BodyEncoding, ErrorKind, ErrorResponse, FailureClass, HttpMethod,
+    RequestFormat, RequestResult, TokenUsage,
+};
 pub use tracker::{StatsSnapshot, StatsTracker};
 
 /// Library version.
@@ -82,8 +120,13 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             endpoints: vec![],
+            load_balancing: LoadBalanceStrategy::default(),
+            latency_decay: endpoint::DEFAULT_LATENCY_DECAY,
+            hash_key: config::default_hash_key(),
+            seed: None,
             request: RequestConfig::default(),
             retry: RetryConfig::default(),
+            processing: ProcessingConfig::default(),
         }
     }
 }
@@ -3,24 +3,51 @@
 //! Run `blaze --help` for usage information.
 
 use anyhow::Result;
-use blaze_api::{Args, Config, Processor};
+use blaze_api::input::{check_requests, ReadOptions};
+use blaze_api::{Args, Command, Config, ProbeResult, Processor};
 use console::style;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tracing::{error, info, Level};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
+#[cfg(feature = "otel")]
+use blaze_api::telemetry::Telemetry;
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Parse CLI arguments
     let args = Args::parse_args();
 
+    // Set up trace export before logging, so spans are bridged from the start
+    #[cfg(feature = "otel")]
+    let telemetry = if args.otel {
+        Some(Telemetry::init()?)
+    } else {
+        None
+    };
+
     // Setup logging
-    setup_logging(&args);
+    setup_logging(
+        &args,
+        #[cfg(feature = "otel")]
+        telemetry.as_ref(),
+    );
 
     // Print banner
     if !args.json_logs {
         print_banner();
     }
 
+    // Init mode: write a starter config, needing neither a config nor input
+    if let Some(Command::Init { path, force }) = &args.command {
+        if let Err(e) = write_starter_config(path, *force) {
+            eprintln!("{} {}", style("Error:").red().bold(), e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     // Load configuration
     let config = match Config::from_args(&args) {
         Ok(c) => c,
@@ -31,82 +58,161 @@ async fn main() -> Result<()> {
         }
     };
 
-    // Validate input file exists
-    if !args.input.exists() {
-        error!("Input file not found: {:?}", args.input);
+    // Expand input patterns and validate every input file exists
+    let inputs = match args.input_paths() {
+        Ok(inputs) => inputs,
+        Err(e) => {
+            error!("Input error: {}", e);
+            eprintln!("{} {}", style("Error:").red().bold(), e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(missing) = inputs.iter().find(|input| !input.exists()) {
+        error!("Input file not found: {:?}", missing);
         eprintln!(
             "{} Input file not found: {}",
             style("Error:").red().bold(),
-            args.input.display()
+            missing.display()
         );
         std::process::exit(1);
     }
 
+    // Check mode: parse the whole input without sending anything
+    if let Some(Command::Check { max_errors }) = &args.command {
+        let options = ReadOptions::new(&config.processing)?;
+        if !check_inputs(&inputs, &options, *max_errors).await? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     // Dry run mode
     if args.dry_run {
         println!("\n{}", style("DRY RUN MODE").yellow().bold());
         println!("Configuration validated successfully.\n");
-        print_config_summary(&args, &config);
+        print_config_summary(&args, &inputs, &config);
+
+        if args.dry_run_probe {
+            let results = Processor::new(config)?.probe_endpoints().await;
+            print_probe_results(&results);
+            if !results.iter().all(ProbeResult::is_reachable) {
+                std::process::exit(1);
+            }
+        }
         return Ok(());
     }
 
     // Print configuration summary
     if args.verbose && !args.json_logs {
-        print_config_summary(&args, &config);
+        print_config_summary(&args, &inputs, &config);
     }
 
     // Create processor and run
-    let processor = Processor::new(config)?;
+    let processing = &config.processing;
+    let rotated =
+        processing.output_rotate_size.is_some() || processing.output_rotate_lines.is_some();
+    let sample = processing.sample_output;
+    let count_only = processing.count_only;
+    let mut processor = Processor::new(config)?;
+    if let Some(Command::Replay { .. }) = &args.command {
+        processor = processor.replay_errors();
+    }
 
     info!(
-        input = %args.input.display(),
+        inputs = ?inputs,
         output = ?args.output,
         "Starting processing"
     );
 
     let result = processor
-        .process_file(
-            args.input.clone(),
+        .process_files(
+            inputs,
             args.output.clone(),
             args.errors.clone(),
             !args.no_progress && !args.json_logs,
         )
-        .await?;
+        .await;
+
+    // Export the run's spans before any early exit below
+    #[cfg(feature = "otel")]
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown();
+    }
+    let result = result?;
 
     // Print results
     if !args.json_logs {
         result.print_summary();
 
+        let numbered = if rotated { " (as numbered files)" } else { "" };
         if let Some(output) = &args.output {
+            let sampled = sample
+                .map(|rate| format!(" (sampled at {rate})"))
+                .unwrap_or_default();
             println!(
-                "\n{} Results saved to: {}",
+                "\n{} Results saved to: {}{}{}",
                 style("✓").green().bold(),
-                output.display()
+                output.display(),
+                numbered,
+                sampled
             );
         }
 
-        if result.failure_count > 0 {
+        if result.failure_count > 0 && !count_only {
             println!(
-                "{} Errors saved to: {}",
+                "{} Errors saved to: {}{}",
                 style("⚠").yellow().bold(),
-                args.errors.display()
+                args.errors.display(),
+                numbered
             );
         }
     } else {
         // JSON output for programmatic consumption
         let json_result = serde_json::json!({
-            "status": "complete",
+            "status": result.status(),
             "total_processed": result.total_processed,
             "success_count": result.success_count,
             "failure_count": result.failure_count,
+            "skipped_count": result.skipped_count,
+            "deduped_count": result.deduped_count,
+            "cached_count": result.cached_count,
+            "cancelled_count": result.cancelled_count,
+            "retries_denied": result.retries_denied,
+            "invalid_headers": result.invalid_headers,
+            "failure_statuses": result.failure_statuses,
+            "status_counts": result.status_counts,
             "success_rate": result.success_rate(),
             "elapsed_seconds": result.elapsed.as_secs_f64(),
             "avg_latency_ms": result.avg_latency_ms,
+            "p50_latency_ms": result.p50_latency_ms,
+            "p95_latency_ms": result.p95_latency_ms,
+            "p99_latency_ms": result.p99_latency_ms,
+            "p50_ttft_ms": result.p50_ttft_ms,
+            "p95_ttft_ms": result.p95_ttft_ms,
             "throughput_rps": result.overall_rps,
+            "prompt_tokens": result.prompt_tokens,
+            "completion_tokens": result.completion_tokens,
+            "estimated_cost": result.estimated_cost,
+            "endpoints": result.endpoints,
         });
         println!("{}", serde_json::to_string(&json_result)?);
     }
 
+    // Exit with a distinct code when the failure threshold stopped the run
+    if result.aborted {
+        std::process::exit(3);
+    }
+
+    // Exit with the conventional SIGINT code so scripts can tell the run was cut short
+    if result.interrupted {
+        std::process::exit(130);
+    }
+
+    // Exit with the code `timeout` uses, so schedulers can tell the time limit was hit
+    if result.time_limited {
+        std::process::exit(124);
+    }
+
     // Exit with error code if there were failures
     if result.failure_count > 0 && result.success_count == 0 {
         std::process::exit(1);
@@ -115,20 +221,27 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-fn setup_logging(args: &Args) {
+fn setup_logging(args: &Args, #[cfg(feature = "otel")] telemetry: Option<&Telemetry>) {
     let level = if args.verbose { Level::DEBUG } else { Level::INFO };
 
     let filter = EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| EnvFilter::new(format!("blaze_api={},blaze={}", level, level)));
 
+    #[cfg(feature = "otel")]
+    let otel = telemetry.map(Telemetry::layer);
+    #[cfg(not(feature = "otel"))]
+    let otel: Option<tracing_subscriber::layer::Identity> = None;
+
     if args.json_logs {
         tracing_subscriber::registry()
             .with(filter)
+            .with(otel)
             .with(fmt::layer().json())
             .init();
     } else {
         tracing_subscriber::registry()
             .with(filter)
+            .with(otel)
             .with(
                 fmt::layer()
                     .with_target(false)
@@ -161,15 +274,41 @@ fn print_banner() {
     println!();
 }
 
-fn print_config_summary(args: &Args, config: &Config) {
+fn print_config_summary(args: &Args, inputs: &[PathBuf], config: &Config) {
     println!("{}", style("Configuration:").bold());
-    println!("  Input:      {}", args.input.display());
+    for input in inputs {
+        println!("  Input:      {}", input.display());
+    }
     if let Some(output) = &args.output {
         println!("  Output:     {}", output.display());
     }
     println!("  Errors:     {}", args.errors.display());
-    println!("  Rate Limit: {} req/sec", config.request.rate_limit);
-    println!("  Workers:    {}", config.request.workers);
+    let request = &config.request;
+    if request.rate_period == Duration::from_secs(1) {
+        println!("  Rate Limit: {} req/sec", request.rate_limit);
+    } else {
+        println!(
+            "  Rate Limit: {} req per {:?}",
+            request.rate_limit, request.rate_period
+        );
+    }
+    if let Some(burst) = request.rate_burst {
+        println!("  Burst:      {burst} requests");
+    }
+    if config.request.adaptive_concurrency {
+        println!(
+            "  Workers:    {}, adapting up to {} (endpoint capacity: {})",
+            config.workers(),
+            config.request.max_workers,
+            config.endpoint_capacity()
+        );
+    } else {
+        println!(
+            "  Workers:    {} (endpoint capacity: {})",
+            config.workers(),
+            config.endpoint_capacity()
+        );
+    }
     println!("  Timeout:    {:?}", config.request.timeout);
     println!("  Retries:    {}", config.retry.max_attempts);
     println!("  Endpoints:  {}", config.endpoints.len());
@@ -184,3 +323,84 @@ fn print_config_summary(args: &Args, config: &Config) {
     }
     println!();
 }
+
+async fn check_inputs(
+    inputs: &[PathBuf],
+    options: &ReadOptions,
+    max_errors: usize,
+) -> Result<bool> {
+    println!("{}", style("Input check:").bold());
+    let mut valid = true;
+    for input in inputs {
+        let check = check_requests(input, options, max_errors).await?;
+        if check.is_valid() {
+            println!(
+                "  {} {} ({} valid)",
+                style("✓").green().bold(),
+                input.display(),
+                check.requests
+            );
+            continue;
+        }
+
+        valid = false;
+        println!(
+            "  {} {} ({} valid, {} malformed)",
+            style("✗").red().bold(),
+            input.display(),
+            check.requests,
+            check.error_count
+        );
+        for error in &check.errors {
+            println!("    {}", style(error).dim());
+        }
+        let unlisted = check.error_count - check.errors.len();
+        if unlisted > 0 {
+            println!("    ... and {unlisted} more");
+        }
+    }
+    println!();
+    Ok(valid)
+}
+
+/// Write the starter config to `path`, refusing to replace an existing file unless `force`.
+fn write_starter_config(path: &Path, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        anyhow::bail!(
+            "{} already exists, pass --force to overwrite it",
+            path.display()
+        );
+    }
+    std::fs::write(path, Config::starter_json()? + "\n")?;
+    println!(
+        "{} Wrote starter config to {}",
+        style("✓").green().bold(),
+        path.display()
+    );
+    println!(
+        "  Set your endpoint in it, then run: blaze --config {} --input requests.jsonl",
+        path.display()
+    );
+    Ok(())
+}
+
+fn print_probe_results(results: &[ProbeResult]) {
+    println!("{}", style("Endpoint probes:").bold());
+    println!("  {:>2} {:>6} {:>9}  Endpoint", "#", "Status", "Latency");
+    for (i, result) in results.iter().enumerate() {
+        let status = match result.status {
+            Some(status) => status.to_string(),
+            None => "-".to_string(),
+        };
+        let latency = format!("{:.0}ms", result.latency.as_secs_f64() * 1000.0);
+        let line = format!("  {:>2} {status:>6} {latency:>9}  {}", i + 1, result.url);
+        match &result.error {
+            Some(error) => println!("{} {}", style(line).red(), style(error).dim()),
+            None => println!("{line}"),
+        }
+    }
+    let reachable = results.iter().filter(|r| r.is_reachable()).count();
+    println!();
+    println!("{} of {} endpoints reachable.", reachable, results.len());
+    println!();
+}
@@ -0,0 +1,300 @@
+//! Prometheus metrics endpoint.
+//!
+//! A minimal embedded HTTP server that serves `GET /metrics` in the
+//! Prometheus text exposition format. Metrics are rendered on demand from
+//! the [`StatsTracker`] and the per-endpoint counters of the [`LoadBalancer`],
+//! so scraping adds no cost to the request hot path.
+
+use crate::endpoint::LoadBalancer;
+use crate::error::{BlazeError, Result};
+use crate::tracker::StatsTracker;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+
+/// Upper bounds of the exported latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Maximum size of an HTTP request head we are willing to read.
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+/// A running metrics server.
+///
+/// Dropping the server also stops it, but [`MetricsServer::shutdown`] waits
+/// for the listener task to exit.
+#[derive(Debug)]
+pub struct MetricsServer {
+    local_addr: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl MetricsServer {
+    /// Bind to an address and start serving metrics on a background task.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::MetricsBind`] if the address cannot be bound.
+    pub async fn start(
+        addr: SocketAddr,
+        stats: Arc<StatsTracker>,
+        load_balancer: Arc<LoadBalancer>,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| BlazeError::MetricsBind { addr, source: e })?;
+        let local_addr = listener
+            .local_addr()
+            .map_err(|e| BlazeError::MetricsBind { addr, source: e })?;
+        info!(addr = %local_addr, "Serving metrics");
+
+        let (shutdown, mut shutdown_rx) = oneshot::channel();
+        let handle = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = &mut shutdown_rx => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, _)) => {
+                            let stats = Arc::clone(&stats);
+                            let load_balancer = Arc::clone(&load_balancer);
+                            tokio::spawn(async move {
+                                if let Err(e) = handle_connection(stream, &stats, &load_balancer).await {
+                                    debug!("Metrics connection failed: {}", e);
+                                }
+                            });
+                        }
+                        Err(e) => debug!("Failed to accept metrics connection: {}", e),
+                    },
+                }
+            }
+        });
+
+        Ok(Self {
+            local_addr,
+            shutdown,
+            handle,
+        })
+    }
+
+    /// Get the address the server is listening on.
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop accepting connections and wait for the server task to exit.
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(());
+        let _ = self.handle.await;
+    }
+}
+
+/// Serve a single HTTP request and close the connection.
+async fn handle_connection(
+    mut stream: TcpStream,
+    stats: &StatsTracker,
+    load_balancer: &LoadBalancer,
+) -> std::io::Result<()> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_REQUEST_HEAD {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..read]);
+    }
+
+    let request_line = head.split(|&b| b == b'\r').next().unwrap_or_default();
+    let mut parts = request_line.split(|&b| b == b' ');
+    let (method, path) = (parts.next(), parts.next());
+
+    let (status_line, content_type, body) = match (method, path) {
+        (Some(b"GET"), Some(b"/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            render(stats, load_balancer),
+        ),
+        _ => ("404 Not Found", "text/plain", "not found\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status_line}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Render all metrics in the Prometheus text exposition format.
+#[must_use]
+pub fn render(stats: &StatsTracker, load_balancer: &LoadBalancer) -> String {
+    let snapshot = stats.snapshot();
+    let mut out = String::new();
+
+    // Writing to a String cannot fail
+    let _ = writeln!(
+        out,
+        "# HELP blaze_requests_total Requests processed, by outcome.\n\
+         # TYPE blaze_requests_total counter\n\
+         blaze_requests_total{{outcome=\"success\"}} {}\n\
+         blaze_requests_total{{outcome=\"failure\"}} {}",
+        snapshot.success_count, snapshot.failure_count
+    );
+    let _ = writeln!(
+        out,
+        "# HELP blaze_requests_per_second Requests completed in the last second.\n\
+         # TYPE blaze_requests_per_second gauge\n\
+         blaze_requests_per_second {}",
+        snapshot.current_rps
+    );
+    let _ = writeln!(
+        out,
+        "# HELP blaze_in_flight Requests currently in flight.\n\
+         # TYPE blaze_in_flight gauge\n\
+         blaze_in_flight {}",
+        load_balancer.total_in_flight()
+    );
+
+    let endpoints = load_balancer.endpoints();
+    let _ = writeln!(
+        out,
+        "# HELP blaze_endpoint_requests_total Requests sent to an endpoint, by outcome.\n\
+         # TYPE blaze_endpoint_requests_total counter"
+    );
+    for endpoint in endpoints {
+        let label = escape_label(endpoint.url());
+        let _ = writeln!(
+            out,
+            "blaze_endpoint_requests_total{{endpoint=\"{label}\",outcome=\"success\"}} {}\n\
+             blaze_endpoint_requests_total{{endpoint=\"{label}\",outcome=\"failure\"}} {}",
+            endpoint.success_count.load(Ordering::Relaxed),
+            endpoint.failure_count.load(Ordering::Relaxed)
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP blaze_endpoint_in_flight Requests currently in flight to an endpoint.\n\
+         # TYPE blaze_endpoint_in_flight gauge"
+    );
+    for endpoint in endpoints {
+        let _ = writeln!(
+            out,
+            "blaze_endpoint_in_flight{{endpoint=\"{}\"}} {}",
+            escape_label(endpoint.url()),
+            endpoint.in_flight.load(Ordering::Relaxed)
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP blaze_endpoint_healthy Whether an endpoint's circuit is closed.\n\
+         # TYPE blaze_endpoint_healthy gauge"
+    );
+    for endpoint in endpoints {
+        let _ = writeln!(
+            out,
+            "blaze_endpoint_healthy{{endpoint=\"{}\"}} {}",
+            escape_label(endpoint.url()),
+            u8::from(endpoint.is_healthy())
+        );
+    }
+
+    let histogram = stats.latency_histogram();
+    let _ = writeln!(
+        out,
+        "# HELP blaze_request_duration_seconds Latency of successful requests.\n\
+         # TYPE blaze_request_duration_seconds histogram"
+    );
+    for bound in LATENCY_BUCKETS {
+        let _ = writeln!(
+            out,
+            "blaze_request_duration_seconds_bucket{{le=\"{bound}\"}} {}",
+            histogram.count_at_most(Duration::from_secs_f64(bound))
+        );
+    }
+    let _ = writeln!(
+        out,
+        "blaze_request_duration_seconds_bucket{{le=\"+Inf\"}} {count}\n\
+         blaze_request_duration_seconds_sum {}\n\
+         blaze_request_duration_seconds_count {count}",
+        stats.total_latency().as_secs_f64(),
+        count = histogram.count()
+    );
+
+    out
+}
+
+/// Escape a Prometheus label value.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EndpointConfig;
+
+    fn load_balancer() -> Arc<LoadBalancer> {
+        Arc::new(
+            LoadBalancer::new(vec![EndpointConfig {
+                url: "http://localhost:8080/\"v1\"".to_string(),
+                weight: 1,
+                api_key: None,
+                model: None,
+                max_concurrent: 10,
+                ..Default::default()
+            }])
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_render() {
+        let stats = StatsTracker::new();
+        stats.record_success(Duration::from_millis(20));
+        stats.record_success(Duration::from_millis(300));
+        stats.record_failure(None);
+
+        let text = render(&stats, &load_balancer());
+        assert!(text.contains("blaze_requests_total{outcome=\"success\"} 2\n"));
+        assert!(text.contains("blaze_requests_total{outcome=\"failure\"} 1\n"));
+        assert!(text.contains(r#"endpoint="http://localhost:8080/\"v1\"",outcome="success"} 0"#));
+        assert!(text.contains("blaze_request_duration_seconds_bucket{le=\"0.01\"} 0\n"));
+        assert!(text.contains("blaze_request_duration_seconds_bucket{le=\"0.05\"} 1\n"));
+        assert!(text.contains("blaze_request_duration_seconds_bucket{le=\"0.5\"} 2\n"));
+        assert!(text.contains("blaze_request_duration_seconds_count 2\n"));
+    }
+
+    #[tokio::test]
+    async fn test_serve_metrics() {
+        let stats = Arc::new(StatsTracker::new());
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let server = MetricsServer::start(addr, stats, load_balancer())
+            .await
+            .unwrap();
+
+        let mut stream = TcpStream::connect(server.local_addr()).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("blaze_in_flight 0"));
+
+        server.shutdown().await;
+    }
+}
@@ -0,0 +1,160 @@
+//! OAuth 2.0 client credentials tokens.
+//!
+//! Endpoints configured for OAuth send short-lived access tokens
+//! instead of a static API key. Each endpoint shares one cached token across
+//! all requests. The token is refreshed shortly before it expires, with a
+//! single fetch in flight at a time, and dropped early if the endpoint
+//! rejects it.
+
+use crate::config::OAuth2Config;
+use crate::error::{BlazeError, Result};
+use parking_lot::RwLock;
+use reqwest::Client;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// How long before expiry a token is refreshed.
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// Token endpoint response, per RFC 6749 section 5.1.
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+/// A fetched access token.
+struct CachedToken {
+    value: Arc<str>,
+    /// When to fetch a new token, if the token expires at all.
+    refresh_at: Option<Instant>,
+}
+
+impl std::fmt::Debug for CachedToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CachedToken")
+            .field("refresh_at", &self.refresh_at)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CachedToken {
+    fn is_fresh(&self) -> bool {
+        self.refresh_at.map_or(true, |at| Instant::now() < at)
+    }
+}
+
+/// Shared, thread-safe cache of an endpoint's access token.
+#[derive(Debug)]
+pub struct TokenCache {
+    config: OAuth2Config,
+    token: RwLock<Option<CachedToken>>,
+    /// Held while fetching, so concurrent requests wait for one fetch.
+    refresh: tokio::sync::Mutex<()>,
+}
+
+impl TokenCache {
+    /// Create an empty cache for the given client credentials.
+    #[must_use]
+    pub fn new(config: OAuth2Config) -> Self {
+        Self {
+            config,
+            token: RwLock::new(None),
+            refresh: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Get a valid access token, fetching a new one if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::TokenRequest`] if the token endpoint can't be
+    /// reached or doesn't issue a token.
+    pub async fn token(&self, client: &Client) -> Result<Arc<str>> {
+        if let Some(token) = self.cached() {
+            return Ok(token);
+        }
+
+        let _refresh = self.refresh.lock().await;
+        // Another request may have fetched a token while this one waited
+        if let Some(token) = self.cached() {
+            return Ok(token);
+        }
+        let token = self.fetch(client).await?;
+        let value = Arc::clone(&token.value);
+        *self.token.write() = Some(token);
+        Ok(value)
+    }
+
+    /// Drop `token` if it is still the cached one, so the next request fetches a new one.
+    pub fn invalidate(&self, token: &str) {
+        let mut cached = self.token.write();
+        if cached
+            .as_ref()
+            .is_some_and(|cached| &*cached.value == token)
+        {
+            *cached = None;
+        }
+    }
+
+    fn cached(&self) -> Option<Arc<str>> {
+        self.token
+            .read()
+            .as_ref()
+            .filter(|token| token.is_fresh())
+            .map(|token| Arc::clone(&token.value))
+    }
+
+    /// Request a new token with the client credentials grant.
+    async fn fetch(&self, client: &Client) -> Result<CachedToken> {
+        let error = |reason: String| BlazeError::TokenRequest {
+            url: self.config.token_url.clone(),
+            reason,
+        };
+
+        let scope = self.config.scopes.join(" ");
+        let mut form = vec![
+            ("grant_type", "client_credentials"),
+            ("client_id", &self.config.client_id),
+            ("client_secret", &self.config.client_secret),
+        ];
+        if !scope.is_empty() {
+            form.push(("scope", &scope));
+        }
+
+        let fetched_at = Instant::now();
+        let response = client
+            .post(&self.config.token_url)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| error(e.to_string()))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(error(format!("HTTP {}: {}", status.as_u16(), body.trim())));
+        }
+        let response: TokenResponse = response
+            .json()
+            .await
+            .map_err(|e| error(format!("invalid token response: {e}")))?;
+
+        // Refresh a margin ahead of expiry, or halfway through very short lifetimes
+        let refresh_at = response.expires_in.map(|secs| {
+            let lifetime = Duration::from_secs(secs);
+            fetched_at + lifetime.saturating_sub(REFRESH_MARGIN.min(lifetime / 2))
+        });
+        debug!(
+            token_url = %self.config.token_url,
+            expires_in = response.expires_in,
+            "Fetched access token"
+        );
+        Ok(CachedToken {
+            value: response.access_token.into(),
+            refresh_at,
+        })
+    }
+}
@@ -0,0 +1,120 @@
+//! Priority-ordered admission of requests.
+//!
+//! Requests are read ahead into a window and admitted highest priority first,
+//! so latency-critical rows don't wait behind best-effort ones. Only requests
+//! inside the window compete: a high-priority request further down the input
+//! waits until the window reaches it. A larger window reorders across more of
+//! the input but holds more requests, bodies included, in memory. Requests of
+//! equal priority, including those without one, keep their input order.
+
+use crate::request::ApiRequest;
+use futures::stream::{self, Stream, StreamExt};
+use futures::FutureExt;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+/// A request waiting in the window, ordered by priority, then input order.
+struct Queued {
+    key: (i64, Reverse<u64>),
+    request: ApiRequest,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+/// Reorder `requests` so the highest priority of the next `window` goes first.
+///
+/// Requests without a priority count as priority 0. The window only fills
+/// with requests that are ready, so a slow input never holds back admission.
+/// A window of 1 keeps the input order.
+pub fn prioritize<'a>(
+    requests: impl Stream<Item = ApiRequest> + Send + 'a,
+    window: usize,
+) -> impl Stream<Item = ApiRequest> + Send + 'a {
+    let window = window.max(1);
+    let state = (requests.boxed().fuse(), BinaryHeap::new(), 0);
+    stream::unfold(
+        state,
+        move |(mut requests, mut queue, mut seq)| async move {
+            while queue.len() < window {
+                // Wait for input only when there is nothing to admit
+                let next = if queue.is_empty() {
+                    requests.next().await
+                } else {
+                    match requests.next().now_or_never() {
+                        Some(next) => next,
+                        None => break,
+                    }
+                };
+                let Some(request) = next else { break };
+                queue.push(Queued {
+                    key: (request.priority.unwrap_or(0), Reverse(seq)),
+                    request,
+                });
+                seq += 1;
+            }
+            let Queued { request, .. } = queue.pop()?;
+            Some((request, (requests, queue, seq)))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(line_number: usize, priority: Option<i64>) -> ApiRequest {
+        ApiRequest {
+            priority,
+            line_number,
+            ..ApiRequest::simple("hello")
+        }
+    }
+
+    async fn order(requests: Vec<ApiRequest>, window: usize) -> Vec<usize> {
+        prioritize(stream::iter(requests), window)
+            .map(|request| request.line_number)
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_prioritize() {
+        // Without priorities the input order is kept
+        let plain = (1..=4).map(|line| request(line, None)).collect();
+        assert_eq!(order(plain, 10).await, [1, 2, 3, 4]);
+
+        // Higher priorities go first, ties and missing priorities in input order
+        let mixed = || {
+            vec![
+                request(1, None),
+                request(2, Some(-1)),
+                request(3, Some(5)),
+                request(4, Some(5)),
+                request(5, None),
+            ]
+        };
+        assert_eq!(order(mixed(), 10).await, [3, 4, 1, 5, 2]);
+
+        // Only requests inside the window are reordered
+        assert_eq!(order(mixed(), 2).await, [1, 3, 4, 5, 2]);
+        assert_eq!(order(mixed(), 1).await, [1, 2, 3, 4, 5]);
+    }
+}
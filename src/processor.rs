@@ -3,48 +3,196 @@
 //! This module coordinates reading requests, distributing them across
 //! endpoints, and writing results with rate limiting and concurrency control.
 
-use crate::client::ApiClient;
-use crate::config::Config;
-use crate::endpoint::LoadBalancer;
+use crate::cache::ResponseCache;
+use crate::checkpoint::{Checkpoint, CompletedLines};
+use crate::client::{ApiClient, ProbeResult};
+use crate::compression::Compression;
+use crate::concurrency::AdaptiveLimiter;
+use crate::config::{Config, OutputFormat, ProcessingConfig};
+use crate::dedup::Deduplicator;
+use crate::endpoint::{Endpoint, EndpointStats, LoadBalanceStrategy, LoadBalancer};
 use crate::error::{BlazeError, Result};
-use crate::request::{ApiRequest, RequestResult};
-use crate::tracker::StatsTracker;
-use futures::stream::{self, StreamExt};
-use governor::{Quota, RateLimiter};
+use crate::events::{EventSender, ProcessEvent};
+use crate::health::HealthState;
+use crate::input::{self, InputSlice, ReadOptions};
+#[cfg(feature = "metrics")]
+use crate::metrics::MetricsServer;
+use crate::priority;
+use crate::projection::Projection;
+use crate::ramp::RampUp;
+use crate::random::RandomSource;
+use crate::reorder::Reorder;
+use crate::report::{HistogramReport, RunFiles, RunReport, RunStatus};
+use crate::request::{
+    ApiRequest, ApiResponse, ErrorKind, ErrorResponse, FailureClass, RequestResult,
+};
+#[cfg(feature = "parquet")]
+use crate::table::ParquetFile;
+use crate::table::{self, Row};
+use crate::tracker::{self, FailureStatuses, StatsSnapshot, StatsTracker};
+use crate::writer::{
+    FlushPolicy, Output, OutputFile, Record, ResultFile, ResultWriter, RotatePolicy,
+};
+use chrono::Utc;
+use futures::future::{self, Either, OptionFuture};
+use futures::stream::{self, Stream, StreamExt};
+use governor::{DefaultDirectRateLimiter, RateLimiter};
 use indicatif::{ProgressBar, ProgressStyle};
 use parking_lot::Mutex;
-use std::num::NonZeroU32;
-use std::path::PathBuf;
+use rand::Rng;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
-use tracing::{info, warn};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+use tracing::{info, info_span, warn, Instrument};
+
+/// How long in-flight requests may take to finish after Ctrl-C.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// How often a paused run checks whether it may admit requests again.
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Completed requests needed before the failure rate threshold applies,
+/// so a handful of early failures can't abort the run.
+const MIN_FAILURE_RATE_SAMPLE: u64 = 100;
+
+/// Endpoint capacity over this many times the concurrency is warned about,
+/// since most endpoint slots would sit idle.
+const IDLE_CAPACITY_FACTOR: usize = 4;
 
 /// Processor for batch API requests.
 pub struct Processor {
     config: Arc<Config>,
     client: ApiClient,
     load_balancer: Arc<LoadBalancer>,
+    /// Global rate limit, also taken from by hedges and retries moved to
+    /// another endpoint.
+    rate_limiter: Arc<DefaultDirectRateLimiter>,
     stats: Arc<StatsTracker>,
+    dedup: Option<Deduplicator>,
+    cache: Option<ResponseCache>,
+    projection: Option<Projection>,
+    read_options: ReadOptions,
+    /// Picks the successful responses written when output is sampled.
+    sampler: RandomSource,
+    /// Set while new requests are held back.
+    paused: Arc<AtomicBool>,
+    /// Where run events are reported.
+    events: EventSender,
+}
+
+/// Throttling and requeue state shared by the requests of one run.
+struct Pipeline {
+    /// Extra pacing while the rate ramps up to the limit.
+    ramp_up: Option<RampUp>,
+    limiter: Option<AdaptiveLimiter>,
+    /// Transient failures set aside for the second pass.
+    requeued: Mutex<Vec<ApiRequest>>,
+    /// Requests dropped before they finished, failed once their pass ends.
+    cancelled: Mutex<Vec<ErrorResponse>>,
+    /// Requests dropped before they were sent, skipped once their pass ends.
+    unsent: Mutex<Vec<ApiRequest>>,
+    /// Ends a pause early so held requests can drain on shutdown.
+    shutdown: watch::Receiver<bool>,
 }
 
 impl Processor {
     /// Create a new processor.
     pub fn new(config: Config) -> Result<Self> {
         let config = Arc::new(config);
-        let client = ApiClient::new(Arc::clone(&config))?;
-        let load_balancer = Arc::new(LoadBalancer::new(config.endpoints.clone())?);
-        let stats = Arc::new(StatsTracker::new());
+        let (concurrency, capacity) = (config.max_in_flight(), config.endpoint_capacity());
+        if concurrency.saturating_mul(IDLE_CAPACITY_FACTOR) < capacity {
+            warn!(
+                concurrency,
+                capacity,
+                "Workers leave most endpoint capacity unused, raise --workers or leave it unset"
+            );
+        }
+        let stats = Arc::new(
+            StatsTracker::new()
+                .with_retry_budget(config.retry.budget_ratio)
+                .with_rps_window(config.processing.rps_window),
+        );
+        let health = match &config.processing.health_file {
+            Some(path) => HealthState::load(path, config.processing.health_ttl),
+            None => HealthState::default(),
+        };
+        let load_balancer = Arc::new(
+            LoadBalancer::new(config.endpoints.clone())?
+                .with_strategy(config.load_balancing)
+                .with_latency_decay(config.latency_decay)
+                .with_seed(config.seed)
+                .with_health(&health),
+        );
+        let rate_limiter = Arc::new(RateLimiter::direct(config.request.rate_quota()));
+        let client = ApiClient::new(Arc::clone(&config))?
+            .with_stats(Arc::clone(&stats))
+            .with_load_balancer(Arc::clone(&load_balancer))
+            .with_rate_limiter(Arc::clone(&rate_limiter));
+        let dedup = config
+            .processing
+            .dedup
+            .then(|| Deduplicator::new(config.processing.dedup_cache_size));
+        let cache = config
+            .processing
+            .cache_dir
+            .as_deref()
+            .map(|dir| ResponseCache::open(dir, config.processing.cache_ttl))
+            .transpose()?;
+        let projection = if config.processing.response_projection.is_empty() {
+            None
+        } else {
+            Some(Projection::new(&config.processing.response_projection)?)
+        };
+        let read_options = ReadOptions::new(&config.processing)?;
+        let sampler = RandomSource::new(config.seed);
 
         Ok(Self {
             config,
             client,
             load_balancer,
+            rate_limiter,
             stats,
+            dedup,
+            cache,
+            projection,
+            read_options,
+            sampler,
+            paused: Arc::new(AtomicBool::new(false)),
+            events: EventSender::default(),
         })
     }
 
+    /// Send run events to `sender` as they happen.
+    ///
+    /// Every completed request is reported, with its result, and so are
+    /// endpoints opening their circuit, endpoints rate limiting requests and
+    /// the end of a [`Processor::process_files`] run. The run waits for room
+    /// in the channel, so a slow consumer slows it down.
+    #[must_use]
+    pub fn with_events(mut self, sender: mpsc::Sender<ProcessEvent>) -> Self {
+        self.events = EventSender::new(sender);
+        self.client = self.client.with_events(self.events.clone());
+        self
+    }
+
+    /// Read the inputs as errors files of earlier runs, sending the requests
+    /// that failed again.
+    ///
+    /// Replayed requests keep their original line numbers, and the input file
+    /// they were first read from as their `source`.
+    #[must_use]
+    pub fn replay_errors(mut self) -> Self {
+        self.read_options.replay = true;
+        self
+    }
+
     /// Process requests from a file.
     pub async fn process_file(
         &self,
@@ -53,204 +201,1222 @@ impl Processor {
         error_path: PathBuf,
         show_progress: bool,
     ) -> Result<ProcessingResult> {
-        // Read all requests first to get total count
-        let requests = self.read_requests(&input_path).await?;
-        let total = requests.len();
+        self.process_files(vec![input_path], output_path, error_path, show_progress)
+            .await
+    }
 
-        info!(total_requests = total, "Loaded requests from file");
-        self.stats.set_total_lines(total);
+    /// Process requests from several files into one set of results.
+    ///
+    /// Files are read in order. With more than one input, each result records
+    /// the file its request came from as its `source`, and resuming is not
+    /// supported.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an input file cannot be read or an output file
+    /// cannot be written.
+    pub async fn process_files(
+        &self,
+        input_paths: Vec<PathBuf>,
+        output_path: Option<PathBuf>,
+        error_path: PathBuf,
+        show_progress: bool,
+    ) -> Result<ProcessingResult> {
+        let started_at = Utc::now();
+        let files = RunFiles {
+            inputs: input_paths.clone(),
+            output: output_path.clone(),
+            errors: error_path.clone(),
+        };
+        // Parent span for the run, joining the caller's trace when one is passed in
+        let input = match input_paths.as_slice() {
+            [path] => path.display().to_string(),
+            paths => format!("{} files", paths.len()),
+        };
+        let span = info_span!("process_file", input);
+        #[cfg(feature = "otel")]
+        crate::telemetry::attach_parent_from_env(&span);
+        let result = self
+            .run(input_paths, output_path, error_path, show_progress)
+            .instrument(span)
+            .await;
 
-        // Setup output files
-        let output_writer = if let Some(path) = &output_path {
-            let file = File::create(path).await.map_err(|e| BlazeError::OutputFileWrite {
-                path: path.clone(),
-                source: e,
-            })?;
-            Some(Arc::new(Mutex::new(BufWriter::new(file))))
-        } else {
-            None
+        // Tell event consumers how the run ended, whichever way that was
+        let snapshot = Box::new(self.stats.snapshot());
+        let event = match &result {
+            Ok(_) => ProcessEvent::RunFinished(snapshot),
+            Err(e) => ProcessEvent::RunFailed {
+                error: e.to_string(),
+                stats: snapshot,
+            },
         };
+        self.events.send(event).await;
 
-        let error_file = File::create(&error_path).await.map_err(|e| BlazeError::OutputFileWrite {
-            path: error_path.clone(),
-            source: e,
-        })?;
-        let error_writer = Arc::new(Mutex::new(BufWriter::new(error_file)));
-
-        // Setup progress bar
-        let progress = if show_progress {
-            let pb = ProgressBar::new(total as u64);
-            pb.set_style(
-                ProgressStyle::default_bar()
-                    .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({percent}%) | {msg}")
-                    .unwrap()
-                    .progress_chars("█▓▒░"),
-            );
-            pb.enable_steady_tick(Duration::from_millis(100));
-            Some(pb)
+        // Write the report however the run ended, so every run leaves a record
+        if let Some(path) = &self.config.processing.report {
+            let (status, error) = match &result {
+                Ok(result) => (result.status(), None),
+                Err(e) => (RunStatus::Failed, Some(e.to_string())),
+            };
+            let report = RunReport {
+                status,
+                error,
+                started_at,
+                finished_at: Utc::now(),
+                files,
+                stats: self.stats.snapshot(),
+                endpoints: self
+                    .load_balancer
+                    .endpoints()
+                    .iter()
+                    .map(|e| e.stats())
+                    .collect(),
+                config: self.config.redacted(),
+            };
+            match report.write(path).await {
+                Ok(()) => info!(path = %path.display(), "Wrote run report"),
+                Err(e) if result.is_ok() => return Err(e),
+                Err(e) => warn!("Failed to write run report: {}", e),
+            }
+        }
+
+        // The latency histograms too, so runs can be compared offline
+        if let Some(path) = &self.config.processing.latency_histogram {
+            let histograms = HistogramReport::capture(&self.stats, self.load_balancer.endpoints());
+            match histograms.write(path).await {
+                Ok(()) => info!(path = %path.display(), "Wrote latency histograms"),
+                Err(e) if result.is_ok() => return Err(e),
+                Err(e) => warn!("Failed to write latency histograms: {}", e),
+            }
+        }
+
+        // Save endpoint health for the next run, however this one ended
+        if let Some(path) = &self.config.processing.health_file {
+            let health = HealthState::capture(self.load_balancer.endpoints());
+            if let Err(e) = health.save(path).await {
+                warn!("Failed to save endpoint health: {}", e);
+            }
+        }
+
+        result
+    }
+
+    /// Run the processing pipeline behind [`Processor::process_files`].
+    async fn run(
+        &self,
+        input_paths: Vec<PathBuf>,
+        output_path: Option<PathBuf>,
+        error_path: PathBuf,
+        show_progress: bool,
+    ) -> Result<ProcessingResult> {
+        // Line numbers restart in every file, so a checkpoint can only track a single input
+        let single_input = match input_paths.as_slice() {
+            [path] => Some(path.clone()),
+            _ => None,
+        };
+        let slice = InputSlice {
+            skip: self.config.processing.skip,
+            take: self.config.processing.take,
+        };
+        let rotate = RotatePolicy {
+            max_bytes: self.config.processing.output_rotate_size,
+            max_lines: self.config.processing.output_rotate_lines,
+        };
+        self.check_files(
+            single_input.is_some(),
+            output_path.as_deref(),
+            slice,
+            rotate,
+        )?;
+        let (checkpoint, completed) =
+            self.open_checkpoint(output_path.as_deref(), single_input.as_deref())?;
+
+        // Requests are streamed, so only count them up front when the progress bar needs a total.
+        // Compressed input is not counted, as that would mean decompressing it twice.
+        let count_total = show_progress
+            && !input_paths
+                .iter()
+                .any(|path| Compression::from_path(path).is_compressed());
+        let total = if count_total {
+            self.count_requests(&input_paths, slice, &completed).await?
         } else {
-            None
+            0
+        };
+        // Only tag requests with their file when there is more than one to tell apart
+        let requests = match &single_input {
+            Some(path) => input::stream_slice(path, &self.read_options, slice).await?,
+            None => input::stream_files(input_paths, &self.read_options),
         };
 
-        // Setup rate limiter
-        let rate_limiter = RateLimiter::direct(Quota::per_second(
-            NonZeroU32::new(self.config.request.rate_limit).unwrap_or(NonZeroU32::MIN),
-        ));
-
-        // Process requests concurrently
-        let workers = self.config.request.workers;
-        let results = stream::iter(requests)
-            .map(|request| {
-                let client = self.client.clone();
-                let lb = Arc::clone(&self.load_balancer);
-                let stats = Arc::clone(&self.stats);
-                let rate_limiter = &rate_limiter;
-                let output = output_writer.clone();
-                let errors = Arc::clone(&error_writer);
-                let progress = progress.clone();
-
-                async move {
-                    // Wait for rate limiter
-                    rate_limiter.until_ready().await;
-
-                    // Select an endpoint
-                    let endpoint = match lb.select() {
-                        Ok(ep) => ep,
-                        Err(e) => {
-                            warn!("Failed to select endpoint: {}", e);
-                            return Err(e);
-                        }
-                    };
+        let write_output = output_path.is_some();
+        let writer = self
+            .spawn_writer(output_path.as_deref(), &error_path, rotate, checkpoint)
+            .await?;
 
-                    // Acquire a slot
-                    if !endpoint.acquire() {
-                        // Wait a bit and try again
-                        tokio::time::sleep(Duration::from_millis(10)).await;
-                        if !endpoint.acquire() {
-                            warn!("Endpoint at capacity, waiting...");
-                            tokio::time::sleep(Duration::from_millis(100)).await;
-                            endpoint.acquire();
-                        }
-                    }
+        // Pay for the handshakes before the first request, not with its latency
+        if self.config.request.prewarm {
+            self.prewarm_endpoints().await;
+        }
 
-                    // Send request
-                    let result = client.send_with_retry(&request, endpoint).await;
-
-                    // Record stats and write output
-                    match &result {
-                        RequestResult::Success(response) => {
-                            let latency = response
-                                .metadata
-                                .as_ref()
-                                .map(|m| Duration::from_millis(m.latency_ms))
-                                .unwrap_or_default();
-                            stats.record_success(latency);
-
-                            if let Some(writer) = &output {
-                                let line = serde_json::to_string(&response).unwrap_or_default();
-                                let mut w = writer.lock();
-                                let _ = futures::executor::block_on(async {
-                                    w.write_all(line.as_bytes()).await?;
-                                    w.write_all(b"\n").await
-                                });
-                            }
-                        }
-                        RequestResult::Failure(error) => {
-                            stats.record_failure();
-                            let line = serde_json::to_string(&error).unwrap_or_default();
-                            let mut w = errors.lock();
-                            let _ = futures::executor::block_on(async {
-                                w.write_all(line.as_bytes()).await?;
-                                w.write_all(b"\n").await
-                            });
-                        }
-                    }
+        let progress = show_progress.then(|| progress_bar(count_total.then_some(total as u64)));
 
-                    // Update progress bar
-                    if let Some(pb) = &progress {
-                        let snapshot = stats.snapshot();
-                        pb.set_message(format!(
-                            "RPS: {:.0} | Success: {} | Failed: {} | Latency: {:.0}ms",
-                            snapshot.current_rps,
-                            snapshot.success_count,
-                            snapshot.failure_count,
-                            snapshot.avg_latency_ms
-                        ));
-                        pb.inc(1);
-                    }
+        // Serve live metrics for the duration of the run
+        #[cfg(feature = "metrics")]
+        let metrics = self.start_metrics().await?;
+
+        let stop = self.stop_signals(progress.clone());
 
-                    Ok(result)
+        // Stop pulling requests at the first input error, but let in-flight requests finish
+        let mut input_error = None;
+        let requests = requests.filter_map(|request| {
+            future::ready(match request {
+                Ok(request) => Some(request),
+                Err(e) => {
+                    input_error = Some(e);
+                    None
                 }
             })
-            .buffer_unordered(workers)
-            .collect::<Vec<_>>()
-            .await;
+        });
+        let requests = requests
+            .filter(|request| {
+                let done = completed.contains(request.line_number);
+                if done {
+                    self.stats.record_skipped(1);
+                }
+                future::ready(!done)
+            })
+            .take_until(shutdown_requested(stop.shutdown.clone()));
+
+        // Responses the output projection did not match, written in full or with empty columns
+        let unprojected = AtomicU64::new(0);
+        let results = self.results(
+            requests,
+            &stop,
+            progress.as_ref(),
+            writer.as_ref().map(|writer| (writer, write_output)),
+            &unprojected,
+        );
+        let (success_count, failure_count) = drain_results(results, stop.shutdown.clone()).await;
 
-        // Flush writers
-        if let Some(writer) = &output_writer {
-            let mut w = writer.lock();
-            w.flush().await.ok();
+        self.warn_unprojected(unprojected.into_inner());
+        stop.stop_listening();
+
+        // Write out queued results and flush, ending any compressed streams
+        if let Some(writer) = writer {
+            writer.finish().await;
         }
+
+        let result = stop.finish(self.summarize(success_count, failure_count));
+        if let Some(pb) = &progress {
+            finish_progress(pb, &result);
+        }
+
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = metrics {
+            metrics.shutdown().await;
+        }
+
+        if let Some(e) = input_error {
+            return Err(e);
+        }
+        Ok(result)
+    }
+
+    /// Count the requests left to send in the input files, for the progress bar and ETA.
+    async fn count_requests(
+        &self,
+        input_paths: &[PathBuf],
+        slice: InputSlice,
+        completed: &CompletedLines,
+    ) -> Result<usize> {
+        let mut total = 0;
+        for path in input_paths {
+            total += input::count_requests(path).await?;
+        }
+        let total = slice.len_of(total).saturating_sub(completed.len());
+        info!(total_requests = total, "Counted requests in input files");
+        self.stats.set_total_lines(total);
+        Ok(total)
+    }
+
+    /// Start the metrics server if an address is configured.
+    #[cfg(feature = "metrics")]
+    async fn start_metrics(&self) -> Result<Option<MetricsServer>> {
+        let Some(addr) = self.config.processing.metrics_addr else {
+            return Ok(None);
+        };
+        let server = MetricsServer::start(
+            addr,
+            Arc::clone(&self.stats),
+            Arc::clone(&self.load_balancer),
+        )
+        .await?;
+        Ok(Some(server))
+    }
+
+    /// Listen for what stops a run from taking new requests: Ctrl-C and the
+    /// time limit, as well as the failure thresholds and request limits
+    /// checked as results complete. In-flight requests are left to finish.
+    fn stop_signals(&self, progress: Option<ProgressBar>) -> StopSignals {
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        let abort_tx = shutdown_tx.clone();
+        let time_limited = Arc::new(AtomicBool::new(false));
+        let mut listeners: Vec<_> = spawn_time_limit(
+            self.config.processing.max_duration,
+            shutdown_tx.clone(),
+            Arc::clone(&time_limited),
+        )
+        .into_iter()
+        .collect();
+        listeners.push(tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                warn!("Interrupted, waiting for in-flight requests to finish");
+                let _ = shutdown_tx.send(true);
+            }
+        }));
+        #[cfg(unix)]
+        listeners.push(self.listen_for_pause(progress));
+        #[cfg(not(unix))]
+        drop(progress);
+
+        StopSignals {
+            shutdown,
+            abort_tx,
+            aborted: AtomicBool::new(false),
+            limit_reached: AtomicBool::new(false),
+            time_limited,
+            listeners,
+        }
+    }
+
+    /// Stop taking new requests once a failure threshold is crossed or every
+    /// endpoint has reached its request limit, flagging which one happened.
+    fn check_stop(&self, stop: &StopSignals) {
+        let StopSignals {
+            abort_tx,
+            aborted,
+            limit_reached,
+            ..
+        } = stop;
+        let processing = &self.config.processing;
+        let check_failures =
+            processing.max_failures.is_some() || processing.max_failure_rate.is_some();
+        if check_failures
+            && failure_threshold_exceeded(processing, &self.stats.snapshot())
+            && !aborted.swap(true, Ordering::Relaxed)
         {
-            let mut w = error_writer.lock();
-            w.flush().await.ok();
+            warn!("Failure threshold exceeded, aborting");
+            let _ = abort_tx.send(true);
         }
 
-        // Finish progress bar
-        if let Some(pb) = &progress {
-            pb.finish_with_message("Complete!");
+        let check_limits = self
+            .config
+            .endpoints
+            .iter()
+            .any(|endpoint| endpoint.max_requests.is_some());
+        if check_limits
+            && self.load_balancer.is_exhausted()
+            && !*abort_tx.borrow()
+            && !limit_reached.swap(true, Ordering::Relaxed)
+        {
+            warn!("Every endpoint reached its request limit, stopping");
+            let _ = abort_tx.send(true);
         }
+    }
+
+    /// Process `requests` into their results, updating progress and checking
+    /// whether to stop as each completes, then queueing it for `writer` along
+    /// with whether successes are written out.
+    ///
+    /// With ordered output, results wait for those before them, and admission
+    /// for room to hold them.
+    fn results<'a>(
+        &'a self,
+        requests: impl Stream<Item = ApiRequest> + Send + 'a,
+        stop: &'a StopSignals,
+        progress: Option<&'a ProgressBar>,
+        writer: Option<(&'a ResultWriter, bool)>,
+        unprojected: &'a AtomicU64,
+    ) -> impl Stream<Item = RequestResult> + 'a {
+        let processing = &self.config.processing;
+        let order = processing
+            .ordered_output
+            .then(|| Reorder::new(processing.reorder_window));
+        let requests = match &order {
+            Some(order) => Either::Left(Arc::clone(order).admit(requests)),
+            None => Either::Right(requests),
+        };
+        // A requeue pass would hold back every result after its first request
+        let requeue = processing.requeue && order.is_none();
+
+        let results = self
+            .process_requests(requests, stop.shutdown.clone(), requeue)
+            .then(move |(line_number, result)| async move {
+                if let Some(pb) = progress {
+                    self.update_progress(pb);
+                }
+                self.check_stop(stop);
+                (line_number, result)
+            });
+        let results = match order {
+            Some(order) => Either::Left(order.restore(results)),
+            None => Either::Right(results),
+        };
+        results.then(move |(line_number, result)| async move {
+            if let Some((writer, write_output)) = writer {
+                self.write_result(writer, write_output, line_number, &result, unprojected)
+                    .await;
+            }
+            result
+        })
+    }
 
-        // Build result
+    /// Warn about responses the output projection did not match.
+    fn warn_unprojected(&self, unprojected: u64) {
+        if unprojected == 0 {
+            return;
+        }
+        let written = if self.config.processing.output_format == OutputFormat::Jsonl {
+            "in full"
+        } else {
+            "with empty columns"
+        };
+        warn!(
+            responses = unprojected,
+            "Responses did not match the output projection and were written {}", written
+        );
+    }
+
+    /// Check that the run's options can be used with its input and output files.
+    fn check_files(
+        &self,
+        single_input: bool,
+        output_path: Option<&Path>,
+        slice: InputSlice,
+        rotate: RotatePolicy,
+    ) -> Result<()> {
+        let processing = &self.config.processing;
+        let resume = processing.resume;
+        if resume && !single_input {
+            return Err(BlazeError::InvalidConfig(
+                "resuming supports a single input file".to_string(),
+            ));
+        }
+        if processing.ordered_output && !single_input {
+            return Err(BlazeError::InvalidConfig(
+                "ordered output supports a single input file".to_string(),
+            ));
+        }
+        if slice != InputSlice::default() && !single_input {
+            return Err(BlazeError::InvalidConfig(
+                "skipping or taking requests supports a single input file".to_string(),
+            ));
+        }
+        if resume && output_path.is_none() {
+            return Err(BlazeError::InvalidConfig(
+                "resuming requires an output file".to_string(),
+            ));
+        }
+        if processing.count_only && output_path.is_some() {
+            return Err(BlazeError::InvalidConfig(
+                "count-only runs write no output file".to_string(),
+            ));
+        }
+        // A previous run may have left an unterminated stream that can't be appended
+        // to, while rotated files start a new one once the last one is found complete
+        if resume
+            && !rotate.is_enabled()
+            && output_path.is_some_and(|path| Compression::from_path(path).is_compressed())
+        {
+            return Err(BlazeError::InvalidConfig(
+                "resuming is not supported with a compressed output file".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Set up the checkpoint next to the output file, returning the lines a
+    /// resumed run already completed.
+    fn open_checkpoint(
+        &self,
+        output_path: Option<&Path>,
+        single_input: Option<&Path>,
+    ) -> Result<(Option<Checkpoint>, CompletedLines)> {
+        let resumable = self.config.processing.output_format.is_resumable();
+        let (Some(path), Some(input_path)) = (output_path, single_input) else {
+            return Ok((None, CompletedLines::default()));
+        };
+        if !resumable {
+            return Ok((None, CompletedLines::default()));
+        }
+
+        let checkpoint_path = Checkpoint::path_for(path);
+        if !self.config.processing.resume {
+            let checkpoint = Checkpoint::create(&checkpoint_path, input_path)?;
+            return Ok((Some(checkpoint), CompletedLines::default()));
+        }
+        let (checkpoint, completed) = Checkpoint::resume(&checkpoint_path, input_path)?;
+        if !completed.is_empty() {
+            info!(
+                completed = completed.len(),
+                "Resuming from checkpoint, skipping completed requests"
+            );
+        }
+        Ok((Some(checkpoint), completed))
+    }
+
+    /// Open the result files and spawn the task that owns them, fed by the
+    /// workers over a bounded channel.
+    ///
+    /// Output files are appended to when resuming. Counting only opens no
+    /// files at all, so results are neither serialized nor written.
+    async fn spawn_writer(
+        &self,
+        output_path: Option<&Path>,
+        error_path: &Path,
+        rotate: RotatePolicy,
+        checkpoint: Option<Checkpoint>,
+    ) -> Result<Option<ResultWriter>> {
+        if self.config.processing.count_only {
+            return Ok(None);
+        }
+        let write_error = |path: &Path, source| BlazeError::OutputFileWrite {
+            path: path.to_path_buf(),
+            source,
+        };
+        let output = match output_path {
+            Some(path) => Some(
+                self.open_output(path, self.config.processing.resume, rotate)
+                    .await
+                    .map_err(|e| write_error(path, e))?,
+            ),
+            None => None,
+        };
+        let errors = ResultFile::create(error_path, rotate)
+            .await
+            .map_err(|e| write_error(error_path, e))?;
+        let flush = FlushPolicy {
+            every: self.config.processing.flush_every,
+            interval: self.config.processing.flush_interval,
+        };
+        Ok(Some(ResultWriter::spawn(output, errors, checkpoint, flush)))
+    }
+
+    /// Show the latest stats in the progress bar and count a finished request.
+    fn update_progress(&self, pb: &ProgressBar) {
         let snapshot = self.stats.snapshot();
-        let success_count = results.iter().filter(|r| r.as_ref().map(|r| r.is_success()).unwrap_or(false)).count();
-        let failure_count = results.len() - success_count;
+        let mut message = format!(
+            "RPS: {:.0} | Success: {} | Failed: {} | Latency: {:.0}ms",
+            snapshot.current_rps,
+            snapshot.success_count,
+            snapshot.failure_count,
+            snapshot.avg_latency_ms
+        );
+        if self.config.request.adaptive_concurrency {
+            let _ = write!(message, " | Workers: {}", snapshot.concurrency_limit);
+        }
+        // Only known when the input was counted up front
+        if let Some(eta) = snapshot.eta() {
+            let _ = write!(message, " | ETA: {}", tracker::format_eta(eta));
+        }
+        if self.is_paused() {
+            message.insert_str(0, "Paused | ");
+        }
+        pb.set_message(message);
+        pb.inc(1);
+    }
 
-        Ok(ProcessingResult {
-            total_processed: results.len(),
+    /// Queue a result for the result files.
+    async fn write_result(
+        &self,
+        writer: &ResultWriter,
+        write_output: bool,
+        line_number: usize,
+        result: &RequestResult,
+        unprojected: &AtomicU64,
+    ) {
+        match result {
+            RequestResult::Success(response) => {
+                if write_output {
+                    let output = self
+                        .is_sampled()
+                        .then(|| self.output_record(response, line_number, unprojected));
+                    writer
+                        .send(Record::Output {
+                            output,
+                            line_number,
+                        })
+                        .await;
+                }
+            }
+            // An unwritable error line is logged rather than written out empty
+            RequestResult::Failure(error) => match serde_json::to_string(&error) {
+                Ok(line) => writer.send(Record::Error { line }).await,
+                Err(e) => warn!(line_number, "Failed to serialize error response: {}", e),
+            },
+            // Nothing was sent, so a resumed run picks the request up again
+            RequestResult::Skipped { .. } => {}
+        }
+    }
+
+    /// Build the result of a run from the stats, as if it ran to completion.
+    fn summarize(&self, success_count: usize, failure_count: usize) -> ProcessingResult {
+        let snapshot = self.stats.snapshot();
+        ProcessingResult {
+            total_processed: success_count + failure_count,
             success_count,
             failure_count,
+            skipped_count: snapshot.skipped_count,
             elapsed: snapshot.elapsed,
             avg_latency_ms: snapshot.avg_latency_ms,
+            p50_latency_ms: snapshot.p50_latency_ms,
+            p95_latency_ms: snapshot.p95_latency_ms,
+            p99_latency_ms: snapshot.p99_latency_ms,
+            p50_ttft_ms: snapshot.p50_ttft_ms,
+            p95_ttft_ms: snapshot.p95_ttft_ms,
             overall_rps: snapshot.overall_rps,
+            requeued_count: snapshot.requeued_count,
+            cancelled_count: snapshot.cancelled_count,
+            retries_denied: snapshot.retries_denied,
+            deduped_count: snapshot.deduped_count,
+            cached_count: snapshot.cached_count,
+            invalid_headers: snapshot.invalid_headers,
+            failure_statuses: snapshot.failure_statuses,
+            status_counts: snapshot.status_counts,
+            interrupted: false,
+            aborted: false,
+            time_limited: false,
+            limit_reached: false,
+            prompt_tokens: snapshot.prompt_tokens,
+            completion_tokens: snapshot.completion_tokens,
+            estimated_cost: snapshot.estimated_cost,
+            endpoints: self
+                .load_balancer
+                .endpoints()
+                .iter()
+                .map(|e| e.stats())
+                .collect(),
+        }
+    }
+
+    /// Process a stream of requests, yielding each result as it completes.
+    ///
+    /// Requests go through the same rate limiting, load balancing, retries and
+    /// deduplication as [`Processor::process_file`] and count towards
+    /// [`Processor::stats`], but nothing is written to disk. Results arrive in
+    /// completion order. If requeueing is enabled, transient failures are
+    /// retried once the input stream ends, and only their final result is
+    /// yielded.
+    ///
+    /// ```rust,no_run
+    /// use blaze_api::{ApiRequest, Config, Processor};
+    /// use futures::{stream, StreamExt};
+    ///
+    /// # async fn run(config: Config) -> blaze_api::Result<()> {
+    /// let processor = Processor::new(config)?;
+    /// let requests = stream::iter(vec![ApiRequest::simple("Hello!")]);
+    /// let mut results = std::pin::pin!(processor.process_stream(requests));
+    /// while let Some(result) = results.next().await {
+    ///     println!("success: {}", result.is_success());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn process_stream<'a>(
+        &'a self,
+        requests: impl Stream<Item = ApiRequest> + Send + 'a,
+    ) -> impl Stream<Item = RequestResult> + Send + 'a {
+        // With the sender gone no shutdown can be requested, so only the input ends the stream
+        let (_, shutdown) = watch::channel(false);
+        self.process_requests(requests, shutdown, self.config.processing.requeue)
+            .map(|(_, result)| result)
+    }
+
+    /// Process requests concurrently, yielding each result with the line
+    /// number of its request.
+    ///
+    /// Requests are admitted highest priority first within the configured
+    /// priority window. Transient failures are set aside when `requeue` is
+    /// set and retried in a second pass once the first drains; those a
+    /// shutdown keeps from their retry are yielded as skipped. With a drain
+    /// timeout, requests still in flight that long after a pass runs out of
+    /// input are cancelled and yielded as timeout failures.
+    fn process_requests<'a>(
+        &'a self,
+        requests: impl Stream<Item = ApiRequest> + Send + 'a,
+        shutdown: watch::Receiver<bool>,
+        requeue: bool,
+    ) -> impl Stream<Item = (usize, RequestResult)> + Send + 'a {
+        // Concurrency is either fixed at `workers` or adapted between 1 and `max_workers`
+        let request_config = &self.config.request;
+        let initial = self.config.workers();
+        let limiter = request_config
+            .adaptive_concurrency
+            .then(|| AdaptiveLimiter::new(initial, 1, request_config.max_workers));
+        let workers = self.config.max_in_flight();
+        self.stats.set_concurrency_limit(initial);
+
+        let ramp_up = (!request_config.ramp_up.is_zero()).then(|| {
+            info!(period = ?request_config.ramp_up, "Ramping up to the rate limit");
+            RampUp::new(request_config.rate_per_second(), request_config.ramp_up)
+        });
+        let pipeline = Arc::new(Pipeline {
+            ramp_up,
+            limiter,
+            requeued: Mutex::new(Vec::new()),
+            cancelled: Mutex::new(Vec::new()),
+            unsent: Mutex::new(Vec::new()),
+            shutdown: shutdown.clone(),
+        });
+
+        let drain_timeout = self.config.processing.drain_timeout;
+        let first_pass = {
+            let pipeline = Arc::clone(&pipeline);
+            let (input_done, drained) = watch::channel(false);
+            // Requests waiting in the priority window are not admitted after a shutdown
+            let requests = priority::prioritize(requests, self.config.processing.priority_window)
+                .take_until(shutdown_requested(shutdown.clone()));
+            mark_end(requests, input_done)
+                .map(move |request| self.process_request(request, Arc::clone(&pipeline), requeue))
+                .buffer_unordered(workers)
+                .take_until(drain_expired(drained, drain_timeout))
+        }
+        .chain(self.cancelled_results(Arc::clone(&pipeline)));
+
+        // Second pass over transient failures, which fail for good if they fail again
+        let stopped = shutdown.clone();
+        let (requeued_done, drained) = watch::channel(false);
+        let cancelled = self.cancelled_results(Arc::clone(&pipeline));
+        let skipped = self.skipped_results(Arc::clone(&pipeline));
+        let second_pass = stream::once(async move {
+            let requeued_count = {
+                // Highest priority last, as requests are popped off the end
+                let mut requeued = pipeline.requeued.lock();
+                requeued.sort_by_key(|request| Reverse(request.priority.unwrap_or(0)));
+                requeued.reverse();
+                requeued.len()
+            };
+            self.stats.record_requeued(requeued_count);
+            if requeued_count > 0 && !*stopped.borrow() {
+                info!(
+                    requeued = requeued_count,
+                    "Retrying transient failures from the first pass"
+                );
+            }
+            // Requests stay set aside until admitted, so a stop leaves the rest skipped
+            let requests = stream::unfold(Arc::clone(&pipeline), |pipeline| async move {
+                let request = pipeline.requeued.lock().pop()?;
+                Some((request, pipeline))
+            });
+            mark_end(requests, requeued_done)
+                .map(move |request| self.process_request(request, Arc::clone(&pipeline), false))
         })
+        .flatten()
+        .take_until(shutdown_requested(shutdown))
+        .buffer_unordered(workers)
+        .take_until(drain_expired(drained, drain_timeout))
+        .chain(cancelled)
+        .chain(skipped);
+
+        first_pass
+            .chain(second_pass)
+            .filter_map(future::ready)
+            .then(move |(line_number, result)| async move {
+                if self.events.is_enabled() {
+                    let result = result.clone();
+                    let event = ProcessEvent::RequestCompleted {
+                        line_number,
+                        result,
+                    };
+                    self.events.send(event).await;
+                }
+                (line_number, result)
+            })
     }
 
-    /// Read requests from a JSONL file.
-    async fn read_requests(&self, path: &PathBuf) -> Result<Vec<ApiRequest>> {
-        let file = File::open(path).await.map_err(|e| BlazeError::InputFileRead {
-            path: path.clone(),
-            source: e,
-        })?;
-
-        let reader = BufReader::new(file);
-        let mut lines = reader.lines();
-        let mut requests = Vec::new();
-        let mut line_number = 0;
-
-        while let Some(line) = lines.next_line().await.map_err(|e| BlazeError::InputFileRead {
-            path: path.clone(),
-            source: e,
-        })? {
-            line_number += 1;
-
-            // Skip empty lines
-            let trimmed = line.trim();
-            if trimmed.is_empty() {
-                continue;
+    /// Yield the requests of a pass cancelled at the drain timeout as timeout
+    /// failures, or as skipped if they had not been sent yet.
+    ///
+    /// Must be chained after the pass, so the pass has been dropped, and its
+    /// requests recorded as cancelled, by the time this is polled.
+    fn cancelled_results(
+        &self,
+        pipeline: Arc<Pipeline>,
+    ) -> impl Stream<Item = Option<(usize, RequestResult)>> + Send + '_ {
+        let log_outcomes = self.config.processing.log_outcomes;
+        stream::once(async move {
+            let cancelled = std::mem::take(&mut *pipeline.cancelled.lock());
+            if !cancelled.is_empty() {
+                warn!(
+                    cancelled = cancelled.len(),
+                    "Cancelled requests still in flight at the drain timeout"
+                );
+                self.stats.record_cancelled(cancelled.len());
             }
+            let failed = stream::iter(cancelled).map(move |error| {
+                let (line_number, status) = (error.line_number, error.status_code);
+                let result = RequestResult::Failure(error);
+                if log_outcomes {
+                    log_outcome(line_number, &result, Duration::ZERO);
+                }
+                self.stats.record_failure(status);
+                Some((line_number, result))
+            });
+            let unsent = std::mem::take(&mut *pipeline.unsent.lock());
+            let unsent = stream::iter(unsent).map(move |request| {
+                let result = RequestResult::Skipped {
+                    reason: "not sent before the drain timeout".to_string(),
+                };
+                if log_outcomes {
+                    log_outcome(request.line_number, &result, Duration::ZERO);
+                }
+                self.record_outcome(&result, false);
+                Some((request.line_number, result))
+            });
+            failed.chain(unsent)
+        })
+        .flatten()
+    }
 
-            let mut request: ApiRequest =
-                serde_json::from_str(trimmed).map_err(|e| BlazeError::JsonParse {
-                    line: line_number,
-                    source: e,
-                })?;
+    /// Yield the transient failures a stop left unretried as skipped.
+    ///
+    /// Must be chained after the second pass, so whatever it did not admit
+    /// is still set aside by the time this is polled.
+    fn skipped_results(
+        &self,
+        pipeline: Arc<Pipeline>,
+    ) -> impl Stream<Item = Option<(usize, RequestResult)>> + Send + '_ {
+        let log_outcomes = self.config.processing.log_outcomes;
+        stream::once(async move {
+            let skipped = std::mem::take(&mut *pipeline.requeued.lock());
+            if !skipped.is_empty() {
+                warn!(
+                    skipped = skipped.len(),
+                    "Skipping retry of transient failures after the run was stopped"
+                );
+            }
+            stream::iter(skipped).map(move |request| {
+                let result = RequestResult::Skipped {
+                    reason: "the run was stopped before its retry".to_string(),
+                };
+                if log_outcomes {
+                    log_outcome(request.line_number, &result, Duration::ZERO);
+                }
+                self.record_outcome(&result, false);
+                Some((request.line_number, result))
+            })
+        })
+        .flatten()
+    }
 
-            request.line_number = line_number;
-            requests.push(request);
+    /// Wait until the run is not paused, or a shutdown has been requested.
+    async fn wait_while_paused(&self, shutdown: &watch::Receiver<bool>) {
+        while self.is_paused() && !*shutdown.borrow() {
+            tokio::time::sleep(PAUSE_POLL_INTERVAL).await;
         }
+    }
+
+    /// Pause on `SIGUSR1` and resume on `SIGUSR2` until the returned task is aborted.
+    #[cfg(unix)]
+    fn listen_for_pause(&self, progress: Option<ProgressBar>) -> tokio::task::JoinHandle<()> {
+        use tokio::signal::unix::{signal, SignalKind};
 
-        Ok(requests)
+        let paused = Arc::clone(&self.paused);
+        tokio::spawn(async move {
+            let (Ok(mut pause), Ok(mut resume)) = (
+                signal(SignalKind::user_defined1()),
+                signal(SignalKind::user_defined2()),
+            ) else {
+                warn!("Failed to listen for pause signals, SIGUSR1 and SIGUSR2 are ignored");
+                return;
+            };
+            loop {
+                tokio::select! {
+                    Some(()) = pause.recv() => {
+                        if !paused.swap(true, Ordering::Relaxed) {
+                            warn!("Paused, in-flight requests will finish; send SIGUSR2 to resume");
+                            if let Some(pb) = &progress {
+                                pb.set_message("Paused (send SIGUSR2 to resume)");
+                            }
+                        }
+                    }
+                    Some(()) = resume.recv() => {
+                        if paused.swap(false, Ordering::Relaxed) {
+                            info!("Resumed");
+                            if let Some(pb) = &progress {
+                                pb.set_message("Resumed");
+                            }
+                        }
+                    }
+                    else => return,
+                }
+            }
+        })
+    }
+
+    /// Send a single request and record its outcome in the stats.
+    ///
+    /// Returns `None` if the request was set aside for the requeue pass.
+    async fn process_request(
+        &self,
+        request: ApiRequest,
+        pipeline: Arc<Pipeline>,
+        requeue: bool,
+    ) -> Option<(usize, RequestResult)> {
+        // Rows that can't be read or have nothing to send fail without taking a slot or an API call
+        if let Some(result) = self.reject_invalid(&request) {
+            return Some((request.line_number, result));
+        }
+
+        // Cached responses don't wait for a slot, the rate limiter or an endpoint
+        if let Some(result) = self.cached_result(&request).await {
+            return Some((request.line_number, result));
+        }
+
+        let limiter = pipeline.limiter.as_ref();
+        let mut in_flight = InFlight::new(&request, &pipeline);
+
+        // Wait for a slot under the adaptive concurrency limit
+        let _permit = OptionFuture::from(limiter.map(AdaptiveLimiter::acquire)).await;
+
+        // Wait for rate limiter, pacing requests further while ramping up
+        if let Some(ramp_up) = &pipeline.ramp_up {
+            ramp_up.until_ready().await;
+        }
+        self.rate_limiter.until_ready().await;
+
+        // Hold the request back while paused, so only requests already sent stay in flight
+        self.wait_while_paused(&pipeline.shutdown).await;
+        in_flight.sending();
+
+        // Select an endpoint, treating no available endpoint as a transient failure
+        let started = Instant::now();
+        let hash_key = self.hash_key(&request);
+        let (client, lb, request_ref) = (&self.client, &self.load_balancer, &request);
+        let hedge_after = self.config.request.hedge_after;
+        let send = move || async move {
+            match lb.select_for_key(hash_key.as_deref()) {
+                Ok(endpoint) => {
+                    // Release a probe of the endpoint if the request is dropped unanswered
+                    let _probe = endpoint.guard_probe();
+
+                    // Wait for a slot, held until the request finishes
+                    let slot = endpoint.acquire().await;
+
+                    // Send request, hedging it to another endpoint if it runs slow
+                    let result = match hedge_after {
+                        Some(delay) => {
+                            client
+                                .send_hedged(request_ref, slot, delay, |e| lb.select_other(e))
+                                .await
+                        }
+                        None => client.send_with_retry(request_ref, slot).await,
+                    };
+                    // A hedge may have been answered by an endpoint with another model
+                    if let (Some((cache, key)), RequestResult::Success(response)) =
+                        (self.cache_key(request_ref, &endpoint), &result)
+                    {
+                        let served = response.metadata.as_ref().map(|m| m.endpoint.as_str());
+                        if served == Some(endpoint.url()) {
+                            cache.put(&key, response).await;
+                        }
+                    }
+                    result
+                }
+                // Left for a later run, as with requests a stop kept from being sent
+                Err(e @ BlazeError::EndpointsExhausted) => RequestResult::Skipped {
+                    reason: e.to_string(),
+                },
+                Err(e) => {
+                    warn!("Failed to select endpoint: {}", e);
+                    RequestResult::Failure(
+                        ErrorResponse::new(request_ref, e.to_string(), 0)
+                            .with_class(FailureClass::Transient),
+                    )
+                }
+            }
+        };
+
+        // Duplicates reuse the response of the first identical request
+        let request_config = &self.config.request;
+        let dedup_key = self.dedup.as_ref().and_then(|dedup| {
+            let key = Deduplicator::key(
+                &request,
+                request_config.body_template.as_ref(),
+                request_config.strict_template,
+            )?;
+            Some((dedup, key))
+        });
+        let result = match dedup_key {
+            Some((dedup, key)) => dedup.run(&key, &request, send).await,
+            None => send().await,
+        };
+        in_flight.finish();
+        let deduped = matches!(&result, RequestResult::Success(response) if response.deduped);
+
+        if let Some(limiter) = limiter.filter(|_| !deduped) {
+            limiter.record(match &result {
+                RequestResult::Success(response) => Some(
+                    response
+                        .metadata
+                        .as_ref()
+                        .map(|m| Duration::from_millis(m.latency_ms))
+                        .unwrap_or_default(),
+                ),
+                RequestResult::Failure(_) | RequestResult::Skipped { .. } => None,
+            });
+            self.stats.set_concurrency_limit(limiter.limit());
+        }
+
+        if requeue && result.is_transient_failure() {
+            pipeline.requeued.lock().push(request);
+            return None;
+        }
+
+        if self.config.processing.log_outcomes {
+            log_outcome(request.line_number, &result, started.elapsed());
+        }
+
+        self.record_outcome(&result, deduped);
+        Some((request.line_number, result))
+    }
+
+    /// Fail a request whose line could not be read, or that has neither
+    /// input nor body if blank rows are rejected, recording the failure in
+    /// the stats.
+    ///
+    /// A body template may render a body from other fields of the row, so
+    /// rows are only checked for blanks when none is configured.
+    fn reject_invalid(&self, request: &ApiRequest) -> Option<RequestResult> {
+        let check_blank = self.config.processing.reject_blank_input
+            && self.config.request.body_template.is_none()
+            && request.method().has_body();
+        let message = match &request.unreadable {
+            Some(reason) => reason.as_str(),
+            None if check_blank && request.is_blank() => "row has neither input nor body",
+            None => return None,
+        };
+        let result = RequestResult::Failure(
+            ErrorResponse::new(request, message, 0).with_kind(ErrorKind::Validation),
+        );
+        if self.config.processing.log_outcomes {
+            log_outcome(request.line_number, &result, Duration::ZERO);
+        }
+        self.record_outcome(&result, false);
+        Some(result)
+    }
+
+    /// Answer a request from the response cache, recording the hit in the stats.
+    ///
+    /// The cache key depends on the model and format of the endpoint, and
+    /// the request could be sent to any of them, so a response cached for
+    /// any endpoint answers it.
+    async fn cached_result(&self, request: &ApiRequest) -> Option<RequestResult> {
+        let cache = self.cache.as_ref()?;
+        let started = Instant::now();
+        let mut keys = Vec::new();
+        for endpoint in self.load_balancer.endpoints() {
+            if let Some((_, key)) = self.cache_key(request, endpoint) {
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+        }
+        for key in keys {
+            if let Some(response) = cache.get(&key, request).await {
+                let result = RequestResult::Success(response);
+                if self.config.processing.log_outcomes {
+                    log_outcome(request.line_number, &result, started.elapsed());
+                }
+                self.record_outcome(&result, false);
+                return Some(result);
+            }
+        }
+        None
+    }
+
+    /// Record the final outcome of a request in the stats.
+    fn record_outcome(&self, result: &RequestResult, deduped: bool) {
+        let stats = &self.stats;
+        match result {
+            RequestResult::Success(_) if deduped => stats.record_deduped(),
+            RequestResult::Success(response) if response.is_cached() => stats.record_cached(),
+            RequestResult::Success(response) => {
+                let latency = response
+                    .metadata
+                    .as_ref()
+                    .map(|m| Duration::from_millis(m.latency_ms))
+                    .unwrap_or_default();
+                stats.record_success(latency);
+                if let Some(metadata) = &response.metadata {
+                    stats.record_success_status(metadata.status);
+                    if let Some(ttft_ms) = metadata.ttft_ms {
+                        stats.record_time_to_first_token(Duration::from_millis(ttft_ms));
+                    }
+                    if let Some(usage) = metadata.usage {
+                        stats.record_usage(usage, metadata.estimated_cost.unwrap_or(0.0));
+                    }
+                }
+            }
+            RequestResult::Failure(error) if error.error_kind == ErrorKind::Validation => {
+                stats.record_validation_failure();
+            }
+            RequestResult::Failure(error) => stats.record_failure(error.status_code),
+            RequestResult::Skipped { .. } => stats.record_skipped(1),
+        }
+    }
+
+    /// Get the response cache with the key of a request as rendered for `endpoint`.
+    fn cache_key(
+        &self,
+        request: &ApiRequest,
+        endpoint: &Endpoint,
+    ) -> Option<(&ResponseCache, String)> {
+        let cache = self.cache.as_ref()?;
+        let request_config = &self.config.request;
+        let key = ResponseCache::key(
+            request,
+            endpoint,
+            request_config.body_template.as_ref(),
+            request_config.strict_template,
+        )?;
+        Some((cache, key))
+    }
+
+    /// Get the consistent hashing key of a request from the configured metadata field.
+    fn hash_key(&self, request: &ApiRequest) -> Option<String> {
+        if self.config.load_balancing != LoadBalanceStrategy::ConsistentHash {
+            return None;
+        }
+        request
+            .metadata
+            .get(&self.config.hash_key)
+            .map(|value| match value {
+                serde_json::Value::String(key) => key.clone(),
+                other => other.to_string(),
+            })
+    }
+
+    /// Decide whether a successful response is written, when output is sampled.
+    fn is_sampled(&self) -> bool {
+        self.config
+            .processing
+            .sample_output
+            .map_or(true, |rate| self.sampler.with(|rng| rng.random_bool(rate)))
+    }
+
+    /// Open the output file in the configured format, appending to it when resuming.
+    async fn open_output(
+        &self,
+        path: &Path,
+        resume: bool,
+        rotate: RotatePolicy,
+    ) -> std::io::Result<OutputFile> {
+        let format = self.config.processing.output_format;
+        // Validation makes sure tabular formats have a projection for their columns
+        let projection = self
+            .projection
+            .as_ref()
+            .filter(|_| format != OutputFormat::Jsonl);
+        #[cfg(feature = "parquet")]
+        if let (OutputFormat::Parquet, Some(projection)) = (format, projection) {
+            return ParquetFile::create(path, projection)
+                .await
+                .map(OutputFile::Parquet);
+        }
+        let file = if resume {
+            ResultFile::append(path, rotate).await?
+        } else {
+            ResultFile::create(path, rotate).await?
+        };
+        Ok(OutputFile::Lines(match projection {
+            Some(projection) => file.with_header(table::csv_header(projection)),
+            None => file,
+        }))
+    }
+
+    /// Convert a response for the output file, applying the output projection.
+    fn output_record(
+        &self,
+        response: &ApiResponse,
+        line_number: usize,
+        unprojected: &AtomicU64,
+    ) -> Output {
+        let format = self.config.processing.output_format;
+        let tabular = self
+            .projection
+            .as_ref()
+            .filter(|_| format != OutputFormat::Jsonl);
+        if let Some(projection) = tabular {
+            let (row, complete) = Row::new(response, line_number, projection);
+            if !complete {
+                unprojected.fetch_add(1, Ordering::Relaxed);
+            }
+            #[cfg(feature = "parquet")]
+            if format == OutputFormat::Parquet {
+                return Output::Row(row);
+            }
+            return Output::Line(row.to_csv());
+        }
+
+        let projected = self.projection.as_ref().and_then(|projection| {
+            let body = projection.apply(&response.response);
+            if body.is_none() {
+                unprojected.fetch_add(1, Ordering::Relaxed);
+            }
+            body
+        });
+        let line = match projected {
+            Some(body) => serde_json::to_string(&ApiResponse {
+                input: response.input.clone(),
+                response: body,
+                metadata: response.metadata.clone(),
+                request_metadata: response.request_metadata.clone(),
+                deduped: response.deduped,
+            }),
+            None => serde_json::to_string(response),
+        };
+        Output::Line(line.unwrap_or_default())
+    }
+
+    /// Probe every endpoint concurrently, returning results in configuration order.
+    pub async fn probe_endpoints(&self) -> Vec<ProbeResult> {
+        let endpoints = self.load_balancer.endpoints();
+        future::join_all(endpoints.iter().map(|endpoint| self.client.probe(endpoint))).await
+    }
+
+    /// Open pooled connections to every endpoint ahead of the first request.
+    async fn prewarm_endpoints(&self) {
+        let endpoints = self.load_balancer.endpoints();
+        let opened = future::join_all(
+            endpoints
+                .iter()
+                .map(|endpoint| self.client.prewarm(endpoint)),
+        )
+        .await;
+        for (endpoint, connections) in endpoints.iter().zip(opened) {
+            info!(
+                endpoint = endpoint.url(),
+                connections, "Prewarmed connections"
+            );
+        }
+    }
+
+    /// Stop admitting new requests. Requests already in flight still finish.
+    ///
+    /// A running [`Processor::process_file`] also pauses on `SIGUSR1`.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    /// Resume admitting requests after [`Processor::pause`], or on `SIGUSR2`.
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    /// Check whether new requests are being held back.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
     }
 
     /// Get the current stats snapshot.
@@ -264,8 +1430,284 @@ impl Processor {
     }
 }
 
+/// What stops a run early, and the tasks listening for it.
+struct StopSignals {
+    /// Flagged once the run should stop taking new requests.
+    shutdown: watch::Receiver<bool>,
+    /// Stops the run from its own checks as results complete.
+    abort_tx: watch::Sender<bool>,
+    /// Set when a failure threshold stopped the run.
+    aborted: AtomicBool,
+    /// Set when every endpoint reached its request limit.
+    limit_reached: AtomicBool,
+    /// Set when the time limit stopped the run.
+    time_limited: Arc<AtomicBool>,
+    /// Tasks listening for Ctrl-C, the time limit and pause signals.
+    listeners: Vec<tokio::task::JoinHandle<()>>,
+}
+
+impl StopSignals {
+    /// Stop the listening tasks once no more requests will be taken.
+    fn stop_listening(&self) {
+        for listener in &self.listeners {
+            listener.abort();
+        }
+    }
+
+    /// Record on a run's result why it stopped early, if it did.
+    fn finish(self, result: ProcessingResult) -> ProcessingResult {
+        let aborted = self.aborted.into_inner();
+        let time_limited = self.time_limited.load(Ordering::Relaxed) && !aborted;
+        let limit_reached = self.limit_reached.into_inner();
+        let interrupted = *self.shutdown.borrow() && !aborted && !time_limited && !limit_reached;
+        ProcessingResult {
+            interrupted,
+            aborted,
+            time_limited,
+            limit_reached,
+            ..result
+        }
+    }
+}
+
+/// Request a shutdown once the run has taken `max_duration`, if set.
+fn spawn_time_limit(
+    max_duration: Option<Duration>,
+    shutdown_tx: watch::Sender<bool>,
+    time_limited: Arc<AtomicBool>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let max_duration = max_duration?;
+    Some(tokio::spawn(async move {
+        tokio::time::sleep(max_duration).await;
+        // A shutdown already under way keeps its own reason
+        if !*shutdown_tx.borrow() {
+            warn!(
+                ?max_duration,
+                "Time limit reached, waiting for in-flight requests to finish"
+            );
+            time_limited.store(true, Ordering::Relaxed);
+            let _ = shutdown_tx.send(true);
+        }
+    }))
+}
+
+/// Resolve once a shutdown has been requested.
+///
+/// Never resolves if the signal listener goes away without requesting one.
+async fn shutdown_requested(mut shutdown: watch::Receiver<bool>) {
+    if shutdown.wait_for(|&requested| requested).await.is_err() {
+        future::pending::<()>().await;
+    }
+}
+
+/// Pass `requests` through, flagging `done` once the last one has been taken.
+fn mark_end<S: Stream>(requests: S, done: watch::Sender<bool>) -> impl Stream<Item = S::Item> {
+    let end = stream::once(async move {
+        let _ = done.send(true);
+        None
+    });
+    requests.chain(end.filter_map(future::ready))
+}
+
+/// Resolve `timeout` after `done` is flagged.
+///
+/// Never resolves without a timeout, or if `done` goes away unflagged.
+async fn drain_expired(mut done: watch::Receiver<bool>, timeout: Option<Duration>) {
+    match timeout {
+        Some(timeout) if done.wait_for(|&done| done).await.is_ok() => {
+            tokio::time::sleep(timeout).await;
+        }
+        _ => future::pending().await,
+    }
+}
+
+/// Records a request dropped before it finishes, as happens to requests
+/// still pending at the drain timeout: as a timeout failure once it has been
+/// sent, and as skipped while it still waits on the rate or concurrency limit.
+struct InFlight<'a> {
+    request: &'a ApiRequest,
+    pipeline: &'a Pipeline,
+    sent: bool,
+}
+
+impl<'a> InFlight<'a> {
+    /// Start tracking `request`, recording it in the pipeline if dropped.
+    fn new(request: &'a ApiRequest, pipeline: &'a Pipeline) -> Self {
+        Self {
+            request,
+            pipeline,
+            sent: false,
+        }
+    }
+
+    /// Mark the request as on its way to an endpoint, so a drop fails it.
+    fn sending(&mut self) {
+        self.sent = true;
+    }
+
+    /// Mark the request as finished, so nothing is recorded.
+    fn finish(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if !self.sent {
+            self.pipeline.unsent.lock().push(self.request.clone());
+            return;
+        }
+        self.pipeline.cancelled.lock().push(
+            ErrorResponse::new(
+                self.request,
+                "cancelled: still in flight at the drain timeout",
+                0,
+            )
+            .with_kind(ErrorKind::Timeout)
+            .with_class(FailureClass::Transient),
+        );
+    }
+}
+
+/// Log the final outcome of a request as a structured `blaze::outcome` event.
+///
+/// `latency` covers every attempt, so it includes retries and backoff.
+fn log_outcome(line_number: usize, result: &RequestResult, latency: Duration) {
+    let latency_ms = u64::try_from(latency.as_millis()).unwrap_or(u64::MAX);
+    match result {
+        RequestResult::Success(response) => {
+            let metadata = response.metadata.as_ref();
+            info!(
+                target: "blaze::outcome",
+                line_number,
+                endpoint = metadata.map(|m| m.endpoint.as_str()),
+                status = metadata.map(|m| m.status),
+                attempts = metadata.map(|m| m.attempts),
+                latency_ms,
+                deduped = response.deduped,
+                cached = response.is_cached(),
+                "Request succeeded"
+            );
+        }
+        RequestResult::Failure(error) => {
+            info!(
+                target: "blaze::outcome",
+                line_number,
+                endpoint = error.endpoint.as_deref(),
+                status = error.status_code,
+                attempts = error.attempts,
+                latency_ms,
+                error_kind = error.error_kind.as_str(),
+                "Request failed"
+            );
+        }
+        RequestResult::Skipped { reason } => {
+            info!(
+                target: "blaze::outcome",
+                line_number,
+                reason,
+                "Request skipped"
+            );
+        }
+    }
+}
+
+/// Shorten a URL to at most `width` characters.
+fn truncate_url(url: &str, width: usize) -> String {
+    if url.chars().count() <= width {
+        return url.to_string();
+    }
+    let head: String = url.chars().take(width - 1).collect();
+    format!("{head}…")
+}
+
+/// Check whether failures have crossed the configured abort thresholds.
+fn failure_threshold_exceeded(config: &ProcessingConfig, snapshot: &StatsSnapshot) -> bool {
+    let too_many = config
+        .max_failures
+        .is_some_and(|max| snapshot.failure_count >= max);
+    #[allow(clippy::cast_precision_loss)]
+    let too_frequent = config.max_failure_rate.is_some_and(|max| {
+        snapshot.total_processed >= MIN_FAILURE_RATE_SAMPLE
+            && snapshot.failure_count as f64 / snapshot.total_processed as f64 >= max
+    });
+    too_many || too_frequent
+}
+
+/// Create the progress bar, with a bar when the total is known and a spinner otherwise.
+fn progress_bar(total: Option<u64>) -> ProgressBar {
+    let pb = match total {
+        Some(total) => ProgressBar::new(total).with_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({percent}%) | {msg}")
+                .unwrap()
+                .progress_chars("█▓▒░"),
+        ),
+        None => ProgressBar::new_spinner().with_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner:.green} [{elapsed_precise}] {pos} requests | {msg}")
+                .unwrap(),
+        ),
+    };
+    pb.enable_steady_tick(Duration::from_millis(100));
+    pb
+}
+
+/// Leave the progress bar with how the run ended.
+fn finish_progress(pb: &ProgressBar, result: &ProcessingResult) {
+    if result.aborted {
+        pb.abandon_with_message("Aborted");
+    } else if result.interrupted {
+        pb.abandon_with_message("Interrupted");
+    } else if result.time_limited {
+        pb.abandon_with_message("Time limit reached");
+    } else if result.limit_reached {
+        pb.abandon_with_message("Endpoint request limits reached");
+    } else {
+        pb.finish_with_message("Complete!");
+    }
+}
+
+/// Drive a stream of results to completion, returning the success and
+/// failure counts.
+///
+/// After a shutdown is requested, in-flight requests get
+/// [`SHUTDOWN_GRACE_PERIOD`] to finish before they are abandoned.
+async fn drain_results(
+    results: impl Stream<Item = RequestResult>,
+    shutdown: watch::Receiver<bool>,
+) -> (usize, usize) {
+    let mut results = pin!(results);
+    let mut deadline = pin!(async {
+        shutdown_requested(shutdown).await;
+        tokio::time::sleep(SHUTDOWN_GRACE_PERIOD).await;
+    });
+
+    let (mut success_count, mut failure_count) = (0, 0);
+    loop {
+        tokio::select! {
+            result = results.next() => match result {
+                Some(result) if result.is_success() => success_count += 1,
+                Some(result) if result.is_skipped() => {}
+                Some(_) => failure_count += 1,
+                None => break,
+            },
+            () = &mut deadline => {
+                warn!("Gave up waiting for in-flight requests");
+                break;
+            }
+        }
+    }
+    (success_count, failure_count)
+}
+
 /// Result of processing a batch of requests.
-#[derive(Debug)]
+///
+/// Everything the CLI prints is here as data: its [`Display`](fmt::Display)
+/// output is the summary [`ProcessingResult::print_summary`] prints, and it
+/// serializes to JSON for programs that consume it.
+#[derive(Debug, Clone, Default, Serialize)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct ProcessingResult {
     /// Total requests processed.
     pub total_processed: usize,
@@ -273,12 +1715,57 @@ pub struct ProcessingResult {
     pub success_count: usize,
     /// Failed requests.
     pub failure_count: usize,
+    /// Requests the run did not send, not included in the processed total.
+    pub skipped_count: usize,
     /// Total elapsed time.
+    #[serde(rename = "elapsed_secs", serialize_with = "tracker::serialize_secs")]
     pub elapsed: Duration,
     /// Average latency in milliseconds.
     pub avg_latency_ms: f64,
+    /// Median latency in milliseconds.
+    pub p50_latency_ms: f64,
+    /// 95th percentile latency in milliseconds.
+    pub p95_latency_ms: f64,
+    /// 99th percentile latency in milliseconds.
+    pub p99_latency_ms: f64,
+    /// Median time to first token of streamed responses, in milliseconds.
+    pub p50_ttft_ms: Option<f64>,
+    /// 95th percentile time to first token of streamed responses, in milliseconds.
+    pub p95_ttft_ms: Option<f64>,
     /// Overall requests per second.
     pub overall_rps: f64,
+    /// Transient failures retried in a second pass.
+    pub requeued_count: usize,
+    /// Requests cancelled at the drain timeout, included in the failures.
+    pub cancelled_count: usize,
+    /// Retries skipped because the retry budget was exhausted.
+    pub retries_denied: u64,
+    /// Requests answered with the response of an identical earlier request.
+    pub deduped_count: u64,
+    /// Requests answered from the response cache.
+    pub cached_count: u64,
+    /// Custom headers left off requests because they were invalid.
+    pub invalid_headers: u64,
+    /// Failed requests by the HTTP status they ended with.
+    pub failure_statuses: FailureStatuses,
+    /// Sent requests by the HTTP status they ended with, successes and failures alike.
+    pub status_counts: BTreeMap<u16, u64>,
+    /// Whether the run was stopped early by Ctrl-C.
+    pub interrupted: bool,
+    /// Whether the run was stopped early by the failure threshold.
+    pub aborted: bool,
+    /// Whether the run was stopped early by its time limit.
+    pub time_limited: bool,
+    /// Whether the run was stopped early because every endpoint reached its request limit.
+    pub limit_reached: bool,
+    /// Total prompt tokens.
+    pub prompt_tokens: u64,
+    /// Total completion tokens.
+    pub completion_tokens: u64,
+    /// Total estimated cost.
+    pub estimated_cost: f64,
+    /// Per-endpoint breakdown.
+    pub endpoints: Vec<EndpointStats>,
 }
 
 impl ProcessingResult {
@@ -291,21 +1778,398 @@ impl ProcessingResult {
         }
     }
 
+    /// Get how the run ended.
+    #[must_use]
+    pub fn status(&self) -> RunStatus {
+        if self.aborted {
+            RunStatus::Aborted
+        } else if self.interrupted {
+            RunStatus::Interrupted
+        } else if self.time_limited {
+            RunStatus::TimeLimited
+        } else if self.limit_reached {
+            RunStatus::LimitReached
+        } else {
+            RunStatus::Complete
+        }
+    }
+
+    /// Get the prompt and completion tokens together.
+    #[must_use]
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
     /// Print a summary of the results.
     pub fn print_summary(&self) {
-        println!("\n{}", "═".repeat(60));
-        println!("                    PROCESSING COMPLETE");
-        println!("{}", "═".repeat(60));
-        println!("  Total Processed:  {}", self.total_processed);
-        println!(
+        println!("\n{self}");
+    }
+
+    /// Format the per-endpoint breakdown of the summary.
+    fn fmt_endpoints(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", "─".repeat(60))?;
+        writeln!(
+            f,
+            "  {:>2} {:<26} {:>7} {:>6} {:>8} {:>9}  State",
+            "#", "Endpoint", "OK", "Failed", "Avg ms", "Peak"
+        )?;
+        for (index, endpoint) in self.endpoints.iter().enumerate() {
+            // Peak requests in flight out of the endpoint's cap
+            let peak = format!("{}/{}", endpoint.max_in_flight, endpoint.max_concurrent);
+            writeln!(
+                f,
+                "  {:>2} {:<26} {:>7} {:>6} {:>8.1} {:>9}  {:?}",
+                index + 1,
+                truncate_url(&endpoint.url, 26),
+                endpoint.success_count,
+                endpoint.failure_count,
+                endpoint.avg_latency_ms,
+                peak,
+                endpoint.circuit_state
+            )?;
+            if !endpoint.failure_statuses.is_empty() {
+                writeln!(f, "     {}", endpoint.failure_statuses.top(5))?;
+            }
+            if let Some(max) = endpoint.max_requests {
+                writeln!(f, "     {}/{} requests taken", endpoint.requests_taken, max)?;
+            }
+            // Break rotated keys down, so a dead one stands out
+            for key in endpoint.keys.iter().filter(|_| endpoint.keys.len() > 1) {
+                writeln!(
+                    f,
+                    "     {:<26} {:>7} {:>6}{}",
+                    format!("key {}", key.key),
+                    key.success_count,
+                    key.failure_count,
+                    if key.sidelined { "  sidelined" } else { "" }
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The summary table the CLI prints at the end of a run.
+impl fmt::Display for ProcessingResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", "═".repeat(60))?;
+        let title = match self.status() {
+            RunStatus::Complete => "                    PROCESSING COMPLETE",
+            RunStatus::Interrupted => "                   PROCESSING INTERRUPTED",
+            RunStatus::Aborted => "                     PROCESSING ABORTED",
+            RunStatus::TimeLimited => "                 PROCESSING TIME LIMIT REACHED",
+            RunStatus::LimitReached => "               ENDPOINT REQUEST LIMITS REACHED",
+            RunStatus::Failed => "                     PROCESSING FAILED",
+        };
+        writeln!(f, "{title}")?;
+        writeln!(f, "{}", "═".repeat(60))?;
+        writeln!(f, "  Total Processed:  {}", self.total_processed)?;
+        writeln!(
+            f,
             "  Successful:       {} ({:.1}%)",
             self.success_count,
             self.success_rate()
+        )?;
+        writeln!(f, "  Failed:           {}", self.failure_count)?;
+        if !self.failure_statuses.is_empty() {
+            writeln!(f, "  Failure Statuses: {}", self.failure_statuses.top(5))?;
+        }
+        if self.skipped_count > 0 {
+            writeln!(f, "  Skipped:          {}", self.skipped_count)?;
+        }
+        if self.requeued_count > 0 {
+            writeln!(f, "  Requeued:         {}", self.requeued_count)?;
+        }
+        if self.cancelled_count > 0 {
+            writeln!(f, "  Cancelled:        {}", self.cancelled_count)?;
+        }
+        if self.retries_denied > 0 {
+            writeln!(f, "  Retries Skipped:  {}", self.retries_denied)?;
+        }
+        if self.deduped_count > 0 {
+            writeln!(f, "  Deduplicated:     {}", self.deduped_count)?;
+        }
+        if self.cached_count > 0 {
+            writeln!(f, "  Cached:           {}", self.cached_count)?;
+        }
+        if self.invalid_headers > 0 {
+            writeln!(f, "  Invalid Headers:  {}", self.invalid_headers)?;
+        }
+        writeln!(f, "  Elapsed Time:     {:.2}s", self.elapsed.as_secs_f64())?;
+        writeln!(f, "  Avg Latency:      {:.1}ms", self.avg_latency_ms)?;
+        writeln!(
+            f,
+            "  p50/p95/p99:      {:.1}ms / {:.1}ms / {:.1}ms",
+            self.p50_latency_ms, self.p95_latency_ms, self.p99_latency_ms
+        )?;
+        if let (Some(p50), Some(p95)) = (self.p50_ttft_ms, self.p95_ttft_ms) {
+            writeln!(f, "  TTFT p50/p95:     {p50:.1}ms / {p95:.1}ms")?;
+        }
+        writeln!(f, "  Throughput:       {:.0} req/sec", self.overall_rps)?;
+        if self.prompt_tokens > 0 || self.completion_tokens > 0 {
+            writeln!(
+                f,
+                "  Tokens:           {} prompt / {} completion",
+                self.prompt_tokens, self.completion_tokens
+            )?;
+        }
+        if self.estimated_cost > 0.0 {
+            writeln!(f, "  Est. Cost:        ${:.4}", self.estimated_cost)?;
+        }
+        if !self.endpoints.is_empty() {
+            self.fmt_endpoints(f)?;
+        }
+        write!(f, "{}", "═".repeat(60))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{EndpointConfig, RequestConfig, RetryConfig};
+
+    #[test]
+    fn test_failure_threshold() {
+        let stats = StatsTracker::new();
+        for _ in 0..60 {
+            stats.record_success(Duration::from_millis(10));
+        }
+        for _ in 0..40 {
+            stats.record_failure(None);
+        }
+        let snapshot = stats.snapshot();
+
+        let config = ProcessingConfig::default();
+        assert!(!failure_threshold_exceeded(&config, &snapshot));
+
+        let config = ProcessingConfig {
+            max_failures: Some(40),
+            ..Default::default()
+        };
+        assert!(failure_threshold_exceeded(&config, &snapshot));
+
+        let config = ProcessingConfig {
+            max_failure_rate: Some(0.5),
+            ..Default::default()
+        };
+        assert!(!failure_threshold_exceeded(&config, &snapshot));
+
+        let config = ProcessingConfig {
+            max_failure_rate: Some(0.4),
+            ..Default::default()
+        };
+        assert!(failure_threshold_exceeded(&config, &snapshot));
+
+        // Early failures alone don't trip the rate
+        let stats = StatsTracker::new();
+        stats.record_failure(None);
+        assert!(!failure_threshold_exceeded(&config, &stats.snapshot()));
+    }
+
+    #[tokio::test]
+    async fn test_process_stream() {
+        // Nothing listens on port 9, so every request fails with a transient error
+        let config = Config {
+            endpoints: vec![EndpointConfig {
+                url: "http://127.0.0.1:9".to_string(),
+                ..Default::default()
+            }],
+            retry: RetryConfig {
+                max_attempts: 1,
+                ..Default::default()
+            },
+            processing: ProcessingConfig {
+                requeue: true,
+                // Admit requests one by one, so the stop below comes after all three
+                priority_window: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let processor = Processor::new(config).unwrap();
+
+        let requests = (1..=3).map(|line_number| ApiRequest {
+            line_number,
+            ..ApiRequest::simple("hello")
+        });
+        let results: Vec<_> = processor
+            .process_stream(stream::iter(requests))
+            .collect()
+            .await;
+
+        // Requeued failures are retried once and yielded only after the retry
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|result| !result.is_success()));
+        let snapshot = processor.stats();
+        assert_eq!(snapshot.failure_count, 3);
+        assert_eq!(snapshot.requeued_count, 3);
+
+        // Stopped before the second pass, the requeued requests are skipped, not failed
+        let processor = Processor::new(Config::clone(&processor.config)).unwrap();
+        let (stop, shutdown) = watch::channel(false);
+        let requests = (1..=3).map(|line_number| ApiRequest {
+            line_number,
+            ..ApiRequest::simple("hello")
+        });
+        let stopping = stream::once(async move {
+            let _ = stop.send(true);
+        });
+        let requests = stream::iter(requests).chain(stopping.filter_map(|()| future::ready(None)));
+        let results: Vec<_> = processor
+            .process_requests(requests, shutdown, true)
+            .collect()
+            .await;
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|(_, result)| result.is_skipped()));
+        let snapshot = processor.stats();
+        assert_eq!(snapshot.failure_count, 0);
+        assert_eq!(snapshot.skipped_count, 3);
+    }
+
+    #[tokio::test]
+    async fn test_pause_resume() {
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(wiremock::matchers::any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .mount(&server)
+            .await;
+        let config = Config {
+            endpoints: vec![EndpointConfig {
+                url: server.uri(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let processor = Processor::new(config).unwrap();
+
+        // Nothing is sent while paused, and everything goes out once resumed
+        processor.pause();
+        let requests = (1..=3).map(|line_number| ApiRequest {
+            line_number,
+            ..ApiRequest::simple("hello")
+        });
+        let (results, ()) = tokio::join!(
+            processor
+                .process_stream(stream::iter(requests))
+                .collect::<Vec<_>>(),
+            async {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                assert!(processor.is_paused());
+                assert_eq!(processor.stats().success_count, 0);
+                processor.resume();
+            }
         );
-        println!("  Failed:           {}", self.failure_count);
-        println!("  Elapsed Time:     {:.2}s", self.elapsed.as_secs_f64());
-        println!("  Avg Latency:      {:.1}ms", self.avg_latency_ms);
-        println!("  Throughput:       {:.0} req/sec", self.overall_rps);
-        println!("{}", "═".repeat(60));
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(RequestResult::is_success));
+    }
+
+    #[tokio::test]
+    async fn test_drain_timeout() {
+        use wiremock::matchers::{any, body_string_contains};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(body_string_contains("slow"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({}))
+                    .set_delay(Duration::from_secs(10)),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .mount(&server)
+            .await;
+        let config = Config {
+            endpoints: vec![EndpointConfig {
+                url: server.uri(),
+                ..Default::default()
+            }],
+            processing: ProcessingConfig {
+                drain_timeout: Some(Duration::from_millis(200)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let processor = Processor::new(config).unwrap();
+
+        // The hung request is cancelled as a timeout instead of holding up the run
+        let requests = ["fast", "slow", "fast"].map(ApiRequest::simple);
+        let started = Instant::now();
+        let results: Vec<_> = processor
+            .process_stream(stream::iter(requests))
+            .collect()
+            .await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(results.len(), 3);
+        let failures: Vec<_> = results
+            .iter()
+            .filter_map(|result| match result {
+                RequestResult::Failure(error) => Some(error),
+                RequestResult::Success(_) | RequestResult::Skipped { .. } => None,
+            })
+            .collect();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].error_kind, ErrorKind::Timeout);
+        let snapshot = processor.stats();
+        assert_eq!(snapshot.failure_count, 1);
+        assert_eq!(snapshot.cancelled_count, 1);
+
+        // A request still waiting on the rate limit was never sent, so it is skipped
+        let config = Config {
+            request: RequestConfig {
+                rate_limit: 1,
+                rate_period: Duration::from_secs(60),
+                ..Default::default()
+            },
+            ..processor.config.as_ref().clone()
+        };
+        let processor = Processor::new(config).unwrap();
+        let requests = ["fast", "fast"].map(ApiRequest::simple);
+        let results: Vec<_> = processor
+            .process_stream(stream::iter(requests))
+            .collect()
+            .await;
+        assert!(results[0].is_success());
+        assert!(matches!(results[1], RequestResult::Skipped { .. }));
+        let snapshot = processor.stats();
+        assert_eq!(snapshot.cancelled_count, 0);
+        assert_eq!(snapshot.skipped_count, 1);
+    }
+
+    #[test]
+    fn test_truncate_url() {
+        assert_eq!(truncate_url("http://a.test/v1", 26), "http://a.test/v1");
+        let truncated = truncate_url("https://api.example.com/v1/chat/completions", 26);
+        assert_eq!(truncated, "https://api.example.com/v…");
+        assert_eq!(truncated.chars().count(), 26);
+    }
+
+    #[test]
+    fn test_result_summary() {
+        let result = ProcessingResult {
+            total_processed: 10,
+            success_count: 8,
+            failure_count: 2,
+            status_counts: BTreeMap::from([(200, 8), (429, 2)]),
+            time_limited: true,
+            prompt_tokens: 100,
+            completion_tokens: 50,
+            ..Default::default()
+        };
+        assert_eq!(result.status(), RunStatus::TimeLimited);
+        assert_eq!(result.total_tokens(), 150);
+
+        let summary = result.to_string();
+        assert!(summary.contains("PROCESSING TIME LIMIT REACHED"));
+        assert!(summary.contains("Successful:       8 (80.0%)"));
+        assert!(summary.contains("Tokens:           100 prompt / 50 completion"));
+
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["status_counts"]["429"], 2);
+        assert_eq!(json["elapsed_secs"], 0.0);
     }
 }
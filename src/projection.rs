@@ -0,0 +1,122 @@
+//! Projection of response bodies onto a subset of fields.
+//!
+//! Paths use a subset of `JSONPath`: dot-separated field names with
+//! optional `[N]` array indexes and an optional leading `$`, such as
+//! `$.choices[0].message.content`.
+
+use crate::error::{BlazeError, Result};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// Extracts named fields from a response body.
+#[derive(Debug, Clone)]
+pub struct Projection {
+    /// Output keys with the JSON pointer each one is read from.
+    fields: Vec<(String, String)>,
+}
+
+impl Projection {
+    /// Build a projection from output keys mapped to paths.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::InvalidConfig`] if a path is malformed.
+    pub fn new(paths: &BTreeMap<String, String>) -> Result<Self> {
+        let fields = paths
+            .iter()
+            .map(|(key, path)| Ok((key.clone(), to_pointer(path)?)))
+            .collect::<Result<_>>()?;
+        Ok(Self { fields })
+    }
+
+    /// Extract the projected fields into an object.
+    ///
+    /// Returns `None` if any path does not match the response.
+    #[must_use]
+    pub fn apply(&self, response: &Value) -> Option<Value> {
+        let mut projected = Map::new();
+        for (key, pointer) in &self.fields {
+            projected.insert(key.clone(), response.pointer(pointer)?.clone());
+        }
+        Some(Value::Object(projected))
+    }
+
+    /// Get the output keys, in the order their fields are extracted.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().map(|(key, _)| key.as_str())
+    }
+
+    /// Extract each projected field, or `None` for those the response lacks.
+    pub fn values<'a>(&'a self, response: &'a Value) -> impl Iterator<Item = Option<&'a Value>> {
+        self.fields
+            .iter()
+            .map(move |(_, pointer)| response.pointer(pointer))
+    }
+}
+
+/// Convert a path such as `choices[0].message.content` to a JSON pointer.
+pub(crate) fn to_pointer(path: &str) -> Result<String> {
+    let invalid = || BlazeError::InvalidConfig(format!("invalid path '{path}'"));
+
+    let trimmed = path.strip_prefix('$').unwrap_or(path);
+    let trimmed = trimmed.strip_prefix('.').unwrap_or(trimmed);
+    if trimmed.is_empty() {
+        return Ok(String::new());
+    }
+
+    let mut pointer = String::new();
+    for part in trimmed.split('.') {
+        let (name, mut indexes) = part.split_at(part.find('[').unwrap_or(part.len()));
+        if name.is_empty() && indexes.is_empty() {
+            return Err(invalid());
+        }
+        if !name.is_empty() {
+            pointer.push('/');
+            pointer.push_str(&name.replace('~', "~0").replace('/', "~1"));
+        }
+        while !indexes.is_empty() {
+            let (index, rest) = indexes
+                .strip_prefix('[')
+                .and_then(|rest| rest.split_once(']'))
+                .ok_or_else(invalid)?;
+            let index: usize = index.parse().map_err(|_| invalid())?;
+            pointer.push('/');
+            pointer.push_str(&index.to_string());
+            indexes = rest;
+        }
+    }
+    Ok(pointer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_projection() {
+        let paths = BTreeMap::from([
+            (
+                "content".to_string(),
+                "$.choices[0].message.content".to_string(),
+            ),
+            ("tokens".to_string(), "usage.total_tokens".to_string()),
+        ]);
+        let projection = Projection::new(&paths).unwrap();
+
+        let response = json!({
+            "choices": [{"message": {"content": "Paris"}}],
+            "usage": {"total_tokens": 12}
+        });
+        assert_eq!(
+            projection.apply(&response),
+            Some(json!({"content": "Paris", "tokens": 12}))
+        );
+        assert_eq!(projection.apply(&json!({"choices": []})), None);
+
+        for bad in ["choices[x]", "choices[0", "a..b"] {
+            let paths = BTreeMap::from([("key".to_string(), bad.to_string())]);
+            assert!(Projection::new(&paths).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Gradual ramp-up of the request rate.
+//!
+//! Starting a run at the full rate limit can trip an endpoint's own throttling
+//! before it settles. During the ramp-up period requests are paced at a rate
+//! that grows linearly from a fraction of the target to the full target. Once
+//! the period is over, pacing stops and the regular rate limiter alone applies.
+
+use parking_lot::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Fraction of the target rate allowed when the ramp starts.
+pub const RAMP_START_FRACTION: f64 = 0.1;
+
+/// Paces requests while the rate ramps up to its target.
+#[derive(Debug)]
+pub struct RampUp {
+    start: Instant,
+    duration: Duration,
+    target_rate: f64,
+    /// Earliest time the next request may go out.
+    next: Mutex<Instant>,
+}
+
+impl RampUp {
+    /// Start ramping up to `target_rate` requests per second over `duration`.
+    #[must_use]
+    pub fn new(target_rate: f64, duration: Duration) -> Self {
+        let start = Instant::now();
+        Self {
+            start,
+            duration,
+            target_rate,
+            next: Mutex::new(start),
+        }
+    }
+
+    /// Get the allowed rate in requests per second after `elapsed` time.
+    #[must_use]
+    pub fn rate_at(&self, elapsed: Duration) -> f64 {
+        let progress = if self.duration.is_zero() {
+            1.0
+        } else {
+            (elapsed.as_secs_f64() / self.duration.as_secs_f64()).min(1.0)
+        };
+        self.target_rate * (RAMP_START_FRACTION + (1.0 - RAMP_START_FRACTION) * progress)
+    }
+
+    /// Check whether the ramp-up period is over.
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.start.elapsed() >= self.duration
+    }
+
+    /// Wait until the current ramp rate allows another request.
+    ///
+    /// Returns immediately once the ramp-up period is over.
+    pub async fn until_ready(&self) {
+        let slot = {
+            let mut next = self.next.lock();
+            let now = Instant::now();
+            if now.duration_since(self.start) >= self.duration {
+                return;
+            }
+            let slot = (*next).max(now);
+            let rate = self.rate_at(slot.duration_since(self.start));
+            *next = slot + Duration::from_secs_f64(1.0 / rate);
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_ramp_up() {
+        let ramp = RampUp::new(1000.0, Duration::from_secs(10));
+        assert!((ramp.rate_at(Duration::ZERO) - 100.0).abs() < 1e-9);
+        assert!((ramp.rate_at(Duration::from_secs(5)) - 550.0).abs() < 1e-9);
+        assert!((ramp.rate_at(Duration::from_secs(20)) - 1000.0).abs() < 1e-9);
+        assert!(!ramp.is_complete());
+
+        // At 100 req/s the first requests are spaced about 10ms apart
+        let start = Instant::now();
+        for _ in 0..6 {
+            ramp.until_ready().await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(45));
+
+        // A finished ramp never waits
+        let ramp = RampUp::new(1.0, Duration::ZERO);
+        assert!(ramp.is_complete());
+        let start = Instant::now();
+        for _ in 0..100 {
+            ramp.until_ready().await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+}
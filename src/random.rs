@@ -0,0 +1,57 @@
+//! Source of randomness for load balancing and backoff jitter.
+//!
+//! By default every draw comes from the thread-local generator. With a seed,
+//! draws come from a single seeded generator instead, so runs with the same
+//! seed make the same endpoint picks and wait the same backoffs, as long as
+//! requests complete in the same order (e.g. with a single worker).
+
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+
+/// Random number generator shared by the requests of a run.
+#[derive(Debug, Default)]
+pub struct RandomSource {
+    seeded: Option<Mutex<StdRng>>,
+}
+
+impl RandomSource {
+    /// Create a source, seeded if `seed` is set and drawing from the thread-local generator otherwise.
+    #[must_use]
+    pub fn new(seed: Option<u64>) -> Self {
+        Self {
+            seeded: seed.map(|seed| Mutex::new(StdRng::seed_from_u64(seed))),
+        }
+    }
+
+    /// Run `f` with the generator.
+    pub fn with<T>(&self, f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+        match &self.seeded {
+            Some(rng) => f(&mut *rng.lock()),
+            None => f(&mut rand::rng()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn test_seeded_source() {
+        let draws = |source: &RandomSource| -> Vec<u32> {
+            (0..20)
+                .map(|_| source.with(|rng| rng.random_range(0..1000)))
+                .collect()
+        };
+
+        // The same seed gives the same draws, a different one does not
+        let seeded = draws(&RandomSource::new(Some(42)));
+        assert_eq!(seeded, draws(&RandomSource::new(Some(42))));
+        assert_ne!(seeded, draws(&RandomSource::new(Some(43))));
+
+        let unseeded = RandomSource::default();
+        assert!(draws(&unseeded).iter().all(|&draw| draw < 1000));
+    }
+}
@@ -0,0 +1,157 @@
+//! Writing results in input order.
+//!
+//! Requests complete out of order, so with ordered output each result waits
+//! in a reorder buffer until the results of every request admitted before it
+//! have been released. A slow request at the head of the line holds back
+//! writing, not sending: later requests keep completing into the buffer. To
+//! cap memory, at most `window` requests are admitted but not yet released,
+//! so once the buffer fills, admission waits for the head to complete. Each
+//! buffered result holds its full response body, so a large window over
+//! large responses can take a lot of memory.
+
+use crate::request::{ApiRequest, RequestResult};
+use futures::stream::{self, Stream, StreamExt};
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// Requests admitted in input order and the results waiting for their turn.
+#[derive(Default)]
+struct State {
+    pending: VecDeque<usize>,
+    /// The line numbers in `pending`, to look results up without a scan.
+    admitted: HashSet<usize>,
+    done: HashMap<usize, RequestResult>,
+}
+
+/// A reorder buffer, shared by the admission and the result side of a run.
+///
+/// Requests are keyed by line number, so they must all come from one input.
+pub struct Reorder {
+    state: Mutex<State>,
+    /// Room left in the window, taken on admission and given back on release.
+    room: Semaphore,
+}
+
+impl Reorder {
+    /// Create a reorder buffer holding back at most `window` requests.
+    #[must_use]
+    pub fn new(window: usize) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(State::default()),
+            room: Semaphore::new(window.max(1)),
+        })
+    }
+
+    /// Admit `requests` in input order, waiting while the window is full.
+    pub fn admit<'a>(
+        self: Arc<Self>,
+        requests: impl Stream<Item = ApiRequest> + Send + 'a,
+    ) -> impl Stream<Item = ApiRequest> + Send + 'a {
+        stream::unfold(
+            (requests.boxed(), self),
+            |(mut requests, order)| async move {
+                let request = requests.next().await?;
+                // The semaphore is never closed, and released results add the room back
+                order.room.acquire().await.ok()?.forget();
+                let mut state = order.state.lock();
+                state.pending.push_back(request.line_number);
+                state.admitted.insert(request.line_number);
+                drop(state);
+                Some((request, (requests, order)))
+            },
+        )
+    }
+
+    /// Release `results` in the order their requests were admitted.
+    ///
+    /// Results of requests that never complete, such as those a shutdown kept
+    /// from being sent, are skipped once `results` ends, releasing the rest.
+    pub fn restore<'a>(
+        self: Arc<Self>,
+        results: impl Stream<Item = (usize, RequestResult)> + Send + 'a,
+    ) -> impl Stream<Item = (usize, RequestResult)> + Send + 'a {
+        let order = Arc::clone(&self);
+        results
+            .map(move |(line_number, result)| stream::iter(order.complete(line_number, result)))
+            .flatten()
+            .chain(stream::once(async move { stream::iter(self.finish()) }).flatten())
+    }
+
+    /// Buffer a result, returning those now at the head of the line.
+    fn complete(&self, line_number: usize, result: RequestResult) -> Vec<(usize, RequestResult)> {
+        let mut state = self.state.lock();
+        if !state.admitted.contains(&line_number) {
+            return vec![(line_number, result)];
+        }
+        state.done.insert(line_number, result);
+
+        let mut ready = Vec::new();
+        while let Some(&head) = state.pending.front() {
+            let Some(result) = state.done.remove(&head) else {
+                break;
+            };
+            state.pending.pop_front();
+            state.admitted.remove(&head);
+            ready.push((head, result));
+        }
+        self.room.add_permits(ready.len());
+        ready
+    }
+
+    /// Release everything still buffered, in admission order.
+    fn finish(&self) -> Vec<(usize, RequestResult)> {
+        let mut state = self.state.lock();
+        let pending = std::mem::take(&mut state.pending);
+        state.admitted.clear();
+        pending
+            .into_iter()
+            .filter_map(|line_number| Some((line_number, state.done.remove(&line_number)?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    fn skipped() -> RequestResult {
+        RequestResult::Skipped {
+            reason: "test".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reorder() {
+        let order = Reorder::new(3);
+        let requests = (1..=5).map(|line| ApiRequest {
+            line_number: line,
+            ..ApiRequest::simple("hello")
+        });
+        let mut admitted = Box::pin(Arc::clone(&order).admit(stream::iter(requests)));
+        for line in 1..=3 {
+            assert_eq!(admitted.next().await.unwrap().line_number, line);
+        }
+        // The window is full until the head completes
+        assert!(admitted.next().now_or_never().is_none());
+        assert!(order.complete(2, skipped()).is_empty());
+        assert!(admitted.next().now_or_never().is_none());
+
+        let ready: Vec<_> = order
+            .complete(1, skipped())
+            .into_iter()
+            .map(|(line, _)| line)
+            .collect();
+        assert_eq!(ready, [1, 2]);
+        assert_eq!(admitted.next().await.unwrap().line_number, 4);
+        assert_eq!(admitted.next().await.unwrap().line_number, 5);
+        assert!(admitted.next().await.is_none());
+
+        // Results out of order come out in admission order, skipping those that never complete
+        let results = stream::iter(vec![(5, skipped()), (3, skipped())]);
+        let lines: Vec<_> = order.restore(results).map(|(line, _)| line).collect().await;
+        assert_eq!(lines, [3, 5]);
+    }
+}
@@ -0,0 +1,209 @@
+//! Machine-readable run reports.
+//!
+//! A report records what a run did and how it was configured, so run
+//! metadata can be archived alongside the results. Secrets in the
+//! configuration are masked before it is written. The latency histograms
+//! of a run can be written as well, for plotting distributions offline.
+
+use crate::config::Config;
+use crate::endpoint::{Endpoint, EndpointStats};
+use crate::error::{BlazeError, Result};
+use crate::histogram::HistogramSnapshot;
+use crate::tracker::{StatsSnapshot, StatsTracker};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// How a run ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    /// Every request was processed.
+    Complete,
+    /// Stopped early by Ctrl-C.
+    Interrupted,
+    /// Stopped early by the failure threshold.
+    Aborted,
+    /// Stopped early by the run's time limit.
+    TimeLimited,
+    /// Stopped early because every endpoint reached its request limit.
+    LimitReached,
+    /// Stopped by an error.
+    Failed,
+}
+
+/// Files a run read from and wrote to.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunFiles {
+    /// Input requests files, in the order they were read.
+    pub inputs: Vec<PathBuf>,
+    /// Output file for successful responses.
+    pub output: Option<PathBuf>,
+    /// Output file for failed requests.
+    pub errors: PathBuf,
+}
+
+/// A structured report of a processing run.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    /// How the run ended.
+    pub status: RunStatus,
+    /// The error that stopped the run, if it failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// When processing started.
+    pub started_at: DateTime<Utc>,
+    /// When processing finished.
+    pub finished_at: DateTime<Utc>,
+    /// Files the run used.
+    pub files: RunFiles,
+    /// Overall statistics, including token usage.
+    pub stats: StatsSnapshot,
+    /// Per-endpoint statistics.
+    pub endpoints: Vec<EndpointStats>,
+    /// The effective configuration, with secrets masked.
+    pub config: Config,
+}
+
+impl RunReport {
+    /// Write the report as pretty-printed JSON.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::OutputFileWrite`] if the file cannot be written.
+    pub async fn write(&self, path: &Path) -> Result<()> {
+        write_json(self, path).await
+    }
+}
+
+/// Latency histograms of the successful requests of a run.
+#[derive(Debug, Clone, Serialize)]
+pub struct HistogramReport {
+    /// Latencies of all successful requests.
+    pub overall: HistogramSnapshot,
+    /// Latencies of the successful requests to each endpoint, by URL.
+    pub endpoints: BTreeMap<String, HistogramSnapshot>,
+}
+
+impl HistogramReport {
+    /// Capture the histograms of a run and its endpoints.
+    #[must_use]
+    pub fn capture(stats: &StatsTracker, endpoints: &[Arc<Endpoint>]) -> Self {
+        Self {
+            overall: stats.latency_histogram().snapshot(),
+            endpoints: endpoints
+                .iter()
+                .map(|e| (e.url().to_string(), e.latency_histogram().snapshot()))
+                .collect(),
+        }
+    }
+
+    /// Write the histograms as pretty-printed JSON.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::OutputFileWrite`] if the file cannot be written.
+    pub async fn write(&self, path: &Path) -> Result<()> {
+        write_json(self, path).await
+    }
+}
+
+/// Write a value to a file as pretty-printed JSON.
+async fn write_json(value: &impl Serialize, path: &Path) -> Result<()> {
+    let json = serde_json::to_vec_pretty(value)?;
+    tokio::fs::write(path, json)
+        .await
+        .map_err(|e| BlazeError::OutputFileWrite {
+            path: path.to_path_buf(),
+            source: e,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EndpointConfig;
+    use crate::endpoint::Endpoint;
+    use crate::tracker::StatsTracker;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_write_report() {
+        let config = Config {
+            endpoints: vec![EndpointConfig {
+                url: "http://localhost:8080".to_string(),
+                api_key: Some("sk-secret".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let endpoint = Arc::new(Endpoint::new(config.endpoints[0].clone()));
+        let _slots = (endpoint.acquire().await, endpoint.acquire().await);
+        endpoint.record_success(Duration::from_millis(40));
+        let stats = StatsTracker::new();
+        stats.record_success(Duration::from_millis(40));
+
+        let report = RunReport {
+            status: RunStatus::Aborted,
+            error: None,
+            started_at: Utc::now(),
+            finished_at: Utc::now(),
+            files: RunFiles {
+                inputs: vec!["requests.jsonl".into()],
+                output: None,
+                errors: "errors.jsonl".into(),
+            },
+            stats: stats.snapshot(),
+            endpoints: vec![endpoint.stats()],
+            config: config.redacted(),
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        report.write(&path).await.unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(!text.contains("sk-secret"));
+        let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(json["status"], "aborted");
+        assert_eq!(json["files"]["inputs"][0], "requests.jsonl");
+        assert_eq!(json["stats"]["success_count"], 1);
+        assert_eq!(json["endpoints"][0]["max_in_flight"], 2);
+        assert_eq!(json["endpoints"][0]["circuit_state"], "Closed");
+        assert_eq!(json["config"]["endpoints"][0]["api_key"], "********");
+    }
+
+    #[tokio::test]
+    async fn test_write_histograms() {
+        let endpoints: Vec<_> = ["http://a:8080", "http://b:8080"]
+            .map(|url| {
+                Arc::new(Endpoint::new(EndpointConfig {
+                    url: url.to_string(),
+                    ..Default::default()
+                }))
+            })
+            .into();
+        let stats = StatsTracker::new();
+        for latency in [Duration::from_millis(40), Duration::from_millis(900)] {
+            endpoints[0].record_success(latency);
+            stats.record_success(latency);
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hist.json");
+        let report = HistogramReport::capture(&stats, &endpoints);
+        report.write(&path).await.unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["overall"]["count"], 2);
+        let slow = &json["endpoints"]["http://a:8080"]["buckets"][1];
+        assert!(slow["lower_us"].as_u64().unwrap() <= 900_000);
+        assert!(slow["upper_us"].as_u64().unwrap() > 900_000);
+        assert_eq!(slow["count"], 1);
+        assert_eq!(json["endpoints"]["http://b:8080"]["count"], 0);
+    }
+}
@@ -3,9 +3,11 @@
 //! This module defines the data structures for API requests and responses,
 //! supporting flexible input formats and structured output.
 
+use crate::error::{BlazeError, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 
 /// An API request read from the input file.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,6 +24,23 @@ pub struct ApiRequest {
     #[serde(default)]
     pub headers: Option<HashMap<String, String>>,
 
+    /// HTTP method (defaults to POST).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<HttpMethod>,
+
+    /// Path appended to the endpoint URL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+
+    /// Scheduling priority. Higher priorities are admitted first, and requests
+    /// without one count as priority 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i64>,
+
+    /// Model for this request, overriding the endpoint's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
     /// Request-specific metadata (passed through to response).
     #[serde(default, flatten)]
     pub metadata: HashMap<String, Value>,
@@ -29,6 +48,15 @@ pub struct ApiRequest {
     /// Line number in the input file (set during parsing).
     #[serde(skip)]
     pub line_number: usize,
+
+    /// Input file the request was read from, set when a run reads several.
+    #[serde(skip)]
+    pub source: Option<Arc<str>>,
+
+    /// Why the request's line could not be read, for lines that fail
+    /// validation instead of being parsed and sent.
+    #[serde(skip)]
+    pub unreadable: Option<String>,
 }
 
 impl ApiRequest {
@@ -38,8 +66,46 @@ impl ApiRequest {
             input: Some(input.into()),
             body: None,
             headers: None,
+            method: None,
+            path: None,
+            priority: None,
+            model: None,
             metadata: HashMap::new(),
             line_number: 0,
+            source: None,
+            unreadable: None,
+        }
+    }
+
+    /// Create a request standing in for a line that could not be read.
+    pub fn unreadable(line_number: usize, reason: impl Into<String>) -> Self {
+        Self {
+            input: None,
+            line_number,
+            unreadable: Some(reason.into()),
+            ..Self::simple("")
+        }
+    }
+
+    /// Rebuild the request behind a line of an errors file, to send it again.
+    ///
+    /// The request keeps its input, body, method, path, headers, priority,
+    /// model and extra fields, and the line number and input file it was
+    /// first read from.
+    #[must_use]
+    pub fn replay(failure: ErrorResponse) -> Self {
+        Self {
+            input: failure.input,
+            body: failure.body,
+            headers: failure.headers.map(|headers| headers.into_iter().collect()),
+            method: failure.method,
+            path: failure.path,
+            priority: failure.priority,
+            model: failure.model,
+            metadata: failure.request_metadata.into_iter().collect(),
+            line_number: failure.line_number,
+            source: failure.source.map(Into::into),
+            ..Self::simple("")
         }
     }
 
@@ -49,32 +115,197 @@ impl ApiRequest {
             input: None,
             body: Some(body),
             headers: None,
+            method: None,
+            path: None,
+            priority: None,
+            model: None,
             metadata: HashMap::new(),
             line_number: 0,
+            source: None,
+            unreadable: None,
         }
     }
 
     /// Build the request body for an LLM endpoint.
-    pub fn build_llm_body(&self, model: Option<&str>) -> Value {
+    ///
+    /// A custom `body` on the request takes precedence, then the configured
+    /// template, then a body in the endpoint's `format` built from `input`.
+    /// The model is added to every body except raw ones: the request's own
+    /// `model` replaces any the template sets, while the endpoint's `model`
+    /// only fills in for bodies without one.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::MissingTemplateField`] if `strict` is set and the
+    /// template references a field the request does not have, or
+    /// [`BlazeError::JsonParse`] if a raw `input` is not valid JSON.
+    pub fn build_llm_body(
+        &self,
+        model: Option<&str>,
+        format: RequestFormat,
+        template: Option<&Value>,
+        strict: bool,
+    ) -> Result<Value> {
         if let Some(body) = &self.body {
             // Use custom body if provided
-            return body.clone();
+            return Ok(body.clone());
         }
 
-        // Build standard LLM request body
         let input = self.input.as_deref().unwrap_or("");
-        let mut body = serde_json::json!({
-            "messages": [{
-                "role": "user",
-                "content": input
-            }]
-        });
+        let mut body = if let Some(template) = template {
+            self.render_template(template, strict)?
+        } else {
+            match format {
+                RequestFormat::Chat => serde_json::json!({
+                    "messages": [{
+                        "role": "user",
+                        "content": input
+                    }]
+                }),
+                RequestFormat::Completion => serde_json::json!({ "prompt": input }),
+                RequestFormat::Embedding => serde_json::json!({ "input": input }),
+                RequestFormat::Raw => {
+                    return serde_json::from_str(input).map_err(|source| BlazeError::JsonParse {
+                        line: self.line_number,
+                        source,
+                    });
+                }
+            }
+        };
+
+        if let Some(fields) = body.as_object_mut() {
+            if let Some(own) = &self.model {
+                fields.insert("model".to_string(), Value::String(own.clone()));
+            } else if let Some(model) = model {
+                fields
+                    .entry("model")
+                    .or_insert_with(|| Value::String(model.to_string()));
+            }
+        }
+
+        Ok(body)
+    }
+
+    /// Get the request metadata, sorted by name, to pass through to the output.
+    #[must_use]
+    pub fn metadata_fields(&self) -> BTreeMap<String, Value> {
+        self.metadata
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Get the value of a template field from `input` or the request metadata.
+    fn template_field(&self, name: &str) -> Option<Value> {
+        match name {
+            "input" => return self.input.clone().map(Value::String),
+            "model" => return self.model.clone().map(Value::String),
+            _ => {}
+        }
+        self.metadata.get(name).cloned()
+    }
+
+    /// Substitute `${field}` placeholders in a template.
+    ///
+    /// A string that is exactly one placeholder is replaced by the field's JSON
+    /// value, keeping numbers and objects typed. Placeholders embedded in longer
+    /// strings are replaced by the field's text.
+    fn render_template(&self, template: &Value, strict: bool) -> Result<Value> {
+        match template {
+            Value::String(text) => {
+                if let Some(name) = text
+                    .strip_prefix("${")
+                    .and_then(|rest| rest.strip_suffix('}'))
+                    .filter(|name| !name.contains('}'))
+                {
+                    if let Some(value) = self.template_field(name) {
+                        return Ok(value);
+                    }
+                }
+                self.render_template_string(text, strict).map(Value::String)
+            }
+            Value::Array(items) => items
+                .iter()
+                .map(|item| self.render_template(item, strict))
+                .collect::<Result<_>>()
+                .map(Value::Array),
+            Value::Object(fields) => fields
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), self.render_template(value, strict)?)))
+                .collect::<Result<_>>()
+                .map(Value::Object),
+            other => Ok(other.clone()),
+        }
+    }
 
-        if let Some(model) = model {
-            body["model"] = Value::String(model.to_string());
+    /// Substitute every `${field}` placeholder in a string with the field's text.
+    fn render_template_string(&self, text: &str, strict: bool) -> Result<String> {
+        let mut rendered = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(start) = rest.find("${") {
+            let Some(len) = rest[start + 2..].find('}') else {
+                break;
+            };
+            let name = &rest[start + 2..start + 2 + len];
+            let placeholder = &rest[start..start + 3 + len];
+            rendered.push_str(&rest[..start]);
+
+            match self.template_field(name) {
+                Some(Value::String(value)) => rendered.push_str(&value),
+                Some(value) => rendered.push_str(&value.to_string()),
+                None if strict => {
+                    return Err(BlazeError::MissingTemplateField {
+                        field: name.to_string(),
+                        line: self.line_number,
+                    })
+                }
+                None => rendered.push_str(placeholder),
+            }
+            rest = &rest[start + 3 + len..];
         }
 
-        body
+        rendered.push_str(rest);
+        Ok(rendered)
+    }
+
+    /// Get the HTTP method to send the request with.
+    #[must_use]
+    pub fn method(&self) -> HttpMethod {
+        self.method.unwrap_or_default()
+    }
+
+    /// Check whether the request has neither an `input` nor a `body` to send.
+    ///
+    /// Whitespace-only input counts as absent, as do null, empty string,
+    /// empty object and empty array bodies.
+    #[must_use]
+    pub fn is_blank(&self) -> bool {
+        let no_input = self
+            .input
+            .as_deref()
+            .map_or(true, |input| input.trim().is_empty());
+        let no_body = match &self.body {
+            None | Some(Value::Null) => true,
+            Some(Value::String(body)) => body.trim().is_empty(),
+            Some(Value::Object(body)) => body.is_empty(),
+            Some(Value::Array(body)) => body.is_empty(),
+            Some(_) => false,
+        };
+        no_input && no_body
+    }
+
+    /// Build the URL to send the request to, appending the request's path to `base`.
+    #[must_use]
+    pub fn url(&self, base: &str) -> String {
+        match self.path.as_deref() {
+            Some(path) if !path.is_empty() => format!(
+                "{}/{}",
+                base.trim_end_matches('/'),
+                path.trim_start_matches('/')
+            ),
+            _ => base.to_string(),
+        }
     }
 
     /// Get a display string for logging.
@@ -93,6 +324,102 @@ impl ApiRequest {
     }
 }
 
+/// Body shape built from a request's `input` for an endpoint.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestFormat {
+    /// Chat completions, as `{"messages": [{"role": "user", "content": input}]}`.
+    #[default]
+    Chat,
+    /// Legacy text completions, as `{"prompt": input}`.
+    Completion,
+    /// Embeddings, as `{"input": input}`.
+    Embedding,
+    /// `input` itself, parsed as JSON, with no model added.
+    Raw,
+}
+
+/// How a request body is encoded when it is sent to an endpoint.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BodyEncoding {
+    /// The body as JSON.
+    #[default]
+    Json,
+    /// The fields of an object body, form-urlencoded. String fields are sent
+    /// as is, other values as their JSON text.
+    Form,
+    /// The body as plain text: a string as is, any other value as its JSON
+    /// text. Requests with only `input` send the input itself.
+    Text,
+}
+
+impl BodyEncoding {
+    /// Get the content type sent unless the endpoint sets its own.
+    #[must_use]
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Form => "application/x-www-form-urlencoded",
+            Self::Text => "text/plain; charset=utf-8",
+        }
+    }
+}
+
+/// HTTP methods a request may use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE", try_from = "String")]
+pub enum HttpMethod {
+    /// `GET`, sent without a body.
+    Get,
+    /// `POST`.
+    #[default]
+    Post,
+    /// `PUT`.
+    Put,
+    /// `PATCH`.
+    Patch,
+    /// `DELETE`.
+    Delete,
+}
+
+impl HttpMethod {
+    /// Check whether requests with this method carry a body.
+    #[must_use]
+    pub fn has_body(self) -> bool {
+        self != Self::Get
+    }
+}
+
+impl TryFrom<String> for HttpMethod {
+    type Error = String;
+
+    fn try_from(method: String) -> std::result::Result<Self, Self::Error> {
+        match method.to_ascii_uppercase().as_str() {
+            "GET" => Ok(Self::Get),
+            "POST" => Ok(Self::Post),
+            "PUT" => Ok(Self::Put),
+            "PATCH" => Ok(Self::Patch),
+            "DELETE" => Ok(Self::Delete),
+            _ => Err(format!(
+                "unsupported HTTP method '{method}', expected one of GET, POST, PUT, PATCH, DELETE"
+            )),
+        }
+    }
+}
+
+impl From<HttpMethod> for reqwest::Method {
+    fn from(method: HttpMethod) -> Self {
+        match method {
+            HttpMethod::Get => Self::GET,
+            HttpMethod::Post => Self::POST,
+            HttpMethod::Put => Self::PUT,
+            HttpMethod::Patch => Self::PATCH,
+            HttpMethod::Delete => Self::DELETE,
+        }
+    }
+}
+
 /// A successful API response.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiResponse {
@@ -106,6 +433,14 @@ pub struct ApiResponse {
     /// Response metadata.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub metadata: Option<ResponseMetadata>,
+
+    /// Extra fields of the request, passed through to join results back to their rows.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub request_metadata: BTreeMap<String, Value>,
+
+    /// Whether this response was reused from an identical earlier request.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deduped: bool,
 }
 
 /// Metadata about the response.
@@ -114,11 +449,75 @@ pub struct ResponseMetadata {
     /// Which endpoint handled the request.
     pub endpoint: String,
 
+    /// Model named in the body sent, so rows can be billed by model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
+    /// HTTP status of the response, or 0 if unknown, as for responses
+    /// cached before statuses were recorded.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub status: u16,
+
     /// Response latency in milliseconds.
     pub latency_ms: u64,
 
+    /// Time to the first streamed token of the successful attempt, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttft_ms: Option<u64>,
+
     /// Number of retry attempts.
     pub attempts: u32,
+
+    /// Token usage reported by the API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+
+    /// Estimated cost from the endpoint's token prices.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub estimated_cost: Option<f64>,
+
+    /// Input file the request came from, when a run reads several.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+
+    /// Id sent in the request id header, to correlate with the provider's logs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+
+    /// Whether the response was read from the response cache instead of the API.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+}
+
+/// Token counts from an OpenAI-style `usage` object.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    /// Tokens in the prompt.
+    pub prompt_tokens: u64,
+    /// Tokens in the completion.
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    /// Extract token usage from a response body, if present.
+    #[must_use]
+    pub fn from_response(response: &Value) -> Option<Self> {
+        let usage = response.get("usage")?;
+        let count = |field| usage.get(field).and_then(Value::as_u64).unwrap_or(0);
+        Some(Self {
+            prompt_tokens: count("prompt_tokens"),
+            completion_tokens: count("completion_tokens"),
+        })
+    }
+
+    /// Estimate the cost given prices per 1,000 prompt and completion tokens.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn cost(&self, input_price_per_1k: f64, output_price_per_1k: f64) -> f64 {
+        (self.prompt_tokens as f64 * input_price_per_1k
+            + self.completion_tokens as f64 * output_price_per_1k)
+            / 1000.0
+    }
 }
 
 impl ApiResponse {
@@ -128,6 +527,8 @@ impl ApiResponse {
             input,
             response,
             metadata: None,
+            request_metadata: BTreeMap::new(),
+            deduped: false,
         }
     }
 
@@ -136,6 +537,19 @@ impl ApiResponse {
         self.metadata = Some(metadata);
         self
     }
+
+    /// Pass through the extra fields of the request the response answers.
+    #[must_use]
+    pub fn with_request_metadata(mut self, request: &ApiRequest) -> Self {
+        self.request_metadata = request.metadata_fields();
+        self
+    }
+
+    /// Check whether the response was read from the response cache.
+    #[must_use]
+    pub fn is_cached(&self) -> bool {
+        self.metadata.as_ref().is_some_and(|m| m.cached)
+    }
 }
 
 /// An error response for failed requests.
@@ -149,6 +563,26 @@ pub struct ErrorResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub body: Option<Value>,
 
+    /// HTTP method the request asked for, if not the default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<HttpMethod>,
+
+    /// Path the request asked for, if not the endpoint's own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+
+    /// Custom headers of the request, as given in its row.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub headers: Option<BTreeMap<String, String>>,
+
+    /// Priority the request was given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i64>,
+
+    /// Model the request asked for, if not the endpoint's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
     /// Error message.
     pub error: String,
 
@@ -156,16 +590,122 @@ pub struct ErrorResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub status_code: Option<u16>,
 
+    /// Endpoint the request was last sent to (if one was selected).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+
     /// Line number in the input file.
-    #[serde(skip_serializing_if = "is_zero")]
+    #[serde(default, skip_serializing_if = "is_zero")]
     pub line_number: usize,
 
+    /// Input file the request came from, when a run reads several.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+
+    /// Extra fields of the request, passed through to join failures back to their rows.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub request_metadata: BTreeMap<String, Value>,
+
     /// Number of attempts made.
     pub attempts: u32,
+
+    /// Id sent in the request id header, to correlate with the provider's logs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+
+    /// Category of the failure, for aggregating without parsing `error`.
+    pub error_kind: ErrorKind,
+
+    /// Whether the failure looks transient or permanent.
+    pub failure_class: FailureClass,
+
+    /// The body as sent, after templating, when requests are captured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_body: Option<Value>,
+
+    /// The custom headers sent, credentials masked, when requests are captured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_headers: Option<BTreeMap<String, String>>,
+}
+
+/// Category of a failed request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// The request timed out.
+    Timeout,
+    /// The endpoint could not be connected to.
+    ConnectionRefused,
+    /// The endpoint returned a 4xx status other than 429.
+    Http4xx,
+    /// The endpoint returned a 5xx status.
+    Http5xx,
+    /// The response body could not be parsed.
+    ParseError,
+    /// The endpoint returned 429 Too Many Requests.
+    RateLimited,
+    /// A 2xx response body failed the configured validation rules, or the
+    /// input row had nothing to send.
+    Validation,
+    /// The response body was larger than `max_response_bytes`.
+    ResponseTooLarge,
+    /// Any other failure.
+    #[default]
+    Other,
+}
+
+impl ErrorKind {
+    /// Categorize an HTTP error status code.
+    #[must_use]
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            429 => Self::RateLimited,
+            400..=499 => Self::Http4xx,
+            500..=599 => Self::Http5xx,
+            _ => Self::Other,
+        }
+    }
+
+    /// Get the name of the category as it appears in the errors file.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Timeout => "timeout",
+            Self::ConnectionRefused => "connection_refused",
+            Self::Http4xx => "http4xx",
+            Self::Http5xx => "http5xx",
+            Self::ParseError => "parse_error",
+            Self::RateLimited => "rate_limited",
+            Self::Validation => "validation",
+            Self::ResponseTooLarge => "response_too_large",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// Classification of a failed request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureClass {
+    /// Likely to succeed later (5xx, 429, timeouts, connection errors).
+    Transient,
+    /// Will fail again if retried (other 4xx, unparseable responses).
+    Permanent,
+}
+
+impl FailureClass {
+    /// Classify a failure by its HTTP status code, if one was received.
+    #[must_use]
+    pub fn from_status(status: Option<u16>) -> Self {
+        match status {
+            None | Some(408 | 429 | 500..=599) => Self::Transient,
+            Some(_) => Self::Permanent,
+        }
+    }
 }
 
-fn is_zero(n: &usize) -> bool {
-    *n == 0
+fn is_zero<T: Default + PartialEq>(n: &T) -> bool {
+    *n == T::default()
 }
 
 impl ErrorResponse {
@@ -174,27 +714,69 @@ impl ErrorResponse {
         Self {
             input: request.input.clone(),
             body: request.body.clone(),
+            method: request.method,
+            path: request.path.clone(),
+            headers: request
+                .headers
+                .as_ref()
+                .map(|headers| headers.clone().into_iter().collect()),
+            priority: request.priority,
+            model: request.model.clone(),
             error: error.into(),
             status_code: None,
+            endpoint: None,
             line_number: request.line_number,
+            source: request.source.as_deref().map(str::to_string),
+            request_metadata: request.metadata_fields(),
             attempts,
+            request_id: None,
+            error_kind: ErrorKind::Other,
+            failure_class: FailureClass::Permanent,
+            sent_body: None,
+            sent_headers: None,
         }
     }
 
+    /// Set the error category.
+    #[must_use]
+    pub fn with_kind(mut self, kind: ErrorKind) -> Self {
+        self.error_kind = kind;
+        self
+    }
+
     /// Set the HTTP status code.
     pub fn with_status(mut self, status: u16) -> Self {
         self.status_code = Some(status);
         self
     }
+
+    /// Set the endpoint the request was sent to.
+    #[must_use]
+    pub fn with_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Set the failure classification.
+    #[must_use]
+    pub fn with_class(mut self, class: FailureClass) -> Self {
+        self.failure_class = class;
+        self
+    }
 }
 
 /// Result of processing a single request.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum RequestResult {
     /// Request succeeded.
     Success(ApiResponse),
     /// Request failed after all retries.
     Failure(ErrorResponse),
+    /// Request was not sent, so it neither succeeded nor failed.
+    Skipped {
+        /// Why the request was not sent.
+        reason: String,
+    },
 }
 
 impl RequestResult {
@@ -202,6 +784,18 @@ impl RequestResult {
     pub fn is_success(&self) -> bool {
         matches!(self, Self::Success(_))
     }
+
+    /// Check if the request was skipped without being sent.
+    #[must_use]
+    pub fn is_skipped(&self) -> bool {
+        matches!(self, Self::Skipped { .. })
+    }
+
+    /// Check if this is a transient failure worth requeueing.
+    #[must_use]
+    pub fn is_transient_failure(&self) -> bool {
+        matches!(self, Self::Failure(e) if e.failure_class == FailureClass::Transient)
+    }
 }
 
 #[cfg(test)]
@@ -218,21 +812,155 @@ mod tests {
     #[test]
     fn test_build_llm_body() {
         let req = ApiRequest::simple("Test input");
-        let body = req.build_llm_body(Some("gpt-4"));
+        let body = req
+            .build_llm_body(Some("gpt-4"), RequestFormat::Chat, None, false)
+            .unwrap();
 
         assert_eq!(body["model"], "gpt-4");
         assert_eq!(body["messages"][0]["content"], "Test input");
     }
 
+    #[test]
+    fn test_request_formats() {
+        let build = |input: &str, format| {
+            ApiRequest::simple(input).build_llm_body(Some("gpt-4"), format, None, false)
+        };
+
+        let body = build("Test input", RequestFormat::Completion).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"prompt": "Test input", "model": "gpt-4"})
+        );
+        let body = build("Test input", RequestFormat::Embedding).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"input": "Test input", "model": "gpt-4"})
+        );
+
+        // Raw input is the whole body, without a model
+        let body = build(r#"{"query": "rust", "top_k": 3}"#, RequestFormat::Raw).unwrap();
+        assert_eq!(body, serde_json::json!({"query": "rust", "top_k": 3}));
+        assert!(matches!(
+            build("not json", RequestFormat::Raw),
+            Err(BlazeError::JsonParse { .. })
+        ));
+    }
+
+    #[test]
+    fn test_body_template() {
+        let template = serde_json::json!({
+            "messages": [{"role": "user", "content": "User ${user_id} asks: ${question}"}],
+            "user": "${user_id}",
+            "tags": "${missing}"
+        });
+        let req: ApiRequest =
+            serde_json::from_str(r#"{"user_id": 5, "question": "Why?"}"#).unwrap();
+
+        let body = req
+            .build_llm_body(Some("gpt-4"), RequestFormat::Chat, Some(&template), false)
+            .unwrap();
+        assert_eq!(body["model"], "gpt-4");
+        assert_eq!(body["messages"][0]["content"], "User 5 asks: Why?");
+        assert_eq!(body["user"], 5);
+        assert_eq!(body["tags"], "${missing}");
+
+        assert!(matches!(
+            req.build_llm_body(None, RequestFormat::Chat, Some(&template), true),
+            Err(BlazeError::MissingTemplateField { field, .. }) if field == "missing"
+        ));
+    }
+
+    #[test]
+    fn test_request_model() {
+        let req: ApiRequest =
+            serde_json::from_str(r#"{"input": "Hard one", "model": "gpt-4o"}"#).unwrap();
+        assert!(!req.metadata.contains_key("model"));
+        let body = req
+            .build_llm_body(Some("gpt-4o-mini"), RequestFormat::Chat, None, false)
+            .unwrap();
+        assert_eq!(body["model"], "gpt-4o");
+
+        // The request's model also replaces one set by the template
+        let template = serde_json::json!({"prompt": "${input}", "model": "davinci"});
+        let body = req
+            .build_llm_body(None, RequestFormat::Chat, Some(&template), false)
+            .unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"prompt": "Hard one", "model": "gpt-4o"})
+        );
+        let body = ApiRequest::simple("Easy one")
+            .build_llm_body(
+                Some("gpt-4o-mini"),
+                RequestFormat::Chat,
+                Some(&template),
+                false,
+            )
+            .unwrap();
+        assert_eq!(body["model"], "davinci");
+    }
+
+    #[test]
+    fn test_method_and_path() {
+        let req = ApiRequest::simple("Hello");
+        assert_eq!(req.method(), HttpMethod::Post);
+        assert_eq!(req.url("http://api.test/v1"), "http://api.test/v1");
+
+        let req: ApiRequest =
+            serde_json::from_str(r#"{"method": "get", "path": "/models/gpt-4"}"#).unwrap();
+        assert_eq!(req.method(), HttpMethod::Get);
+        assert!(!req.method().has_body());
+        assert_eq!(
+            req.url("http://api.test/v1/"),
+            "http://api.test/v1/models/gpt-4"
+        );
+        assert!(!req.metadata.contains_key("method"));
+
+        let err = serde_json::from_str::<ApiRequest>(r#"{"method": "FETCH"}"#).unwrap_err();
+        assert!(err.to_string().contains("unsupported HTTP method 'FETCH'"));
+    }
+
     #[test]
     fn test_custom_body() {
         let custom = serde_json::json!({"custom": "data"});
         let req = ApiRequest::with_body(custom.clone());
-        let body = req.build_llm_body(Some("gpt-4"));
+        let body = req
+            .build_llm_body(Some("gpt-4"), RequestFormat::Chat, None, false)
+            .unwrap();
 
         assert_eq!(body, custom);
     }
 
+    #[test]
+    fn test_blank_request() {
+        assert!(!ApiRequest::simple("Hello").is_blank());
+        assert!(!ApiRequest::with_body(serde_json::json!({"custom": "data"})).is_blank());
+
+        let blank: ApiRequest = serde_json::from_str(r#"{"id": "a"}"#).unwrap();
+        assert!(blank.is_blank());
+        assert!(ApiRequest::simple("  \n").is_blank());
+        for body in [
+            serde_json::json!(null),
+            serde_json::json!(""),
+            serde_json::json!({}),
+        ] {
+            assert!(ApiRequest::with_body(body).is_blank());
+        }
+    }
+
+    #[test]
+    fn test_token_usage() {
+        let response = serde_json::json!({
+            "usage": {"prompt_tokens": 1500, "completion_tokens": 500, "total_tokens": 2000}
+        });
+        let usage = TokenUsage::from_response(&response).unwrap();
+        assert_eq!(usage.prompt_tokens, 1500);
+        assert_eq!(usage.completion_tokens, 500);
+        assert!((usage.cost(0.01, 0.03) - 0.03).abs() < 1e-9);
+
+        assert!(TokenUsage::from_response(&serde_json::json!({"choices": []})).is_none());
+    }
+
     #[test]
     fn test_error_response() {
         let req = ApiRequest::simple("Test");
@@ -240,5 +968,44 @@ mod tests {
 
         assert_eq!(err.error, "Connection refused");
         assert_eq!(err.attempts, 3);
+        assert_eq!(err.failure_class, FailureClass::Permanent);
+    }
+
+    #[test]
+    fn test_error_kind() {
+        assert_eq!(ErrorKind::from_status(429), ErrorKind::RateLimited);
+        assert_eq!(ErrorKind::from_status(401), ErrorKind::Http4xx);
+        assert_eq!(ErrorKind::from_status(503), ErrorKind::Http5xx);
+
+        let req = ApiRequest::simple("Test");
+        let err = ErrorResponse::new(&req, "HTTP 429: slow down", 3)
+            .with_status(429)
+            .with_kind(ErrorKind::from_status(429));
+        let json = serde_json::to_value(&err).unwrap();
+        assert_eq!(json["error_kind"], "rate_limited");
+        assert_eq!(json["status_code"], 429);
+        assert!(json.get("endpoint").is_none());
+
+        // The log name matches the serialized one
+        for kind in [
+            ErrorKind::ConnectionRefused,
+            ErrorKind::Http4xx,
+            ErrorKind::Other,
+        ] {
+            assert_eq!(serde_json::to_value(kind).unwrap(), kind.as_str());
+        }
+        let err = err.with_endpoint("http://a.test/v1");
+        let json = serde_json::to_value(&err).unwrap();
+        assert_eq!(json["endpoint"], "http://a.test/v1");
+    }
+
+    #[test]
+    fn test_failure_class() {
+        for status in [None, Some(408), Some(429), Some(500), Some(503)] {
+            assert_eq!(FailureClass::from_status(status), FailureClass::Transient);
+        }
+        for status in [Some(200), Some(400), Some(401), Some(404)] {
+            assert_eq!(FailureClass::from_status(status), FailureClass::Permanent);
+        }
     }
 }
@@ -0,0 +1,250 @@
+//! Assembly of streamed (server-sent events) chat completion responses.
+//!
+//! Endpoints asked for `"stream": true` answer with a `text/event-stream` of
+//! `data:` events, each carrying a chunk with `choices[].delta.content`, and
+//! end with `data: [DONE]`. The chunks are stitched back into the shape of a
+//! regular chat completion, so output files, validation and usage tracking
+//! work the same as for buffered responses.
+
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Why an event stream could not be assembled into a response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamError {
+    /// An event was invalid or reported an error.
+    Invalid(String),
+    /// The stream ended before `[DONE]` or a finish reason, as when the
+    /// connection drops mid-response.
+    Truncated,
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(message) => f.write_str(message),
+            Self::Truncated => f.write_str("event stream ended before the response was complete"),
+        }
+    }
+}
+
+/// A choice being assembled from its deltas.
+#[derive(Debug, Default)]
+struct Choice {
+    role: Option<Value>,
+    content: String,
+    finish_reason: Option<Value>,
+}
+
+/// Builds a chat completion from the bytes of an event stream.
+#[derive(Debug, Default)]
+pub struct StreamAssembler {
+    /// Bytes of an incomplete line.
+    line: Vec<u8>,
+    /// Data lines of the event being read.
+    data: Vec<String>,
+    /// Top-level fields such as `id` and `model`, from the first chunk that has them.
+    fields: Map<String, Value>,
+    choices: BTreeMap<u64, Choice>,
+    usage: Option<Value>,
+    has_content: bool,
+    done: bool,
+}
+
+impl StreamAssembler {
+    /// Create an assembler for a new stream.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next bytes of the stream.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message if an event is not valid JSON or reports an error.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<(), String> {
+        for &byte in bytes {
+            if byte != b'\n' {
+                self.line.push(byte);
+                continue;
+            }
+            let mut line = std::mem::take(&mut self.line);
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            self.line_complete(&String::from_utf8_lossy(&line))?;
+        }
+        Ok(())
+    }
+
+    /// Check whether any content has arrived yet.
+    #[must_use]
+    pub fn has_content(&self) -> bool {
+        self.has_content
+    }
+
+    /// Finish the stream, returning the assembled chat completion.
+    ///
+    /// A stream is complete once it has sent `[DONE]` or a finish reason for
+    /// every choice.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StreamError::Invalid`] if the last event is invalid, and
+    /// [`StreamError::Truncated`] if the stream ended before it was complete.
+    pub fn finish(mut self) -> Result<Value, StreamError> {
+        // A final event need not be followed by a blank line
+        let line = String::from_utf8_lossy(&std::mem::take(&mut self.line)).into_owned();
+        self.line_complete(&line).map_err(StreamError::Invalid)?;
+        self.line_complete("").map_err(StreamError::Invalid)?;
+        let finished = !self.choices.is_empty()
+            && self
+                .choices
+                .values()
+                .all(|choice| choice.finish_reason.is_some());
+        if !self.done && !finished {
+            return Err(StreamError::Truncated);
+        }
+
+        let mut response = self.fields;
+        response.insert("object".to_string(), json!("chat.completion"));
+        let choices = self
+            .choices
+            .into_iter()
+            .map(|(index, choice)| {
+                json!({
+                    "index": index,
+                    "message": {
+                        "role": choice.role.unwrap_or_else(|| json!("assistant")),
+                        "content": choice.content,
+                    },
+                    "finish_reason": choice.finish_reason,
+                })
+            })
+            .collect();
+        response.insert("choices".to_string(), Value::Array(choices));
+        if let Some(usage) = self.usage {
+            response.insert("usage".to_string(), usage);
+        }
+        Ok(Value::Object(response))
+    }
+
+    /// Handle a complete line, dispatching the event at a blank line.
+    fn line_complete(&mut self, line: &str) -> Result<(), String> {
+        if line.is_empty() {
+            let data = std::mem::take(&mut self.data);
+            return if data.is_empty() {
+                Ok(())
+            } else {
+                self.event(&data.join("\n"))
+            };
+        }
+        // Comments, event names and ids carry nothing to assemble
+        if let Some(data) = line.strip_prefix("data:") {
+            self.data
+                .push(data.strip_prefix(' ').unwrap_or(data).to_string());
+        }
+        Ok(())
+    }
+
+    /// Merge the chunk carried by one event.
+    fn event(&mut self, data: &str) -> Result<(), String> {
+        if self.done {
+            return Ok(());
+        }
+        if data.trim() == "[DONE]" {
+            self.done = true;
+            return Ok(());
+        }
+        let chunk: Value =
+            serde_json::from_str(data).map_err(|e| format!("invalid stream event: {e}"))?;
+        if let Some(error) = chunk.get("error") {
+            return Err(format!("stream reported an error: {error}"));
+        }
+        let Value::Object(mut chunk) = chunk else {
+            return Err("stream event is not a JSON object".to_string());
+        };
+
+        if let Some(usage) = chunk.remove("usage").filter(|usage| !usage.is_null()) {
+            self.usage = Some(usage);
+        }
+        let choices = chunk.remove("choices");
+        for (key, value) in chunk {
+            self.fields.entry(key).or_insert(value);
+        }
+
+        for choice in choices
+            .as_ref()
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let index = choice.get("index").and_then(Value::as_u64).unwrap_or(0);
+            let assembled = self.choices.entry(index).or_default();
+            if let Some(delta) = choice.get("delta") {
+                if let Some(role) = delta.get("role").filter(|role| !role.is_null()) {
+                    assembled.role = Some(role.clone());
+                }
+                if let Some(content) = delta.get("content").and_then(Value::as_str) {
+                    assembled.content.push_str(content);
+                    self.has_content |= !content.is_empty();
+                }
+            }
+            if let Some(reason) = choice.get("finish_reason").filter(|r| !r.is_null()) {
+                assembled.finish_reason = Some(reason.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_assembler() {
+        let stream = concat!(
+            ": keep-alive\n\n",
+            "data: {\"id\":\"c1\",\"model\":\"gpt-4\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"\"}}]}\n\n",
+            "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hel\"}}]}\r\n\r\n",
+            "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"lo\"},\"finish_reason\":\"stop\"}]}\n\n",
+            "data: {\"id\":\"c1\",\"choices\":[],\"usage\":{\"prompt_tokens\":3,\"completion_tokens\":2}}\n\n",
+            "data: [DONE]\n\n",
+        );
+
+        // Chunks can split events and lines anywhere
+        let mut assembler = StreamAssembler::new();
+        let (head, tail) = stream.as_bytes().split_at(150);
+        assembler.push(head).unwrap();
+        assert!(!assembler.has_content());
+        assembler.push(tail).unwrap();
+        assert!(assembler.has_content());
+
+        let response = assembler.finish().unwrap();
+        assert_eq!(response["id"], "c1");
+        assert_eq!(response["model"], "gpt-4");
+        assert_eq!(response["choices"][0]["message"]["role"], "assistant");
+        assert_eq!(response["choices"][0]["message"]["content"], "Hello");
+        assert_eq!(response["choices"][0]["finish_reason"], "stop");
+        assert_eq!(response["usage"]["completion_tokens"], 2);
+
+        let mut assembler = StreamAssembler::new();
+        let error = assembler.push(b"data: {\"error\":{\"message\":\"overloaded\"}}\n\n");
+        assert!(error.unwrap_err().contains("overloaded"));
+        assert_eq!(StreamAssembler::new().finish(), Err(StreamError::Truncated));
+
+        // A stream cut off before a finish reason or [DONE] is incomplete
+        let partial = b"data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hel\"}}]}\n\n";
+        let mut assembler = StreamAssembler::new();
+        assembler.push(partial).unwrap();
+        assert_eq!(assembler.finish(), Err(StreamError::Truncated));
+        let finished = b"data: {\"choices\":[{\"index\":0,\"finish_reason\":\"stop\"}]}\n\n";
+        let mut assembler = StreamAssembler::new();
+        assembler.push(partial).unwrap();
+        assembler.push(finished).unwrap();
+        assert!(assembler.finish().is_ok());
+    }
+}
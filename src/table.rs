@@ -0,0 +1,331 @@
+//! Tabular output of successful responses.
+//!
+//! CSV and Parquet files have a fixed set of columns: the request's line
+//! number, input file and input, then one column per key of the output
+//! projection, in key order. String fields are written as they are, other
+//! values as JSON, and fields that are null or missing are left empty.
+
+use crate::projection::Projection;
+use crate::request::ApiResponse;
+use serde_json::Value;
+
+/// Columns written before the projected fields.
+const LEADING_COLUMNS: [&str; 3] = ["line_number", "source", "input"];
+
+/// Get the names of the columns of a table of `projection`.
+pub fn columns(projection: &Projection) -> impl Iterator<Item = &str> {
+    LEADING_COLUMNS.into_iter().chain(projection.keys())
+}
+
+/// Get the header row of a CSV file of `projection`, without a line terminator.
+#[must_use]
+pub fn csv_header(projection: &Projection) -> String {
+    csv_record(columns(projection))
+}
+
+/// A successful response flattened into the columns of a table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    /// Input line the response answers.
+    pub line_number: usize,
+    /// Input file the request came from, when a run reads several.
+    pub source: Option<String>,
+    /// The original input.
+    pub input: Option<String>,
+    /// The projected fields, in column order.
+    pub fields: Vec<Option<String>>,
+}
+
+impl Row {
+    /// Flatten a response into a row, also returning whether the response
+    /// had every projected field.
+    #[must_use]
+    pub fn new(
+        response: &ApiResponse,
+        line_number: usize,
+        projection: &Projection,
+    ) -> (Self, bool) {
+        let values: Vec<_> = projection.values(&response.response).collect();
+        let complete = values.iter().all(Option::is_some);
+        let row = Self {
+            line_number,
+            source: response.metadata.as_ref().and_then(|m| m.source.clone()),
+            input: response.input.clone(),
+            fields: values
+                .into_iter()
+                .map(|value| value.and_then(cell))
+                .collect(),
+        };
+        (row, complete)
+    }
+
+    /// Serialize the row as a CSV record, without a line terminator.
+    #[must_use]
+    pub fn to_csv(&self) -> String {
+        let line_number = self.line_number.to_string();
+        let leading = [
+            Some(line_number.as_str()),
+            self.source.as_deref(),
+            self.input.as_deref(),
+        ];
+        let cells = leading
+            .into_iter()
+            .chain(self.fields.iter().map(Option::as_deref));
+        csv_record(cells.map(Option::unwrap_or_default))
+    }
+}
+
+/// Get the text of a field: strings as they are, other values as JSON, and nothing for null.
+fn cell(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(text) => Some(text.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+/// Encode cells as a CSV record, quoting those that need it.
+fn csv_record<'a>(cells: impl IntoIterator<Item = &'a str>) -> String {
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(Vec::new());
+    // Writing to memory can't fail, and every table has columns
+    let _ = writer.write_record(cells);
+    let mut record = writer.into_inner().unwrap_or_default();
+    if record.last() == Some(&b'\n') {
+        record.pop();
+    }
+    String::from_utf8(record).unwrap_or_default()
+}
+
+#[cfg(feature = "parquet")]
+pub use self::parquet_file::ParquetFile;
+
+#[cfg(feature = "parquet")]
+mod parquet_file {
+    use super::{columns, Row, LEADING_COLUMNS};
+    use crate::projection::Projection;
+    use arrow_array::{ArrayRef, RecordBatch, StringArray, UInt64Array};
+    use arrow_schema::{DataType, Field, Schema, SchemaRef};
+    use parquet::arrow::AsyncArrowWriter;
+    use parquet::basic::Compression;
+    use parquet::file::properties::WriterProperties;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use tokio::fs::File;
+
+    /// Rows collected before they are encoded together.
+    const BATCH_ROWS: usize = 1024;
+
+    /// Rows per row group, bounding the memory held for the group being written.
+    const ROW_GROUP_ROWS: usize = 64 * 1024;
+
+    /// A Parquet file of responses, with a `line_number` column of integers
+    /// and a nullable text column for each of the others.
+    ///
+    /// Rows reach the file a row group at a time, and the file can only be
+    /// read once it is finished.
+    pub struct ParquetFile {
+        writer: AsyncArrowWriter<File>,
+        schema: SchemaRef,
+        path: PathBuf,
+        rows: Vec<Row>,
+    }
+
+    impl std::fmt::Debug for ParquetFile {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("ParquetFile")
+                .field("path", &self.path)
+                .field("rows", &self.rows.len())
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl ParquetFile {
+        /// Create the file at `path`, with a column for each field of `projection`.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the file cannot be created.
+        pub async fn create(path: &Path, projection: &Projection) -> io::Result<Self> {
+            let fields: Vec<_> = columns(projection)
+                .map(|name| match name {
+                    "line_number" => Field::new(name, DataType::UInt64, false),
+                    name => Field::new(name, DataType::Utf8, true),
+                })
+                .collect();
+            let schema = Arc::new(Schema::new(fields));
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .set_max_row_group_size(ROW_GROUP_ROWS)
+                .build();
+            let file = File::create(path).await?;
+            let writer = AsyncArrowWriter::try_new(file, Arc::clone(&schema), Some(properties))
+                .map_err(io::Error::other)?;
+            Ok(Self {
+                writer,
+                schema,
+                path: path.to_path_buf(),
+                rows: Vec::with_capacity(BATCH_ROWS),
+            })
+        }
+
+        /// Get the path of the file.
+        #[must_use]
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+
+        /// Add a row, encoding the rows collected so far once there are enough.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if a full row group cannot be written.
+        pub async fn write(&mut self, row: Row) -> io::Result<()> {
+            self.rows.push(row);
+            if self.rows.len() >= BATCH_ROWS {
+                self.write_batch().await?;
+            }
+            Ok(())
+        }
+
+        /// Encode the rows collected so far into the row group being written.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if a full row group cannot be written.
+        pub async fn flush(&mut self) -> io::Result<()> {
+            self.write_batch().await
+        }
+
+        /// Write the remaining rows and the footer, completing the file.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the rows or the footer cannot be written.
+        pub async fn finish(&mut self) -> io::Result<()> {
+            self.write_batch().await?;
+            self.writer.finish().await.map_err(io::Error::other)?;
+            Ok(())
+        }
+
+        async fn write_batch(&mut self) -> io::Result<()> {
+            if self.rows.is_empty() {
+                return Ok(());
+            }
+            let rows = std::mem::take(&mut self.rows);
+            let text = |cell: fn(&Row) -> Option<&str>| -> ArrayRef {
+                Arc::new(rows.iter().map(cell).collect::<StringArray>())
+            };
+            let mut arrays = vec![
+                Arc::new(UInt64Array::from_iter_values(
+                    rows.iter().map(|row| row.line_number as u64),
+                )) as ArrayRef,
+                text(|row| row.source.as_deref()),
+                text(|row| row.input.as_deref()),
+            ];
+            for index in 0..self.schema.fields().len() - LEADING_COLUMNS.len() {
+                let cells = rows.iter().map(|row| row.fields[index].as_deref());
+                arrays.push(Arc::new(cells.collect::<StringArray>()));
+            }
+            let batch =
+                RecordBatch::try_new(Arc::clone(&self.schema), arrays).map_err(io::Error::other)?;
+            self.rows = rows;
+            self.rows.clear();
+            self.writer.write(&batch).await.map_err(io::Error::other)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    fn projection() -> Projection {
+        Projection::new(&BTreeMap::from([
+            ("content".to_string(), "choices[0].text".to_string()),
+            ("tokens".to_string(), "usage.total_tokens".to_string()),
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_csv_rows() {
+        let projection = projection();
+        assert_eq!(
+            csv_header(&projection),
+            "line_number,source,input,content,tokens"
+        );
+
+        let response = ApiResponse::new(
+            Some("say \"hi\"".to_string()),
+            json!({"choices": [{"text": "hi,\nthere"}], "usage": {"total_tokens": 7}}),
+        );
+        let (row, complete) = Row::new(&response, 3, &projection);
+        assert!(complete);
+        assert_eq!(row.to_csv(), "3,,\"say \"\"hi\"\"\",\"hi,\nthere\",7");
+
+        // Missing fields leave their cells empty
+        let response = ApiResponse::new(None, json!({"choices": [{"text": null}]}));
+        let (row, complete) = Row::new(&response, 4, &projection);
+        assert!(!complete);
+        assert_eq!(row.fields, [None, None]);
+        assert_eq!(row.to_csv(), "4,,,,");
+    }
+
+    #[cfg(feature = "parquet")]
+    #[tokio::test]
+    async fn test_parquet_file() {
+        use arrow_array::{Array, RecordBatch, StringArray, UInt64Array};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("out.parquet");
+        let projection = projection();
+        let mut file = ParquetFile::create(&path, &projection).await.unwrap();
+        for n in 1..=3000 {
+            let response = ApiResponse::new(
+                Some(format!("prompt {n}")),
+                json!({"choices": [{"text": format!("answer {n}")}]}),
+            );
+            file.write(Row::new(&response, n, &projection).0)
+                .await
+                .unwrap();
+        }
+        file.finish().await.unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<_> = reader.map(Result::unwrap).collect();
+        assert_eq!(
+            batches.iter().map(RecordBatch::num_rows).sum::<usize>(),
+            3000
+        );
+        let batch = &batches[0];
+        let names: Vec<_> = batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect();
+        assert_eq!(names, columns(&projection).collect::<Vec<_>>());
+        let lines = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(lines.value(0), 1);
+        let content = batch
+            .column(3)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(content.value(0), "answer 1");
+        assert!(batch.column(4).is_null(0));
+    }
+}
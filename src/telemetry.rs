@@ -0,0 +1,75 @@
+//! OpenTelemetry trace export.
+//!
+//! Bridges the crate's `tracing` spans into OpenTelemetry and exports them
+//! over OTLP/HTTP. The collector is configured with the standard
+//! `OTEL_EXPORTER_OTLP_ENDPOINT` and `OTEL_EXPORTER_OTLP_HEADERS` variables.
+//! A W3C trace context in `TRACEPARENT` (and optionally `TRACESTATE`) makes
+//! the run span a child of an existing trace.
+
+use crate::error::{BlazeError, Result};
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use std::collections::HashMap;
+use tracing::{warn, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Service name reported on exported spans.
+const SERVICE_NAME: &str = "blaze";
+
+/// Exports spans to an OTLP collector until shut down.
+#[derive(Debug)]
+pub struct Telemetry {
+    provider: SdkTracerProvider,
+}
+
+impl Telemetry {
+    /// Set up an OTLP exporter with batched span export.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::Telemetry`] if the exporter cannot be built.
+    pub fn init() -> Result<Self> {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .build()
+            .map_err(|e| BlazeError::Telemetry(e.to_string()))?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+            .build();
+        Ok(Self { provider })
+    }
+
+    /// Get a `tracing` layer that records spans into this exporter.
+    #[must_use]
+    pub fn layer<S>(&self) -> OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.provider.tracer(SERVICE_NAME))
+    }
+
+    /// Export any buffered spans and stop the exporter.
+    pub fn shutdown(self) {
+        if let Err(e) = self.provider.shutdown() {
+            warn!("Failed to export traces: {}", e);
+        }
+    }
+}
+
+/// Make `span` a child of the trace context in `TRACEPARENT`, if set.
+pub fn attach_parent_from_env(span: &tracing::Span) {
+    let Ok(traceparent) = std::env::var("TRACEPARENT") else {
+        return;
+    };
+    let mut carrier = HashMap::from([("traceparent".to_string(), traceparent)]);
+    if let Ok(tracestate) = std::env::var("TRACESTATE") {
+        carrier.insert("tracestate".to_string(), tracestate);
+    }
+    let _ = span.set_parent(TraceContextPropagator::new().extract(&carrier));
+}
@@ -3,11 +3,28 @@
 //! This module provides real-time tracking of request statistics
 //! including success/failure counts, latency, and throughput.
 
+use crate::histogram::LatencyHistogram;
+use crate::request::TokenUsage;
 use parking_lot::Mutex;
-use std::collections::VecDeque;
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write as _;
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
 
+/// Rolling window the retry budget is measured over.
+pub const RETRY_BUDGET_WINDOW: Duration = Duration::from_secs(10);
+
+/// Retries allowed per window on top of the budget, so a run can retry
+/// before its first success.
+pub const RETRY_BUDGET_MIN_RETRIES: u64 = 10;
+
+/// Default ratio of retries to successful requests allowed per window.
+pub const DEFAULT_RETRY_BUDGET_RATIO: f64 = 0.1;
+
+/// Default window the current requests per second are measured over.
+pub const DEFAULT_RPS_WINDOW: Duration = Duration::from_secs(1);
+
 /// Statistics tracker for request processing.
 #[derive(Debug)]
 pub struct StatsTracker {
@@ -19,12 +36,44 @@ pub struct StatsTracker {
     success_count: AtomicU64,
     /// Failed requests.
     failure_count: AtomicU64,
+    /// Successful requests answered from the deduplication cache.
+    deduped_count: AtomicU64,
+    /// Successful requests answered from the response cache.
+    cached_count: AtomicU64,
+    /// Transient failures set aside for a second pass.
+    requeued_count: AtomicUsize,
+    /// Requests cancelled at the drain timeout.
+    cancelled_count: AtomicUsize,
+    /// Requests the run did not send, such as those completed by a previous run.
+    skipped_count: AtomicUsize,
+    /// Custom headers left off requests because they were invalid.
+    invalid_headers: AtomicU64,
+    /// Failed requests by the HTTP status they ended with.
+    failure_statuses: StatusCounter,
+    /// Successful requests by the HTTP status they ended with.
+    success_statuses: StatusCounter,
     /// Total latency in microseconds.
     total_latency_us: AtomicU64,
+    /// Latency distribution of successful requests.
+    latency_histogram: LatencyHistogram,
+    /// Time to first token of streamed responses.
+    ttft_histogram: LatencyHistogram,
+    /// Total prompt tokens reported by the API.
+    prompt_tokens: AtomicU64,
+    /// Total completion tokens reported by the API.
+    completion_tokens: AtomicU64,
+    /// Total estimated cost, stored as `f64` bits.
+    estimated_cost: AtomicU64,
+    /// Current concurrency limit.
+    concurrency_limit: AtomicUsize,
     /// Requests in the last second (for RPS calculation).
     recent_requests: Mutex<VecDeque<Instant>>,
+    /// Window the current requests per second are measured over.
+    rps_window: Duration,
     /// Total input lines.
     total_lines: AtomicUsize,
+    /// Cap on retries relative to recent successes.
+    retry_budget: RetryBudget,
 }
 
 impl StatsTracker {
@@ -35,30 +84,149 @@ impl StatsTracker {
             total_processed: AtomicU64::new(0),
             success_count: AtomicU64::new(0),
             failure_count: AtomicU64::new(0),
+            deduped_count: AtomicU64::new(0),
+            cached_count: AtomicU64::new(0),
+            requeued_count: AtomicUsize::new(0),
+            cancelled_count: AtomicUsize::new(0),
+            skipped_count: AtomicUsize::new(0),
+            invalid_headers: AtomicU64::new(0),
+            failure_statuses: StatusCounter::default(),
+            success_statuses: StatusCounter::default(),
             total_latency_us: AtomicU64::new(0),
+            latency_histogram: LatencyHistogram::new(),
+            ttft_histogram: LatencyHistogram::new(),
+            prompt_tokens: AtomicU64::new(0),
+            completion_tokens: AtomicU64::new(0),
+            estimated_cost: AtomicU64::new(0f64.to_bits()),
+            concurrency_limit: AtomicUsize::new(0),
             recent_requests: Mutex::new(VecDeque::new()),
+            rps_window: DEFAULT_RPS_WINDOW,
             total_lines: AtomicUsize::new(0),
+            retry_budget: RetryBudget::new(DEFAULT_RETRY_BUDGET_RATIO),
         }
     }
 
+    /// Allow `ratio` retries per successful request in the retry budget.
+    #[must_use]
+    pub fn with_retry_budget(mut self, ratio: f64) -> Self {
+        self.retry_budget = RetryBudget::new(ratio);
+        self
+    }
+
+    /// Measure the current requests per second over `window` instead of 1 second.
+    #[must_use]
+    pub fn with_rps_window(mut self, window: Duration) -> Self {
+        self.rps_window = window;
+        self
+    }
+
+    /// Get the retry budget shared by the requests of a run.
+    pub fn retry_budget(&self) -> &RetryBudget {
+        &self.retry_budget
+    }
+
     /// Set the total number of input lines.
     pub fn set_total_lines(&self, total: usize) {
         self.total_lines.store(total, Ordering::Relaxed);
     }
 
+    /// Set the current concurrency limit.
+    pub fn set_concurrency_limit(&self, limit: usize) {
+        self.concurrency_limit.store(limit, Ordering::Relaxed);
+    }
+
     /// Record a successful request.
     pub fn record_success(&self, latency: Duration) {
         self.total_processed.fetch_add(1, Ordering::Relaxed);
         self.success_count.fetch_add(1, Ordering::Relaxed);
         self.total_latency_us
             .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+        self.latency_histogram.record(latency);
+        self.record_recent();
+    }
+
+    /// Record the time to first token of a streamed response.
+    pub fn record_time_to_first_token(&self, ttft: Duration) {
+        self.ttft_histogram.record(ttft);
+    }
+
+    /// Record a request answered from the deduplication cache.
+    ///
+    /// It counts as a success, but not towards latency.
+    pub fn record_deduped(&self) {
+        self.total_processed.fetch_add(1, Ordering::Relaxed);
+        self.success_count.fetch_add(1, Ordering::Relaxed);
+        self.deduped_count.fetch_add(1, Ordering::Relaxed);
+        self.record_recent();
+    }
+
+    /// Record a request answered from the response cache.
+    ///
+    /// Like a deduplicated request, it counts as a success, but not towards latency.
+    pub fn record_cached(&self) {
+        self.total_processed.fetch_add(1, Ordering::Relaxed);
+        self.success_count.fetch_add(1, Ordering::Relaxed);
+        self.cached_count.fetch_add(1, Ordering::Relaxed);
+        self.record_recent();
+    }
+
+    /// Record a custom header left off a request because it was invalid.
+    pub fn record_invalid_header(&self) {
+        self.invalid_headers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record transient failures set aside for a second pass.
+    pub fn record_requeued(&self, count: usize) {
+        self.requeued_count.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Record requests cancelled at the drain timeout.
+    ///
+    /// Each is also recorded as a failure.
+    pub fn record_cancelled(&self, count: usize) {
+        self.cancelled_count.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Record requests the run did not send.
+    ///
+    /// Skipped requests count as neither successes nor failures.
+    pub fn record_skipped(&self, count: usize) {
+        self.skipped_count.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Record token usage and its estimated cost.
+    pub fn record_usage(&self, usage: TokenUsage, cost: f64) {
+        self.prompt_tokens
+            .fetch_add(usage.prompt_tokens, Ordering::Relaxed);
+        self.completion_tokens
+            .fetch_add(usage.completion_tokens, Ordering::Relaxed);
+        if cost > 0.0 {
+            let _ =
+                self.estimated_cost
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                        Some((f64::from_bits(bits) + cost).to_bits())
+                    });
+        }
+    }
+
+    /// Record the HTTP status a successful request ended with, for the status counts.
+    pub fn record_success_status(&self, status: u16) {
+        self.success_statuses.record(Some(status));
+    }
+
+    /// Record a failed request, with the HTTP status it ended with if any.
+    pub fn record_failure(&self, status: Option<u16>) {
+        self.total_processed.fetch_add(1, Ordering::Relaxed);
+        self.failure_count.fetch_add(1, Ordering::Relaxed);
+        self.failure_statuses.record(status);
         self.record_recent();
     }
 
-    /// Record a failed request.
-    pub fn record_failure(&self) {
+    /// Record a request that failed validation, which has no HTTP status.
+    pub fn record_validation_failure(&self) {
         self.total_processed.fetch_add(1, Ordering::Relaxed);
         self.failure_count.fetch_add(1, Ordering::Relaxed);
+        self.failure_statuses.record_validation();
         self.record_recent();
     }
 
@@ -67,34 +235,37 @@ impl StatsTracker {
         let now = Instant::now();
         let mut recent = self.recent_requests.lock();
         recent.push_back(now);
+        self.evict_recent(&mut recent, now);
+    }
 
-        // Remove entries older than 1 second
-        let cutoff = now - Duration::from_secs(1);
-        while let Some(front) = recent.front() {
-            if *front < cutoff {
-                recent.pop_front();
-            } else {
-                break;
-            }
+    /// Drop requests that fell out of the RPS window.
+    fn evict_recent(&self, recent: &mut VecDeque<Instant>, now: Instant) {
+        let Some(cutoff) = now.checked_sub(self.rps_window) else {
+            return;
+        };
+        while recent.front().is_some_and(|&front| front < cutoff) {
+            recent.pop_front();
         }
     }
 
-    /// Get the current requests per second.
+    /// Get the latency distribution of successful requests.
+    pub fn latency_histogram(&self) -> &LatencyHistogram {
+        &self.latency_histogram
+    }
+
+    /// Get the total latency of successful requests.
+    pub fn total_latency(&self) -> Duration {
+        Duration::from_micros(self.total_latency_us.load(Ordering::Relaxed))
+    }
+
+    /// Get the current requests per second, averaged over the RPS window.
+    #[allow(clippy::cast_precision_loss)]
     pub fn requests_per_second(&self) -> f64 {
         let now = Instant::now();
         let mut recent = self.recent_requests.lock();
+        self.evict_recent(&mut recent, now);
 
-        // Remove old entries
-        let cutoff = now - Duration::from_secs(1);
-        while let Some(front) = recent.front() {
-            if *front < cutoff {
-                recent.pop_front();
-            } else {
-                break;
-            }
-        }
-
-        recent.len() as f64
+        recent.len() as f64 / self.rps_window.as_secs_f64()
     }
 
     /// Get the current statistics snapshot.
@@ -103,11 +274,15 @@ impl StatsTracker {
         let total = self.total_processed.load(Ordering::Relaxed);
         let success = self.success_count.load(Ordering::Relaxed);
         let failure = self.failure_count.load(Ordering::Relaxed);
+        let deduped = self.deduped_count.load(Ordering::Relaxed);
+        let cached = self.cached_count.load(Ordering::Relaxed);
+        let sent = success.saturating_sub(deduped + cached);
         let total_latency = self.total_latency_us.load(Ordering::Relaxed);
         let total_lines = self.total_lines.load(Ordering::Relaxed);
 
-        let avg_latency_ms = if success > 0 {
-            (total_latency as f64 / success as f64) / 1000.0
+        #[allow(clippy::cast_precision_loss)]
+        let avg_latency_ms = if sent > 0 {
+            (total_latency as f64 / sent as f64) / 1000.0
         } else {
             0.0
         };
@@ -124,16 +299,51 @@ impl StatsTracker {
             0.0
         };
 
+        let percentiles: Vec<f64> = self
+            .latency_histogram
+            .quantiles(&[0.50, 0.95, 0.99])
+            .iter()
+            .map(|latency| latency.as_secs_f64() * 1000.0)
+            .collect();
+        let ttft_ms = |quantile| {
+            (self.ttft_histogram.count() > 0)
+                .then(|| self.ttft_histogram.quantile(quantile).as_secs_f64() * 1000.0)
+        };
+
+        let failure_statuses = self.failure_statuses.snapshot();
+        let mut status_counts = self.success_statuses.snapshot().by_status;
+        for (&status, &count) in &failure_statuses.by_status {
+            *status_counts.entry(status).or_insert(0) += count;
+        }
+
         StatsSnapshot {
             elapsed,
             total_processed: total,
             success_count: success,
             failure_count: failure,
+            deduped_count: deduped,
+            cached_count: cached,
+            requeued_count: self.requeued_count.load(Ordering::Relaxed),
+            cancelled_count: self.cancelled_count.load(Ordering::Relaxed),
+            skipped_count: self.skipped_count.load(Ordering::Relaxed),
+            invalid_headers: self.invalid_headers.load(Ordering::Relaxed),
+            failure_statuses: failure_statuses.clone(),
+            status_counts,
+            retries_denied: self.retry_budget.denied(),
             avg_latency_ms,
+            p50_latency_ms: percentiles[0],
+            p95_latency_ms: percentiles[1],
+            p99_latency_ms: percentiles[2],
+            p50_ttft_ms: ttft_ms(0.50),
+            p95_ttft_ms: ttft_ms(0.95),
             current_rps: self.requests_per_second(),
             overall_rps,
             total_lines,
             progress,
+            prompt_tokens: self.prompt_tokens.load(Ordering::Relaxed),
+            completion_tokens: self.completion_tokens.load(Ordering::Relaxed),
+            estimated_cost: f64::from_bits(self.estimated_cost.load(Ordering::Relaxed)),
+            concurrency_limit: self.concurrency_limit.load(Ordering::Relaxed),
         }
     }
 }
@@ -145,9 +355,10 @@ impl Default for StatsTracker {
 }
 
 /// A snapshot of current statistics.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct StatsSnapshot {
     /// Elapsed time since start.
+    #[serde(rename = "elapsed_secs", serialize_with = "serialize_secs")]
     pub elapsed: Duration,
     /// Total requests processed.
     pub total_processed: u64,
@@ -155,8 +366,39 @@ pub struct StatsSnapshot {
     pub success_count: u64,
     /// Failed requests.
     pub failure_count: u64,
+    /// Successful requests answered from the deduplication cache.
+    pub deduped_count: u64,
+    /// Successful requests answered from the response cache.
+    pub cached_count: u64,
+    /// Transient failures set aside for a second pass.
+    pub requeued_count: usize,
+    /// Requests cancelled at the drain timeout.
+    pub cancelled_count: usize,
+    /// Requests the run did not send.
+    pub skipped_count: usize,
+    /// Custom headers left off requests because they were invalid.
+    pub invalid_headers: u64,
+    /// Failed requests by the HTTP status they ended with.
+    pub failure_statuses: FailureStatuses,
+    /// Sent requests by the HTTP status they ended with, successes and
+    /// failures alike. Failures without a status are only in `failure_statuses`.
+    pub status_counts: BTreeMap<u16, u64>,
+    /// Retries skipped because the retry budget was exhausted.
+    pub retries_denied: u64,
     /// Average latency in milliseconds.
     pub avg_latency_ms: f64,
+    /// Median latency in milliseconds.
+    pub p50_latency_ms: f64,
+    /// 95th percentile latency in milliseconds.
+    pub p95_latency_ms: f64,
+    /// 99th percentile latency in milliseconds.
+    pub p99_latency_ms: f64,
+    /// Median time to first token of streamed responses, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p50_ttft_ms: Option<f64>,
+    /// 95th percentile time to first token of streamed responses, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p95_ttft_ms: Option<f64>,
     /// Current requests per second.
     pub current_rps: f64,
     /// Overall requests per second.
@@ -165,6 +407,22 @@ pub struct StatsSnapshot {
     pub total_lines: usize,
     /// Progress percentage.
     pub progress: f64,
+    /// Total prompt tokens.
+    pub prompt_tokens: u64,
+    /// Total completion tokens.
+    pub completion_tokens: u64,
+    /// Total estimated cost.
+    pub estimated_cost: f64,
+    /// Current concurrency limit.
+    pub concurrency_limit: usize,
+}
+
+/// Serialize a duration as fractional seconds.
+pub(crate) fn serialize_secs<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
 }
 
 impl StatsSnapshot {
@@ -178,9 +436,11 @@ impl StatsSnapshot {
     }
 
     /// Get the estimated time remaining.
+    #[allow(clippy::cast_precision_loss)]
     pub fn eta(&self) -> Option<Duration> {
         if self.overall_rps > 0.0 && self.total_lines > 0 {
-            let remaining = self.total_lines.saturating_sub(self.total_processed as usize);
+            let processed = usize::try_from(self.total_processed).unwrap_or(usize::MAX);
+            let remaining = self.total_lines.saturating_sub(processed);
             let seconds = remaining as f64 / self.overall_rps;
             Some(Duration::from_secs_f64(seconds))
         } else {
@@ -188,9 +448,9 @@ impl StatsSnapshot {
         }
     }
 
-    /// Format as a human-readable summary.
+    /// Format as a human-readable summary, with the ETA once it is known.
     pub fn summary(&self) -> String {
-        format!(
+        let mut summary = format!(
             "Processed: {}/{} ({:.1}%) | Success: {} | Failed: {} | Avg Latency: {:.1}ms | RPS: {:.0}",
             self.total_processed,
             self.total_lines,
@@ -199,7 +459,202 @@ impl StatsSnapshot {
             self.failure_count,
             self.avg_latency_ms,
             self.current_rps
-        )
+        );
+        if let Some(eta) = self.eta() {
+            let _ = write!(summary, " | ETA: {}", format_eta(eta));
+        }
+        summary
+    }
+}
+
+/// Format a time remaining for display, e.g. `1h 05m`, `4m 10s` or `9s`.
+#[must_use]
+pub fn format_eta(eta: Duration) -> String {
+    let seconds = eta.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, seconds) => format!("{seconds}s"),
+        (0, minutes, seconds) => format!("{minutes}m {seconds:02}s"),
+        (hours, minutes, _) => format!("{hours}h {minutes:02}m"),
+    }
+}
+
+/// Counts failures by the HTTP status they ended with.
+#[derive(Debug, Default)]
+pub struct StatusCounter {
+    by_status: Mutex<HashMap<u16, u64>>,
+    /// Failures without a status, such as timeouts and connection errors.
+    without_status: AtomicU64,
+    /// Requests that failed validation, whether unsent or rejected responses.
+    validation: AtomicU64,
+}
+
+impl StatusCounter {
+    /// Count a request, under its status if it got one.
+    pub fn record(&self, status: Option<u16>) {
+        match status {
+            Some(status) => *self.by_status.lock().entry(status).or_insert(0) += 1,
+            None => {
+                self.without_status.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Count a request that failed validation, apart from those without a status.
+    pub fn record_validation(&self) {
+        self.validation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Get the counts so far.
+    pub fn snapshot(&self) -> FailureStatuses {
+        FailureStatuses {
+            by_status: self
+                .by_status
+                .lock()
+                .iter()
+                .map(|(&status, &count)| (status, count))
+                .collect(),
+            without_status: self.without_status.load(Ordering::Relaxed),
+            validation: self.validation.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Failures by the HTTP status they ended with.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FailureStatuses {
+    /// Failures by status code.
+    pub by_status: BTreeMap<u16, u64>,
+    /// Failures without a status, such as timeouts and connection errors.
+    pub without_status: u64,
+    /// Requests that failed validation, such as unreadable rows and
+    /// responses rejected by the validation rules.
+    pub validation: u64,
+}
+
+impl FailureStatuses {
+    /// Check whether no failures were counted.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.by_status.is_empty() && self.without_status == 0 && self.validation == 0
+    }
+
+    /// Describe the `limit` most frequent statuses, e.g. `429: 120, 500: 30, no status: 4`,
+    /// with validation failures counted as `validation`.
+    #[must_use]
+    pub fn top(&self, limit: usize) -> String {
+        let mut counts: Vec<_> = self
+            .by_status
+            .iter()
+            .map(|(status, &count)| (status.to_string(), count))
+            .collect();
+        if self.without_status > 0 {
+            counts.push(("no status".to_string(), self.without_status));
+        }
+        if self.validation > 0 {
+            counts.push(("validation".to_string(), self.validation));
+        }
+        // Stable, so equal counts stay in status order
+        counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        counts
+            .iter()
+            .take(limit)
+            .map(|(status, count)| format!("{status}: {count}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Successes and retries counted during one second of the window.
+#[derive(Debug, Clone, Copy, Default)]
+struct BudgetBucket {
+    second: u64,
+    successes: u64,
+    retries: u64,
+}
+
+/// Limits retries to a fraction of recent successful requests.
+///
+/// During a broad outage every request fails and would retry, multiplying the
+/// load on endpoints that are already struggling. The budget allows
+/// [`RETRY_BUDGET_MIN_RETRIES`] plus `ratio` retries per success within the
+/// last [`RETRY_BUDGET_WINDOW`]; past that, failures are returned without
+/// retrying.
+#[derive(Debug)]
+pub struct RetryBudget {
+    ratio: f64,
+    start: Instant,
+    /// The current second's bucket and the earlier ones still in the window, oldest first.
+    buckets: Mutex<(BudgetBucket, VecDeque<BudgetBucket>)>,
+    /// Retries refused since the start.
+    denied: AtomicU64,
+}
+
+impl RetryBudget {
+    /// Create a budget allowing `ratio` retries per successful request.
+    #[must_use]
+    pub fn new(ratio: f64) -> Self {
+        Self {
+            ratio,
+            start: Instant::now(),
+            buckets: Mutex::new((BudgetBucket::default(), VecDeque::new())),
+            denied: AtomicU64::new(0),
+        }
+    }
+
+    /// Record a successful request, adding to the budget.
+    pub fn record_success(&self) {
+        self.update(|current, _| current.successes += 1);
+    }
+
+    /// Take a retry from the budget, returning whether one was available.
+    pub fn try_retry(&self) -> bool {
+        let ratio = self.ratio;
+        let allowed = self.update(|current, (successes, retries)| {
+            #[allow(clippy::cast_precision_loss)]
+            let allowed =
+                (retries as f64) < successes as f64 * ratio + RETRY_BUDGET_MIN_RETRIES as f64;
+            if allowed {
+                current.retries += 1;
+            }
+            allowed
+        });
+        if !allowed {
+            self.denied.fetch_add(1, Ordering::Relaxed);
+        }
+        allowed
+    }
+
+    /// Get the number of retries refused since the start.
+    pub fn denied(&self) -> u64 {
+        self.denied.load(Ordering::Relaxed)
+    }
+
+    /// Update the current bucket, given the success and retry totals of the window.
+    fn update<T>(&self, f: impl FnOnce(&mut BudgetBucket, (u64, u64)) -> T) -> T {
+        let second = self.start.elapsed().as_secs();
+        let mut buckets = self.buckets.lock();
+        let (current, past) = &mut *buckets;
+
+        // Start a new bucket each second, dropping those that have left the window
+        if current.second != second {
+            past.push_back(std::mem::take(current));
+            current.second = second;
+        }
+        let window = RETRY_BUDGET_WINDOW.as_secs();
+        while past
+            .front()
+            .is_some_and(|bucket| bucket.second + window <= second)
+        {
+            past.pop_front();
+        }
+
+        let totals = past
+            .iter()
+            .chain([&*current])
+            .fold((0, 0), |(successes, retries), bucket| {
+                (successes + bucket.successes, retries + bucket.retries)
+            });
+        f(current, totals)
     }
 }
 
@@ -214,13 +669,97 @@ mod tests {
 
         tracker.record_success(Duration::from_millis(50));
         tracker.record_success(Duration::from_millis(100));
-        tracker.record_failure();
+        tracker.record_failure(None);
 
         let snapshot = tracker.snapshot();
         assert_eq!(snapshot.total_processed, 3);
         assert_eq!(snapshot.success_count, 2);
         assert_eq!(snapshot.failure_count, 1);
         assert_eq!(snapshot.avg_latency_ms, 75.0);
+        assert!(snapshot.p50_latency_ms >= 50.0 && snapshot.p50_latency_ms < 53.0);
+        assert!(snapshot.p99_latency_ms >= 100.0 && snapshot.p99_latency_ms < 105.0);
+        assert_eq!(snapshot.p50_ttft_ms, None);
+        assert_eq!(snapshot.prompt_tokens, 0);
+
+        tracker.record_time_to_first_token(Duration::from_millis(20));
+        let p50_ttft_ms = tracker.snapshot().p50_ttft_ms.unwrap();
+        assert!((20.0..22.0).contains(&p50_ttft_ms));
+
+        let usage = TokenUsage {
+            prompt_tokens: 100,
+            completion_tokens: 20,
+        };
+        tracker.record_usage(usage, 0.25);
+        tracker.record_usage(usage, 0.5);
+        let snapshot = tracker.snapshot();
+        assert_eq!(snapshot.prompt_tokens, 200);
+        assert_eq!(snapshot.completion_tokens, 40);
+        assert!((snapshot.estimated_cost - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_failure_statuses() {
+        let tracker = StatsTracker::new();
+        for status in [Some(500), Some(429), None, Some(429), Some(400), Some(429)] {
+            tracker.record_failure(status);
+        }
+        let statuses = tracker.snapshot().failure_statuses;
+        assert_eq!(statuses.by_status[&429], 3);
+        assert_eq!(statuses.without_status, 1);
+        assert_eq!(statuses.top(3), "429: 3, 400: 1, 500: 1");
+        assert_eq!(statuses.top(10), "429: 3, 400: 1, 500: 1, no status: 1");
+
+        // Validation failures are counted apart from those without a status
+        tracker.record_validation_failure();
+        let statuses = tracker.snapshot().failure_statuses;
+        assert_eq!((statuses.without_status, statuses.validation), (1, 1));
+        assert!(statuses.top(10).ends_with("no status: 1, validation: 1"));
+        assert_eq!(tracker.snapshot().failure_count, 7);
+
+        let json = serde_json::to_value(&statuses).unwrap();
+        assert_eq!(json["by_status"]["429"], 3);
+        assert!(StatsTracker::new().snapshot().failure_statuses.is_empty());
+
+        // Status counts take in successes too, but not failures without a status
+        tracker.record_success(Duration::from_millis(10));
+        tracker.record_success_status(200);
+        let counts = tracker.snapshot().status_counts;
+        assert_eq!(counts.values().sum::<u64>(), 6);
+        assert_eq!((counts[&200], counts[&429]), (1, 3));
+    }
+
+    #[test]
+    fn test_eta() {
+        let tracker = StatsTracker::new();
+        tracker.record_success(Duration::from_millis(10));
+        assert_eq!(tracker.snapshot().eta(), None);
+        assert!(!tracker.snapshot().summary().contains("ETA"));
+
+        tracker.set_total_lines(1_000_000);
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(tracker.snapshot().summary().contains("ETA: "));
+
+        assert_eq!(format_eta(Duration::from_secs(9)), "9s");
+        assert_eq!(format_eta(Duration::from_secs(250)), "4m 10s");
+        assert_eq!(format_eta(Duration::from_secs(3900)), "1h 05m");
+    }
+
+    #[test]
+    fn test_rps_window() {
+        let tracker = StatsTracker::new();
+        for _ in 0..4 {
+            tracker.record_success(Duration::from_millis(10));
+        }
+        assert!((tracker.requests_per_second() - 4.0).abs() < 1e-9);
+
+        // A window other than a second is scaled back to a rate per second
+        let tracker = StatsTracker::new().with_rps_window(Duration::from_millis(200));
+        for _ in 0..10 {
+            tracker.record_failure(None);
+        }
+        assert!((tracker.requests_per_second() - 50.0).abs() < 1e-9);
+        std::thread::sleep(Duration::from_millis(250));
+        assert!(tracker.requests_per_second().abs() < 1e-9);
     }
 
     #[test]
@@ -231,10 +770,26 @@ mod tests {
             tracker.record_success(Duration::from_millis(10));
         }
         for _ in 0..2 {
-            tracker.record_failure();
+            tracker.record_failure(None);
         }
 
         let snapshot = tracker.snapshot();
         assert_eq!(snapshot.success_rate(), 80.0);
     }
+
+    #[test]
+    fn test_retry_budget() {
+        let budget = RetryBudget::new(0.5);
+        let retries = |count| (0..count).filter(|_| budget.try_retry()).count();
+
+        // The minimum is available before any success
+        assert_eq!(retries(15), 10);
+        assert_eq!(budget.denied(), 5);
+
+        // Each success then adds half a retry
+        for _ in 0..10 {
+            budget.record_success();
+        }
+        assert_eq!(retries(10), 5);
+    }
 }
@@ -0,0 +1,124 @@
+//! Success criteria for response bodies.
+//!
+//! A 2xx status doesn't always mean a request worked, as some endpoints
+//! answer 200 with an error object. Validation rules check fields of the
+//! response body, and a body that breaks any rule is retried like any other
+//! transient failure.
+
+use crate::config::ValidationRule;
+use crate::error::Result;
+use crate::projection::to_pointer;
+use serde_json::Value;
+
+/// A validation rule with its path resolved to a JSON pointer.
+#[derive(Debug, Clone)]
+struct Check {
+    path: String,
+    pointer: String,
+    condition: Condition,
+}
+
+#[derive(Debug, Clone)]
+enum Condition {
+    Present,
+    Absent,
+    NotEqual(Value),
+}
+
+/// Checks response bodies against the configured rules.
+#[derive(Debug, Clone)]
+pub struct ResponseValidator {
+    checks: Vec<Check>,
+}
+
+impl ResponseValidator {
+    /// Build a validator from rules.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlazeError::InvalidConfig`](crate::BlazeError::InvalidConfig)
+    /// if a path is malformed.
+    pub fn new(rules: &[ValidationRule]) -> Result<Self> {
+        let checks = rules
+            .iter()
+            .map(|rule| {
+                let (path, condition) = match rule {
+                    ValidationRule::Present { path } => (path, Condition::Present),
+                    ValidationRule::Absent { path } => (path, Condition::Absent),
+                    ValidationRule::NotEqual { path, value } => {
+                        (path, Condition::NotEqual(value.clone()))
+                    }
+                };
+                Ok(Check {
+                    path: path.clone(),
+                    pointer: to_pointer(path)?,
+                    condition,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { checks })
+    }
+
+    /// Check a response body against every rule.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the first rule the body breaks.
+    pub fn check(&self, body: &Value) -> std::result::Result<(), String> {
+        for check in &self.checks {
+            let field = body.pointer(&check.pointer);
+            match (&check.condition, field) {
+                (Condition::Present, None) => {
+                    return Err(format!("field '{}' is missing", check.path));
+                }
+                (Condition::Absent, Some(_)) => {
+                    return Err(format!("field '{}' is present", check.path));
+                }
+                (Condition::NotEqual(value), Some(field)) if field == value => {
+                    return Err(format!("field '{}' equals {value}", check.path));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validation_rules() {
+        let rules: Vec<ValidationRule> = serde_json::from_value(json!([
+            {"rule": "present", "path": "$.choices[0].message.content"},
+            {"rule": "absent", "path": "error"},
+            {"rule": "not_equal", "path": "status", "value": "failed"}
+        ]))
+        .unwrap();
+        let validator = ResponseValidator::new(&rules).unwrap();
+
+        let ok = json!({"choices": [{"message": {"content": "Paris"}}], "status": "done"});
+        assert_eq!(validator.check(&ok), Ok(()));
+        assert_eq!(
+            validator.check(&json!({"choices": []})),
+            Err("field '$.choices[0].message.content' is missing".to_string())
+        );
+        let soft_error = json!({"choices": [{"message": {"content": ""}}], "error": "overloaded"});
+        assert_eq!(
+            validator.check(&soft_error),
+            Err("field 'error' is present".to_string())
+        );
+        let failed = json!({"choices": [{"message": {"content": ""}}], "status": "failed"});
+        assert_eq!(
+            validator.check(&failed),
+            Err("field 'status' equals \"failed\"".to_string())
+        );
+
+        let bad = [ValidationRule::Absent {
+            path: "a..b".to_string(),
+        }];
+        assert!(ResponseValidator::new(&bad).is_err());
+    }
+}
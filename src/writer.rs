@@ -0,0 +1,806 @@
+//! Dedicated writer task for result files.
+//!
+//! Workers send serialized lines over a bounded channel, and a single task
+//! owns the output, error and checkpoint files. No file lock is held across
+//! an await, and a full channel makes workers wait until the disk catches up.
+//! Periodic flushes happen on the writer task too, so workers never wait on
+//! them beyond a fuller channel, and so does rolling over to a new numbered
+//! file when a result file is rotated.
+//!
+//! Output is written as lines for JSONL and CSV files, and as rows for
+//! Parquet files, which the writer batches into row groups.
+
+use crate::checkpoint::{self, Checkpoint};
+use crate::compression::{self, Compression, FileWriter};
+use crate::error::BlazeError;
+#[cfg(feature = "parquet")]
+use crate::table::{ParquetFile, Row};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs::{File, OpenOptions};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{Interval, MissedTickBehavior};
+use tracing::{info, warn};
+
+/// Lines buffered between workers and the writer task.
+const CHANNEL_CAPACITY: usize = 4096;
+
+/// A line for one of the result files.
+#[derive(Debug)]
+pub enum Record {
+    /// A successful response, for the output file.
+    Output {
+        /// The response as written, or `None` for one left out of sampled
+        /// output, which is only recorded in the checkpoint.
+        output: Option<Output>,
+        /// Input line it answers, recorded in the checkpoint.
+        line_number: usize,
+    },
+    /// A failed request, for the errors file.
+    Error {
+        /// Serialized error.
+        line: String,
+    },
+}
+
+/// A successful response as written to the output file.
+#[derive(Debug)]
+pub enum Output {
+    /// A serialized line, for JSONL and CSV files.
+    Line(String),
+    /// A row, for Parquet files.
+    #[cfg(feature = "parquet")]
+    Row(Row),
+}
+
+/// When the writer flushes its files before the end of the run.
+///
+/// Without a flush, results sit in the write buffers until they fill, and a
+/// killed run loses them. Either limit triggers a flush; with neither, files
+/// are only flushed when they are finished.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlushPolicy {
+    /// Flush after this many records.
+    pub every: Option<usize>,
+    /// Flush at this interval, if anything was written since the last flush.
+    pub interval: Option<Duration>,
+}
+
+/// When result files roll over to a new numbered file.
+///
+/// Either limit starts a new file; with neither, each result file is a
+/// single file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RotatePolicy {
+    /// Start a new file once the current one holds this many bytes, counted before compression.
+    pub max_bytes: Option<u64>,
+    /// Start a new file once the current one holds this many lines.
+    pub max_lines: Option<u64>,
+}
+
+impl RotatePolicy {
+    /// Check whether files are rotated at all.
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes.is_some() || self.max_lines.is_some()
+    }
+}
+
+/// A result file, rolling over to numbered files if it is rotated.
+///
+/// A rotated `results.jsonl` is written as `results.000.jsonl`,
+/// `results.001.jsonl` and so on, each compressed on its own if the name
+/// asks for it. Lines are never split across files, and the next file is
+/// created before the full one is finished, so writes never go nowhere.
+#[derive(Debug)]
+pub struct ResultFile {
+    writer: FileWriter,
+    /// Path of the file being written.
+    path: PathBuf,
+    /// Path the numbered files are named after.
+    base: PathBuf,
+    rotate: RotatePolicy,
+    /// Number of the file being written.
+    index: usize,
+    /// Bytes written to the file, before compression.
+    bytes: u64,
+    /// Lines written to the file.
+    lines: u64,
+    /// Line written at the start of each file, such as a CSV header.
+    header: Option<String>,
+    /// Whether the file being written already starts with the header.
+    headed: bool,
+}
+
+impl ResultFile {
+    /// Create the file at `path`, or its first numbered file if it is rotated.
+    ///
+    /// Numbered files left by an earlier run are removed, the way a file
+    /// that is not rotated is truncated.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file cannot be created or removed.
+    pub async fn create(path: &Path, rotate: RotatePolicy) -> io::Result<Self> {
+        if !rotate.is_enabled() {
+            let file = File::create(path).await?;
+            return Ok(Self::new(file, path, rotate, 0));
+        }
+        for (_, stale) in numbered_files(path)? {
+            tokio::fs::remove_file(stale).await?;
+        }
+        Self::create_numbered(path, rotate, 0).await
+    }
+
+    /// Open the file at `path` to append to it, or start the numbered file
+    /// after the last one if it is rotated.
+    ///
+    /// A line left partly written at the end of the file by a killed run is
+    /// terminated first, as it is in the last numbered file. Numbered files
+    /// of the earlier run are otherwise left as they are.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file cannot be opened, read or created, or if
+    /// the last compressed numbered file was left unfinished: its stream
+    /// can't be ended afterwards, and its lines would be lost on decoding.
+    pub async fn append(path: &Path, rotate: RotatePolicy) -> io::Result<Self> {
+        if !rotate.is_enabled() {
+            if path.exists() {
+                checkpoint::terminate_partial_line(path)?;
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await?;
+            let mut result = Self::new(file, path, rotate, 0);
+            // A file with lines already has its header
+            result.headed = file_len(path).await > 0;
+            return Ok(result);
+        }
+        let last = numbered_files(path)?
+            .into_iter()
+            .max_by_key(|&(index, _)| index);
+        if let Some((_, last)) = &last {
+            if Compression::from_path(last).is_compressed() {
+                compression::check_complete(last).await.map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{} was not finished by the earlier run: {e}",
+                            last.display()
+                        ),
+                    )
+                })?;
+            } else {
+                checkpoint::terminate_partial_line(last)?;
+            }
+        }
+        let next = last.map_or(0, |(index, _)| index + 1);
+        Self::create_numbered(path, rotate, next).await
+    }
+
+    /// Create the numbered file `index` of `base`.
+    async fn create_numbered(base: &Path, rotate: RotatePolicy, index: usize) -> io::Result<Self> {
+        let path = numbered_path(base, index);
+        let file = File::create(&path).await?;
+        let mut result = Self::new(file, base, rotate, index);
+        result.path = path;
+        Ok(result)
+    }
+
+    fn new(file: File, base: &Path, rotate: RotatePolicy, index: usize) -> Self {
+        Self {
+            writer: FileWriter::new(file, base),
+            path: base.to_path_buf(),
+            base: base.to_path_buf(),
+            rotate,
+            index,
+            bytes: 0,
+            lines: 0,
+            header: None,
+            headed: false,
+        }
+    }
+
+    /// Start each file with `header`, unless it is appended to and already has lines.
+    #[must_use]
+    pub fn with_header(mut self, header: String) -> Self {
+        self.header = Some(header);
+        self
+    }
+
+    /// Get the path of the file being written.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write a line, first moving on to the next numbered file if this one is full.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the line cannot be written, or the next file
+    /// cannot be created.
+    pub async fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.is_full() {
+            self.next_file().await?;
+        }
+        if !self.headed {
+            self.headed = true;
+            if let Some(header) = &self.header {
+                let header = format!("{header}\n");
+                self.writer.write_all(header.as_bytes()).await?;
+                self.bytes += header.len() as u64;
+            }
+        }
+        let line = format!("{line}\n");
+        self.writer.write_all(line.as_bytes()).await?;
+        self.bytes += line.len() as u64;
+        self.lines += 1;
+        Ok(())
+    }
+
+    /// Check whether the file has reached a rotation limit.
+    fn is_full(&self) -> bool {
+        let RotatePolicy {
+            max_bytes,
+            max_lines,
+        } = self.rotate;
+        max_bytes.is_some_and(|max| self.bytes >= max)
+            || max_lines.is_some_and(|max| self.lines >= max)
+    }
+
+    /// Switch to the next numbered file, then finish the full one.
+    async fn next_file(&mut self) -> io::Result<()> {
+        let path = numbered_path(&self.base, self.index + 1);
+        let file = File::create(&path).await?;
+        let mut full = std::mem::replace(&mut self.writer, FileWriter::new(file, &self.base));
+        let full_path = std::mem::replace(&mut self.path, path);
+        self.index += 1;
+        self.bytes = 0;
+        self.lines = 0;
+        self.headed = false;
+        info!(
+            path = %full_path.display(),
+            next = %self.path.display(),
+            "Result file full, starting the next one"
+        );
+        full.finish().await
+    }
+
+    /// Flush buffered data to the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data cannot be flushed.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush().await
+    }
+
+    /// Flush buffered data and sync it to disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data cannot be flushed or synced.
+    pub async fn sync(&mut self) -> io::Result<()> {
+        self.writer.sync().await
+    }
+
+    /// Finish the file being written, ending any compressed stream.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data cannot be flushed or synced.
+    pub async fn finish(&mut self) -> io::Result<()> {
+        self.writer.finish().await
+    }
+}
+
+/// Report output of the wrong kind for the file at `path`.
+#[cfg(feature = "parquet")]
+fn mismatched(path: &Path, output: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} can't take {output}", path.display()),
+    )
+}
+
+/// Get the length of the file at `path`, or 0 if it can't be read.
+async fn file_len(path: &Path) -> u64 {
+    tokio::fs::metadata(path)
+        .await
+        .map_or(0, |metadata| metadata.len())
+}
+
+/// The file successful responses are written to.
+#[derive(Debug)]
+pub enum OutputFile {
+    /// A JSONL or CSV file.
+    Lines(ResultFile),
+    /// A Parquet file.
+    #[cfg(feature = "parquet")]
+    Parquet(ParquetFile),
+}
+
+impl OutputFile {
+    /// Get the path of the file being written.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Lines(file) => file.path(),
+            #[cfg(feature = "parquet")]
+            Self::Parquet(file) => file.path(),
+        }
+    }
+
+    /// Write a response.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the response cannot be written, or if it is a row
+    /// for a file of lines or a line for a Parquet file.
+    pub async fn write(&mut self, output: Output) -> io::Result<()> {
+        match (self, output) {
+            (Self::Lines(file), Output::Line(line)) => file.write_line(&line).await,
+            #[cfg(feature = "parquet")]
+            (Self::Parquet(file), Output::Row(row)) => file.write(row).await,
+            #[cfg(feature = "parquet")]
+            (Self::Lines(file), Output::Row(_)) => Err(mismatched(file.path(), "a row")),
+            #[cfg(feature = "parquet")]
+            (Self::Parquet(file), Output::Line(_)) => Err(mismatched(file.path(), "a line")),
+        }
+    }
+
+    /// Flush buffered data to the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data cannot be flushed.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Lines(file) => file.flush().await,
+            #[cfg(feature = "parquet")]
+            Self::Parquet(file) => file.flush().await,
+        }
+    }
+
+    /// Flush buffered data and sync it to disk.
+    ///
+    /// Parquet rows only reach the disk a row group at a time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data cannot be flushed or synced.
+    pub async fn sync(&mut self) -> io::Result<()> {
+        match self {
+            Self::Lines(file) => file.sync().await,
+            #[cfg(feature = "parquet")]
+            Self::Parquet(file) => file.flush().await,
+        }
+    }
+
+    /// Finish the file, ending any compressed stream or writing the Parquet footer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data cannot be written.
+    pub async fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Self::Lines(file) => file.finish().await,
+            #[cfg(feature = "parquet")]
+            Self::Parquet(file) => file.finish().await,
+        }
+    }
+}
+
+/// Split a file name into the parts a file number goes between: `results`
+/// and `.jsonl.gz` for `results.jsonl.gz`.
+fn split_name(base: &Path) -> (String, String) {
+    let name = base
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let compressed = Compression::from_path(base).is_compressed();
+    let (rest, compression) = match name.rsplit_once('.') {
+        Some((rest, ext)) if compressed => (rest.to_string(), format!(".{ext}")),
+        _ => (name.clone(), String::new()),
+    };
+    match rest.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem.to_string(), format!(".{ext}{compression}")),
+        _ => (rest, compression),
+    }
+}
+
+/// Get the path of the numbered file `index` of `base`.
+fn numbered_path(base: &Path, index: usize) -> PathBuf {
+    let (stem, suffix) = split_name(base);
+    base.with_file_name(format!("{stem}.{index:03}{suffix}"))
+}
+
+/// Find the numbered files of `base` that exist, with their numbers.
+fn numbered_files(base: &Path) -> io::Result<Vec<(usize, PathBuf)>> {
+    let (stem, suffix) = split_name(base);
+    let dir = match base.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let number = name
+            .strip_prefix(stem.as_str())
+            .and_then(|rest| rest.strip_prefix('.'))
+            .and_then(|rest| rest.strip_suffix(suffix.as_str()))
+            .filter(|number| number.len() >= 3 && number.bytes().all(|b| b.is_ascii_digit()));
+        if let Some(index) = number.and_then(|number| number.parse().ok()) {
+            files.push((index, path));
+        }
+    }
+    Ok(files)
+}
+
+/// Handle to the task writing result files.
+#[derive(Debug)]
+pub struct ResultWriter {
+    tx: mpsc::Sender<Record>,
+    task: JoinHandle<()>,
+}
+
+impl ResultWriter {
+    /// Spawn the writer task.
+    ///
+    /// Successful responses are dropped when `output` is `None`.
+    #[must_use]
+    pub fn spawn(
+        output: Option<OutputFile>,
+        errors: ResultFile,
+        checkpoint: Option<Checkpoint>,
+        flush: FlushPolicy,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let task = tokio::spawn(write_records(rx, output, errors, checkpoint, flush));
+        Self { tx, task }
+    }
+
+    /// Queue a record, waiting while the channel is full.
+    pub async fn send(&self, record: Record) {
+        if self.tx.send(record).await.is_err() {
+            warn!("Result writer has stopped, dropping record");
+        }
+    }
+
+    /// Write all queued records, then flush and sync every file.
+    ///
+    /// Compressed streams are ended, so no more records can be written.
+    pub async fn finish(self) {
+        drop(self.tx);
+        if let Err(e) = self.task.await {
+            warn!("Result writer failed: {}", e);
+        }
+    }
+}
+
+/// Write records until every sender is gone, then finish the files.
+async fn write_records(
+    mut rx: mpsc::Receiver<Record>,
+    mut output: Option<OutputFile>,
+    mut errors: ResultFile,
+    mut checkpoint: Option<Checkpoint>,
+    flush: FlushPolicy,
+) {
+    let mut ticker = flush.interval.map(|period| {
+        let mut ticker = tokio::time::interval(period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        ticker
+    });
+    let mut unflushed = 0;
+
+    loop {
+        let record = tokio::select! {
+            record = rx.recv() => match record {
+                Some(record) => record,
+                None => break,
+            },
+            () = tick(&mut ticker) => {
+                if unflushed > 0 {
+                    flush_files(&mut output, &mut errors).await;
+                    unflushed = 0;
+                }
+                continue;
+            }
+        };
+        match record {
+            Record::Output {
+                output: response,
+                line_number,
+            } => {
+                let Some(file) = &mut output else {
+                    continue;
+                };
+                if let Some(response) = response {
+                    if let Err(e) = file.write(response).await {
+                        warn!(path = %file.path().display(), "Failed to write output file: {}", e);
+                        // A result that isn't written stays out of the checkpoint,
+                        // so a resumed run sends its request again
+                        continue;
+                    }
+                }
+
+                if let Some(checkpoint) = &mut checkpoint {
+                    let synced = match checkpoint.record(line_number) {
+                        // The output must be on disk before the checkpoint claims it
+                        Ok(true) => match file.sync().await {
+                            Ok(()) => checkpoint.sync(),
+                            Err(e) => Err(BlazeError::OutputFileWrite {
+                                path: file.path().to_path_buf(),
+                                source: e,
+                            }),
+                        },
+                        Ok(false) => Ok(()),
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = synced {
+                        warn!("Failed to update checkpoint: {}", e);
+                    }
+                }
+            }
+            Record::Error { line } => {
+                if let Err(e) = errors.write_line(&line).await {
+                    warn!(path = %errors.path().display(), "Failed to write errors file: {}", e);
+                }
+            }
+        }
+        unflushed += 1;
+        if flush.every.is_some_and(|every| unflushed >= every) {
+            flush_files(&mut output, &mut errors).await;
+            unflushed = 0;
+        }
+    }
+
+    // Flush writers, ending any compressed streams
+    if let Some(file) = &mut output {
+        file.finish().await.ok();
+    }
+    errors.finish().await.ok();
+    if let Some(checkpoint) = &mut checkpoint {
+        if let Err(e) = checkpoint.sync() {
+            warn!("Failed to sync checkpoint: {}", e);
+        }
+    }
+}
+
+/// Wait for the next flush tick, or forever without a flush interval.
+async fn tick(ticker: &mut Option<Interval>) {
+    match ticker {
+        Some(ticker) => {
+            ticker.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Flush the output and errors files, warning if either fails.
+async fn flush_files(output: &mut Option<OutputFile>, errors: &mut ResultFile) {
+    if let Some(file) = output {
+        if let Err(e) = file.flush().await {
+            warn!(path = %file.path().display(), "Failed to flush output file: {}", e);
+        }
+    }
+    if let Err(e) = errors.flush().await {
+        warn!("Failed to flush errors file: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_result_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("requests.jsonl");
+        std::fs::write(&input, "{}\n{}\n").unwrap();
+        let output = dir.path().join("results.jsonl.gz");
+        let errors = dir.path().join("errors.jsonl");
+        let checkpoint_path = Checkpoint::path_for(&output);
+
+        let writer = ResultWriter::spawn(
+            Some(OutputFile::Lines(
+                ResultFile::create(&output, RotatePolicy::default())
+                    .await
+                    .unwrap(),
+            )),
+            ResultFile::create(&errors, RotatePolicy::default())
+                .await
+                .unwrap(),
+            Some(Checkpoint::create(&checkpoint_path, &input).unwrap()),
+            FlushPolicy::default(),
+        );
+        for line_number in 1..=2 {
+            writer
+                .send(Record::Output {
+                    output: Some(Output::Line(format!("{{\"n\":{line_number}}}"))),
+                    line_number,
+                })
+                .await;
+        }
+        writer
+            .send(Record::Error {
+                line: "{\"error\":\"boom\"}".to_string(),
+            })
+            .await;
+        writer.finish().await;
+
+        let mut lines = crate::compression::open_reader(&output)
+            .await
+            .map(tokio::io::AsyncBufReadExt::lines)
+            .unwrap();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "{\"n\":1}");
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "{\"n\":2}");
+        assert_eq!(
+            std::fs::read_to_string(&errors).unwrap(),
+            "{\"error\":\"boom\"}\n"
+        );
+        let (_, completed) = Checkpoint::resume(&checkpoint_path, &input).unwrap();
+        assert_eq!(completed.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_periodic_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("results.jsonl");
+        let errors = dir.path().join("errors.jsonl");
+        let writer = ResultWriter::spawn(
+            Some(OutputFile::Lines(
+                ResultFile::create(&output, RotatePolicy::default())
+                    .await
+                    .unwrap(),
+            )),
+            ResultFile::create(&errors, RotatePolicy::default())
+                .await
+                .unwrap(),
+            None,
+            FlushPolicy {
+                every: Some(2),
+                interval: Some(Duration::from_millis(20)),
+            },
+        );
+
+        // Both limits flush before the files are finished
+        for line_number in 1..=2 {
+            writer
+                .send(Record::Output {
+                    output: Some(Output::Line(format!("{{\"n\":{line_number}}}"))),
+                    line_number,
+                })
+                .await;
+        }
+        writer
+            .send(Record::Error {
+                line: "{\"error\":\"boom\"}".to_string(),
+            })
+            .await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "{\"n\":1}\n{\"n\":2}\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&errors).unwrap(),
+            "{\"error\":\"boom\"}\n"
+        );
+        writer.finish().await;
+    }
+
+    #[tokio::test]
+    async fn test_rotate_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("results.jsonl.gz");
+        let errors = dir.path().join("errors.jsonl");
+        std::fs::write(dir.path().join("errors.007.jsonl"), "stale\n").unwrap();
+
+        let by_lines = RotatePolicy {
+            max_bytes: None,
+            max_lines: Some(2),
+        };
+        let by_size = RotatePolicy {
+            max_bytes: Some(10),
+            max_lines: None,
+        };
+        let writer = ResultWriter::spawn(
+            Some(OutputFile::Lines(
+                ResultFile::create(&output, by_lines).await.unwrap(),
+            )),
+            ResultFile::create(&errors, by_size).await.unwrap(),
+            None,
+            FlushPolicy::default(),
+        );
+        for n in 1..=5 {
+            let line = format!("{{\"n\":{n}}}");
+            writer
+                .send(Record::Output {
+                    output: Some(Output::Line(line.clone())),
+                    line_number: n,
+                })
+                .await;
+            writer.send(Record::Error { line }).await;
+        }
+        writer.finish().await;
+
+        // Each numbered file is a complete compressed stream
+        let read = |name: &str| {
+            let path = dir.path().join(name);
+            async move {
+                let mut lines = crate::compression::open_reader(&path)
+                    .await
+                    .map(tokio::io::AsyncBufReadExt::lines)
+                    .unwrap();
+                let mut read = Vec::new();
+                while let Some(line) = lines.next_line().await.unwrap() {
+                    read.push(line);
+                }
+                read
+            }
+        };
+        assert_eq!(
+            read("results.000.jsonl.gz").await,
+            ["{\"n\":1}", "{\"n\":2}"]
+        );
+        assert_eq!(read("results.002.jsonl.gz").await, ["{\"n\":5}"]);
+        assert!(!output.exists());
+
+        // Lines of 8 bytes fill 10 bytes after two, and stale files are gone
+        let mut names: Vec<_> = numbered_files(&errors)
+            .unwrap()
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect();
+        names.sort_unstable();
+        assert_eq!(names, [0, 1, 2]);
+        assert_eq!(read("errors.001.jsonl").await, ["{\"n\":3}", "{\"n\":4}"]);
+
+        // Appending starts after the last numbered file
+        let appended = ResultFile::append(&errors, by_size).await.unwrap();
+        assert_eq!(appended.path(), dir.path().join("errors.003.jsonl"));
+
+        // A line left half written in the last file is terminated first
+        std::fs::write(appended.path(), "{\"n\":6").unwrap();
+        let appended = ResultFile::append(&errors, by_size).await.unwrap();
+        assert_eq!(appended.path(), dir.path().join("errors.004.jsonl"));
+        assert_eq!(read("errors.003.jsonl").await, ["{\"n\":6"]);
+        assert!(std::fs::read_to_string(dir.path().join("errors.003.jsonl"))
+            .unwrap()
+            .ends_with('\n'));
+
+        // A complete compressed file is appended after, but not an unfinished one
+        let mut appended = ResultFile::append(&output, by_lines).await.unwrap();
+        assert_eq!(appended.path(), dir.path().join("results.003.jsonl.gz"));
+        appended.write_line("{\"n\":6}").await.unwrap();
+        appended.flush().await.unwrap();
+        let error = ResultFile::append(&output, by_lines).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        appended.finish().await.unwrap();
+        assert!(ResultFile::append(&output, by_lines).await.is_ok());
+    }
+
+    #[test]
+    fn test_numbered_path() {
+        let cases = [
+            ("out/results.jsonl", "out/results.004.jsonl"),
+            ("results.jsonl.zst", "results.004.jsonl.zst"),
+            ("results.v2.jsonl", "results.v2.004.jsonl"),
+            ("results", "results.004"),
+            ("results.gz", "results.004.gz"),
+        ];
+        for (base, numbered) in cases {
+            assert_eq!(numbered_path(Path::new(base), 4), Path::new(numbered));
+        }
+    }
+}
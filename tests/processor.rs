@@ -0,0 +1,780 @@
+//! End-to-end runs of the processor against mock endpoints.
+
+use blaze_api::{
+    Config, EndpointConfig, JitterKind, LoadBalanceStrategy, OutputFormat, ProcessEvent,
+    ProcessingConfig, Processor, RequestConfig, RetryConfig,
+};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tempfile::TempDir;
+use wiremock::matchers::{any, body_string_contains};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// A config for the given endpoints that retries quickly and never requeues.
+fn config(urls: &[String]) -> Config {
+    Config {
+        endpoints: urls
+            .iter()
+            .map(|url| EndpointConfig {
+                url: url.clone(),
+                ..Default::default()
+            })
+            .collect(),
+        retry: RetryConfig {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+            jitter: JitterKind::None,
+            ..Default::default()
+        },
+        processing: ProcessingConfig {
+            requeue: false,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Write one request per prompt to an input file.
+fn write_input(dir: &TempDir, prompts: &[&str]) -> PathBuf {
+    let path = dir.path().join("requests.jsonl");
+    let lines: Vec<_> = prompts
+        .iter()
+        .map(|prompt| json!({ "input": prompt }).to_string())
+        .collect();
+    std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+    path
+}
+
+/// Read a JSONL result file, ordered by input.
+fn read_lines(path: &Path) -> Vec<Value> {
+    let mut lines: Vec<Value> = std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    lines.sort_by_key(|line| line["input"].as_str().unwrap_or_default().to_string());
+    lines
+}
+
+fn completion() -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "choices": [{ "message": { "role": "assistant", "content": "Hi" } }]
+    }))
+}
+
+#[tokio::test]
+async fn test_retries_and_failures() {
+    let server = MockServer::start().await;
+    // Mocks mounted first take precedence
+    Mock::given(body_string_contains("bad"))
+        .respond_with(ResponseTemplate::new(400).set_body_string("bad request"))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(body_string_contains("broken"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(3)
+        .mount(&server)
+        .await;
+    Mock::given(body_string_contains("flaky"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(2)
+        .expect(2)
+        .mount(&server)
+        .await;
+    Mock::given(any())
+        .respond_with(completion())
+        .mount(&server)
+        .await;
+
+    let dir = TempDir::new().unwrap();
+    let input = write_input(&dir, &["ok", "flaky", "broken", "bad"]);
+    let (output, errors) = (dir.path().join("out.jsonl"), dir.path().join("err.jsonl"));
+    let processor = Processor::new(config(&[server.uri()])).unwrap();
+    let result = processor
+        .process_file(input, Some(output.clone()), errors.clone(), false)
+        .await
+        .unwrap();
+    assert_eq!(result.success_count, 2);
+    assert_eq!(result.failure_count, 2);
+    assert_eq!(
+        result.status_counts.into_iter().collect::<Vec<_>>(),
+        [(200, 2), (400, 1), (500, 1)]
+    );
+    assert_eq!(result.endpoints[0].success_count, 2);
+
+    // A 500 is retried until it succeeds
+    let output = read_lines(&output);
+    assert_eq!(output.len(), 2);
+    assert_eq!(output[0]["input"], "flaky");
+    assert_eq!(output[0]["metadata"]["attempts"], 3);
+    assert_eq!(
+        output[0]["response"]["choices"][0]["message"]["content"],
+        "Hi"
+    );
+    assert_eq!(output[1]["input"], "ok");
+    assert_eq!(output[1]["metadata"]["attempts"], 1);
+
+    // Persistent 500s use up every attempt, a 400 fails on the first
+    let errors = read_lines(&errors);
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0]["input"], "bad");
+    assert_eq!(errors[0]["status_code"], 400);
+    assert_eq!(errors[0]["attempts"], 1);
+    assert_eq!(errors[0]["failure_class"], "permanent");
+    assert_eq!(errors[0]["error_kind"], "http4xx");
+    assert_eq!(errors[1]["input"], "broken");
+    assert_eq!(errors[1]["status_code"], 500);
+    assert_eq!(errors[1]["attempts"], 3);
+    assert_eq!(errors[1]["failure_class"], "transient");
+    assert_eq!(errors[1]["error_kind"], "http5xx");
+
+    server.verify().await;
+}
+
+#[tokio::test]
+async fn test_requeue() {
+    let server = MockServer::start().await;
+    // Fails all three attempts of the first pass, then succeeds in the second
+    Mock::given(body_string_contains("flaky"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(3)
+        .expect(3)
+        .mount(&server)
+        .await;
+    Mock::given(any())
+        .respond_with(completion())
+        .mount(&server)
+        .await;
+
+    let dir = TempDir::new().unwrap();
+    let input = write_input(&dir, &["ok", "flaky"]);
+    let (output, errors) = (dir.path().join("out.jsonl"), dir.path().join("err.jsonl"));
+    let mut config = config(&[server.uri()]);
+    config.processing.requeue = true;
+    let processor = Processor::new(config).unwrap();
+    let result = processor
+        .process_file(input, Some(output.clone()), errors.clone(), false)
+        .await
+        .unwrap();
+    assert_eq!(result.success_count, 2);
+    assert_eq!(result.requeued_count, 1);
+    assert_eq!(read_lines(&output).len(), 2);
+    assert!(read_lines(&errors).is_empty());
+
+    server.verify().await;
+}
+
+#[tokio::test]
+async fn test_load_balancing() {
+    let (a, b) = (MockServer::start().await, MockServer::start().await);
+    for server in [&a, &b] {
+        Mock::given(any())
+            .respond_with(completion())
+            .expect(5)
+            .mount(server)
+            .await;
+    }
+
+    // One worker takes turns between equally weighted endpoints
+    let dir = TempDir::new().unwrap();
+    let input = write_input(&dir, &["hello"; 10]);
+    let config = Config {
+        load_balancing: LoadBalanceStrategy::WeightedRoundRobin,
+        request: RequestConfig {
+            workers: Some(1),
+            ..Default::default()
+        },
+        ..config(&[a.uri(), b.uri()])
+    };
+    let processor = Processor::new(config).unwrap();
+    let output = dir.path().join("out.jsonl");
+    let result = processor
+        .process_file(
+            input,
+            Some(output.clone()),
+            dir.path().join("err.jsonl"),
+            false,
+        )
+        .await
+        .unwrap();
+    assert_eq!(result.success_count, 10);
+    for endpoint in &result.endpoints {
+        assert_eq!(endpoint.success_count, 5);
+    }
+
+    // Each result records the endpoint that answered it
+    let endpoints: Vec<_> = read_lines(&output)
+        .iter()
+        .map(|line| line["metadata"]["endpoint"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(endpoints.iter().filter(|&url| *url == a.uri()).count(), 5);
+
+    a.verify().await;
+    b.verify().await;
+}
+
+#[tokio::test]
+async fn test_ordered_output() {
+    let server = MockServer::start().await;
+    Mock::given(body_string_contains("slow"))
+        .respond_with(completion().set_delay(Duration::from_millis(200)))
+        .mount(&server)
+        .await;
+    Mock::given(any())
+        .respond_with(completion())
+        .mount(&server)
+        .await;
+
+    // The slow head of the line holds back writing, but not the requests behind it
+    let dir = TempDir::new().unwrap();
+    let prompts = ["slow", "a", "b", "c", "d", "e"];
+    let input = write_input(&dir, &prompts);
+    let mut config = config(&[server.uri()]);
+    config.processing.ordered_output = true;
+    config.processing.reorder_window = 4;
+    let processor = Processor::new(config).unwrap();
+    let output = dir.path().join("out.jsonl");
+    let result = processor
+        .process_file(
+            input,
+            Some(output.clone()),
+            dir.path().join("err.jsonl"),
+            false,
+        )
+        .await
+        .unwrap();
+    assert_eq!(result.success_count, 6);
+
+    let inputs: Vec<_> = std::fs::read_to_string(&output)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["input"].clone())
+        .collect();
+    assert_eq!(inputs, prompts);
+}
+
+#[tokio::test]
+async fn test_max_duration() {
+    let server = MockServer::start().await;
+    Mock::given(any())
+        .respond_with(completion().set_delay(Duration::from_millis(300)))
+        .mount(&server)
+        .await;
+
+    // The request in flight when time runs out finishes, the rest are left for a resumed run
+    let dir = TempDir::new().unwrap();
+    let input = write_input(&dir, &["a", "b", "c"]);
+    let output = dir.path().join("out.jsonl");
+    let mut config = Config {
+        request: RequestConfig {
+            workers: Some(1),
+            ..Default::default()
+        },
+        ..config(&[server.uri()])
+    };
+    config.processing.max_duration = Some(Duration::from_millis(100));
+    let processor = Processor::new(config.clone()).unwrap();
+    let result = processor
+        .process_file(
+            input.clone(),
+            Some(output.clone()),
+            dir.path().join("err.jsonl"),
+            false,
+        )
+        .await
+        .unwrap();
+    assert!(result.time_limited);
+    assert!(!result.interrupted);
+    assert_eq!(result.success_count, 1);
+    assert_eq!(read_lines(&output)[0]["input"], "a");
+
+    config.processing.max_duration = None;
+    config.processing.resume = true;
+    let processor = Processor::new(config).unwrap();
+    let result = processor
+        .process_file(
+            input,
+            Some(output.clone()),
+            dir.path().join("err.jsonl"),
+            false,
+        )
+        .await
+        .unwrap();
+    assert!(!result.time_limited);
+    assert_eq!(result.success_count, 2);
+    assert_eq!(read_lines(&output).len(), 3);
+}
+
+#[tokio::test]
+async fn test_response_cache() {
+    let server = MockServer::start().await;
+    Mock::given(any())
+        .respond_with(completion())
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    // The second run answers both requests from the cache the first one filled,
+    // without waiting for the rate limiter
+    let dir = TempDir::new().unwrap();
+    let input = write_input(&dir, &["a", "b"]);
+    let output = dir.path().join("out.jsonl");
+    let mut config = config(&[server.uri()]);
+    config.processing.cache_dir = Some(dir.path().join("cache"));
+    for cached in [false, true] {
+        if cached {
+            config.request.rate_limit = 1;
+            config.request.rate_period = Duration::from_secs(3600);
+        }
+        let processor = Processor::new(config.clone()).unwrap();
+        let run = processor.process_file(
+            input.clone(),
+            Some(output.clone()),
+            dir.path().join("err.jsonl"),
+            false,
+        );
+        let result = tokio::time::timeout(Duration::from_secs(10), run)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.success_count, 2);
+        assert_eq!(result.cached_count, if cached { 2 } else { 0 });
+        let lines = read_lines(&output);
+        assert_eq!(
+            lines[0]["metadata"]["cached"].as_bool().unwrap_or(false),
+            cached
+        );
+        assert_eq!(
+            lines[1]["response"]["choices"][0]["message"]["content"],
+            "Hi"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_events() {
+    let server = MockServer::start().await;
+    Mock::given(body_string_contains("limited"))
+        .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "0"))
+        .mount(&server)
+        .await;
+    Mock::given(any())
+        .respond_with(completion())
+        .mount(&server)
+        .await;
+
+    let dir = TempDir::new().unwrap();
+    let input = write_input(&dir, &["ok", "limited"]);
+    let mut config = config(&[server.uri()]);
+    config.endpoints[0].unhealthy_after = 1;
+    let (sender, mut events) = tokio::sync::mpsc::channel(64);
+    let processor = Processor::new(config).unwrap().with_events(sender);
+    processor
+        .process_file(input, None, dir.path().join("err.jsonl"), false)
+        .await
+        .unwrap();
+    drop(processor);
+
+    let mut completed = Vec::new();
+    let (mut rate_limited, mut unhealthy, mut finished) = (0, 0, None);
+    while let Some(event) = events.recv().await {
+        match event {
+            ProcessEvent::RequestCompleted {
+                line_number,
+                result,
+            } => completed.push((line_number, result.is_success())),
+            ProcessEvent::RateLimited { retry_after, .. } => {
+                assert_eq!(retry_after, Some(Duration::ZERO));
+                rate_limited += 1;
+            }
+            ProcessEvent::EndpointMarkedUnhealthy { url } => {
+                assert_eq!(url, server.uri());
+                unhealthy += 1;
+            }
+            ProcessEvent::RunFinished(stats) => finished = Some(stats),
+            ProcessEvent::RunFailed { error, .. } => panic!("run failed: {error}"),
+        }
+    }
+    completed.sort_unstable();
+    assert_eq!(completed, [(1, true), (2, false)]);
+    assert_eq!(rate_limited, 3);
+    assert_eq!(unhealthy, 1);
+    assert_eq!(finished.unwrap().total_processed, 2);
+}
+
+#[tokio::test]
+async fn test_failed_run_event() {
+    let server = MockServer::start().await;
+    Mock::given(any())
+        .respond_with(completion())
+        .mount(&server)
+        .await;
+
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("requests.jsonl");
+    std::fs::write(&input, "{\"input\": \"ok\"}\nnot json\n").unwrap();
+    let (sender, mut events) = tokio::sync::mpsc::channel(64);
+    let processor = Processor::new(config(&[server.uri()]))
+        .unwrap()
+        .with_events(sender);
+    assert!(processor
+        .process_file(input, None, dir.path().join("err.jsonl"), false)
+        .await
+        .is_err());
+    drop(processor);
+
+    // A run stopped by malformed input still reports how it ended
+    let mut failed = None;
+    while let Some(event) = events.recv().await {
+        match event {
+            ProcessEvent::RunFailed { error, stats } => failed = Some((error, stats)),
+            ProcessEvent::RunFinished(_) => panic!("a run with malformed input should fail"),
+            _ => {}
+        }
+    }
+    let (error, stats) = failed.unwrap();
+    assert!(error.contains("line 2"), "{error}");
+    assert_eq!(stats.total_processed, 1);
+}
+
+#[tokio::test]
+async fn test_blank_rows() {
+    let server = MockServer::start().await;
+    Mock::given(any())
+        .respond_with(completion())
+        .mount(&server)
+        .await;
+
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("requests.jsonl");
+    std::fs::write(
+        &input,
+        "{\"input\": \"a\"}\n{\"id\": 2}\n{\"input\": \" \"}\n",
+    )
+    .unwrap();
+    let errors = dir.path().join("err.jsonl");
+    for reject in [true, false] {
+        let mut config = config(&[server.uri()]);
+        config.processing.reject_blank_input = reject;
+        let result = Processor::new(config)
+            .unwrap()
+            .process_file(input.clone(), None, errors.clone(), false)
+            .await
+            .unwrap();
+        if reject {
+            // Blank rows fail with their line numbers, without reaching the endpoint
+            assert_eq!(result.success_count, 1);
+            let mut lines = read_lines(&errors);
+            lines.sort_by_key(|line| line["line_number"].as_u64());
+            assert_eq!(lines.len(), 2);
+            assert_eq!(lines[0]["line_number"], 2);
+            assert_eq!(lines[0]["error_kind"], "validation");
+            assert_eq!(lines[1]["line_number"], 3);
+            assert_eq!(server.received_requests().await.unwrap().len(), 1);
+        } else {
+            assert_eq!(result.success_count, 3);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_unreadable_rows() {
+    let server = MockServer::start().await;
+    Mock::given(any())
+        .respond_with(completion())
+        .mount(&server)
+        .await;
+
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("requests.jsonl");
+    let long = format!("{{\"input\": \"{}\"}}\n", "x".repeat(200));
+    let mut content = b"{\"input\": \"a\"}\n{\"input\": \"\xc3\x28\"}\n".to_vec();
+    content.extend_from_slice(long.as_bytes());
+    content.extend_from_slice(b"{\"input\": \"b\"}\n");
+    std::fs::write(&input, content).unwrap();
+
+    // Bad lines fail with their line numbers, and the lines after them are still sent
+    let errors = dir.path().join("err.jsonl");
+    let mut config = config(&[server.uri()]);
+    config.processing.max_line_length = 100;
+    let result = Processor::new(config)
+        .unwrap()
+        .process_file(input, None, errors.clone(), false)
+        .await
+        .unwrap();
+    assert_eq!(result.success_count, 2);
+    assert_eq!(result.failure_count, 2);
+    let mut lines = read_lines(&errors);
+    lines.sort_by_key(|line| line["line_number"].as_u64());
+    assert_eq!(lines[0]["line_number"], 2);
+    assert_eq!(lines[0]["error_kind"], "validation");
+    assert_eq!(lines[0]["error"], "line 2 is not valid UTF-8");
+    assert_eq!(lines[1]["line_number"], 3);
+    assert_eq!(lines[1]["error"], "line 3 is longer than 100 bytes");
+    assert_eq!(server.received_requests().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_request_metadata() {
+    let server = MockServer::start().await;
+    Mock::given(body_string_contains("bad"))
+        .respond_with(ResponseTemplate::new(400))
+        .mount(&server)
+        .await;
+    Mock::given(any())
+        .respond_with(completion())
+        .mount(&server)
+        .await;
+
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("requests.jsonl");
+    std::fs::write(
+        &input,
+        "{\"input\": \"good\", \"order_id\": 41, \"tags\": [\"a\"]}\n\
+         {\"input\": \"bad\", \"order_id\": 42}\n\
+         {\"input\": \"plain\"}\n",
+    )
+    .unwrap();
+    let (output, errors) = (dir.path().join("out.jsonl"), dir.path().join("err.jsonl"));
+    Processor::new(config(&[server.uri()]))
+        .unwrap()
+        .process_file(input, Some(output.clone()), errors.clone(), false)
+        .await
+        .unwrap();
+
+    // Extra fields come back on both outputs, and rows without any add no key
+    let mut lines = read_lines(&output);
+    lines.sort_by_key(|line| line["input"].as_str().map(str::to_string));
+    assert_eq!(
+        lines[0]["request_metadata"],
+        json!({"order_id": 41, "tags": ["a"]})
+    );
+    assert!(lines[1].get("request_metadata").is_none());
+    let failures = read_lines(&errors);
+    assert_eq!(failures[0]["request_metadata"], json!({"order_id": 42}));
+}
+
+#[tokio::test]
+async fn test_replay_errors() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("requests.jsonl");
+    std::fs::write(
+        &input,
+        concat!(
+            "{\"input\": \"a\"}\n",
+            "{\"input\": \"b\", \"id\": 2, \"model\": \"m2\", \"path\": \"/v2/chat\", ",
+            "\"method\": \"PUT\", \"headers\": {\"x-tenant\": \"t2\"}}\n",
+            "{\"body\": {\"n\": 3}}\n",
+        ),
+    )
+    .unwrap();
+    let errors = dir.path().join("err.jsonl");
+
+    // The first run fails everything
+    let outage = Mock::given(any())
+        .respond_with(ResponseTemplate::new(400))
+        .mount_as_scoped(&server)
+        .await;
+    let result = Processor::new(config(&[server.uri()]))
+        .unwrap()
+        .process_file(input, None, errors.clone(), false)
+        .await
+        .unwrap();
+    assert_eq!(result.failure_count, 3);
+    drop(outage);
+
+    // Replaying its errors sends the same requests, under their original line numbers
+    Mock::given(any())
+        .respond_with(completion())
+        .mount(&server)
+        .await;
+    let (output, retried) = (dir.path().join("out.jsonl"), dir.path().join("err2.jsonl"));
+    let result = Processor::new(config(&[server.uri()]))
+        .unwrap()
+        .replay_errors()
+        .process_file(errors, Some(output.clone()), retried, false)
+        .await
+        .unwrap();
+    assert_eq!(result.success_count, 3);
+    let mut lines = read_lines(&output);
+    lines.sort_by_key(|line| line["input"].as_str().map(str::to_string));
+    assert_eq!(lines[0]["input"], Value::Null);
+    assert_eq!(lines[1]["input"], "a");
+    assert_eq!(lines[2]["input"], "b");
+    assert_eq!(lines[2]["request_metadata"], json!({"id": 2}));
+
+    let received = server.received_requests().await.unwrap();
+    let replayed: Vec<Value> = received[3..]
+        .iter()
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .collect();
+    assert!(replayed.contains(&json!({"n": 3})));
+
+    // Along with the method, path, headers and model each request asked for
+    let b = received[3..]
+        .iter()
+        .find(|request| request.url.path() == "/v2/chat")
+        .unwrap();
+    assert_eq!(b.method.as_str(), "PUT");
+    assert_eq!(b.headers.get("x-tenant").unwrap(), "t2");
+    let body: Value = serde_json::from_slice(&b.body).unwrap();
+    assert_eq!(body["model"], "m2");
+    let checkpoint = std::fs::read_to_string(dir.path().join("out.jsonl.checkpoint")).unwrap();
+    let mut completed: Vec<u64> = checkpoint
+        .lines()
+        .filter_map(|line| line.parse().ok())
+        .collect();
+    completed.sort_unstable();
+    assert_eq!(completed, [1, 2, 3]);
+}
+
+#[tokio::test]
+async fn test_sampled_output() {
+    let server = MockServer::start().await;
+    Mock::given(body_string_contains("bad"))
+        .respond_with(ResponseTemplate::new(400))
+        .mount(&server)
+        .await;
+    Mock::given(any())
+        .respond_with(completion())
+        .mount(&server)
+        .await;
+
+    let dir = TempDir::new().unwrap();
+    let prompts: Vec<_> = (0..100).map(|n| format!("p{n:02}")).collect();
+    let mut prompts: Vec<_> = prompts.iter().map(String::as_str).collect();
+    prompts.push("bad");
+    let input = write_input(&dir, &prompts);
+    let (output, errors) = (dir.path().join("out.jsonl"), dir.path().join("err.jsonl"));
+    let processor = |resume| {
+        let mut config = config(&[server.uri()]);
+        config.seed = Some(7);
+        config.request.workers = Some(1);
+        config.processing.sample_output = Some(0.2);
+        config.processing.resume = resume;
+        Processor::new(config).unwrap()
+    };
+
+    // Every request counts, but only some successes are written; failures all are
+    let result = processor(false)
+        .process_file(input.clone(), Some(output.clone()), errors.clone(), false)
+        .await
+        .unwrap();
+    assert_eq!(result.success_count, 100);
+    let written = read_lines(&output).len();
+    assert!(written > 0 && written < 50, "wrote {written} of 100");
+    assert_eq!(read_lines(&errors).len(), 1);
+
+    // Successes left out of the sample are still checkpointed, so a resumed run skips them
+    processor(true)
+        .process_file(input, Some(output), errors, false)
+        .await
+        .unwrap();
+    assert_eq!(server.received_requests().await.unwrap().len(), 102);
+}
+
+#[tokio::test]
+async fn test_rotated_output() {
+    let server = MockServer::start().await;
+    Mock::given(any())
+        .respond_with(completion())
+        .mount(&server)
+        .await;
+
+    let dir = TempDir::new().unwrap();
+    let input = write_input(&dir, &["a", "b", "c"]);
+    let output = dir.path().join("results.jsonl");
+    let mut config = config(&[server.uri()]);
+    config.processing.output_rotate_lines = Some(2);
+    let result = Processor::new(config)
+        .unwrap()
+        .process_file(input, Some(output), dir.path().join("err.jsonl"), false)
+        .await
+        .unwrap();
+    assert_eq!(result.success_count, 3);
+
+    let first = read_lines(&dir.path().join("results.000.jsonl"));
+    let second = read_lines(&dir.path().join("results.001.jsonl"));
+    assert_eq!((first.len(), second.len()), (2, 1));
+    assert!(dir.path().join("err.000.jsonl").exists());
+    assert!(!dir.path().join("results.jsonl").exists());
+}
+
+#[tokio::test]
+async fn test_csv_output() {
+    let server = MockServer::start().await;
+    Mock::given(body_string_contains("odd"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"choices": []})))
+        .mount(&server)
+        .await;
+    Mock::given(any())
+        .respond_with(completion())
+        .mount(&server)
+        .await;
+
+    let dir = TempDir::new().unwrap();
+    let input = write_input(&dir, &["a", "odd, one"]);
+    let output = dir.path().join("results.csv");
+    let mut config = config(&[server.uri()]);
+    config.processing.output_format = OutputFormat::Csv;
+    config.processing.response_projection = [
+        ("content", "choices[0].message.content"),
+        ("role", "choices[0].message.role"),
+    ]
+    .into_iter()
+    .map(|(key, path)| (key.to_string(), path.to_string()))
+    .collect();
+    Processor::new(config)
+        .unwrap()
+        .process_file(
+            input,
+            Some(output.clone()),
+            dir.path().join("err.jsonl"),
+            false,
+        )
+        .await
+        .unwrap();
+
+    // A header, then one row per response, with empty cells for missing fields
+    let content = std::fs::read_to_string(&output).unwrap();
+    let mut lines: Vec<_> = content.lines().collect();
+    assert_eq!(lines.remove(0), "line_number,source,input,content,role");
+    lines.sort_unstable();
+    assert_eq!(lines, ["1,,a,Hi,assistant", "2,,\"odd, one\",,"]);
+}
+
+#[tokio::test]
+async fn test_count_only() {
+    let server = MockServer::start().await;
+    Mock::given(body_string_contains("bad"))
+        .respond_with(ResponseTemplate::new(400))
+        .mount(&server)
+        .await;
+    Mock::given(any())
+        .respond_with(completion())
+        .mount(&server)
+        .await;
+
+    let dir = TempDir::new().unwrap();
+    let input = write_input(&dir, &["a", "b", "bad"]);
+    let errors = dir.path().join("err.jsonl");
+    let mut config = config(&[server.uri()]);
+    config.processing.count_only = true;
+    let processor = Processor::new(config).unwrap();
+    let result = processor
+        .process_file(input.clone(), None, errors.clone(), false)
+        .await
+        .unwrap();
+
+    // Results are tallied, but no file is written
+    assert_eq!((result.success_count, result.failure_count), (2, 1));
+    assert!(!errors.exists());
+    assert!(processor
+        .process_file(input, Some(dir.path().join("out.jsonl")), errors, false)
+        .await
+        .is_err());
+}